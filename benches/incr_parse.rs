@@ -0,0 +1,145 @@
+// 增量命令解析的吞吐对比: 原来逐字节read_exact的解析方式和CommandParser
+// cargo bench --bench incr_parse
+use redis_shake_rs::rdb::loader::{rdbReader, Loader};
+use redis_shake_rs::utils::command::{CommandParser, Frame};
+
+use async_std::task::block_on;
+use tokio::io::AsyncWriteExt;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// 和redis-benchmark默认的一样, value是3个字节
+fn stream(n: usize) -> Vec<u8> {
+    let mut out = vec![];
+    for i in 0..n {
+        let key = format!("key:{:012}", i);
+        let value = "xxx";
+        let args: Vec<&str> = if i % 4 == 0 {
+            vec!["HSET", &key, "field", value]
+        } else {
+            vec!["SET", &key, value]
+        };
+        out.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+        for a in args {
+            out.extend_from_slice(format!("${}\r\n{}\r\n", a.len(), a).as_bytes());
+        }
+    }
+    out
+}
+
+// 原来incr里的解析: 每个头部字节一次read_exact, 每个参数单独分配
+async fn legacy<R: tokio::io::AsyncRead + Unpin>(r: &mut rdbReader<R>) -> (usize, usize) {
+    let (mut cmds, mut bytes) = (0, 0);
+    loop {
+        let mut p = [0; 1];
+        if r.readRawExact(&mut p).await.is_err() {
+            break;
+        }
+        let mut bytes_count = 1;
+        let mut args_num_vec = Vec::new();
+        loop {
+            let mut p_ = [0; 1];
+            bytes_count += r.readRawExact(&mut p_).await.unwrap();
+            if p_[0] == b'\n' {
+                break;
+            } else if p_[0] != b'\r' {
+                args_num_vec.push(p_[0])
+            }
+        }
+        let args_num = String::from_utf8(args_num_vec).unwrap().parse::<i32>().unwrap();
+        let mut args = Vec::with_capacity(args_num as usize);
+        let mut cmd_name = vec![];
+        for i in 0..args_num {
+            let mut len_vec = Vec::new();
+            loop {
+                let mut p_ = [0; 1];
+                bytes_count += r.readRawExact(&mut p_).await.unwrap();
+                if p_[0] == b'$' {
+                    len_vec.clear();
+                } else if p_[0] == b'\n' {
+                    break;
+                } else if p_[0] != b'\r' {
+                    len_vec.push(p_[0])
+                }
+            }
+            let len = String::from_utf8(len_vec).unwrap().parse::<i32>().unwrap();
+            let mut p_: Vec<u8> = vec![0; len as usize];
+            r.readRawExact(&mut p_).await.unwrap();
+            if i == 0 {
+                cmd_name = p_.clone()
+            }
+            args.push(p_);
+            let mut crlf = vec![0; 2];
+            r.readRawExact(&mut crlf).await.unwrap();
+            bytes_count += len as usize + 2;
+        }
+        assert!(!cmd_name.is_empty() && !args.is_empty());
+        cmds += 1;
+        bytes += bytes_count;
+    }
+    (cmds, bytes)
+}
+
+async fn buffered<R: tokio::io::AsyncRead + Unpin>(r: &mut rdbReader<R>) -> (usize, usize) {
+    let (mut cmds, mut bytes) = (0, 0);
+    let mut parser = CommandParser::new();
+    loop {
+        while let Some(frame) = parser.next_frame().unwrap() {
+            bytes += frame.size();
+            if let Frame::Command(_) = frame {
+                cmds += 1;
+            }
+        }
+        if r.readRawBuf(parser.buffer_mut()).await.unwrap() == 0 {
+            break;
+        }
+    }
+    (cmds, bytes)
+}
+
+fn run<F: Fn(Vec<u8>) -> (usize, usize)>(name: &str, data: &[u8], f: F) -> Duration {
+    // 取5次里最快的一次
+    let mut best = Duration::from_secs(u64::MAX);
+    let len = data.len();
+    for _ in 0..5 {
+        let data = data.to_vec();
+        let start = Instant::now();
+        let (cmds, bytes) = f(data);
+        let cost = start.elapsed();
+        assert_eq!(bytes, len);
+        assert_eq!(cmds, 200_000);
+        best = std::cmp::min(best, cost);
+    }
+    println!(
+        "{:<10} {:>10.2?} {:>8.1} MB/s",
+        name,
+        best,
+        data.len() as f64 / best.as_secs_f64() / 1024.0 / 1024.0
+    );
+    best
+}
+
+// 和mod_full一样, 源端的数据写进管道, loader从带缓冲的管道读取
+fn piped<T, F: FnOnce(Loader) -> T>(data: Vec<u8>, f: F) -> T {
+    let (mut writer, reader) = async_pipe::pipe();
+    let handle = std::thread::spawn(move || {
+        block_on(async move {
+            writer.write_all(&data).await.unwrap();
+        })
+    });
+    let reader = tokio::io::BufReader::with_capacity(10 * 1024 * 1024, reader);
+    let r = f(Loader::new(Rc::new(RefCell::new(reader))));
+    handle.join().unwrap();
+    r
+}
+
+fn main() {
+    let data = stream(200_000);
+    let old = run("legacy", &data, |d| block_on(legacy(&mut rdbReader::fromBytes(d))));
+    let new = run("buffered", &data, |d| block_on(buffered(&mut rdbReader::fromBytes(d))));
+    println!("speedup    {:.1}x", old.as_secs_f64() / new.as_secs_f64());
+    let old = run("legacy", &data, |d| piped(d, |mut l| block_on(legacy(&mut l.rdbReader))));
+    let new = run("buffered", &data, |d| piped(d, |mut l| block_on(buffered(&mut l.rdbReader))));
+    println!("speedup    {:.1}x", old.as_secs_f64() / new.as_secs_f64());
+}
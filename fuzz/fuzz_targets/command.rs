@@ -0,0 +1,26 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use redis_shake_rs::utils::command::{CommandParser, Frame};
+
+// 分两次喂数据, 解析出来的字节数加上剩下的必须等于输入的长度
+fuzz_target!(|data: &[u8]| {
+    let mut parser = CommandParser::new();
+    let half = data.len() / 2;
+    let mut used = 0;
+    for part in [&data[..half], &data[half..]].iter() {
+        parser.feed(part);
+        loop {
+            match parser.next_frame() {
+                Ok(Some(frame)) => {
+                    if let Frame::Command(c) = &frame {
+                        assert_eq!(c.args().count(), c.arg_count());
+                    }
+                    used += frame.size();
+                }
+                Ok(None) => break,
+                Err(_) => return,
+            }
+        }
+    }
+    assert_eq!(used + parser.buffered(), data.len());
+});
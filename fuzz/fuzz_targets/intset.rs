@@ -0,0 +1,10 @@
+#![no_main]
+use async_std::task::block_on;
+use libfuzzer_sys::fuzz_target;
+use redis_shake_rs::rdb::loader::rdbReader;
+use redis_shake_rs::rdb::slice_buffer::sliceBuffer;
+
+fuzz_target!(|data: &[u8]| {
+    let mut r = rdbReader::fromBytes(vec![]);
+    let _ = block_on(r.ReadIntset(&mut sliceBuffer::new(data.to_vec())));
+});
@@ -0,0 +1,9 @@
+#![no_main]
+use async_std::task::block_on;
+use libfuzzer_sys::fuzz_target;
+use redis_shake_rs::rdb::loader::rdbReader;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = block_on(rdbReader::fromBytes(data.to_vec()).readEncodedLength());
+    let _ = block_on(rdbReader::fromBytes(data.to_vec()).ReadLength64());
+});
@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use redis_shake_rs::rdb::loader::lzfDecompress;
+
+// 前两个字节作为解压后的长度
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let outlen = u16::from_le_bytes([data[0], data[1]]) as usize;
+    let _ = lzfDecompress(&data[2..], outlen);
+});
@@ -0,0 +1,22 @@
+#![no_main]
+use async_std::task::block_on;
+use libfuzzer_sys::fuzz_target;
+use redis_shake_rs::rdb::loader::rdbReader;
+use redis_shake_rs::rdb::slice_buffer::sliceBuffer;
+
+fuzz_target!(|data: &[u8]| {
+    let mut r = rdbReader::fromBytes(vec![]);
+    let mut buf = sliceBuffer::new(data.to_vec());
+    let _ = block_on(async {
+        r.CountZipmapItems(&mut buf).await?;
+        loop {
+            let (length, _) = r.readZipmapItemLength(&mut buf, false).await?;
+            if length == -1 {
+                break;
+            }
+            buf.Slice(length)?;
+            r.ReadZipmapItem(&mut buf, true).await?;
+        }
+        Ok::<_, Box<dyn std::error::Error>>(())
+    });
+});
@@ -1,15 +1,99 @@
+use redis_shake_rs::utils::config::Config;
 use redis_shake_rs::utils::run::Runner;
+use redis_shake_rs::utils::slot::SlotLayout;
 
 use async_std::task;
+use std::process::exit;
 fn main() {
+    let conf = match Config::from_args() {
+        Ok(d) => d,
+        Err(e) => {
+            println!("参数错误: {}", e);
+            exit(1);
+        }
+    };
     println!("Started task!");
-    task::block_on(run());
+    task::block_on(run(Box::leak(Box::new(conf))));
     println!("Stopped task!");
 }
-async fn run(){
-    let source_url = "127.0.0.1:6379";
-    let source_pass  = "";
-    let target_url = "127.0.0.1:6400";
-    let target_pass  = "";
-    Runner::mod_full(source_url,source_pass,target_url,target_pass).await;
-}
\ No newline at end of file
+async fn run(conf: &'static Config){
+    match conf.mode.as_str() {
+        "full" => {
+            if let Err(e) = Runner::mod_full(&conf.source_url, &conf.source_pass, &conf.target_url, &conf.target_pass, &conf.filter, conf.function_policy, conf.module_policy, conf.inflight_limit, conf.crc_policy, conf.error_policy, conf.target_connections, conf.reply_policy, &conf.deadletter).await {
+                println!("full error: {}", e);
+                exit(1);
+            }
+        }
+        "filter" => {
+            if let Err(e) = Runner::mod_filter(&conf.input, &conf.output, &conf.filter, conf.rdb_compression, conf.output_compression, conf.crc_policy, conf.error_policy).await {
+                println!("filter error: {}", e);
+                exit(1);
+            }
+        }
+        "rump" => {
+            if let Err(e) = Runner::mod_rump(&conf.source_url, &conf.source_pass, &conf.target_url, &conf.target_pass, &conf.filter, conf.rump_batch).await {
+                println!("rump error: {}", e);
+                exit(1);
+            }
+        }
+        "keyspace" => {
+            if let Err(e) = Runner::mod_keyspace(
+                &conf.source_url,
+                &conf.source_pass,
+                &conf.target_url,
+                &conf.target_pass,
+                &conf.filter,
+                conf.keyspace_queue_size,
+                conf.keyspace_debounce_ms,
+            ).await {
+                println!("keyspace error: {}", e);
+                exit(1);
+            }
+        }
+        "aof" => {
+            if let Err(e) = Runner::mod_aof(&conf.input, &conf.target_url, &conf.target_pass, &conf.filter, conf.aof_stop_at, conf.function_policy, conf.module_policy, conf.inflight_limit, conf.crc_policy, conf.error_policy, conf.target_connections, conf.reply_policy, &conf.deadletter).await {
+                println!("aof error: {}", e);
+                exit(1);
+            }
+        }
+        "redrive" => {
+            if let Err(e) = Runner::mod_redrive(&conf.input, &conf.target_url, &conf.target_pass, conf.target_connections, conf.reply_policy, &conf.deadletter, &conf.redrive_errors).await {
+                println!("redrive error: {}", e);
+                exit(1);
+            }
+        }
+        "reshard" => {
+            let layout = if conf.reshard_slots.is_empty() {
+                SlotLayout::from_cluster_shards(&conf.reshard_cluster, &conf.reshard_cluster_pass).await
+            } else {
+                SlotLayout::parse(&conf.reshard_slots)
+            };
+            let layout = match layout {
+                Ok(d) => d,
+                Err(e) => {
+                    println!("slot layout error: {}", e);
+                    exit(1);
+                }
+            };
+            if let Err(e) = Runner::mod_reshard(&conf.input, &conf.output, &layout, &conf.filter, conf.rdb_compression, conf.output_compression, conf.crc_policy, conf.error_policy).await {
+                println!("reshard error: {}", e);
+                exit(1);
+            }
+        }
+        "rdb-diff" => {
+            match Runner::mod_diff(&conf.input, &conf.diff_input, &conf.output, &conf.diff, conf.crc_policy, conf.error_policy).await {
+                Ok(summary) if summary.is_same() => {}
+                // 有不一样的地方时返回2, 方便脚本判断
+                Ok(_) => exit(2),
+                Err(e) => {
+                    println!("rdb-diff error: {}", e);
+                    exit(1);
+                }
+            }
+        }
+        _ => {
+            println!("未知的模式 {}", conf.mode);
+            exit(1);
+        }
+    }
+}
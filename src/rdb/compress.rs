@@ -0,0 +1,127 @@
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+// 备份文件的压缩格式, 读取时按magic判断, 写出时按参数或者扩展名
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+impl Compression {
+    pub fn parse(s: &str) -> Result<Compression, Box<dyn Error>> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            _ => Err(Box::from(format!("未知的压缩格式 {}", s))),
+        }
+    }
+    // 文件开头的magic, lz4只支持frame格式
+    pub fn detect(magic: &[u8]) -> Compression {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if magic.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+            Compression::Lz4
+        } else {
+            Compression::None
+        }
+    }
+    pub fn from_path(path: &str) -> Compression {
+        if path.ends_with(".gz") {
+            Compression::Gzip
+        } else if path.ends_with(".zst") {
+            Compression::Zstd
+        } else if path.ends_with(".lz4") {
+            Compression::Lz4
+        } else {
+            Compression::None
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+            Compression::Lz4 => ".lz4",
+        }
+    }
+}
+
+// 打开文件, 压缩过的边读边解压, Loader看到的是解压后的数据, 校验和也按解压后的数据计算
+pub fn open_file(path: &str) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    let mut file = BufReader::with_capacity(1024 * 1024, File::open(path)?);
+    let compression = Compression::detect(file.fill_buf()?);
+    if compression != Compression::None {
+        println!("{} 是{:?}压缩的文件", path, compression);
+    }
+    Ok(match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+        Compression::Lz4 => Box::new(FrameDecoder::new(file)),
+    })
+}
+
+// 写出rdb的文件, 压缩格式需要在最后finish写入结尾
+pub enum CompressWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+    Lz4(FrameEncoder<BufWriter<File>>),
+    Finished,
+}
+
+impl CompressWriter {
+    pub fn create(path: &str, compression: Compression) -> Result<CompressWriter, Box<dyn Error>> {
+        let file = BufWriter::with_capacity(10 * 1024 * 1024, File::create(path)?);
+        Ok(match compression {
+            Compression::None => CompressWriter::Plain(file),
+            Compression::Gzip => CompressWriter::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+            Compression::Zstd => CompressWriter::Zstd(zstd::Encoder::new(file, 0)?),
+            Compression::Lz4 => CompressWriter::Lz4(FrameEncoder::new(file)),
+        })
+    }
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut file = match std::mem::replace(self, CompressWriter::Finished) {
+            CompressWriter::Plain(w) => w,
+            CompressWriter::Gzip(w) => w.finish()?,
+            CompressWriter::Zstd(w) => w.finish()?,
+            CompressWriter::Lz4(w) => w.finish()?,
+            CompressWriter::Finished => return Ok(()),
+        };
+        file.flush()?;
+        Ok(())
+    }
+}
+
+impl Write for CompressWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressWriter::Plain(w) => w.write(buf),
+            CompressWriter::Gzip(w) => w.write(buf),
+            CompressWriter::Zstd(w) => w.write(buf),
+            CompressWriter::Lz4(w) => w.write(buf),
+            CompressWriter::Finished => Err(io::Error::other("文件已经写完")),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressWriter::Plain(w) => w.flush(),
+            CompressWriter::Gzip(w) => w.flush(),
+            CompressWriter::Zstd(w) => w.flush(),
+            CompressWriter::Lz4(w) => w.flush(),
+            CompressWriter::Finished => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::io::{self, Write};
+
+// redis使用的crc64: Jones多项式, reflected, 初始值0, 结果不取反
+// crc64(0, "123456789") = 0xe9c6d914c4b8d9ca
+const POLY: u64 = 0x95ac9329ac4bc9b5;
+
+const fn make_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut j = 0;
+        while j < 8 {
+            if crc & 1 == 1 {
+                crc = (crc >> 1) ^ POLY;
+            } else {
+                crc = crc >> 1;
+            }
+            j = j + 1;
+        }
+        table[i] = crc;
+        i = i + 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u64; 256] = make_table();
+
+#[derive(Default)]
+pub struct Crc64 {
+    crc: u64,
+}
+
+impl Crc64 {
+    pub fn new() -> Crc64 {
+        Crc64 { crc: 0 }
+    }
+    pub fn get(&self) -> u64 {
+        self.crc
+    }
+    pub fn update(&mut self, p: &[u8]) {
+        let mut crc = self.crc;
+        for b in p {
+            crc = CRC_TABLE[((crc ^ *b as u64) & 0xff) as usize] ^ (crc >> 8);
+        }
+        self.crc = crc;
+    }
+}
+
+impl Write for Crc64 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
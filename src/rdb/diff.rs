@@ -0,0 +1,499 @@
+use crate::rdb::function::library_name;
+use crate::rdb::loader::{rdbTypeName, BinEntry};
+use crate::rdb::salvage::{CrcPolicy, ErrorPolicy};
+use crate::rdb::value::{Item, StreamMeta, visit, Visitor};
+use crate::utils::source::open_rdb_file;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// 两个rdb逐个key比较
+// 先把两边的key按hash拆到临时文件的分区里, 再逐个分区比较, 同一时间只有A的一个分区在内存中
+#[derive(Clone, Debug)]
+pub struct DiffOptions {
+    pub partitions: usize,
+    // 临时文件的目录, 为空时使用系统的临时目录
+    pub temp_dir: String,
+    // 两边的过期时间相差不超过它认为一致, RESTORE到目的端的过期时间是按相对时间算的
+    pub ttl_tolerance_ms: u64,
+    // 每个key最多报告多少个不一样的元素
+    pub max_detail: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            partitions: 16,
+            temp_dir: String::new(),
+            ttl_tolerance_ms: 0,
+            max_detail: 10,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DiffSummary {
+    pub keys_a: u64,
+    pub keys_b: u64,
+    pub only_a: u64,
+    pub only_b: u64,
+    pub type_diff: u64,
+    pub value_diff: u64,
+    pub ttl_diff: u64,
+    pub same: u64,
+    pub function_diff: u64,
+}
+
+impl DiffSummary {
+    pub fn is_same(&self) -> bool {
+        self.only_a + self.only_b + self.type_diff + self.value_diff + self.ttl_diff + self.function_diff == 0
+    }
+    pub fn print(&self) {
+        println!(
+            "[DIFF] keys a:{} b:{} only in a:{} only in b:{} type:{} value:{} ttl:{} same:{} function:{}",
+            self.keys_a,
+            self.keys_b,
+            self.only_a,
+            self.only_b,
+            self.type_diff,
+            self.value_diff,
+            self.ttl_diff,
+            self.same,
+            self.function_diff
+        );
+    }
+}
+
+// 一个key展开成按名字比较的元素: list用下标, set和zset用member, hash用field, stream用id
+// string和module只有一个名字为空的元素
+struct KeyValue {
+    t: u8,
+    expire_at: u64,
+    // 名字 -> (值, hash field的过期时间)
+    elements: BTreeMap<Vec<u8>, (Vec<u8>, u64)>,
+}
+
+// 临时文件的格式: 'K' db key type expire_at, 之后是这个key的 'E' name value ttl
+const RECORD_KEY: u8 = b'K';
+const RECORD_ELEMENT: u8 = b'E';
+
+fn partition(db: u32, key: &[u8], n: usize) -> usize {
+    let mut h = DefaultHasher::new();
+    db.hash(&mut h);
+    key.hash(&mut h);
+    (h.finish() % n as u64) as usize
+}
+
+fn write_bytes<W: Write>(w: &mut W, b: &[u8]) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(b.len() as u32)?;
+    w.write_all(b)
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let n = r.read_u32::<LittleEndian>()?;
+    let mut b = vec![0u8; n as usize];
+    r.read_exact(&mut b)?;
+    Ok(b)
+}
+
+fn render_meta(meta: &StreamMeta) -> Vec<u8> {
+    format!(
+        "length:{} last_id:{} first_id:{:?} max_deleted_id:{:?} entries_added:{:?}",
+        meta.length,
+        meta.last_id,
+        meta.first_id.map(|d| d.to_string()),
+        meta.max_deleted_id.map(|d| d.to_string()),
+        meta.entries_added
+    )
+    .into_bytes()
+}
+
+// 把一个rdb的key写到各个分区的临时文件
+struct PartitionWriter {
+    files: Vec<BufWriter<File>>,
+    current: usize,
+    // list元素的下标
+    index: u64,
+    keys: u64,
+    // library名字 -> 代码
+    functions: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl PartitionWriter {
+    fn element(&mut self, name: &[u8], value: &[u8], ttl: u64) -> io::Result<()> {
+        let w = &mut self.files[self.current];
+        w.write_u8(RECORD_ELEMENT)?;
+        write_bytes(w, name)?;
+        write_bytes(w, value)?;
+        w.write_u64::<LittleEndian>(ttl)
+    }
+}
+
+impl Visitor for PartitionWriter {
+    fn function(&mut self, code: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.functions.insert(library_name(code).unwrap_or_default(), code.to_vec());
+        Ok(())
+    }
+    fn key(&mut self, e: &BinEntry) -> Result<bool, Box<dyn Error>> {
+        // 拆分的key后面的段和第一段在同一个分区, 紧跟在后面
+        if e.IsFirstChunk() {
+            self.current = partition(e.DB, &e.Key, self.files.len());
+            self.index = 0;
+            self.keys = self.keys + 1;
+            let w = &mut self.files[self.current];
+            w.write_u8(RECORD_KEY)?;
+            w.write_u32::<LittleEndian>(e.DB)?;
+            write_bytes(w, &e.Key)?;
+            w.write_u8(e.Type)?;
+            w.write_u64::<LittleEndian>(e.ExpireAt)?;
+        }
+        Ok(true)
+    }
+    fn item(&mut self, e: &BinEntry, item: Item) -> Result<(), Box<dyn Error>> {
+        match item {
+            Item::String(v) => self.element(b"", &v, 0)?,
+            Item::Member(m) if rdbTypeName(e.Type) == "list" => {
+                let name = format!("{}", self.index);
+                self.index = self.index + 1;
+                self.element(name.as_bytes(), &m, 0)?
+            }
+            Item::Member(m) => self.element(&m, b"", 0)?,
+            Item::Scored(m, score) => self.element(&m, format!("{}", score).as_bytes(), 0)?,
+            Item::Field(f, v, ttl) => self.element(&f, &v, ttl)?,
+            Item::StreamEntry(d) => {
+                let mut value = vec![];
+                for (f, v) in d.fields.iter() {
+                    value.extend_from_slice(format!("{:?}={:?} ", String::from_utf8_lossy(f), String::from_utf8_lossy(v)).as_bytes());
+                }
+                self.element(d.id.to_string().as_bytes(), &value, 0)?
+            }
+            Item::StreamMeta(d) => {
+                self.element(b"meta", &render_meta(&d), 0)?;
+                for g in d.groups.iter() {
+                    let mut value = format!("last_id:{} entries_read:{:?} pending:", g.last_id, g.entries_read);
+                    for p in g.pending.iter() {
+                        value.push_str(&format!("{}/{} ", p.id, p.delivery_count));
+                    }
+                    for c in g.consumers.iter() {
+                        value.push_str(&format!("consumer:{:?} pending:{:?} ", String::from_utf8_lossy(&c.name), c.pending.len()));
+                    }
+                    let mut name = b"group ".to_vec();
+                    name.extend_from_slice(&g.name);
+                    self.element(&name, value.as_bytes(), 0)?;
+                }
+            }
+            Item::Module(d) => self.element(b"", &d.raw, 0)?,
+        }
+        Ok(())
+    }
+}
+
+// db和key
+type DbKey = (u32, Vec<u8>);
+
+// 按顺序读取一个分区文件, 每次返回一个完整的key
+struct PartitionReader {
+    r: BufReader<File>,
+    pending: Option<(u32, Vec<u8>, u8, u64)>,
+}
+
+impl PartitionReader {
+    fn open(path: &Path) -> Result<PartitionReader, Box<dyn Error>> {
+        Ok(PartitionReader {
+            r: BufReader::with_capacity(1024 * 1024, File::open(path)?),
+            pending: None,
+        })
+    }
+    fn read_head(&mut self) -> io::Result<(u32, Vec<u8>, u8, u64)> {
+        let db = self.r.read_u32::<LittleEndian>()?;
+        let key = read_bytes(&mut self.r)?;
+        let t = self.r.read_u8()?;
+        let expire_at = self.r.read_u64::<LittleEndian>()?;
+        Ok((db, key, t, expire_at))
+    }
+    // 文件结束时返回None
+    fn read_tag(&mut self) -> io::Result<Option<u8>> {
+        match self.r.read_u8() {
+            Ok(d) => Ok(Some(d)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    fn next_key(&mut self) -> Result<Option<(DbKey, KeyValue)>, Box<dyn Error>> {
+        let (db, key, t, expire_at) = match self.pending.take() {
+            Some(d) => d,
+            None => match self.read_tag()? {
+                None => return Ok(None),
+                Some(RECORD_KEY) => self.read_head()?,
+                Some(_) => return Err(Box::from("diff: 临时文件损坏")),
+            },
+        };
+        let mut value = KeyValue {
+            t,
+            expire_at,
+            elements: BTreeMap::new(),
+        };
+        loop {
+            match self.read_tag()? {
+                None => break,
+                Some(RECORD_ELEMENT) => {
+                    let name = read_bytes(&mut self.r)?;
+                    let v = read_bytes(&mut self.r)?;
+                    let ttl = self.r.read_u64::<LittleEndian>()?;
+                    value.elements.insert(name, (v, ttl));
+                }
+                Some(RECORD_KEY) => {
+                    self.pending = Some(self.read_head()?);
+                    break;
+                }
+                Some(_) => return Err(Box::from("diff: 临时文件损坏")),
+            }
+        }
+        Ok(Some(((db, key), value)))
+    }
+}
+
+// 报告中显示的值, 太长的只显示长度
+fn show(b: &[u8]) -> String {
+    if b.len() > 64 {
+        return format!("<{} bytes>", b.len());
+    }
+    format!("{:?}", String::from_utf8_lossy(b))
+}
+
+fn show_element(value: &(Vec<u8>, u64)) -> String {
+    if value.1 == 0 {
+        return show(&value.0);
+    }
+    format!("{} (expire at {})", show(&value.0), value.1)
+}
+
+// string和module的元素没有名字
+fn element_name(label: &str, name: &[u8]) -> String {
+    if name.is_empty() {
+        return String::new();
+    }
+    format!("{}{}: ", label, show(name))
+}
+
+fn ttl_differs(a: u64, b: u64, tolerance: u64) -> bool {
+    if a == 0 || b == 0 {
+        return a != b;
+    }
+    a.abs_diff(b) > tolerance
+}
+
+struct Differ<'a> {
+    opts: &'a DiffOptions,
+    out: &'a mut dyn Write,
+    summary: DiffSummary,
+}
+
+impl<'a> Differ<'a> {
+    fn only(&mut self, side: &str, db: u32, key: &[u8], value: &KeyValue) -> io::Result<()> {
+        writeln!(self.out, "only-in-{} db{} {} type:{}", side, db, show(key), rdbTypeName(value.t))
+    }
+    fn compare(&mut self, db: u32, key: &[u8], a: &KeyValue, b: &KeyValue) -> io::Result<()> {
+        let (ta, tb) = (rdbTypeName(a.t), rdbTypeName(b.t));
+        if ta != tb {
+            self.summary.type_diff = self.summary.type_diff + 1;
+            return writeln!(self.out, "type db{} {} a:{} b:{}", db, show(key), ta, tb);
+        }
+        let mut same = true;
+        if ttl_differs(a.expire_at, b.expire_at, self.opts.ttl_tolerance_ms) {
+            same = false;
+            self.summary.ttl_diff = self.summary.ttl_diff + 1;
+            writeln!(self.out, "ttl db{} {} a:{} b:{}", db, show(key), a.expire_at, b.expire_at)?;
+        }
+        let label = match ta {
+            "list" => "index ",
+            "set" | "zset" => "member ",
+            "hash" => "field ",
+            _ => "",
+        };
+        // 两边都是按名字排序的, 合并着比较
+        let mut details = vec![];
+        let mut count = 0usize;
+        let mut ia = a.elements.iter().peekable();
+        let mut ib = b.elements.iter().peekable();
+        loop {
+            let line = match (ia.peek(), ib.peek()) {
+                (None, None) => break,
+                (Some((na, va)), Some((nb, vb))) if na == nb => {
+                    let differs = va.0 != vb.0 || ttl_differs(va.1, vb.1, self.opts.ttl_tolerance_ms);
+                    let line = format!("{}a:{} b:{}", element_name(label, na), show_element(va), show_element(vb));
+                    ia.next();
+                    ib.next();
+                    if !differs {
+                        continue;
+                    }
+                    line
+                }
+                (Some((na, va)), Some((nb, _))) if na < nb => {
+                    let line = format!("{}only in a {}", element_name(label, na), show_element(va));
+                    ia.next();
+                    line
+                }
+                (Some((na, va)), None) => {
+                    let line = format!("{}only in a {}", element_name(label, na), show_element(va));
+                    ia.next();
+                    line
+                }
+                (_, Some((nb, vb))) => {
+                    let line = format!("{}only in b {}", element_name(label, nb), show_element(vb));
+                    ib.next();
+                    line
+                }
+            };
+            count = count + 1;
+            if details.len() < self.opts.max_detail {
+                details.push(line);
+            }
+        }
+        if count > 0 {
+            same = false;
+            self.summary.value_diff = self.summary.value_diff + 1;
+            writeln!(self.out, "value db{} {} type:{} {} elements differ", db, show(key), ta, count)?;
+            for line in details {
+                writeln!(self.out, "    {}", line)?;
+            }
+        }
+        if same {
+            self.summary.same = self.summary.same + 1;
+        }
+        Ok(())
+    }
+    fn functions(&mut self, a: &BTreeMap<Vec<u8>, Vec<u8>>, b: &BTreeMap<Vec<u8>, Vec<u8>>) -> io::Result<()> {
+        for (name, code) in a.iter() {
+            match b.get(name) {
+                None => writeln!(self.out, "function only-in-a {}", show(name))?,
+                Some(other) if other != code => writeln!(self.out, "function differ {}", show(name))?,
+                Some(_) => continue,
+            }
+            self.summary.function_diff = self.summary.function_diff + 1;
+        }
+        for name in b.keys() {
+            if !a.contains_key(name) {
+                writeln!(self.out, "function only-in-b {}", show(name))?;
+                self.summary.function_diff = self.summary.function_diff + 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+// 读取一个rdb写到分区文件, 返回key数和function
+async fn split(
+    input: &str,
+    paths: &[PathBuf],
+    crc_policy: CrcPolicy,
+    error_policy: ErrorPolicy,
+) -> Result<(u64, BTreeMap<Vec<u8>, Vec<u8>>), Box<dyn Error>> {
+    let mut loader = open_rdb_file(input).await?;
+    loader.crcPolicy = crc_policy;
+    loader.errorPolicy = error_policy;
+    loader.Header().await?;
+    let mut files = vec![];
+    for path in paths {
+        files.push(BufWriter::with_capacity(64 * 1024, File::create(path)?));
+    }
+    let mut writer = PartitionWriter {
+        files,
+        current: 0,
+        index: 0,
+        keys: 0,
+        functions: BTreeMap::new(),
+    };
+    visit(&mut loader, &mut writer).await?;
+    for f in writer.files.iter_mut() {
+        f.flush()?;
+    }
+    Ok((writer.keys, writer.functions))
+}
+
+// 比较两个rdb, 不一样的地方按行写到out
+pub async fn diff(
+    a: &str,
+    b: &str,
+    opts: &DiffOptions,
+    crc_policy: CrcPolicy,
+    error_policy: ErrorPolicy,
+    out: &mut dyn Write,
+) -> Result<DiffSummary, Box<dyn Error>> {
+    let base = if opts.temp_dir.is_empty() {
+        std::env::temp_dir()
+    } else {
+        PathBuf::from(&opts.temp_dir)
+    };
+    // 同一个进程里可能同时有多个diff
+    static SEQ: AtomicUsize = AtomicUsize::new(0);
+    let dir = base.join(format!(
+        "redis-shake-rs-diff-{}-{}",
+        std::process::id(),
+        SEQ.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::create_dir_all(&dir)?;
+    let rsl = diff_in(a, b, opts, crc_policy, error_policy, out, &dir).await;
+    fs::remove_dir_all(&dir)?;
+    rsl
+}
+
+async fn diff_in(
+    a: &str,
+    b: &str,
+    opts: &DiffOptions,
+    crc_policy: CrcPolicy,
+    error_policy: ErrorPolicy,
+    out: &mut dyn Write,
+    dir: &Path,
+) -> Result<DiffSummary, Box<dyn Error>> {
+    let n = std::cmp::max(opts.partitions, 1);
+    let paths_a: Vec<PathBuf> = (0..n).map(|i| dir.join(format!("a.{}", i))).collect();
+    let paths_b: Vec<PathBuf> = (0..n).map(|i| dir.join(format!("b.{}", i))).collect();
+    let (keys_a, functions_a) = split(a, &paths_a, crc_policy, error_policy).await?;
+    println!("[DIFF] {} keys:{}", a, keys_a);
+    let (keys_b, functions_b) = split(b, &paths_b, crc_policy, error_policy).await?;
+    println!("[DIFF] {} keys:{}", b, keys_b);
+    let mut differ = Differ {
+        opts,
+        out,
+        summary: DiffSummary {
+            keys_a,
+            keys_b,
+            ..DiffSummary::default()
+        },
+    };
+    differ.functions(&functions_a, &functions_b)?;
+    for i in 0..n {
+        let mut left = BTreeMap::new();
+        let mut reader = PartitionReader::open(&paths_a[i])?;
+        while let Some((k, v)) = reader.next_key()? {
+            left.insert(k, v);
+        }
+        let mut reader = PartitionReader::open(&paths_b[i])?;
+        while let Some(((db, key), vb)) = reader.next_key()? {
+            match left.remove(&(db, key.clone())) {
+                Some(va) => differ.compare(db, &key, &va, &vb)?,
+                None => {
+                    differ.summary.only_b = differ.summary.only_b + 1;
+                    differ.only("b", db, &key, &vb)?;
+                }
+            }
+        }
+        for ((db, key), va) in left.iter() {
+            differ.summary.only_a = differ.summary.only_a + 1;
+            differ.only("a", *db, key, va)?;
+        }
+        fs::remove_file(&paths_a[i])?;
+        fs::remove_file(&paths_b[i])?;
+    }
+    differ.out.flush()?;
+    Ok(differ.summary)
+}
@@ -1,338 +1,223 @@
-use crate::rdb::loader;
 use crate::rdb::loader::{
-    rdbReader, BinEntry, Loader, RDBTypeStreamListPacks, RdbFlagAUX, RdbTypeQuicklist,
+    rdbFlagFunction2, rdbIsStream, rdbTypeMinVersion, rdbTypeName, BinEntry, Loader, RdbFlagAUX, RdbMetadata,
+    RdbTypeModule2, RdbTypeQuicklist,
 };
-use crate::rdb::slice_buffer::sliceBuffer;
-use redis::{Cmd};
+use crate::rdb::function::Functions;
+use crate::rdb::module::Modules;
+use crate::rdb::stream::over_restore_stream_entry;
+use crate::rdb::value::{Item, ValueIter};
+use crate::utils::filter::Filter;
+use crate::utils::version::TargetVersion;
+use crate::utils::memory::CmdSender;
 
-use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::error;
 use std::error::Error;
 
+use tokio::io::AsyncRead;
 
-use std::rc::Rc;
-
-use tokio::sync::mpsc::{Sender};
-use async_std::task::spawn;
-
-use crc64::Crc64;
-use time::{Time};
-use tokio::io::{AsyncWriteExt, BufReader};
-
+use crate::utils::clock::now_ms;
 
+// 全量阶段的统计, 用来和源端的INFO keyspace对比
+#[derive(Clone, Debug, Default)]
+pub struct FullSummary {
+    // db -> (key数, 带过期时间的key数), 是rdb里的全部key, 包括过滤掉和已经过期的
+    pub keys: BTreeMap<u32, (u64, u64)>,
+    pub types: BTreeMap<&'static str, u64>,
+    // 读取时已经过期, 没有写入目的端
+    pub expired_skipped: u64,
+    pub filtered: u64,
+    // 每种方式写入的key数: RESTORE, 目的端版本不够用命令重建, 大key拆成命令
+    pub restore: u64,
+    pub rebuild: u64,
+    pub big_split: u64,
+}
 
+impl FullSummary {
+    fn add(&mut self, e: &BinEntry) {
+        let db = self.keys.entry(e.DB).or_insert((0, 0));
+        db.0 = db.0 + 1;
+        if e.ExpireAt != 0 {
+            db.1 = db.1 + 1;
+        }
+        *self.types.entry(rdbTypeName(e.Type)).or_insert(0) += 1;
+    }
+    pub fn print(&self, metadata: &RdbMetadata) {
+        println!(
+            "[FULL] restore keys:{} rebuild keys:{} big keys:{} expired skipped:{} filtered:{}",
+            self.restore, self.rebuild, self.big_split, self.expired_skipped, self.filtered
+        );
+        // 和INFO keyspace一样的格式, 后面是rdb中RESIZEDB记录的大小
+        let mut dbs: Vec<u32> = self.keys.keys().chain(metadata.DbSizes.keys()).cloned().collect();
+        dbs.sort();
+        dbs.dedup();
+        for db in dbs {
+            let (keys, expires) = self.keys.get(&db).cloned().unwrap_or((0, 0));
+            match metadata.DbSizes.get(&db) {
+                Some((size, expire_size)) => println!(
+                    "[FULL] db{}:keys={},expires={} resizedb:keys={},expires={}",
+                    db, keys, expires, size, expire_size
+                ),
+                None => println!("[FULL] db{}:keys={},expires={}", db, keys, expires),
+            }
+        }
+        for (t, n) in &self.types {
+            println!("[FULL] type {}:{}", t, n);
+        }
+    }
+}
 
-pub async fn full(
-    loader: &mut Loader,
-    full_cmd_sender: &mut Sender<Cmd>,
-) -> Result<(), Box<dyn Error>> {
+pub async fn full<R: AsyncRead + Unpin>(
+    loader: &mut Loader<R>,
+    full_cmd_sender: &mut CmdSender,
+    filter: &Filter,
+    functions: &Functions,
+    modules: &mut Modules,
+    target: TargetVersion,
+) -> Result<FullSummary, Box<dyn Error>> {
     let mut now_db_index = 0;
+    let mut summary = FullSummary::default();
+    // 拆分的key按第一段判断是否过期, 后面的段跟着跳过
+    let mut skipping_expired = false;
     loop {
-        let mut e = BinEntry {
-            DB: 0,
-            Key: vec![],
-            Type: 0,
-            Value: vec![],
-            ExpireAt: 0,
-            RealMemberCount: 0,
-            NeedReadLen: 0,
-            IdleTime: 0,
-            Freq: 0,
-        };
+        let mut e = BinEntry::default();
         match loader.NextBinEntry(&mut e).await {
             Ok(()) => {
+                full_cmd_sender.offset = loader.rdbReader.nread as u64;
+                if e.Type == rdbFlagFunction2 {
+                    // function不属于某个db, 不需要SELECT
+                    if let Some(cmd) = functions.full_cmd(&e.Value)? {
+                        full_cmd_sender.send(cmd).await?;
+                    }
+                    continue;
+                }
+                if e.Type != RdbFlagAUX {
+                    if e.IsFirstChunk() {
+                        summary.add(&e);
+                        skipping_expired = e.ExpireAt != 0 && e.ExpireAt <= now_ms();
+                        if skipping_expired {
+                            summary.expired_skipped = summary.expired_skipped + 1;
+                        }
+                    }
+                    if skipping_expired {
+                        continue;
+                    }
+                }
+                if e.Type != RdbFlagAUX && filter.filter_entry(&e) {
+                    if e.IsFirstChunk() {
+                        summary.filtered = summary.filtered + 1;
+                    }
+                    continue;
+                }
+                if e.Type != RdbFlagAUX && !modules.check(&e.Key, &e.Value)? {
+                    continue;
+                }
                 // 切换DB
                 if now_db_index != e.DB {
                     now_db_index = e.DB;
-                    full_cmd_sender.send(redis::cmd("SELECT").arg(e.DB).to_owned()).await;
+                    full_cmd_sender.send(redis::cmd("SELECT").arg(e.DB).to_owned()).await?;
                 };
                 if e.Type == RdbTypeQuicklist {
-                    full_cmd_sender.send(redis::cmd("DEL").arg(e.Key.clone()).to_owned()).await;
-                    OverRestoreQuicklistEntry(&e,full_cmd_sender).await;
-                    if e.ExpireAt != 0 {
-                        full_cmd_sender.send( redis::cmd("EXPIREAT").arg(e.Key.clone()).arg(e.ExpireAt).to_owned()).await;
+                    if e.IsFirstChunk() {
+                        summary.big_split = summary.big_split + 1;
                     }
+                    OverRestoreBigRdbEntry(&e, full_cmd_sender, target).await?;
                 } else if e.Type == RdbFlagAUX
                     && String::from_utf8_lossy(e.Key.clone().as_slice()).eq("lua")
                 {
-                    full_cmd_sender.send( redis::cmd("SCRIPT").arg("load").arg(e.Value).to_owned()).await;
-                } else if e.Type != RDBTypeStreamListPacks
-                    && (e.Value.len() >= 10*1024*1024 || e.RealMemberCount != 0)
-                {
-                    OverRestoreBigRdbEntry(&e,full_cmd_sender).await;
+                    full_cmd_sender.send(redis::cmd("SCRIPT").arg("load").arg(e.Value).to_owned()).await?;
+                } else if e.Type != RdbTypeModule2 && (e.Value.len() >= 10*1024*1024 || e.RealMemberCount != 0) {
+                    // 大key和拆分成多段的key, 每一段拆成普通命令
+                    if e.IsFirstChunk() {
+                        summary.big_split = summary.big_split + 1;
+                    }
+                    OverRestoreBigRdbEntry(&e, full_cmd_sender, target).await?;
+                } else if rdbTypeMinVersion(e.Type) > target.rdb && e.Type != RdbTypeModule2 {
+                    // 目的端读不了这个编码, 用普通命令重建
+                    summary.rebuild = summary.rebuild + 1;
+                    OverRestoreBigRdbEntry(&e, full_cmd_sender, target).await?;
                 } else {
+                    summary.restore = summary.restore + 1;
                     let mut ttlms = 0;
-                    if e.ExpireAt != 0{
-                        let now = Time::now().millisecond();
-                        if now>= e.ExpireAt as u16 {
-                            ttlms = 1
-                        }else{
-                            ttlms = e.ExpireAt - now as u64
-                        }
+                    if e.ExpireAt != 0 {
+                        // 上面已经跳过了过期的key, 这里至少保留1ms
+                        ttlms = std::cmp::max(e.ExpireAt.saturating_sub(now_ms()), 1);
                     }
-                    full_cmd_sender.send(redis::cmd("DEL").arg(e.Key.clone()).to_owned()).await;
-                    full_cmd_sender.send(redis::cmd("RESTORE").arg(e.Key).arg(ttlms).arg(e.Value).to_owned()).await;
+                    full_cmd_sender.send(redis::cmd("DEL").arg(e.Key.clone()).to_owned()).await?;
+                    full_cmd_sender.send(redis::cmd("RESTORE").arg(e.Key).arg(ttlms).arg(e.Value).to_owned()).await?;
                 }
             }
             Err(e) => {
                 if e.to_string().eq("RDB END") {
                     println!("RDB END!");
-                    loader.Footer().await.unwrap();
+                    println!("[FULL] target rdb version:{}", target.rdb);
+                    loader.metadata.Print();
+                    summary.print(&loader.metadata);
+                    modules.print_report();
+                    loader.Footer().await?;
+                    loader.PrintReport();
                     break;
                 } else {
-                    // todo 取消
-                    // panic!("RDB parse err is {}", e);
+                    // salvage模式下能跳过的错误loader已经处理了
+                    loader.PrintReport();
+                    return Err(e);
                 }
             }
         }
     };
-    Ok(())
-}
-pub async fn OverRestoreQuicklistEntry(
-    e: &BinEntry,
-    full_cmd_sender: &mut Sender<Cmd>
-) -> Result<(), Box<dyn error::Error>> {
-    let ( mut write,read) = async_pipe::pipe();
-    let value = e.Value.clone();
-    spawn(async move{
-        write.write_all(value.as_slice()).await.unwrap();
-    });
-    let mut r = rdbReader {
-        raw: Rc::new(RefCell::new(BufReader::new(read))),
-        crc64:Crc64::new(),
-        is_cache_buf: false,
-        buf: vec![],
-        nread: 0,
-        remainMember: 0,
-        lastReadCount: 0,
-        totMemberCount: 0,
-    };
-    r.ReadByte().await?;
-    let n = r.ReadLength().await?;
-    for _ in 0..n {
-        let ziplist = r.ReadString().await?;
-        let mut buf = sliceBuffer::new(ziplist);
-        let zln = r.ReadZiplistLength(&mut buf).await?;
-        for _ in 0..zln {
-            let entry = r.ReadZiplistEntry(&mut buf).await?;
-            full_cmd_sender.send(redis::cmd("RPUSH").arg(e.Key.clone()).arg(0).arg(entry).to_owned()).await;
-        }
-    }
-    Ok(())
+    Ok(summary)
 }
+// 大key或者目的端不能RESTORE的key, 按元素拆成普通命令
+// 拆分成多段的key第一段先删除旧的值, 每一段结束都设置过期时间
 pub async fn OverRestoreBigRdbEntry(
     e: &BinEntry,
-    full_cmd_sender: &mut Sender<Cmd>
+    full_cmd_sender: &mut CmdSender,
+    target: TargetVersion,
 ) -> Result<(), Box<dyn error::Error>> {
-    let ( mut write,read) = async_pipe::pipe();
-    let value = e.Value.clone();
-    spawn(async move{
-        write.write_all(value.as_slice()).await.unwrap();
-    });
-    let mut r = rdbReader {
-        raw: Rc::new(RefCell::new(BufReader::new(read))),
-        is_cache_buf: false,
-        buf: vec![],
-        crc64:Crc64::new(),
-        nread: 0,
-        remainMember: 0,
-        lastReadCount: 0,
-        totMemberCount: 0,
-    };
-    let t = r.ReadByte().await?;
-    match t {
-        loader::RdbTypeHashZiplist => {
-            let ziplist = r.ReadString().await?;
-            let mut buf = sliceBuffer::new(ziplist);
-            let mut length = r.ReadZiplistLength(&mut buf).await?;
-            length = length / 2;
-            println!(
-                "restore big hash key {} field count {}",
-                String::from_utf8(e.Key.clone()).unwrap().as_str(),
-                length
-            );
-            for _ in 0..length {
-                let filed = r.ReadZiplistEntry(&mut buf).await?;
-                let value = r.ReadZiplistEntry(&mut buf).await?;
-                full_cmd_sender.send(redis::cmd("HSET").arg(e.Key.clone()).arg(filed).arg(value).to_owned()).await;
-            }
-        }
-        loader::RdbTypeZSetZiplist => {
-            let ziplist = r.ReadString().await?;
-            let mut buf = sliceBuffer::new(ziplist);
-            let mut cardinality = r.ReadZiplistLength(&mut buf).await?;
-            cardinality = cardinality / 2;
-            println!(
-                "restore big zset key {} field count {}",
-                String::from_utf8(e.Key.clone()).unwrap().as_str(),
-                cardinality
-            );
-            for _ in 0..cardinality {
-                let member = r.ReadZiplistEntry(&mut buf).await?;
-                let scoreBytes = r.ReadZiplistEntry(&mut buf).await?;
-                String::from_utf8_lossy(scoreBytes.clone().as_ref())
-                    .parse::<f64>()?;
-                full_cmd_sender.send(redis::cmd("ZADD").arg(e.Key.clone()).arg(scoreBytes).arg(member).to_owned()).await;
-            }
-        }
-        loader::RdbTypeSetIntset => {
-            let intset = r.ReadString().await?;
-            let mut buf = sliceBuffer::new(intset);
-            let intSizeBytes = buf.Slice(4)?;
-            let intSize = r.u32(intSizeBytes.as_slice());
-            println!("intSizeBytes {:?}",intSizeBytes);
-            if intSize != 2 && intSize != 4 && intSize != 8 {
-                panic!("rdb: unknown intset encoding ");
-            }
-            let lenBytes = buf.Slice(4)?;
-            let cardinality = r.u32(lenBytes.as_slice());
-            println!(
-                "restore big set key {} field count {}",
-                String::from_utf8(e.Key.clone()).unwrap().as_str(),
-                cardinality
-            );
-            for _ in 0..cardinality {
-                let intBytes = buf.Slice(intSize as i32)?;
-                let mut intString = vec![];
-                match intSize {
-                    2 => {
-                        intString = format!("{}", r.u16(intBytes.as_slice())).into_bytes();
-                    }
-                    4 => {
-                        intString = format!("{}", r.u32(intBytes.as_slice())).into_bytes();
-                    }
-                    8 => {
-                        intString = format!("{}", r.u64(intBytes.as_slice())).into_bytes();
-                    }
-                    _ => {}
-                }
-                full_cmd_sender.send(redis::cmd("SADD").arg(e.Key.clone()).arg(intString).to_owned()).await;
-            }
-        }
-        loader::RdbTypeListZiplist => {
-            let ziplist = r.ReadString().await?;
-            let mut buf = sliceBuffer::new(ziplist);
-            let length = r.ReadZiplistLength(&mut buf).await?;
-            println!(
-                "restore big list key {} field count {}",
-                String::from_utf8(e.Key.clone()).unwrap().as_str(),
-                length
-            );
-            for _ in 0..length {
-                let entry = r.ReadZiplistEntry(&mut buf).await?;
-                full_cmd_sender.send(redis::cmd("RPUSH").arg(e.Key.clone()).arg(entry).to_owned()).await;
-            }
-        }
-        loader::RdbTypeHashZipmap => {
-            let mut length = 0;
-            let ziplist = r.ReadString().await?;
-            let mut buf = sliceBuffer::new(ziplist);
-            let lenByte = r.ReadByte().await?;
-            if lenByte >= 254 {
-                length = r.CountZipmapItems(&mut buf).await?;
-                length = length / 2;
-            } else {
-                length = lenByte as i32;
-            }
-            println!(
-                "restore big hash key {} field count {}",
-                String::from_utf8(e.Key.clone()).unwrap().as_str(),
-                length
-            );
-            for _ in 0..length {
-                let field = r.ReadZipmapItem(&mut buf, false).await?;
-                let value = r.ReadZipmapItem(&mut buf, true).await?;
-                full_cmd_sender.send(redis::cmd("HSET").arg(e.Key.clone()).arg(field).arg(value).to_owned()).await;
-            }
-        }
-        loader::RdbTypeString => {
-            let value = r.ReadString().await?;
-            full_cmd_sender.send(redis::cmd("SET").arg(e.Key.clone()).arg(value).to_owned()).await;
-        }
-        loader::RdbTypeList => {
-            let n = r.ReadLength().await?;
-            println!(
-                "restore big list key {} field count {}",
-                String::from_utf8(e.Key.clone()).unwrap().as_str(),
-                n
-            );
-            for _ in 0..n {
-                let field = r.ReadString().await?;
-                full_cmd_sender.send(redis::cmd("RPUSH").arg(e.Key.clone()).arg(field).to_owned()).await;
-            }
-        }
-        loader::RdbTypeSet => {
-            let n = r.ReadLength().await?;
-            println!(
-                "restore big set key {} field count {}",
-                String::from_utf8(e.Key.clone()).unwrap().as_str(),
-                n
-            );
-            for _ in 0..n {
-                let member = r.ReadString().await?;
-                full_cmd_sender.send(redis::cmd("SADD").arg(e.Key.clone()).arg(member).to_owned()).await;
-            }
-        }
-        loader::RdbTypeZSet | loader::RdbTypeZSet2 => {
-            let n = r.ReadLength().await?;
-            println!(
-                "restore big zset key {} field count {}",
-                String::from_utf8(e.Key.clone()).unwrap().as_str(),
-                n
-            );
-            for _ in 0..n {
-                let member = r.ReadString().await?;
-                let score;
-                if t == loader::RdbTypeZSet2 {
-                    score = r.ReadDouble().await?;
-                } else {
-                    score = r.ReadFloat().await?;
-                }
-                println!(
-                    "restore zset key {} field count {} member {}",
-                    String::from_utf8(e.Key.clone()).unwrap().as_str(),
-                    score,
-                    String::from_utf8(member.clone()).unwrap().as_str()
-                );
-                full_cmd_sender.send( redis::cmd("ZADD").arg(e.Key.clone()).arg(score).arg(member).to_owned()).await;
-            }
-        }
-        loader::RdbTypeHash => {
-            let n;
-            if e.NeedReadLen == 1 {
-                let rlen = r.ReadLength().await?;
-                if e.RealMemberCount != 0 {
-                    n = e.RealMemberCount
-                } else {
-                    n = rlen
-                }
-            } else {
-                n = e.RealMemberCount
-            }
-            println!(
-                "restore big hash key {} field count {}",
-                String::from_utf8(e.Key.clone()).unwrap().as_str(),
-                n
-            );
-            for _ in 0..n {
-                let field = r.ReadString().await?;
-                let value = r.ReadString().await?;
-                full_cmd_sender.send( redis::cmd("HSET").arg(e.Key.clone()).arg(field).arg(value).to_owned()).await;
-            }
-        }
-        loader::RdbTypeQuicklist => {
-            let n = r.ReadLength().await?;
-            for _ in 0..n {
-                let ziplist = r.ReadString().await?;
-                let mut buf = sliceBuffer::new(ziplist);
-                let zln = r.ReadLength().await?;
-                for _ in 0..zln {
-                    let entry = r.ReadZiplistEntry(&mut buf).await?;
-                    full_cmd_sender.send(redis::cmd("RPUSH").arg(e.Key.clone()).arg(entry).to_owned()).await;
+    if rdbIsStream(e.Type) {
+        return over_restore_stream_entry(e, full_cmd_sender, target).await;
+    }
+    if e.IsFirstChunk() {
+        full_cmd_sender.send(redis::cmd("DEL").arg(e.Key.clone()).to_owned()).await?;
+    }
+    let mut it = ValueIter::new(e).await?;
+    let is_list = rdbTypeName(e.Type) == "list";
+    let mut count = 0u64;
+    while let Some(item) = it.next_item().await? {
+        count = count + 1;
+        let cmd = match item {
+            Item::String(value) => redis::cmd("SET").arg(e.Key.clone()).arg(value).to_owned(),
+            Item::Member(member) if is_list => redis::cmd("RPUSH").arg(e.Key.clone()).arg(member).to_owned(),
+            Item::Member(member) => redis::cmd("SADD").arg(e.Key.clone()).arg(member).to_owned(),
+            Item::Scored(member, score) => redis::cmd("ZADD").arg(e.Key.clone()).arg(score_arg(score)).arg(member).to_owned(),
+            Item::Field(field, value, expire_at) => {
+                full_cmd_sender.send(redis::cmd("HSET").arg(e.Key.clone()).arg(field.clone()).arg(value).to_owned()).await?;
+                if expire_at == 0 {
+                    continue;
                 }
+                // hash field的过期时间, 需要目的端为redis 7.4+
+                redis::cmd("HPEXPIREAT").arg(e.Key.clone()).arg(expire_at).arg("FIELDS").arg(1).arg(field).to_owned()
             }
-        }
-        _ => panic!("restore big key error"),
-    };
+            _ => return Err(Box::from(format!("restore big key error, type {}", e.Type))),
+        };
+        full_cmd_sender.send(cmd).await?;
+    }
+    if e.ExpireAt != 0 {
+        full_cmd_sender.send(redis::cmd("PEXPIREAT").arg(e.Key.clone()).arg(e.ExpireAt).to_owned()).await?;
+    }
+    println!(
+        "restore big {} key {} field count {}",
+        rdbTypeName(e.Type),
+        String::from_utf8_lossy(&e.Key),
+        count
+    );
     Ok(())
 }
+
+// redis-rs用dtoa格式化f64, inf会变成1.797693134862316e308, redis解析时超出范围会报错
+fn score_arg(score: f64) -> String {
+    if score.is_infinite() {
+        return if score > 0.0 { "inf".to_string() } else { "-inf".to_string() };
+    }
+    format!("{}", score)
+}
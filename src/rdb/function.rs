@@ -0,0 +1,114 @@
+use crate::utils::conn::open_redis_sync_conn;
+use bytes::Bytes;
+use redis::{Cmd, RedisResult, Value};
+
+use std::collections::HashSet;
+use std::error::Error;
+
+// 目的端已经存在同名library时的处理
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FunctionPolicy {
+    // FUNCTION LOAD REPLACE 覆盖
+    Replace,
+    // 保留目的端的library
+    Skip,
+    // 全量阶段直接报错退出, 增量阶段命令原样发送
+    Fail,
+}
+
+impl FunctionPolicy {
+    pub fn parse(s: &str) -> Result<FunctionPolicy, Box<dyn Error>> {
+        match s {
+            "replace" => Ok(FunctionPolicy::Replace),
+            "skip" => Ok(FunctionPolicy::Skip),
+            "fail" => Ok(FunctionPolicy::Fail),
+            _ => Err(Box::from(format!("未知的function冲突策略 {}", s))),
+        }
+    }
+}
+
+pub struct Functions {
+    pub policy: FunctionPolicy,
+    // 目的端已经存在的library
+    pub existing: HashSet<Vec<u8>>,
+}
+
+impl Functions {
+    // 查询目的端的FUNCTION LIST, 7.0以下的版本没有function
+    pub async fn load_target(url: &str, pass: &str, policy: FunctionPolicy) -> Result<Functions, Box<dyn Error>> {
+        let mut conn = open_redis_sync_conn(url, pass, "").await?;
+        let list: RedisResult<Value> = redis::cmd("FUNCTION").arg("LIST").query_async(&mut conn).await;
+        let mut existing = HashSet::new();
+        if let Ok(Value::Bulk(libraries)) = list {
+            for library in libraries {
+                if let Value::Bulk(fields) = library {
+                    for kv in fields.chunks(2) {
+                        if let (Value::Data(k), Some(Value::Data(v))) = (&kv[0], kv.get(1)) {
+                            if k.as_slice() == b"library_name" {
+                                existing.insert(v.clone());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Functions { policy, existing })
+    }
+    // 全量阶段加载rdb中的library, 返回None表示跳过
+    pub fn full_cmd(&self, code: &[u8]) -> Result<Option<Cmd>, Box<dyn Error>> {
+        let name = library_name(code).unwrap_or_default();
+        let mut cmd = redis::cmd("FUNCTION");
+        cmd.arg("LOAD");
+        if self.existing.contains(&name) {
+            match self.policy {
+                FunctionPolicy::Replace => {
+                    cmd.arg("REPLACE");
+                }
+                FunctionPolicy::Skip => {
+                    println!("目的端已经存在library {}, 跳过", String::from_utf8_lossy(&name));
+                    return Ok(None);
+                }
+                FunctionPolicy::Fail => {
+                    return Err(Box::from(format!("目的端已经存在library {}", String::from_utf8_lossy(&name))));
+                }
+            }
+        }
+        cmd.arg(code);
+        Ok(Some(cmd))
+    }
+}
+
+// library代码的第一行: #!lua name=mylib
+pub fn library_name(code: &[u8]) -> Option<Vec<u8>> {
+    let line = code.split(|b| *b == b'\n').next()?;
+    if !line.starts_with(b"#!") {
+        return None;
+    }
+    line.split(|b| *b == b' ')
+        .find(|s| s.starts_with(b"name="))
+        .map(|s| s[5..].to_vec())
+}
+
+// 增量阶段的FUNCTION LOAD/RESTORE按策略改写
+// FUNCTION LOAD [REPLACE] code, FUNCTION RESTORE payload [FLUSH|APPEND|REPLACE]
+pub fn rewrite_incr_args(args: &mut Vec<Bytes>, policy: FunctionPolicy) {
+    if args.len() < 3 || !args[0].eq_ignore_ascii_case(b"function") || policy == FunctionPolicy::Fail {
+        return;
+    }
+    if args[1].eq_ignore_ascii_case(b"load") {
+        let has_replace = args.len() > 3 && args[2].eq_ignore_ascii_case(b"replace");
+        if policy == FunctionPolicy::Replace && !has_replace {
+            args.insert(2, Bytes::from_static(b"REPLACE"));
+        } else if policy == FunctionPolicy::Skip && has_replace {
+            // 不覆盖目的端的library, 冲突时目的端会返回错误
+            args.remove(2);
+        }
+    } else if args[1].eq_ignore_ascii_case(b"restore") {
+        args.truncate(3);
+        if policy == FunctionPolicy::Replace {
+            args.push(Bytes::from_static(b"REPLACE"));
+        } else {
+            args.push(Bytes::from_static(b"APPEND"));
+        }
+    }
+}
@@ -1,59 +1,56 @@
-use redis::{ErrorKind, Cmd, aio, RedisResult, Value};
+use redis::Cmd;
 
 use std::error::Error;
 
-use crate::utils::conn::{open_redis_sync_conn};
+use crate::utils::apply::{Applier, ReplyPolicy};
+use crate::utils::deadletter::DeadLetter;
 use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::sync::mpsc::channel;
 use std::sync::Arc;
 use async_std::task::{spawn,sleep};
 use std::time::Duration;
+use crate::rdb::function::{rewrite_incr_args, FunctionPolicy};
 use crate::rdb::loader::Loader;
-use tokio::io::AsyncReadExt;
-use redis::aio::ConnectionLike;
-use tokio::sync::mpsc::error::TryRecvError;
-#[macro_export(atomic_u64_fetch_add)]
+use crate::utils::memory::{cmd_size, InflightLimit};
+use crate::utils::command::{CommandParser, Frame};
+use crate::utils::resp::parse_int;
+use tokio::io::AsyncRead;
+#[macro_export]
 macro_rules! atomic_u64_fetch_add {
     ($data:ident,$inr:expr) => {
         $data.fetch_add($inr, Ordering::Relaxed)
     };
 }
-#[macro_export(atomic_u64_load)]
+#[macro_export]
 macro_rules! atomic_u64_load {
     ($data:ident) => {
-        $data.load(Ordering::Relaxed);
+        $data.load(Ordering::Relaxed)
     };
 }
-macro_rules! send_cmd {
-    // 连接，发送的包,发送统计，单次发送的count统计，超过多少就发送的值
-    ($conn:ident,$pipe:ident,$send_count:ident,$batch_count:ident,$over_max_to_send:expr) => {
-        if $batch_count > $over_max_to_send {
-            match $conn.req_packed_commands(&$pipe, 0, $batch_count).await {
-                Ok(_d) => {}
-                Err(e) => match e.kind() {
-                    ErrorKind::IoError => {
-                        break;
-                    }
-                    _ => {
-                        println!("增量阶段读取响应错误:{}", e.to_string());
-                    }
-                },
-            };
-            atomic_u64_fetch_add!($send_count, $batch_count as u64);
-            $batch_count = 0;
-            $pipe.clear();
-        }
-    };
-}
-
-pub async fn incr(
-    loader: &mut Loader,
+// stop_at_ts不为0时,遇到aof中大于它的#TS:注释就停止
+// FUNCTION LOAD/RESTORE按function_policy改写, FUNCTION DELETE/FLUSH原样发送
+// 解析出来还没有发送的命令受inflight的上限约束
+// 命令按key分到connections个连接上并行发送, 目的端返回的错误按reply_policy处理
+// start_offset为增量数据开始的偏移量, 加上每个命令的长度就是命令在源端的偏移量, 记录在死信文件中
+// raw和loader共用, 只在这里顺序读取, await期间没有别的借用
+#[allow(clippy::await_holding_refcell_ref)]
+#[allow(clippy::too_many_arguments)]
+pub async fn incr<R: AsyncRead + Unpin>(
+    loader: &mut Loader<R>,
     target_url: &'static str,
     target_pass: &'static str,
+    connections: usize,
+    reply_policy: ReplyPolicy,
+    deadletter: Option<Arc<DeadLetter>>,
+    start_offset: u64,
+    stop_at_ts: u64,
+    function_policy: FunctionPolicy,
+    inflight: Arc<InflightLimit>,
 ) -> Result<(), Box<dyn Error>> {
-    let (mut sender, mut receiver) = channel::<cmd_pack>(20000);
-    let send_count = Arc::new(AtomicU64::new(0));
-    let send_count_c = send_count.clone();
+    let (mut sender, mut receiver) = channel::<(Cmd, u64)>(20000);
+    let mut applier = Applier::new(target_url, target_pass, connections, inflight.clone(), reply_policy, deadletter);
+    let stats = applier.stats.clone();
+    let send_count_c = applier.sent.clone();
     let parse_count = Arc::new(AtomicU64::new(0));
     let parse_count_c = parse_count.clone();
     let count_all_bytes = Arc::new(AtomicU64::new(0));
@@ -64,155 +61,106 @@ pub async fn incr(
             let pcc = atomic_u64_load!(parse_count_c);
             let scc = atomic_u64_load!(send_count_c);
             println!(
-                "[INC] parse_cmd_number:{} send_cmd_number:{} left:{:>5} all bytes:{}",
+                "[INC] parse_cmd_number:{} send_cmd_number:{} left:{:>5} all bytes:{} errors:{}",
                 pcc,
                 scc,
                 pcc - scc,
-                cabc
+                cabc,
+                stats.errors.load(Ordering::Relaxed)
             );
             // 清零
             sleep(Duration::from_secs(1)).await;
         }
     });
     // 发送
-    spawn(async move  {
-        let mut pipe= redis::pipe();
-        let mut batch_count = 0;
-        let mut conn: aio::Connection;
-        let mut last_select_full_pack = redis::Cmd::new();
-        loop {
-            loop {
-                sleep(Duration::from_secs(1)).await;
-                println!("连接目的端redis中...");
-                let index = "0";
-                conn = match open_redis_sync_conn(target_url, target_pass, index).await {
-                    Ok(d) => {
-                        // 选择redis的db
-                        d
-                    }
-                    Err(_e) => {
-                        continue;
-                    }
-                };
-                let mut isEmpty = true;
-                for _d in last_select_full_pack.args_iter(){
-                    isEmpty = false;
-                    break;
-                };
-                if !isEmpty {
-                    let result: RedisResult<Value> = last_select_full_pack.query_async(&mut conn).await;
-                    match result {
-                        Ok(_d1) => {
-                            print!("重新目的端redis");
-                        }
-                        Err(_e) => continue,
-                    }
-                }
-                println!("连接成功!");
-                break;
-            }
-            loop {
-                match receiver.try_recv() {
-                    Ok(pack) => {
-                        if pack.cmd_name=="select".as_bytes().to_vec(){
-                            last_select_full_pack = pack.cmd.clone();
-                        };
-                        pipe.add_command(pack.cmd);
-                        batch_count = batch_count + 1;
-                        send_cmd!(conn, pipe, send_count, batch_count, 10000);
-                    }
-                    Err(e) => {
-                        match e {
-                            TryRecvError::Empty=>{
-                                send_cmd!(conn, pipe, send_count, batch_count, 0);
-                                sleep(Duration::from_millis(100)).await;
-                            },
-                            TryRecvError::Closed=>{
-                                break;
-                            }
-                        }
-                    }
+    let inflight_c = inflight.clone();
+    let send_handle = spawn(async move {
+        while let Some((cmd, offset)) = receiver.recv().await {
+            if applier.send(cmd, offset).await.is_err() {
+                // 目的端出错停止, 丢弃还在队列里的命令, 解析方随后发送失败
+                receiver.close();
+                while let Some((cmd, _)) = receiver.recv().await {
+                    inflight_c.release(cmd_size(&cmd));
                 }
             }
         }
+        // 源端已经读完(aof), 发送剩下的命令后退出
+        if applier.barriers > 0 {
+            println!("[INC] 屏障命令:{}", applier.barriers);
+        }
+        // 关闭时等待剩下的命令发送完成, 之后的统计才是完整的
+        let stats = applier.stats.clone();
+        let result = applier.close().await;
+        stats.print("INC");
+        result
     });
-    // 解包
-    loop {
-        let mut p = [0; 1];
-        let r_len = loader.rdbReader.raw.borrow_mut().read_exact(&mut p).await.unwrap();
-        if r_len != 0 {
-            // 这里就是一个完整的包体
-            let mut pack = cmd_pack {
-                cmd: redis::Cmd::new(),
-                cmd_name: vec![],
-            };
-            let mut bytes_count = 1;
-            if p[0] == '*' as u8 {
-                let mut args_num_vec = Vec::new();
-                loop {
-                    let mut p_ = [0; 1];
-                    let r_len = loader.rdbReader.raw.borrow_mut().read_exact(&mut p_).await.unwrap();
-                    if r_len != 0 {
-                        bytes_count+=r_len;
-                        if p_[0] == '\r' as u8 {
-                        } else if p_[0] == '\n' as u8 {
-                            break;
-                        } else {
-                            args_num_vec.push(p_[0])
+    // 解包, 数据直接读到parser的缓冲里, 解析出的命令共享这块内存
+    let mut parser = CommandParser::new();
+    let mut offset = start_offset;
+    'parse: loop {
+        while let Some(frame) = parser.next_frame()? {
+            // 统计全部
+            atomic_u64_fetch_add!(count_all_bytes_c, frame.size() as u64);
+            offset = offset + frame.size() as u64;
+            match frame {
+                Frame::Command(command) => {
+                    let mut cmd = Cmd::new();
+                    if command.name().eq_ignore_ascii_case(b"function") {
+                        let mut args = command.to_args();
+                        rewrite_incr_args(&mut args, function_policy);
+                        for arg in args {
+                            cmd.arg(&arg[..]);
+                        }
+                    } else {
+                        for arg in command.args() {
+                            cmd.arg(arg);
                         }
                     }
+                    // 解析加1
+                    atomic_u64_fetch_add!(parse_count, 1);
+                    // 发送
+                    let size = cmd_size(&cmd);
+                    inflight.acquire(size).await;
+                    if sender.send((cmd, offset)).await.is_err() {
+                        // 发送方已经出错退出, 错误在下面返回
+                        inflight.release(size);
+                        break 'parse;
+                    }
                 }
-                let args_num = String::from_utf8(args_num_vec)
-                    .unwrap()
-                    .parse::<i32>()
-                    .unwrap();
-                for i in 0..args_num {
-                    // 先读$
-                    let mut args_num_vec = Vec::new();
-                    loop {
-                        let mut p_ = [0; 1];
-                        let r_len = loader.rdbReader.raw.borrow_mut().read_exact(&mut p_).await.unwrap();
-                        if r_len != 0 {
-                            bytes_count+=r_len;
-                            if p_[0] == '\r' as u8 {
-                            } else if p_[0] == '$' as u8 {
-                                args_num_vec.clear();
-                            } else if p_[0] == '\n' as u8 {
-                                break;
-                            } else {
-                                args_num_vec.push(p_[0])
-                            }
+                // aof中的注释, 例如 #TS:1628217470
+                Frame::Comment(line, _) => {
+                    if line.starts_with(b"TS:") && stop_at_ts != 0 {
+                        let ts = parse_int(&line[3..])? as u64;
+                        if ts > stop_at_ts {
+                            println!("到达停止时间 {}, 停止回放", ts);
+                            break 'parse;
                         }
                     }
-                    // 再读数据
-                    let args_num = String::from_utf8(args_num_vec)
-                        .unwrap()
-                        .parse::<i32>()
-                        .unwrap();
-                    let mut p_: Vec<u8> = vec![0; args_num as usize];
-                    loader.rdbReader.raw.borrow_mut().read_exact(&mut p_).await.unwrap();
-                    bytes_count+=args_num as usize;
-                    if i == 0 {
-                        pack.cmd_name = p_.clone()
-                    }
-                    pack.cmd.arg(p_);
-                    // 读取 /r/n
-                    let mut p_: Vec<u8> = vec![0; 2];
-                    loader.rdbReader.raw.borrow_mut().read_exact(&mut p_).await.unwrap();
-                    bytes_count+=2;
                 }
-
-                // 解析加1
-                atomic_u64_fetch_add!(parse_count, 1);
-                // 统计全部
-                atomic_u64_fetch_add!(count_all_bytes_c, bytes_count as u64);
-                // 发送
-                sender.send(pack).await;
-            } else {
-                print!("{}", p[0] as char);
+                Frame::Empty(_) => {}
+            }
+        }
+        let r_len = match loader.rdbReader.readRawBuf(parser.buffer_mut()).await {
+            Ok(d) => d,
+            Err(e) => {
+                // 读取出错不是数据读完, 发送完已经解析的命令后返回错误
+                println!("读取增量数据出错 {}", e);
+                drop(sender);
+                send_handle.await?;
+                return Err(Box::new(e));
+            }
+        };
+        if r_len == 0 {
+            // 数据读完了
+            if parser.buffered() > 0 {
+                println!("增量数据结尾有{}字节不完整的命令", parser.buffered());
             }
+            break;
         }
     }
+    // 等待剩余的命令发送完成
+    drop(sender);
+    send_handle.await?;
     Ok(())
 }
 /*
@@ -226,8 +174,3 @@ b
 $1
 c
 */
-#[derive(Clone)]
-pub struct cmd_pack {
-    cmd_name: Vec<u8>,
-    cmd: Cmd, // 储存完整的命令包
-}
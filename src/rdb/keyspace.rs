@@ -0,0 +1,235 @@
+use crate::rdb::rump::{copy_keys, RumpStat};
+use crate::utils::conn::open_redis_sync_conn;
+use crate::utils::filter::Filter;
+use redis::aio::Connection;
+use redis::Msg;
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use async_std::task::{sleep, spawn};
+use futures_util::StreamExt;
+
+// 有变化的key,同一个key在去抖时间内只同步一次
+pub struct ChangedKeys {
+    keys: HashSet<(u32, Vec<u8>)>,
+    order: VecDeque<(Instant, u32, Vec<u8>)>,
+    cap: usize,
+    pub dropped: u64,
+}
+
+impl ChangedKeys {
+    pub fn new(cap: usize) -> ChangedKeys {
+        ChangedKeys {
+            keys: HashSet::new(),
+            order: VecDeque::new(),
+            cap,
+            dropped: 0,
+        }
+    }
+    // 队列满了就丢弃,返回false
+    pub fn push(&mut self, db: u32, key: Vec<u8>) -> bool {
+        let k = (db, key);
+        if self.keys.contains(&k) {
+            return true;
+        }
+        if self.keys.len() >= self.cap {
+            self.dropped = self.dropped + 1;
+            return false;
+        }
+        self.order.push_back((Instant::now(), k.0, k.1.clone()));
+        self.keys.insert(k);
+        true
+    }
+    // 取出已经超过去抖时间的key
+    pub fn pop_ready(&mut self, debounce: Duration, max: usize) -> Vec<(u32, Vec<u8>)> {
+        let mut rsl = vec![];
+        while rsl.len() < max {
+            match self.order.front() {
+                Some((t, _, _)) if t.elapsed() >= debounce => {}
+                _ => break,
+            }
+            let (_, db, key) = self.order.pop_front().unwrap();
+            let k = (db, key);
+            self.keys.remove(&k);
+            rsl.push(k);
+        }
+        rsl
+    }
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+// 基于keyspace notification的增量同步, 只能用于禁用了psync的源端
+pub async fn keyspace(
+    source_url: &'static str,
+    source_pass: &'static str,
+    target_url: &'static str,
+    target_pass: &'static str,
+    filter: &'static Filter,
+    queue_size: usize,
+    debounce: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let mut conn = open_redis_sync_conn(source_url, source_pass, "").await?;
+    check_notify_config(&mut conn).await?;
+    let changed = Arc::new(Mutex::new(ChangedKeys::new(queue_size)));
+    let changed_c = changed.clone();
+    let stat = Arc::new(RumpStat::default());
+    let stat_c = stat.clone();
+    let reconnects = Arc::new(AtomicU64::new(0));
+    let reconnects_c = reconnects.clone();
+    // 订阅
+    spawn(async move {
+        let mut last_dropped = 0;
+        loop {
+            let mut pubsub = match open_redis_sync_conn(source_url, source_pass, "").await.map_err(|e| e.to_string()) {
+                Ok(d) => d.into_pubsub(),
+                Err(e) => {
+                    println!("连接源端订阅失败: {}", e);
+                    sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            if let Err(e) = pubsub.psubscribe("__keyspace@*__:*").await {
+                println!("订阅keyspace失败: {}", e);
+                sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+            println!("订阅keyspace成功!");
+            let mut messages = pubsub.on_message();
+            while let Some(msg) = messages.next().await {
+                if let Some((db, key)) = parse_keyspace_msg(&msg) {
+                    if filter.filter_db(db) || filter.filter_key(&key) {
+                        continue;
+                    }
+                    changed_c.lock().unwrap().push(db, key);
+                }
+            }
+            // 断开期间的通知已经丢失了,需要提示
+            let dropped = changed_c.lock().unwrap().dropped;
+            reconnects_c.fetch_add(1, Ordering::Relaxed);
+            println!(
+                "[KEYSPACE] 订阅连接断开, 断开期间的通知已丢失, 队列已丢弃通知:{} (本次新增 {}), 建议重新执行rump",
+                dropped,
+                dropped - last_dropped
+            );
+            last_dropped = dropped;
+        }
+    });
+    // 输出进度
+    let changed_s = changed.clone();
+    spawn(async move {
+        loop {
+            let (queued, dropped) = {
+                let c = changed_s.lock().unwrap();
+                (c.len(), c.dropped)
+            };
+            println!(
+                "[KEYSPACE] queued:{} dropped:{} reconnects:{} restored:{} rebuilt:{} deleted:{} failed:{}",
+                queued,
+                dropped,
+                reconnects.load(Ordering::Relaxed),
+                stat_c.restored.load(Ordering::Relaxed),
+                stat_c.rebuilt.load(Ordering::Relaxed),
+                stat_c.deleted.load(Ordering::Relaxed),
+                stat_c.failed.load(Ordering::Relaxed)
+            );
+            sleep(Duration::from_secs(1)).await;
+        }
+    });
+    // 同步有变化的key
+    let mut conns: HashMap<u32, (Connection, Connection)> = HashMap::new();
+    loop {
+        let ready = changed.lock().unwrap().pop_ready(debounce, 1000);
+        if ready.is_empty() {
+            sleep(Duration::from_millis(10)).await;
+            continue;
+        }
+        let mut by_db: HashMap<u32, Vec<Vec<u8>>> = HashMap::new();
+        for (db, key) in ready {
+            by_db.entry(db).or_default().push(key);
+        }
+        for (db, keys) in by_db {
+            if let Entry::Vacant(e) = conns.entry(db) {
+                let index = format!("{}", db);
+                let source = open_redis_sync_conn(source_url, source_pass, &index).await?;
+                let target = open_redis_sync_conn(target_url, target_pass, &index).await?;
+                e.insert((source, target));
+            }
+            let (source, target) = conns.get_mut(&db).unwrap();
+            if let Err(e) = copy_keys(source, target, &keys, filter, &stat, true).await {
+                println!("同步key失败: {}, 重新放回队列", e);
+                conns.remove(&db);
+                let mut c = changed.lock().unwrap();
+                for key in keys {
+                    c.push(db, key);
+                }
+            }
+        }
+    }
+}
+
+// __keyspace@0__:foo
+fn parse_keyspace_msg(msg: &Msg) -> Option<(u32, Vec<u8>)> {
+    let channel: Vec<u8> = msg.get_channel().ok()?;
+    let prefix = b"__keyspace@";
+    if !channel.starts_with(prefix) {
+        return None;
+    }
+    let rest = &channel[prefix.len()..];
+    let end = rest.windows(3).position(|w| w == b"__:")?;
+    let db = String::from_utf8_lossy(&rest[..end]).parse::<u32>().ok()?;
+    Some((db, rest[end + 3..].to_vec()))
+}
+
+// 需要K(keyspace)以及所有类型的事件, 不满足时尝试CONFIG SET
+async fn check_notify_config(conn: &mut Connection) -> Result<(), Box<dyn Error>> {
+    let config: redis::RedisResult<Vec<String>> = redis::cmd("CONFIG")
+        .arg("GET")
+        .arg("notify-keyspace-events")
+        .query_async(conn)
+        .await;
+    // 云厂商一般禁用了CONFIG,只能在控制台的参数里设置
+    let config = match config {
+        Ok(d) => d,
+        Err(e) => {
+            println!("无法检查notify-keyspace-events({}), 请确认已经设置为KA", e);
+            return Ok(());
+        }
+    };
+    let flags = config.get(1).cloned().unwrap_or_default();
+    println!("notify-keyspace-events is {:?}", flags);
+    let has_all = flags.contains('A') || "g$lshzxet".chars().all(|c| flags.contains(c));
+    if flags.contains('K') && has_all {
+        return Ok(());
+    }
+    let mut new_flags = flags.clone();
+    for c in "KA".chars() {
+        if !new_flags.contains(c) {
+            new_flags.push(c);
+        }
+    }
+    let result: redis::RedisResult<()> = redis::cmd("CONFIG")
+        .arg("SET")
+        .arg("notify-keyspace-events")
+        .arg(new_flags.as_str())
+        .query_async(conn)
+        .await;
+    match result {
+        Ok(()) => {
+            println!("notify-keyspace-events set to {:?}", new_flags);
+            Ok(())
+        }
+        Err(e) => Err(Box::from(format!(
+            "notify-keyspace-events 为 {:?}, 需要包含KA, 设置失败: {}",
+            flags, e
+        ))),
+    }
+}
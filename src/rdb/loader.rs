@@ -1,24 +1,85 @@
 use crate::rdb::slice_buffer::sliceBuffer;
+use crate::rdb::function::library_name;
+use crate::rdb::salvage::{CrcPolicy, ErrorPolicy};
 use byteorder::{LittleEndian, WriteBytesExt};
-use crc64::Crc64;
+use bytes::BytesMut;
+use crate::rdb::crc64::Crc64;
 
 use std::cell::{RefCell};
+use std::collections::BTreeMap;
 
 use std::error::Error;
-use std::f32::INFINITY;
-use std::f64::{NAN, NEG_INFINITY};
-use std::io::{ Write};
+use std::io::{self, Cursor, Read, Write};
 
+use std::pin::Pin;
 use std::rc::Rc;
-use tokio::io::{AsyncReadExt, BufReader};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
 use async_pipe::PipeReader;
 
 
-pub struct Loader {
-    pub rdbReader: rdbReader,
+// R是数据来源, 默认是复制连接/文件转发过来的管道
+pub struct Loader<R = BufReader<PipeReader>> {
+    pub rdbReader: rdbReader<R>,
     db: u32,
     lastEntry: Box<BinEntry>,
+    pub crcPolicy: CrcPolicy,
+    pub errorPolicy: ErrorPolicy,
+    // 最后一个完整解析的key, 出错时报告
+    pub lastGoodKey: Vec<u8>,
+    // salvage跳过的字节数和次数
+    pub skippedBytes: u64,
+    pub skipCount: u64,
+    // salvage一直找到数据末尾也没有找到可以继续的位置
+    pub truncated: bool,
+    // 头部的版本, AUX字段和RESIZEDB
+    pub metadata: RdbMetadata,
 }
+
+// rdb中key以外的信息, 可以和源端的INFO对比
+#[derive(Clone, Debug, Default)]
+pub struct RdbMetadata {
+    pub Version: i32,
+    pub RedisVer: String,
+    pub RedisBits: i64,
+    pub Ctime: i64,
+    pub UsedMem: i64,
+    pub ReplId: String,
+    pub ReplOffset: i64,
+    pub AofBase: bool,
+    // 按出现顺序的全部AUX字段, 包括上面解析过的
+    pub Aux: Vec<(String, String)>,
+    // RESIZEDB: db -> (key数, 带过期时间的key数)
+    pub DbSizes: BTreeMap<u32, (u64, u64)>,
+}
+
+impl RdbMetadata {
+    pub fn setAux(&mut self, key: &[u8], value: &[u8]) {
+        let key = String::from_utf8_lossy(key).to_string();
+        let value = String::from_utf8_lossy(value).to_string();
+        let int = value.parse::<i64>().unwrap_or(0);
+        match key.as_str() {
+            "redis-ver" => self.RedisVer = value.clone(),
+            "redis-bits" => self.RedisBits = int,
+            "ctime" => self.Ctime = int,
+            "used-mem" => self.UsedMem = int,
+            "repl-id" => self.ReplId = value.clone(),
+            "repl-offset" => self.ReplOffset = int,
+            "aof-base" => self.AofBase = int != 0,
+            _ => {}
+        }
+        self.Aux.push((key, value));
+    }
+    pub fn Print(&self) {
+        println!(
+            "[RDB] version:{} redis-ver:{} redis-bits:{} ctime:{} used-mem:{} repl-id:{} repl-offset:{} aof-base:{}",
+            self.Version, self.RedisVer, self.RedisBits, self.Ctime, self.UsedMem, self.ReplId, self.ReplOffset, self.AofBase
+        );
+    }
+}
+pub const rdbFlagSlotInfo: u8 = 0xf4;
+pub const rdbFlagFunction2: u8 = 0xf5;
+pub const rdbFlagFunctionPreGa: u8 = 0xf6;
 pub const rdbFlagModuleAux: u8 = 0xf7;
 pub const rdbFlagIdle: u8 = 0xf8;
 pub const rdbFlagFreq: u8 = 0xf9;
@@ -35,6 +96,8 @@ pub const RdbTypeSet: u8 = 2;
 pub const RdbTypeZSet: u8 = 3;
 pub const RdbTypeHash: u8 = 4;
 pub const RdbTypeZSet2: u8 = 5;
+pub const RdbTypeModule: u8 = 6;
+pub const RdbTypeModule2: u8 = 7;
 
 pub const RdbTypeHashZipmap: u8 = 9;
 pub const RdbTypeListZiplist: u8 = 10;
@@ -43,6 +106,60 @@ pub const RdbTypeZSetZiplist: u8 = 12;
 pub const RdbTypeHashZiplist: u8 = 13;
 pub const RdbTypeQuicklist: u8 = 14;
 pub const RDBTypeStreamListPacks: u8 = 15; // stream;
+// redis 7.x
+pub const RdbTypeHashListpack: u8 = 16;
+pub const RdbTypeZSetListpack: u8 = 17;
+pub const RdbTypeQuicklist2: u8 = 18;
+pub const RDBTypeStreamListPacks2: u8 = 19;
+pub const RdbTypeSetListpack: u8 = 20;
+pub const RDBTypeStreamListPacks3: u8 = 21;
+
+// redis 7.4 hash field expiration
+pub const RdbTypeHashMetadataPreGa: u8 = 22;
+pub const RdbTypeHashListpackExPreGa: u8 = 23;
+pub const RdbTypeHashMetadata: u8 = 24;
+pub const RdbTypeHashListpackEx: u8 = 25;
+
+// quicklist2的节点类型
+pub const quicklistNodeContainerPlain: u32 = 1;
+pub const defaultMaxChunk: usize = 16*1024*1024;
+pub const maxStringLen: usize = 512*1024*1024;
+pub const quicklistNodeContainerPacked: u32 = 2;
+
+// filter里按类型过滤时使用的名字
+pub fn rdbTypeName(t: u8) -> &'static str {
+    match t {
+        RdbTypeString => "string",
+        RdbTypeList | RdbTypeListZiplist | RdbTypeQuicklist | RdbTypeQuicklist2 => "list",
+        RdbTypeSet | RdbTypeSetIntset | RdbTypeSetListpack => "set",
+        RdbTypeZSet | RdbTypeZSet2 | RdbTypeZSetZiplist | RdbTypeZSetListpack => "zset",
+        RdbTypeHash | RdbTypeHashZipmap | RdbTypeHashZiplist | RdbTypeHashListpack
+        | RdbTypeHashMetadataPreGa | RdbTypeHashListpackExPreGa | RdbTypeHashMetadata
+        | RdbTypeHashListpackEx => "hash",
+        RDBTypeStreamListPacks | RDBTypeStreamListPacks2 | RDBTypeStreamListPacks3 => "stream",
+        RdbTypeModule | RdbTypeModule2 => "module",
+        _ => "unknown",
+    }
+}
+
+// 能读取这个类型的最低rdb版本, 目的端的rdb版本低于它时不能RESTORE
+pub fn rdbTypeMinVersion(t: u8) -> u16 {
+    match t {
+        RdbTypeString | RdbTypeList | RdbTypeSet | RdbTypeZSet | RdbTypeHash | RdbTypeHashZipmap
+        | RdbTypeListZiplist | RdbTypeSetIntset | RdbTypeZSetZiplist | RdbTypeHashZiplist => 6,
+        RdbTypeQuicklist => 7,
+        RdbTypeZSet2 | RdbTypeModule | RdbTypeModule2 => 8,
+        RDBTypeStreamListPacks => 9,
+        RdbTypeHashListpack | RdbTypeZSetListpack | RdbTypeQuicklist2 | RDBTypeStreamListPacks2
+        | RdbTypeSetListpack => 10,
+        RDBTypeStreamListPacks3 => 11,
+        _ => 12,
+    }
+}
+
+pub fn rdbIsStream(t: u8) -> bool {
+    t == RDBTypeStreamListPacks || t == RDBTypeStreamListPacks2 || t == RDBTypeStreamListPacks3
+}
 
 pub const rdbEncInt8: u8 = 0;
 pub const rdbEncInt16: u8 = 1;
@@ -73,78 +190,156 @@ pub const rdbZiplistInt64: u8 = 0xe0;
 pub const rdbZiplistInt24: u8 = 0xf0;
 pub const rdbZiplistInt8: u8 = 0xfe;
 pub const rdbZiplistInt4: u8 = 15;
-impl Loader {
-    pub fn new(r: Rc<RefCell<BufReader<PipeReader>>>) -> Loader {
+pub const zipEnd: u8 = 0xff;
+
+pub const lpEncoding7BitUint: u8 = 0x00;
+pub const lpEncoding6BitStr: u8 = 0x80;
+pub const lpEncoding13BitInt: u8 = 0xc0;
+pub const lpEncoding12BitStr: u8 = 0xe0;
+pub const lpEncoding32BitStr: u8 = 0xf0;
+pub const lpEncoding16BitInt: u8 = 0xf1;
+pub const lpEncoding24BitInt: u8 = 0xf2;
+pub const lpEncoding32BitInt: u8 = 0xf3;
+pub const lpEncoding64BitInt: u8 = 0xf4;
+pub const lpEOF: u8 = 0xff;
+// 同步的Read(例如文件)包装成AsyncRead, 读取会阻塞当前线程, 不需要管道转发
+pub struct SyncReader<R: Read>(pub R);
+
+impl<R: Read + Unpin> AsyncRead for SyncReader<R> {
+    fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().0.read(buf))
+    }
+}
+
+impl Loader<Cursor<Vec<u8>>> {
+    // 解析内存中完整的rdb
+    pub fn fromBytes(data: Vec<u8>) -> Self {
+        Loader::new(Rc::new(RefCell::new(Cursor::new(data))))
+    }
+}
+
+impl<F: Read + Unpin> Loader<SyncReader<std::io::BufReader<F>>> {
+    pub fn fromRead(r: F) -> Self {
+        let buf = std::io::BufReader::with_capacity(10*1024*1024, r);
+        Loader::new(Rc::new(RefCell::new(SyncReader(buf))))
+    }
+}
+
+// raw在Loader和rdbReader之间共用, 只在一个任务里顺序读取, await期间没有别的借用
+#[allow(clippy::await_holding_refcell_ref)]
+impl<R: AsyncRead + Unpin> Loader<R> {
+    pub fn new(r: Rc<RefCell<R>>) -> Loader<R> {
         Loader {
-            rdbReader: rdbReader {
-                raw: r,
-                crc64: Crc64::new(),
-                is_cache_buf: false,
-                buf: vec![],
-                nread: 0,
-                remainMember: 0,
-                lastReadCount: 0,
-                totMemberCount: 0,
-            },
+            rdbReader: rdbReader::new(r),
             db: 0,
-            lastEntry: Box::from(BinEntry {
-                DB: 0,
-                Key: vec![],
-                Type: 0,
-                Value: vec![],
-                ExpireAt: 0,
-                RealMemberCount: 0,
-                NeedReadLen: 0,
-                IdleTime: 0,
-                Freq: 0,
-            }),
-        }
-    }
-    pub async fn Header(&mut self) -> Result<(), Box<dyn Error>> {
-        let mut head_byt = [0 as u8; 9];
+            lastEntry: Box::from(BinEntry::default()),
+            crcPolicy: CrcPolicy::Fail,
+            errorPolicy: ErrorPolicy::Abort,
+            lastGoodKey: vec![],
+            skippedBytes: 0,
+            skipCount: 0,
+            truncated: false,
+            metadata: RdbMetadata::default(),
+        }
+    }
+    pub async fn Header(&mut self) -> Result<i32, Box<dyn Error>> {
+        let mut head_byt = [0u8; 9];
         self.readFull(&mut head_byt).await?;
         if head_byt[0..5].ne("REDIS".as_bytes()) {
             return Err(Box::from("不是rdb文件的header"));
         }
         let version = String::from_utf8(Vec::from(&head_byt[5..9]))?.parse::<i32>()?;
         println!("rdb version is {}", version);
-        Ok(())
+        self.metadata.Version = version;
+        Ok(version)
     }
     async fn readFull(&mut self, p: &mut [u8]) -> Result<(), Box<dyn Error>> {
-        self.rdbReader.raw.borrow_mut().read_exact(p).await;
-        self.rdbReader.crc64.write_all(p);
-        if self.rdbReader.is_cache_buf{
-            self.rdbReader.buf.append(&mut p.to_vec());
-        }
-        Ok(())
+        self.rdbReader.readExact(p).await
     }
     pub async fn Footer(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.truncated {
+            println!("[RDB] 数据不完整, 没有校验和");
+            return Ok(());
+        }
         let crc = self.rdbReader.crc64.get();
         let rdb_file_u64 = self.rdbReader.readUint64().await?;
-        if rdb_file_u64 != crc {
-            println!("{},{}", rdb_file_u64, crc);
-            return Err(Box::from("sum校验 不一致!{}{}"));
-        };
-        Ok(())
+        // 关闭了rdbchecksum时校验和为0
+        if rdb_file_u64 == 0 || rdb_file_u64 == crc {
+            return Ok(());
+        }
+        let msg = format!("rdb校验和不一致 文件中:{} 计算:{}", rdb_file_u64, crc);
+        // salvage跳过了数据, 校验和一定不一致
+        if self.crcPolicy == CrcPolicy::Warn || self.skipCount > 0 {
+            println!("[RDB] {}", msg);
+            return Ok(());
+        }
+        Err(Box::from(msg))
     }
+    pub fn PrintReport(&self) {
+        println!(
+            "[RDB] offset:{} last good key:{} salvage skipped bytes:{} times:{}",
+            self.rdbReader.nread,
+            String::from_utf8_lossy(&self.lastGoodKey),
+            self.skippedBytes,
+            self.skipCount
+        );
+    }
+    // 解析错误按errorPolicy处理: abort返回带offset和最后一个完整key的错误, salvage跳到下一个能解析的位置继续
     pub async fn NextBinEntry(&mut self, entry: &mut BinEntry) -> Result<(), Box<dyn Error>> {
         loop {
-            let mut t = 0;
-            if self.rdbReader.remainMember != 0 {
-                t = self.lastEntry.Type
-            } else {
-                t = self.rdbReader.ReadByte().await?;
+            let start = self.rdbReader.nread;
+            if self.errorPolicy == ErrorPolicy::Salvage {
+                self.rdbReader.journal = Some(vec![]);
+            }
+            let err = match self.nextBinEntry(entry).await {
+                Ok(()) => {
+                    self.rdbReader.journal = None;
+                    if entry.Type != RdbFlagAUX && entry.Type != rdbFlagFunction2 {
+                        self.lastGoodKey = entry.Key.clone();
+                    }
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+            if err.to_string().eq("RDB END") {
+                return Err(err);
             }
+            let journal = self.rdbReader.journal.take().unwrap_or_default();
+            let msg = format!(
+                "解析rdb出错 offset:{} 最后一个完整的key:{} error:{}",
+                self.rdbReader.nread,
+                String::from_utf8_lossy(&self.lastGoodKey),
+                err
+            );
+            if self.errorPolicy == ErrorPolicy::Abort {
+                return Err(Box::from(msg));
+            }
+            println!("[RDB] {}", msg);
+            self.salvage(start, journal).await?;
+            entry.ExpireAt = 0;
+            entry.NeedReadLen = 0;
+            entry.IdleTime = 0;
+            entry.Freq = 0;
+        }
+    }
+    pub(crate) async fn nextBinEntry(&mut self, entry: &mut BinEntry) -> Result<(), Box<dyn Error>> {
+        loop {
+            let t = if self.rdbReader.remainMember != 0 {
+                self.lastEntry.Type
+            } else {
+                self.rdbReader.ReadByte().await?
+            };
             match t {
                 RdbFlagAUX => {
                     let aux_key = self.rdbReader.ReadString().await?;
                     let aux_value = self.rdbReader.ReadString().await?;
+                    self.metadata.setAux(&aux_key, &aux_value);
                     println!(
                         "Aux information key:{:?} {:?}",
                         String::from_utf8(aux_key.clone()),
                         String::from_utf8(aux_value.clone())
                     );
-                    if (&aux_key).eq(&Vec::from("lua".as_bytes())) {
+                    if aux_key.eq(&Vec::from("lua".as_bytes())) {
                         entry.DB = self.db;
                         entry.Key = aux_key;
                         entry.Type = t;
@@ -156,6 +351,7 @@ impl Loader {
                     let db_size = self.rdbReader.ReadLength().await?;
                     let expire_size = self.rdbReader.ReadLength().await?;
                     println!("db_size:{} expire_size: {}", db_size, expire_size);
+                    self.metadata.DbSizes.insert(self.db, (db_size as u64, expire_size as u64));
                 }
                 rdbFlagExpiryMS => {
                     let ttlms = self.rdbReader.readUint64().await?;
@@ -172,9 +368,31 @@ impl Loader {
                 rdbFlagEOF => {
                     return Err(Box::from("RDB END"));
                 }
+                rdbFlagSlotInfo => {
+                    let slot_id = self.rdbReader.ReadLength().await?;
+                    let slot_size = self.rdbReader.ReadLength().await?;
+                    let expires_slot_size = self.rdbReader.ReadLength().await?;
+                    println!("slot:{} size:{} expires_size:{}", slot_id, slot_size, expires_slot_size);
+                }
+                rdbFlagFunction2 => {
+                    // 只有一个字符串: library的代码, 名字在代码第一行
+                    let code = self.rdbReader.ReadString().await?;
+                    entry.DB = self.db;
+                    entry.Key = library_name(&code).unwrap_or_default();
+                    entry.Type = t;
+                    entry.Value = code;
+                    return Ok(());
+                }
+                rdbFlagFunctionPreGa => {
+                    // 7.0 rc版本的格式, redis 7.0 GA也不再支持加载
+                    return Err(Box::from("不支持7.0之前的function格式(RDB_OPCODE_FUNCTION_PRE_GA)"));
+                }
                 rdbFlagModuleAux => {
-                    let _ = self.rdbReader.ReadLength().await?;
-                    rdbLoadCheckModuleValue(self).await?;
+                    // module的aux数据只能由module自己加载, 跳过
+                    let module_id = self.rdbReader.ReadLength64().await?;
+                    rdbLoadCheckModuleValue(&mut self.rdbReader).await?;
+                    let (name, version) = rdbModuleName(module_id);
+                    println!("跳过module aux数据 {} version:{}", name, version);
                 }
                 rdbFlagIdle => {
                     let idle = self.rdbReader.ReadLength().await?;
@@ -191,7 +409,8 @@ impl Loader {
                         key = self.rdbReader.ReadString().await?;
                         entry.NeedReadLen = 1; // read value length when it's the first time.
                     } else {
-                        key = self.lastEntry.Key.clone()
+                        key = self.lastEntry.Key.clone();
+                        entry.ExpireAt = self.lastEntry.ExpireAt;
                     }
                     //log.Debugf("l %p r %p", l, l.rdbReader)
                     //log.Debug("remainMember:", l.remainMember, " key:", string(key[:]), " type:", t)
@@ -201,6 +420,7 @@ impl Loader {
                     entry.Key = key;
                     entry.Type = t;
                     entry.Value = createValueDump(t, val);
+                    entry.FieldExpires = std::mem::take(&mut self.rdbReader.fieldExpires);
                     // entry.RealMemberCount = l.lastReadCount
                     if self.rdbReader.lastReadCount == self.rdbReader.totMemberCount {
                         entry.RealMemberCount = 0
@@ -208,7 +428,10 @@ impl Loader {
                         // RealMemberCount > 0 means this is big entry which also is a split key.
                         entry.RealMemberCount = self.rdbReader.lastReadCount
                     }
+                    // 后续的段只需要key, 类型和过期时间
+                    let value = std::mem::take(&mut entry.Value);
                     self.lastEntry = Box::from(entry.clone());
+                    entry.Value = value;
                     // 每次循环把buf清空，因为没用,只有在 readObjectValue 才有用
                     if self.rdbReader.is_cache_buf{
                         self.rdbReader.buf.clear();
@@ -220,10 +443,9 @@ impl Loader {
             }
 
         }
-        return Err(Box::from("RDB END"));
     }
 }
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct BinEntry {
     pub DB: u32,
     pub Key: Vec<u8>,
@@ -234,9 +456,17 @@ pub struct BinEntry {
     pub NeedReadLen: u8,
     pub IdleTime: u32,
     pub Freq: u8,
+    // hash中带过期时间的field和过期时间(毫秒)
+    pub FieldExpires: Vec<(Vec<u8>, u64)>,
 }
-pub struct rdbReader {
-    pub raw: Rc<RefCell<BufReader<PipeReader>>>,
+impl BinEntry {
+    // 大key拆分后的第一段, 或者没有拆分的key. 只有第一段带长度
+    pub fn IsFirstChunk(&self) -> bool {
+        self.NeedReadLen == 1 || self.RealMemberCount == 0
+    }
+}
+pub struct rdbReader<R = BufReader<PipeReader>> {
+    pub raw: Rc<RefCell<R>>,
     pub crc64:Crc64,
     pub is_cache_buf:bool,
     pub buf: Vec<u8>,
@@ -244,16 +474,20 @@ pub struct rdbReader {
     pub remainMember: u32,
     pub lastReadCount: u32,
     pub totMemberCount: u32,
+    pub fieldExpires: Vec<(Vec<u8>, u64)>,
+    // 大key拆分时每段的大小
+    pub maxChunk: usize,
+    // salvage往后查找时多读的数据, 在raw之前读取
+    pub replay: Vec<u8>,
+    pub replayPos: usize,
+    // salvage模式下记录当前entry读过的数据, 出错时从entry开始的下一个字节重新查找
+    pub journal: Option<Vec<u8>>,
 }
 macro_rules! read_uint {
     ($fun_name_uint:ident,$fun_name_int:ident,$n:expr,$reslut_fun:ident,$result_type:ty,$result_type_int:ty) => (
         pub async fn $fun_name_uint(&mut self) -> Result<$result_type, Box<dyn Error>> {
             let mut p: Vec<u8> = vec![0; $n];
-            self.raw.borrow_mut().read_exact(p.as_mut()).await?;
-            self.crc64.write_all(p.to_vec().as_slice())?;
-            if self.is_cache_buf {
-                self.buf.append(p.to_vec().as_mut());
-            }
+            self.readExact(p.as_mut()).await?;
             Ok(self.$reslut_fun(&p))
         }
         pub async fn $fun_name_int(&mut self)->Result<$result_type_int, Box<dyn Error>> {
@@ -262,14 +496,10 @@ macro_rules! read_uint {
     );
 }
 macro_rules! read_uint_big {
-    ($fun_name:ident,$n:expr,$reslut_fun:ident) => (
-         pub async fn $fun_name(&mut self) -> Result<u32, Box<dyn Error>> {
-            let mut p = [0 as u8; $n];
-            self.raw.borrow_mut().read_exact(p.as_mut()).await?;
-            self.crc64.write_all(p.to_vec().as_slice())?;
-            if self.is_cache_buf {
-                self.buf.append(p.to_vec().as_mut());
-            }
+    ($fun_name:ident,$n:expr,$reslut_fun:ident,$result_type:ty) => (
+         pub async fn $fun_name(&mut self) -> Result<$result_type, Box<dyn Error>> {
+            let mut p = [0u8; $n];
+            self.readExact(p.as_mut()).await?;
             Ok(self.$reslut_fun(&p))
         }
     );
@@ -296,7 +526,107 @@ macro_rules! base_u {
         }
     );
 }
-impl rdbReader {
+impl rdbReader<Cursor<Vec<u8>>> {
+    // 读取内存中的数据, 用于解析value dump
+    pub fn fromBytes(data: Vec<u8>) -> Self {
+        rdbReader::new(Rc::new(RefCell::new(Cursor::new(data))))
+    }
+}
+#[allow(clippy::await_holding_refcell_ref)]
+impl<R: AsyncRead + Unpin> rdbReader<R> {
+    pub fn new(r: Rc<RefCell<R>>) -> rdbReader<R> {
+        rdbReader {
+            raw: r,
+            crc64: Crc64::new(),
+            is_cache_buf: false,
+            buf: vec![],
+            nread: 0,
+            remainMember: 0,
+            lastReadCount: 0,
+            totMemberCount: 0,
+            fieldExpires: vec![],
+            maxChunk: defaultMaxChunk,
+            replay: vec![],
+            replayPos: 0,
+            journal: None,
+        }
+    }
+    // 解析用到的数据都从这里读取, 计算crc并且记录offset
+    async fn readExact(&mut self, p: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        if self.journal.is_some() {
+            self.readJournalExact(p).await?;
+        } else {
+            self.readRawExact(p).await?;
+        }
+        self.crc64.write_all(p)?;
+        if self.is_cache_buf {
+            self.buf.extend_from_slice(p);
+        }
+        self.nread = self.nread + p.len() as i64;
+        Ok(())
+    }
+    // 读到一半出错时已经读到的数据也要记到journal里, 否则salvage会丢掉这部分数据
+    async fn readJournalExact(&mut self, p: &mut [u8]) -> io::Result<()> {
+        let mut n = 0;
+        let mut result = Ok(());
+        while n < p.len() {
+            match self.readRaw(&mut p[n..]).await {
+                Ok(0) => {
+                    result = Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                    break;
+                }
+                Ok(r) => n = n + r,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            journal.extend_from_slice(&p[..n]);
+        }
+        result
+    }
+    // 不计算crc, 先读salvage退回的数据
+    pub async fn readRaw(&mut self, p: &mut [u8]) -> io::Result<usize> {
+        if self.replayPos < self.replay.len() {
+            let n = std::cmp::min(p.len(), self.replay.len() - self.replayPos);
+            p[..n].copy_from_slice(&self.replay[self.replayPos..self.replayPos + n]);
+            self.replayPos = self.replayPos + n;
+            if self.replayPos == self.replay.len() {
+                self.replay.clear();
+                self.replayPos = 0;
+            }
+            return Ok(n);
+        }
+        self.raw.borrow_mut().read(p).await
+    }
+    // 和readRaw一样, 直接读到buf的空闲空间里, 增量阶段解析命令用
+    pub async fn readRawBuf(&mut self, buf: &mut BytesMut) -> io::Result<usize> {
+        if self.replayPos < self.replay.len() {
+            let n = self.replay.len() - self.replayPos;
+            buf.extend_from_slice(&self.replay[self.replayPos..]);
+            self.replay.clear();
+            self.replayPos = 0;
+            return Ok(n);
+        }
+        self.raw.borrow_mut().read_buf(buf).await
+    }
+    // 增量阶段的命令也从这里读取
+    pub async fn readRawExact(&mut self, p: &mut [u8]) -> io::Result<usize> {
+        if self.replay.is_empty() {
+            return self.raw.borrow_mut().read_exact(p).await;
+        }
+        let mut n = 0;
+        while n < p.len() {
+            let r = self.readRaw(&mut p[n..]).await?;
+            if r == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+            n = n + r;
+        }
+        Ok(n)
+    }
     pub async fn ReadZipmapItem(
         &mut self,
         buf: &mut sliceBuffer,
@@ -307,7 +637,7 @@ impl rdbReader {
             return Ok(vec![]);
         };
         let value = buf.Slice(length)?;
-        buf.Seek(free as i64, 1);
+        buf.Seek(free as i64, 1)?;
         Ok(value)
     }
     pub async fn readZipmapItemLength(
@@ -316,27 +646,26 @@ impl rdbReader {
         readFree: bool,
     ) -> Result<(i32, i32), Box<dyn Error>> {
         let b = buf.ReadByte()?;
-        match b {
-            253 => {
-                let s = buf.Slice(5)?;
-                return Ok((
-                    self.u32big(s.as_slice()) as i32,
-                    (*s.get(4).unwrap()) as i32,
-                ));
-            }
+        let length = match b {
+            // 0到253是长度本身, 254后面是4字节的长度, 和redis的zipmapDecodeLength一样是小端
             254 => {
-                return Err(Box::from("rdb: invalid zipmap item length"));
+                let s = buf.Slice(4)?;
+                let length = self.u32(s.as_slice());
+                if length > i32::MAX as u32 {
+                    return Err(Box::from(format!("rdb: zipmap item length {} too large", length)));
+                }
+                length as i32
             }
             255 => {
                 return Ok((-1, 1));
             }
-            _ => {}
+            _ => b as i32,
         };
         let mut free = 0;
         if readFree {
             free = buf.ReadByte()?;
         };
-        Ok((b as i32, free as i32))
+        Ok((length, free as i32))
     }
     pub async fn CountZipmapItems(&mut self, buf: &mut sliceBuffer) -> Result<i32, Box<dyn Error>> {
         let mut n = 0;
@@ -345,7 +674,7 @@ impl rdbReader {
             if strLen == -1 {
                 break;
             };
-            buf.Seek((strLen + free) as i64, 1)?;
+            buf.Seek(strLen as i64 + free as i64, 1)?;
             n = n + 1;
         }
         buf.Seek(0, 0)?;
@@ -357,45 +686,40 @@ impl rdbReader {
             buf.Seek(4, 1)?; // skip the 4-byte prevlen
         };
         let header = buf.ReadByte()?;
-        if (header >> 6) as u8 == rdbZiplist6bitlenString {
-            return Ok(buf.Slice((header & 0x3f) as i32)?);
+        if (header >> 6) == rdbZiplist6bitlenString {
+            return buf.Slice((header & 0x3f) as i32);
         };
-        if (header >> 6) as u8 == rdbZiplist14bitlenString {
+        if (header >> 6) == rdbZiplist14bitlenString {
             let b = buf.ReadByte()?;
-            return Ok(buf.Slice((((header & 0x3f) as i32) << 8) as i32 | b as i32)?);
+            return buf.Slice((((header & 0x3f) as i32) << 8) | b as i32);
         }
-        if (header >> 6) as u8 == rdbZiplist32bitlenString {
+        if (header >> 6) == rdbZiplist32bitlenString {
             let lenBytes = buf.Slice(4)?;
-            return Ok(buf.Slice(self.u32big(lenBytes.as_ref()) as i32)?);
+            return buf.Slice(self.u32big(lenBytes.as_ref()) as i32);
         }
-        if header == rdbZiplistInt16 as u8 {
+        if header == rdbZiplistInt16 {
             let intBytes = buf.Slice(2)?;
-            return Ok(format!("{}", self.u16(intBytes.as_slice())).into_bytes());
+            return Ok(format!("{}", self.u16(intBytes.as_slice()) as i16).into_bytes());
         }
-        if header == rdbZiplistInt32 as u8 {
+        if header == rdbZiplistInt32 {
             let intBytes = buf.Slice(4)?;
-            return Ok(format!("{}", self.u32(intBytes.as_slice())).into_bytes());
+            return Ok(format!("{}", self.u32(intBytes.as_slice()) as i32).into_bytes());
         }
-        if header == rdbZiplistInt64 as u8 {
+        if header == rdbZiplistInt64 {
             let intBytes = buf.Slice(8)?;
-            return Ok(format!("{}", self.u64(intBytes.as_slice())).into_bytes());
+            return Ok(format!("{}", self.u64(intBytes.as_slice()) as i64).into_bytes());
         }
-        if header == rdbZiplistInt24 as u8 {
-            let intBytes_ = [0 as u8; 3];
-            buf.Read(&mut intBytes_.to_vec())?;
-            let mut intBytes = [0 as u8;4];
-            let mut index = 0;
-            for _ in intBytes_.iter(){
-                *intBytes.get_mut(index+1).unwrap() = *intBytes_.get(index).unwrap();
-                index=index+1;
-            }
-            return Ok(format!("{}", self.u32(intBytes.as_ref()) >> 8).into_bytes());
+        if header == rdbZiplistInt24 {
+            let intBytes = buf.Slice(3)?;
+            // 放到高24位再算术右移,保留符号
+            let v = (self.u32(&[0, intBytes[0], intBytes[1], intBytes[2]]) as i32) >> 8;
+            return Ok(format!("{}", v).into_bytes());
         }
-        if header == rdbZiplistInt8 as u8 {
+        if header == rdbZiplistInt8 {
             let b = buf.ReadByte()?;
             return Ok(format!("{}", b as i8).into_bytes());
         }
-        if (header >> 4) as u8 == rdbZiplistInt4 {
+        if (header >> 4) == rdbZiplistInt4 {
             return Ok(format!("{}", (header & 0x0f) as i64 - 1).into_bytes());
         }
         Err(Box::from("rdb: unknown ziplist header byte"))
@@ -403,15 +727,134 @@ impl rdbReader {
     pub async fn ReadZiplistLength(&mut self, buf: &mut sliceBuffer) -> Result<i64, Box<dyn Error>> {
         buf.Seek(8, 0)?; // skip the zlbytes and zltail
         let lenBytes = buf.Slice(2)?;
-        Ok(self.u16(lenBytes.as_slice()) as i64)
+        let length = self.u16(lenBytes.as_slice()) as i64;
+        if length != 65535 {
+            return Ok(length);
+        }
+        // 元素太多的时候需要遍历计算
+        let mut n = 0;
+        while buf.s.get(buf.i as usize).is_some_and(|b| *b != zipEnd) {
+            self.ReadZiplistEntry(buf).await?;
+            n = n + 1;
+        }
+        buf.Seek(10, 0)?;
+        Ok(n)
     }
-    pub async fn ReadByte(&mut self) -> Result<u8, Box<dyn Error>> {
-        let mut p = [0 as u8; 1];
-        self.raw.borrow_mut().read_exact(p.as_mut()).await?;
-        self.crc64.write_all(p.to_vec().as_slice())?;
-        if self.is_cache_buf{
-            self.buf.append(p.to_vec().as_mut());
+    // listpack: 4字节总长度 + 2字节元素个数 + 元素 + 0xff
+    pub async fn ReadListpackLength(&mut self, buf: &mut sliceBuffer) -> Result<i64, Box<dyn Error>> {
+        buf.Seek(4, 0)?; // skip the total bytes
+        let lenBytes = buf.Slice(2)?;
+        let length = self.u16(lenBytes.as_slice()) as i64;
+        if length != 65535 {
+            return Ok(length);
+        }
+        // 元素太多的时候需要遍历计算
+        let mut n = 0;
+        while buf.s.get(buf.i as usize).is_some_and(|b| *b != lpEOF) {
+            self.ReadListpackEntry(buf).await?;
+            n = n + 1;
         }
+        buf.Seek(6, 0)?;
+        Ok(n)
+    }
+    pub async fn ReadListpackEntry(&mut self, buf: &mut sliceBuffer) -> Result<Vec<u8>, Box<dyn Error>> {
+        let header = buf.ReadByte()?;
+        let entryLen;
+        let rsl;
+        if header & 0x80 == lpEncoding7BitUint {
+            entryLen = 1;
+            rsl = format!("{}", header & 0x7f).into_bytes();
+        } else if header & 0xc0 == lpEncoding6BitStr {
+            let length = (header & 0x3f) as i32;
+            entryLen = 1 + length;
+            rsl = buf.Slice(length)?;
+        } else if header & 0xe0 == lpEncoding13BitInt {
+            let b = buf.ReadByte()?;
+            let mut v = ((header & 0x1f) as i64) << 8 | b as i64;
+            if v >= 1 << 12 {
+                v = v - (1 << 13);
+            }
+            entryLen = 2;
+            rsl = format!("{}", v).into_bytes();
+        } else if header & 0xf0 == lpEncoding12BitStr {
+            let b = buf.ReadByte()?;
+            let length = ((header & 0x0f) as i32) << 8 | b as i32;
+            entryLen = 2 + length;
+            rsl = buf.Slice(length)?;
+        } else {
+            match header {
+                lpEncoding32BitStr => {
+                    let lenBytes = buf.Slice(4)?;
+                    let length = self.u32(lenBytes.as_slice()) as i32;
+                    rsl = buf.Slice(length)?;
+                    entryLen = 5 + length;
+                }
+                lpEncoding16BitInt => {
+                    let intBytes = buf.Slice(2)?;
+                    entryLen = 3;
+                    rsl = format!("{}", self.u16(intBytes.as_slice()) as i16).into_bytes();
+                }
+                lpEncoding24BitInt => {
+                    let intBytes = buf.Slice(3)?;
+                    // 放到高24位再算术右移,保留符号
+                    let v = ((self.u32(&[0, intBytes[0], intBytes[1], intBytes[2]]) as i32) >> 8) as i64;
+                    entryLen = 4;
+                    rsl = format!("{}", v).into_bytes();
+                }
+                lpEncoding32BitInt => {
+                    let intBytes = buf.Slice(4)?;
+                    entryLen = 5;
+                    rsl = format!("{}", self.u32(intBytes.as_slice()) as i32).into_bytes();
+                }
+                lpEncoding64BitInt => {
+                    let intBytes = buf.Slice(8)?;
+                    entryLen = 9;
+                    rsl = format!("{}", self.u64(intBytes.as_slice()) as i64).into_bytes();
+                }
+                _ => {
+                    return Err(Box::from(format!("rdb: unknown listpack header byte {}", header)));
+                }
+            }
+        }
+        // 跳过backlen, 和redis的lpEncodeBacklen一致
+        let backlen = if entryLen <= 127 {
+            1
+        } else if entryLen < 16383 {
+            2
+        } else if entryLen < 2097151 {
+            3
+        } else if entryLen < 268435455 {
+            4
+        } else {
+            5
+        };
+        buf.Seek(backlen as i64, 1)?;
+        Ok(rsl)
+    }
+    // intset: 4字节的编码(每个元素的字节数), 4字节的元素个数, 然后是有序的元素, 都是小端
+    pub async fn ReadIntset(&mut self, buf: &mut sliceBuffer) -> Result<Vec<i64>, Box<dyn Error>> {
+        let size = self.u32(&buf.Slice(4)?);
+        let n = self.u32(&buf.Slice(4)?);
+        if size != 2 && size != 4 && size != 8 {
+            return Err(Box::from(format!("rdb: unknown intset encoding {}", size)));
+        }
+        if n as u64 * size as u64 > (buf.s.len() - 8) as u64 {
+            return Err(Box::from(format!("rdb: intset length {} out of range", n)));
+        }
+        let mut rsl = Vec::with_capacity(n as usize);
+        for _ in 0..n {
+            let b = buf.Slice(size as i32)?;
+            rsl.push(match size {
+                2 => self.u16(&b) as i16 as i64,
+                4 => self.u32(&b) as i32 as i64,
+                _ => self.u64(&b) as i64,
+            });
+        }
+        Ok(rsl)
+    }
+    pub async fn ReadByte(&mut self) -> Result<u8, Box<dyn Error>> {
+        let mut p = [0u8; 1];
+        self.readExact(p.as_mut()).await?;
         Ok(p[0])
     }
     pub async fn ReadString(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
@@ -422,31 +865,28 @@ impl rdbReader {
         match length as u8 {
             rdbEncInt8 => {
                 let i = self.readInt8().await?;
-                return Ok(Vec::from(format!("{}", i)));
+                Ok(Vec::from(format!("{}", i)))
             }
             rdbEncInt16 => {
                 let i = self.readInt16().await?;
-                return Ok(Vec::from(format!("{}", i)));
+                Ok(Vec::from(format!("{}", i)))
             }
             rdbEncInt32 => {
                 let i = self.readInt32().await?;
-                return Ok(Vec::from(format!("{}", i)));
+                Ok(Vec::from(format!("{}", i)))
             }
             rdbEncLZF => {
                 let inlen = self.ReadLength().await?;
                 let outlen = self.ReadLength().await?;
                 let in_data = self.ReadBytes(inlen as usize).await?;
-                return lzfDecompress(&in_data, outlen as usize);
-            }
-            _ => {
-                return Err(Box::from("invalid encoded-string"));
+                lzfDecompress(&in_data, outlen as usize)
             }
+            _ => Err(Box::from("invalid encoded-string")),
         }
-        Ok(Vec::from("".as_bytes()))
     }
     pub async fn readEncodedLength(&mut self) -> Result<(u32, bool), Box<dyn Error>> {
         let u = self.readUint8().await?;
-        let mut length = 0;
+        let length;
         let mut encoded = false;
         match u >> 6 {
             rdb6bitLen => {
@@ -465,7 +905,11 @@ impl rdbReader {
                     length = self.readUint32BigEndian().await?;
                 }
                 rdb64bitLen => {
-                    length = self.readUint64BigEndian().await?;
+                    let l = self.readUint64BigEndian().await?;
+                    if l > u32::MAX as u64 {
+                        return Err(Box::from(format!("length {} too large", l)));
+                    }
+                    length = l as u32;
                 }
                 _ => {
                     return Err(Box::from(format!("unknown encoding length {}",u)));
@@ -478,20 +922,20 @@ impl rdbReader {
     read_uint!(readUint16,readInt16,2,u16,u16,i16);
     read_uint!(readUint32,readInt32,4,u32,u32,i32);
     read_uint!(readUint64,readInt64,8,u64,u64,i64);
-    read_uint_big!(readUint32BigEndian,4,u32big);
-    read_uint_big!(readUint64BigEndian,8,u32big);
+    read_uint_big!(readUint32BigEndian,4,u32big,u32);
+    read_uint_big!(readUint64BigEndian,8,u64big,u64);
     base_u!(u8,u8big,u8,0);
     base_u!(u16,u16big,u16,1);
     base_u!(u32,u32big,u32,3);
     base_u!(u64,u64big,u64,7);
     pub async fn ReadBytes(&mut self, n: usize) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut p: Vec<u8> = vec![0; n];
-        self.raw.borrow_mut().read_exact(&mut p).await?;
-        self.crc64.write_all(p.to_vec().as_slice())?;
-        if self.is_cache_buf{
-            self.buf.append(p.clone().as_mut());
+        // 和redis的proto-max-bulk-len一样, 超过的一定是坏数据, 不要按这个长度读取和分配内存
+        if n > maxStringLen {
+            return Err(Box::from(format!("string length {} too large", n)));
         }
-        Ok(p)
+        let mut p: Vec<u8> = vec![0; n];
+        self.readExact(&mut p).await?;
+                Ok(p)
     }
     pub async fn ReadLength(&mut self) -> Result<u32, Box<dyn Error>> {
         let (length, encoded) = self.readEncodedLength().await?;
@@ -500,146 +944,233 @@ impl rdbReader {
         };
         Ok(length)
     }
+    // 64位的长度, 用于ttl等可能超过u32的值
+    pub async fn ReadLength64(&mut self) -> Result<u64, Box<dyn Error>> {
+        let u = self.readUint8().await?;
+        match u >> 6 {
+            rdb6bitLen => Ok((u & 0x3f) as u64),
+            rdb14bitLen => {
+                let u2 = self.readUint8().await?;
+                Ok((((u & 0x3f) as u64) << 8) + u2 as u64)
+            }
+            rdbEncVal => Err(Box::from("encoded-length")),
+            _ => match u {
+                rdb32bitLen => Ok(self.readUint32BigEndian().await? as u64),
+                rdb64bitLen => self.readUint64BigEndian().await,
+                _ => Err(Box::from(format!("unknown encoding length {}", u))),
+            },
+        }
+    }
     pub async fn ReadFloat(&mut self) -> Result<f64, Box<dyn Error>> {
         let u = self.readUint8().await?;
         match u {
-            253 => {
-                return Ok(NAN);
-            }
-            254 => return Ok(INFINITY as f64),
-            255 => return Ok(NEG_INFINITY),
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
             _ => {
                 let b = self.ReadBytes(u as usize).await?;
-                return Ok(String::from_utf8(b)?.parse::<f64>()?);
+                Ok(String::from_utf8(b)?.parse::<f64>()?)
             }
         }
     }
     pub async fn ReadDouble(&mut self) -> Result<f64, Box<dyn Error>> {
-        let mut p = [0 as u8; 8];
-        self.raw.borrow_mut().read_exact(p.as_mut()).await?;
-        self.crc64.write_all(p.to_vec().as_slice())?;
-        if self.is_cache_buf {
-            self.buf.append(p.to_vec().as_mut());
-        }
-        Ok(self.u64(&p) as f64)
+        let mut p = [0u8; 8];
+        self.readExact(p.as_mut()).await?;
+        Ok(f64::from_bits(self.u64(&p)))
     }
 
     pub async fn readObjectValue(&mut self, t: u8) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut lr = self;
+        let lr = self;
         lr.is_cache_buf = true;
+        lr.fieldExpires.clear();
         match t {
             RdbFlagAUX | rdbFlagResizeDB | RdbTypeHashZipmap | RdbTypeListZiplist
-            | RdbTypeSetIntset | RdbTypeZSetZiplist | RdbTypeHashZiplist | RdbTypeString => {
+            | RdbTypeSetIntset | RdbTypeZSetZiplist | RdbTypeHashZiplist | RdbTypeString
+            | RdbTypeHashListpack | RdbTypeZSetListpack | RdbTypeSetListpack => {
                 lr.lastReadCount = 0;
                 lr.remainMember = 0;
                 lr.totMemberCount = 0;
                 lr.ReadString().await?;
             }
-            RdbTypeList | RdbTypeSet | RdbTypeQuicklist => {
+            RdbTypeHashMetadataPreGa | RdbTypeHashMetadata => {
                 lr.lastReadCount = 0;
                 lr.remainMember = 0;
                 lr.totMemberCount = 0;
+                let mut minExpire = 0;
+                if t == RdbTypeHashMetadata {
+                    minExpire = lr.readUint64().await?;
+                }
                 let n = lr.ReadLength().await?;
                 for _i in 0..n {
+                    // 7.4的rc和GA都是ttl在前, 然后是field和value, 0表示没有过期时间
+                    let mut ttl = lr.ReadLength64().await?;
+                    if t == RdbTypeHashMetadata && ttl != 0 {
+                        // GA保存的是相对minExpire的值
+                        ttl = ttl + minExpire - 1;
+                    }
+                    let field = lr.ReadString().await?;
                     lr.ReadString().await?;
+                    if ttl != 0 {
+                        lr.fieldExpires.push((field, ttl));
+                    }
                 }
             }
-            RdbTypeZSet | RdbTypeZSet2 => {
+            RdbTypeHashListpackExPreGa | RdbTypeHashListpackEx => {
                 lr.lastReadCount = 0;
                 lr.remainMember = 0;
                 lr.totMemberCount = 0;
-                let n = lr.ReadLength().await?;
+                if t == RdbTypeHashListpackEx {
+                    // minExpire
+                    lr.readUint64().await?;
+                }
+                let listpack = lr.ReadString().await?;
+                // field value ttl 三个一组, ttl为0表示没有过期时间
+                let mut buf = sliceBuffer::new(listpack);
+                let n = lr.ReadListpackLength(&mut buf).await? / 3;
                 for _i in 0..n {
-                    lr.ReadString().await?;
-                    if t == RdbTypeZSet2 {
-                        lr.ReadDouble().await?;
-                    } else {
-                        lr.ReadFloat().await?;
+                    let field = lr.ReadListpackEntry(&mut buf).await?;
+                    lr.ReadListpackEntry(&mut buf).await?;
+                    let ttl = String::from_utf8_lossy(&lr.ReadListpackEntry(&mut buf).await?).parse::<u64>()?;
+                    if ttl != 0 {
+                        lr.fieldExpires.push((field, ttl));
                     }
                 }
             }
-            RdbTypeHash => {
-                let mut n = 0;
-                if lr.remainMember != 0 {
-                    n = lr.remainMember
+            RdbTypeList | RdbTypeSet | RdbTypeQuicklist | RdbTypeQuicklist2 | RdbTypeZSet | RdbTypeZSet2
+            | RdbTypeHash | RDBTypeStreamListPacks | RDBTypeStreamListPacks2 | RDBTypeStreamListPacks3 => {
+                // 大key按成员拆成多段, 每段不超过maxChunk, 只有第一段有长度
+                let n = if lr.remainMember != 0 {
+                    lr.remainMember
                 } else {
                     let rlen = lr.ReadLength().await?;
-                    n = rlen;
                     lr.totMemberCount = rlen;
-                }
+                    rlen
+                };
                 lr.lastReadCount = 0;
                 for i in 0..n {
-                    lr.ReadString().await?;
-                    lr.ReadString().await?;
+                    lr.readObjectMember(t).await?;
                     lr.lastReadCount = lr.lastReadCount + 1;
-                    if lr.buf.len() > 16*1024*1024 && i != (n - 1) {
+                    if lr.buf.len() > lr.maxChunk && i != (n - 1) {
                         lr.remainMember = n - i - 1;
-                        // log.Infof("r %p", lr)
-                        // log.Info("r: ", lr, " set remainMember:", lr.remainMember)
-                        // debug.FreeOSMemory()
                         break;
                     }
                 }
                 if lr.lastReadCount == n {
-                    lr.remainMember = 0
+                    lr.remainMember = 0;
+                    // stream的元数据在最后一段
+                    if rdbIsStream(t) {
+                        lr.readStreamMeta(t).await?;
+                    }
                 }
             }
-            RDBTypeStreamListPacks => {
+            RdbTypeModule2 => {
                 lr.lastReadCount = 0;
                 lr.remainMember = 0;
                 lr.totMemberCount = 0;
-                let nListPacks = lr.ReadLength().await?;
-                // list pack length
-                for _ in 0..nListPacks {
-                    // read twice
-                    lr.ReadString().await?;
-                    lr.ReadString().await?;
-                }
-                // items
-                lr.ReadLength().await?;
-                // last_entry_id timestamp second
-                lr.ReadLength().await?;
-                // last_entry_id timestamp millisecond
-                lr.ReadLength().await?;
-                // cgroups length
-                let nCgroups = lr.ReadLength().await?;
-                for _ in 0..nCgroups {
-                    // cname
-                    lr.ReadString().await?;
-                    // last_cg_entry_id timestamp second
-                    lr.ReadLength().await?;
-                    // last_cg_entry_id timestamp millisecond
-                    lr.ReadLength().await?;
-                    // pending number
-                    let nPending = lr.ReadLength().await?;
-                    for _ in 0..nPending {
-                        // eid, read 16 bytes
-                        lr.ReadBytes(16).await?;
-                        // seen_time
-                        lr.ReadBytes(8).await?;
-                        // delivery_count
-                        lr.ReadLength().await?;
-                    }
-                    // consumers
-                    let nConsumers = lr.ReadLength().await?;
-                    for _ in 0..nConsumers {
-                        // cname
-                        lr.ReadString().await?;
-                        // seen_time
-                        lr.ReadBytes(8).await?;
-                        // pending
-                        let nPending2 = lr.ReadLength().await?;
-                        for _ in 0..nPending2 {
-                            lr.ReadBytes(16).await;
-                        }
-                    }
-                }
+                lr.ReadLength64().await?;
+                rdbLoadCheckModuleValue(lr).await?;
+            }
+            RdbTypeModule => {
+                // 老格式没有opcode, 只有module自己能解析
+                return Err(Box::from("不支持RDB_TYPE_MODULE(6), 需要redis 4.0以上生成的MODULE_2格式"));
             }
             _ => {
                 return Err(Box::from(format!("unknown object-type {}", t)));
             }
         };
-        Ok(lr.buf.clone())
+        Ok(std::mem::take(&mut lr.buf))
+    }
+    // 集合类型的一个成员, stream是一个listpack节点
+    async fn readObjectMember(&mut self, t: u8) -> Result<(), Box<dyn Error>> {
+        match t {
+            RdbTypeList | RdbTypeSet | RdbTypeQuicklist => {
+                self.ReadString().await?;
+            }
+            RdbTypeQuicklist2 => {
+                // container: 1 plain, 2 packed(listpack)
+                self.ReadLength().await?;
+                self.ReadString().await?;
+            }
+            RdbTypeZSet | RdbTypeZSet2 => {
+                self.ReadString().await?;
+                if t == RdbTypeZSet2 {
+                    self.ReadDouble().await?;
+                } else {
+                    self.ReadFloat().await?;
+                }
+            }
+            RdbTypeHash => {
+                self.ReadString().await?;
+                self.ReadString().await?;
+            }
+            _ => {
+                // master id和listpack
+                self.ReadString().await?;
+                self.ReadString().await?;
+            }
+        }
+        Ok(())
+    }
+    async fn readStreamMeta(&mut self, t: u8) -> Result<(), Box<dyn Error>> {
+        let lr = self;
+        // items
+        lr.ReadLength64().await?;
+        // last_entry_id timestamp second
+        lr.ReadLength64().await?;
+        // last_entry_id timestamp millisecond
+        lr.ReadLength64().await?;
+        if t != RDBTypeStreamListPacks {
+            // first_entry_id
+            lr.ReadLength64().await?;
+            lr.ReadLength64().await?;
+            // max_deleted_entry_id
+            lr.ReadLength64().await?;
+            lr.ReadLength64().await?;
+            // entries_added
+            lr.ReadLength64().await?;
+        }
+        // cgroups length
+        let nCgroups = lr.ReadLength64().await?;
+        for _ in 0..nCgroups {
+            // cname
+            lr.ReadString().await?;
+            // last_cg_entry_id timestamp second
+            lr.ReadLength64().await?;
+            // last_cg_entry_id timestamp millisecond
+            lr.ReadLength64().await?;
+            if t != RDBTypeStreamListPacks {
+                // entries_read
+                lr.ReadLength64().await?;
+            }
+            // pending number
+            let nPending = lr.ReadLength64().await?;
+            for _ in 0..nPending {
+                // eid, read 16 bytes
+                lr.ReadBytes(16).await?;
+                // seen_time
+                lr.ReadBytes(8).await?;
+                // delivery_count
+                lr.ReadLength64().await?;
+            }
+            // consumers
+            let nConsumers = lr.ReadLength64().await?;
+            for _ in 0..nConsumers {
+                // cname
+                lr.ReadString().await?;
+                // seen_time
+                lr.ReadBytes(8).await?;
+                if t == RDBTypeStreamListPacks3 {
+                    // active_time
+                    lr.ReadBytes(8).await?;
+                }
+                // pending
+                let nPending2 = lr.ReadLength64().await?;
+                for _ in 0..nPending2 {
+                    lr.ReadBytes(16).await?;
+                }
+            }
+        }
+        Ok(())
     }
 }
 macro_rules! must_get {
@@ -662,7 +1193,11 @@ macro_rules! must_get_mut {
         }
     );
 }
-pub fn lzfDecompress(in_data: &Vec<u8>, outlen: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+// 输入和outlen都来自rdb, 坏数据返回错误, 不能panic
+pub fn lzfDecompress(in_data: &[u8], outlen: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    if outlen > maxStringLen {
+        return Err(Box::from(format!("lzf: outlen {} too large", outlen)));
+    }
     let mut out: Vec<u8> = vec![0; outlen];
     let (mut i, mut o) = (0, 0);
     while i < in_data.len() {
@@ -680,10 +1215,11 @@ pub fn lzfDecompress(in_data: &Vec<u8>, outlen: usize) -> Result<Vec<u8>, Box<dy
                 length = length + must_get!(in_data,i) as i32;
                 i = i + 1;
             }
-            let mut ref_d = o
-                - ((ctrl & 0x1f) << 8) as usize
-                - must_get!(in_data,i) as usize
-                - 1;
+            let back = (((ctrl & 0x1f) as usize) << 8) + must_get!(in_data,i) as usize + 1;
+            if back > o {
+                return Err(Box::from("lzf: invalid back reference"));
+            }
+            let mut ref_d = o - back;
             i = i + 1;
             for _ in 0..=(length + 1) {
                 * must_get_mut!(out,o) = must_get!(out,ref_d);
@@ -692,47 +1228,83 @@ pub fn lzfDecompress(in_data: &Vec<u8>, outlen: usize) -> Result<Vec<u8>, Box<dy
             }
         }
     }
+    if o != outlen {
+        return Err(Box::from(format!("lzf: decompressed length {} != {}", o, outlen)));
+    }
     Ok(out)
 }
 
-async fn rdbLoadCheckModuleValue(l: &mut Loader) -> Result<(), Box<dyn Error>> {
-    let _opcode: u32 = 0;
+// module的数据: 若干个(opcode, 值), 以EOF结束
+// float和double保存的是二进制(4字节和8字节)
+async fn rdbLoadCheckModuleValue<R: AsyncRead + Unpin>(r: &mut rdbReader<R>) -> Result<(), Box<dyn Error>> {
     loop {
-        let opcode = l.rdbReader.ReadLength().await?;
-        if opcode == rdbModuleOpcodeEof {
-            break;
-        }
-        match opcode {
-            rdbModuleOpcodeSint => {
-                l.rdbReader.ReadLength().await?;
-            }
-            rdbModuleOpcodeUint => {
-                l.rdbReader.ReadLength().await?;
+        let opcode = r.ReadLength64().await?;
+        match opcode as u32 {
+            rdbModuleOpcodeEof => break,
+            rdbModuleOpcodeSint | rdbModuleOpcodeUint => {
+                r.ReadLength64().await?;
             }
             rdbModuleOpcodeString => {
-                l.rdbReader.ReadString().await?;
+                r.ReadString().await?;
             }
             rdbModuleOpcodeFloat => {
-                l.rdbReader.ReadFloat().await?;
+                r.ReadBytes(4).await?;
             }
             rdbModuleOpcodeDouble => {
-                l.rdbReader.ReadDouble().await?;
+                r.ReadBytes(8).await?;
             }
-            _ => {}
+            _ => return Err(Box::from(format!("unknown module opcode {}", opcode))),
         }
     }
     Ok(())
 }
-// 把数据变成6版本的
+
+const moduleTypeNameCharSet: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// module id: 9个字符每个6位 + 10位的版本号
+pub fn rdbModuleName(module_id: u64) -> (String, u64) {
+    let mut name = [0u8; 9];
+    let mut id = module_id >> 10;
+    for j in (0..9).rev() {
+        name[j] = moduleTypeNameCharSet[(id & 63) as usize];
+        id = id >> 6;
+    }
+    (String::from_utf8_lossy(&name).to_string(), module_id & 1023)
+}
+
+// 从value dump(或者DUMP命令的payload)中取出module id
+pub fn rdbModuleIdFromDump(dump: &[u8]) -> Option<u64> {
+    if dump.len() < 2 || (dump[0] != RdbTypeModule && dump[0] != RdbTypeModule2) {
+        return None;
+    }
+    let u = dump[1];
+    match u >> 6 {
+        rdb6bitLen => Some((u & 0x3f) as u64),
+        rdb14bitLen => Some((((u & 0x3f) as u64) << 8) + *dump.get(2)? as u64),
+        _ => match u {
+            rdb32bitLen if dump.len() >= 6 => {
+                Some(u32::from_be_bytes([dump[2], dump[3], dump[4], dump[5]]) as u64)
+            }
+            rdb64bitLen if dump.len() >= 10 => {
+                let mut p = [0u8; 8];
+                p.copy_from_slice(&dump[2..10]);
+                Some(u64::from_be_bytes(p))
+            }
+            _ => None,
+        },
+    }
+}
+// 把数据变成DUMP的格式, 版本号是这个类型需要的最低rdb版本
 pub fn createValueDump(t: u8, val: Vec<u8>) -> Vec<u8> {
-    let mut wtr = vec![];
+    let mut wtr = Vec::with_capacity(val.len() + 11);
     let mut crc = Crc64::new();
     wtr.push(t);
     crc.write_u8(t).unwrap();
-    wtr.append(&mut val.clone());
-    crc.write(val.as_slice()).unwrap();
-    wtr.write_u16::<LittleEndian>(6 as u16).unwrap();
-    crc.write_u16::<LittleEndian>(6 as u16).unwrap();
+    wtr.extend_from_slice(&val);
+    crc.write_all(val.as_slice()).unwrap();
+    let version = rdbTypeMinVersion(t);
+    wtr.write_u16::<LittleEndian>(version).unwrap();
+    crc.write_u16::<LittleEndian>(version).unwrap();
     wtr.write_u64::<LittleEndian>(crc.get()).unwrap();
-    return wtr;
+    wtr
 }
@@ -1,4 +1,20 @@
+// 从Go版移植的模块, 保留Go的命名
+#[allow(non_snake_case, non_upper_case_globals, non_camel_case_types)]
 pub mod full;
+#[allow(non_snake_case, non_upper_case_globals, non_camel_case_types)]
 pub mod incr;
+#[allow(non_snake_case, non_upper_case_globals, non_camel_case_types)]
 pub mod loader;
-pub mod slice_buffer;
\ No newline at end of file
+#[allow(non_snake_case, non_upper_case_globals, non_camel_case_types)]
+pub mod slice_buffer;
+pub mod writer;
+pub mod crc64;
+pub mod rump;
+pub mod keyspace;
+pub mod function;
+pub mod module;
+pub mod stream;
+pub mod value;
+pub mod salvage;
+pub mod compress;
+pub mod diff;
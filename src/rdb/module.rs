@@ -0,0 +1,124 @@
+use crate::rdb::loader::{rdbModuleIdFromDump, rdbModuleName};
+use crate::utils::conn::open_redis_sync_conn;
+use redis::{RedisResult, Value};
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+
+// 目的端没有加载对应module时的处理
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModulePolicy {
+    // 跳过这个key, 在报告中列出
+    Skip,
+    // 直接报错退出
+    Fail,
+}
+
+impl ModulePolicy {
+    pub fn parse(s: &str) -> Result<ModulePolicy, Box<dyn Error>> {
+        match s {
+            "skip" => Ok(ModulePolicy::Skip),
+            "fail" => Ok(ModulePolicy::Fail),
+            _ => Err(Box::from(format!("未知的module策略 {}", s))),
+        }
+    }
+}
+
+// 常见module注册的类型名和MODULE LIST中的名字
+const KNOWN_MODULE_TYPES: &[(&str, &str)] = &[
+    ("ReJSON-RL", "rejson"),
+    ("MBbloom--", "bf"),
+    ("MBbloomCF", "bf"),
+    ("CMSk-TYPE", "bf"),
+    ("TopK-TYPE", "bf"),
+    ("TDIS-TYPE", "bf"),
+    ("TSDB-TYPE", "timeseries"),
+    ("graphdata", "graph"),
+    ("ft_index0", "search"),
+    ("ft_invidx", "search"),
+    ("ft_tagidx", "search"),
+];
+
+pub struct Modules {
+    pub policy: ModulePolicy,
+    // 目的端MODULE LIST中的名字, 小写
+    pub loaded: HashSet<String>,
+    // 按module类型名统计: (同步的key数, 跳过的key)
+    pub report: BTreeMap<String, (u64, Vec<Vec<u8>>)>,
+}
+
+impl Modules {
+    // MODULE LIST返回 [[name, ReJSON, ver, 20609, ...], ...]
+    pub async fn load_target(url: &str, pass: &str, policy: ModulePolicy) -> Result<Modules, Box<dyn Error>> {
+        let mut conn = open_redis_sync_conn(url, pass, "").await?;
+        let list: RedisResult<Value> = redis::cmd("MODULE").arg("LIST").query_async(&mut conn).await;
+        let loaded = match list {
+            Ok(v) => parse_module_list(&v),
+            // 4.0以下没有MODULE命令, 也就不可能加载module
+            Err(e) if e.to_string().contains("unknown command") => HashSet::new(),
+            Err(e) => return Err(Box::from(format!("查询目的端的MODULE LIST失败: {}", e))),
+        };
+        println!("目的端已加载的module {:?}", loaded);
+        Ok(Modules {
+            policy,
+            loaded,
+            report: BTreeMap::new(),
+        })
+    }
+    // module类型的key是否发送到目的端, 不是module类型的直接返回true
+    // 不认识的类型名无法确认目的端是否加载了对应的module, 同样按policy处理
+    pub fn check(&mut self, key: &[u8], dump: &[u8]) -> Result<bool, Box<dyn Error>> {
+        let module_id = match rdbModuleIdFromDump(dump) {
+            Some(d) => d,
+            None => return Ok(true),
+        };
+        let (type_name, _) = rdbModuleName(module_id);
+        let missing = match KNOWN_MODULE_TYPES.iter().find(|(t, _)| *t == type_name) {
+            Some((_, module)) => !self.loaded.contains(*module),
+            None => true,
+        };
+        let stat = self.report.entry(type_name.clone()).or_insert_with(|| (0, vec![]));
+        if !missing {
+            stat.0 = stat.0 + 1;
+            return Ok(true);
+        }
+        match self.policy {
+            ModulePolicy::Skip => {
+                stat.1.push(key.to_vec());
+                Ok(false)
+            }
+            ModulePolicy::Fail => Err(Box::from(format!(
+                "目的端没有加载module类型 {} (key {}), 或者是不认识的module类型",
+                type_name,
+                String::from_utf8_lossy(key)
+            ))),
+        }
+    }
+    pub fn print_report(&self) {
+        for (type_name, (synced, skipped)) in self.report.iter() {
+            println!("[MODULE] type:{} synced keys:{} skipped keys:{}", type_name, synced, skipped.len());
+            for key in skipped.iter() {
+                println!("[MODULE] skipped {} {}", type_name, String::from_utf8_lossy(key));
+            }
+        }
+    }
+}
+
+// MODULE LIST中的module名字, 小写
+pub fn parse_module_list(list: &Value) -> HashSet<String> {
+    let mut loaded = HashSet::new();
+    if let Value::Bulk(modules) = list {
+        for module in modules {
+            if let Value::Bulk(fields) = module {
+                for kv in fields.chunks(2) {
+                    if let (Value::Data(k), Some(Value::Data(v))) = (&kv[0], kv.get(1)) {
+                        if k.as_slice() == b"name" {
+                            loaded.insert(String::from_utf8_lossy(v).to_lowercase());
+                        }
+                    }
+                }
+            }
+        }
+    }
+    loaded
+}
@@ -0,0 +1,345 @@
+use crate::utils::conn::open_redis_sync_conn;
+use crate::utils::filter::Filter;
+use crate::{atomic_u64_fetch_add, atomic_u64_load};
+use redis::aio::Connection;
+use redis::{Cmd, RedisResult, Value};
+
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use async_std::task::{sleep, spawn};
+
+// 不支持psync的源端(云厂商),用SCAN + DUMP + RESTORE做一次全量复制
+pub async fn rump(
+    source_url: &str,
+    source_pass: &str,
+    target_url: &str,
+    target_pass: &str,
+    filter: &Filter,
+    batch: usize,
+) -> Result<(), Box<dyn Error>> {
+    let stat = Arc::new(RumpStat::default());
+    let stat_c = stat.clone();
+    let done = Arc::new(AtomicU64::new(0));
+    let done_c = done.clone();
+    // 输出进度
+    spawn(async move {
+        loop {
+            stat_c.print();
+            if atomic_u64_load!(done_c) == 1 {
+                break;
+            }
+            sleep(Duration::from_secs(1)).await;
+        }
+    });
+    let dbs = match open_redis_sync_conn(source_url, source_pass, "").await {
+        Ok(mut info_conn) => keyspace_dbs(&mut info_conn).await,
+        Err(e) => Err(e),
+    };
+    let dbs = match dbs {
+        Ok(d) => d,
+        Err(e) => {
+            atomic_u64_fetch_add!(done, 1);
+            return Err(Box::from(format!("读取源端的keyspace失败: {}", e)));
+        }
+    };
+    // 一个db失败了继续复制其它的db, 最后汇总失败的db
+    let mut failed_dbs = vec![];
+    for db in dbs {
+        if filter.filter_db(db) {
+            continue;
+        }
+        let scanned = stat.scanned.load(Ordering::Relaxed);
+        match copy_db(source_url, source_pass, target_url, target_pass, db, filter, batch, &stat).await {
+            Ok(()) => {
+                println!("[RUMP] db {} 完成, scanned:{}", db, stat.scanned.load(Ordering::Relaxed) - scanned);
+            }
+            Err(e) => {
+                println!("[RUMP] db {} 失败, scanned:{} error: {}", db, stat.scanned.load(Ordering::Relaxed) - scanned, e);
+                failed_dbs.push(db);
+            }
+        }
+    }
+    atomic_u64_fetch_add!(done, 1);
+    stat.print();
+    if !failed_dbs.is_empty() {
+        return Err(Box::from(format!("db {:?} 复制失败", failed_dbs)));
+    }
+    Ok(())
+}
+
+// SCAN一个db的全部key并复制到目的端
+#[allow(clippy::too_many_arguments)]
+async fn copy_db(
+    source_url: &str,
+    source_pass: &str,
+    target_url: &str,
+    target_pass: &str,
+    db: u32,
+    filter: &Filter,
+    batch: usize,
+    stat: &RumpStat,
+) -> Result<(), Box<dyn Error>> {
+    let index = format!("{}", db);
+    let mut source = open_redis_sync_conn(source_url, source_pass, &index).await?;
+    let mut target = open_redis_sync_conn(target_url, target_pass, &index).await?;
+    let mut cursor: u64 = 0;
+    loop {
+        let (next, keys): (u64, Vec<Vec<u8>>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("COUNT")
+            .arg(batch)
+            .query_async(&mut source)
+            .await?;
+        let keys: Vec<Vec<u8>> = keys.into_iter().filter(|k| !filter.filter_key(k)).collect();
+        copy_keys(&mut source, &mut target, &keys, filter, stat, false).await?;
+        cursor = next;
+        if cursor == 0 {
+            return Ok(());
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RumpStat {
+    pub scanned: AtomicU64,
+    pub restored: AtomicU64,
+    pub rebuilt: AtomicU64,
+    pub skipped: AtomicU64,
+    pub deleted: AtomicU64,
+    pub failed: AtomicU64,
+}
+
+impl RumpStat {
+    pub fn print(&self) {
+        println!(
+            "[RUMP] scanned:{} restored:{} rebuilt:{} skipped:{} deleted:{} failed:{}",
+            self.scanned.load(Ordering::Relaxed),
+            self.restored.load(Ordering::Relaxed),
+            self.rebuilt.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed),
+            self.deleted.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed)
+        );
+    }
+}
+
+// INFO keyspace 中 db0:keys=1,expires=0,avg_ttl=0
+pub async fn keyspace_dbs(conn: &mut Connection) -> Result<Vec<u32>, Box<dyn Error>> {
+    let info: String = redis::cmd("INFO").arg("keyspace").query_async(conn).await?;
+    parse_keyspace(&info)
+}
+
+pub fn parse_keyspace(info: &str) -> Result<Vec<u32>, Box<dyn Error>> {
+    let mut dbs = vec![];
+    for line in info.lines() {
+        if line.starts_with("db") {
+            if let Some(i) = line.find(':') {
+                dbs.push(line[2..i].parse::<u32>()?);
+            }
+        }
+    }
+    Ok(dbs)
+}
+
+// 批量DUMP和PTTL, 然后RESTORE到目的端
+// del_missing为true时,源端已经不存在的key在目的端删除(增量同步)
+pub async fn copy_keys(
+    source: &mut Connection,
+    target: &mut Connection,
+    keys: &[Vec<u8>],
+    filter: &Filter,
+    stat: &RumpStat,
+    del_missing: bool,
+) -> Result<(), Box<dyn Error>> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+    let mut pipe = redis::pipe();
+    for key in keys {
+        pipe.cmd("DUMP").arg(key.as_slice()).cmd("PTTL").arg(key.as_slice());
+    }
+    let values: Vec<Value> = pipe.query_async(source).await?;
+    let mut restores = vec![];
+    let mut missing = redis::pipe();
+    let mut missing_count = 0;
+    for (i, kv) in values.chunks(2).enumerate() {
+        stat.scanned.fetch_add(1, Ordering::Relaxed);
+        let payload = match &kv[0] {
+            Value::Data(d) => d.clone(),
+            // key在SCAN之后被删除了
+            _ => {
+                if del_missing {
+                    missing.cmd("DEL").arg(keys[i].as_slice()).ignore();
+                    missing_count = missing_count + 1;
+                } else {
+                    stat.skipped.fetch_add(1, Ordering::Relaxed);
+                }
+                continue;
+            }
+        };
+        let pttl = match kv.get(1) {
+            Some(Value::Int(d)) => *d,
+            _ => -1,
+        };
+        // payload第一个字节就是rdb的类型
+        if pttl == -2 || filter.filter_type(payload[0]) {
+            stat.skipped.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+        restores.push((&keys[i], if pttl > 0 { pttl } else { 0 }, payload));
+    }
+    if missing_count > 0 {
+        let _: Value = missing.query_async(target).await?;
+        stat.deleted.fetch_add(missing_count, Ordering::Relaxed);
+    }
+    if restores.is_empty() {
+        return Ok(());
+    }
+    let mut pipe = redis::pipe();
+    for (key, ttl, payload) in restores.iter() {
+        pipe.cmd("RESTORE").arg(key.as_slice()).arg(*ttl).arg(payload.as_slice()).arg("REPLACE");
+    }
+    let result: RedisResult<Value> = pipe.query_async(target).await;
+    if result.is_ok() {
+        stat.restored.fetch_add(restores.len() as u64, Ordering::Relaxed);
+        return Ok(());
+    }
+    // 批量中有失败的,逐个重试, 版本不兼容的改成按类型重建
+    for (key, ttl, payload) in restores.iter() {
+        let result: RedisResult<Value> = redis::cmd("RESTORE")
+            .arg(key.as_slice())
+            .arg(*ttl)
+            .arg(payload.as_slice())
+            .arg("REPLACE")
+            .query_async(target)
+            .await;
+        match result {
+            Ok(_) => {
+                stat.restored.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) if is_payload_incompatible(&e.to_string()) => {
+                match rebuild_key(source, target, key, *ttl).await {
+                    Ok(()) => {
+                        stat.rebuilt.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        println!("rebuild key {} error: {}", String::from_utf8_lossy(key), e);
+                        stat.failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            Err(e) => {
+                println!("restore key {} error: {}", String::from_utf8_lossy(key), e);
+                stat.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_payload_incompatible(err: &str) -> bool {
+    err.contains("payload version") || err.contains("Bad data format")
+}
+
+// 按类型读取源端的数据,用普通命令在目的端重建
+pub async fn rebuild_key(
+    source: &mut Connection,
+    target: &mut Connection,
+    key: &[u8],
+    ttl: i64,
+) -> Result<(), Box<dyn Error>> {
+    let key_type: String = redis::cmd("TYPE").arg(key).query_async(source).await?;
+    let _: Value = redis::cmd("DEL").arg(key).query_async(target).await?;
+    match key_type.as_str() {
+        "none" => return Ok(()),
+        "string" => {
+            let value: Vec<u8> = redis::cmd("GET").arg(key).query_async(source).await?;
+            let _: Value = redis::cmd("SET").arg(key).arg(value).query_async(target).await?;
+        }
+        "list" => {
+            let mut start = 0;
+            loop {
+                let items: Vec<Vec<u8>> = redis::cmd("LRANGE")
+                    .arg(key)
+                    .arg(start)
+                    .arg(start + 999)
+                    .query_async(source)
+                    .await?;
+                if items.is_empty() {
+                    break;
+                }
+                start = start + items.len();
+                let _: Value = redis::cmd("RPUSH").arg(key).arg(items).query_async(target).await?;
+            }
+        }
+        "set" | "zset" | "hash" => {
+            let (scan, write) = match key_type.as_str() {
+                "set" => ("SSCAN", "SADD"),
+                "zset" => ("ZSCAN", "ZADD"),
+                // 多个field的HSET要4.0以上, 目的端可能是更老的版本
+                _ => ("HSCAN", "HMSET"),
+            };
+            let mut cursor: u64 = 0;
+            loop {
+                let (next, items): (u64, Vec<Vec<u8>>) = redis::cmd(scan)
+                    .arg(key)
+                    .arg(cursor)
+                    .arg("COUNT")
+                    .arg(1000)
+                    .query_async(source)
+                    .await?;
+                if !items.is_empty() {
+                    let _: Value = rebuild_cmd(write, key, items).query_async(target).await?;
+                }
+                cursor = next;
+                if cursor == 0 {
+                    break;
+                }
+            }
+        }
+        "stream" => {
+            let mut start = String::from("-");
+            loop {
+                let entries: Vec<(String, Vec<Vec<u8>>)> = redis::cmd("XRANGE")
+                    .arg(key)
+                    .arg(start.as_str())
+                    .arg("+")
+                    .arg("COUNT")
+                    .arg(1000)
+                    .query_async(source)
+                    .await?;
+                if entries.is_empty() {
+                    break;
+                }
+                let mut pipe = redis::pipe();
+                for (id, fields) in entries.iter() {
+                    pipe.cmd("XADD").arg(key).arg(id.as_str()).arg(fields.clone()).ignore();
+                }
+                let _: Value = pipe.query_async(target).await?;
+                start = format!("({}", entries[entries.len() - 1].0);
+            }
+        }
+        _ => return Err(Box::from(format!("不支持重建的类型 {}", key_type))),
+    }
+    if ttl > 0 {
+        let _: Value = redis::cmd("PEXPIRE").arg(key).arg(ttl).query_async(target).await?;
+    }
+    Ok(())
+}
+
+// SSCAN/ZSCAN/HSCAN读到的一批元素写入目的端的命令
+pub fn rebuild_cmd(write: &str, key: &[u8], items: Vec<Vec<u8>>) -> Cmd {
+    let mut cmd = redis::cmd(write);
+    cmd.arg(key);
+    if write == "ZADD" {
+        // ZSCAN返回 member score, ZADD需要 score member
+        for ms in items.chunks(2) {
+            cmd.arg(ms[1].as_slice()).arg(ms[0].as_slice());
+        }
+    } else {
+        cmd.arg(items);
+    }
+    cmd
+}
@@ -0,0 +1,161 @@
+use crate::rdb::loader::{rdbFlagEOF, rdbTypeName, BinEntry, Loader};
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::io::{self, Cursor};
+use std::rc::Rc;
+use tokio::io::AsyncRead;
+
+// rdb校验和不一致时的处理
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CrcPolicy {
+    Fail,
+    // 只打印警告
+    Warn,
+}
+
+impl CrcPolicy {
+    pub fn parse(s: &str) -> Result<CrcPolicy, Box<dyn Error>> {
+        match s {
+            "fail" => Ok(CrcPolicy::Fail),
+            "warn" => Ok(CrcPolicy::Warn),
+            _ => Err(Box::from(format!("未知的校验和策略 {}", s))),
+        }
+    }
+}
+
+// rdb解析出错时的处理
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorPolicy {
+    Abort,
+    // 跳过坏数据, 从下一个能解析的opcode继续
+    Salvage,
+}
+
+impl ErrorPolicy {
+    pub fn parse(s: &str) -> Result<ErrorPolicy, Box<dyn Error>> {
+        match s {
+            "abort" => Ok(ErrorPolicy::Abort),
+            "salvage" => Ok(ErrorPolicy::Salvage),
+            _ => Err(Box::from(format!("未知的解析错误策略 {}", s))),
+        }
+    }
+}
+
+// 往后查找时最多看这么多数据, 出错位置之后比它大的key会被跳过
+const SALVAGE_WINDOW: usize = 1024 * 1024;
+
+enum Probe {
+    Match,
+    NeedMore,
+    Mismatch,
+}
+
+// 一个entry之后可以出现的opcode
+fn is_opcode(b: u8) -> bool {
+    b >= 0xf4 || rdbTypeName(b) != "unknown"
+}
+
+// 可以作为继续解析的起点: key的类型, 以及key前面的SELECTDB, 过期时间, LRU/LFU, function
+// AUX之类的只在rdb开头出现, 不作为起点
+fn is_resume_opcode(b: u8) -> bool {
+    match b {
+        0xf5 | 0xf8 | 0xf9 | 0xfc | 0xfd | 0xfe | 0xff => true,
+        _ => rdbTypeName(b) != "unknown",
+    }
+}
+
+fn is_eof(e: &(dyn Error + 'static)) -> bool {
+    e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+// 从data开头能否完整解析出一个entry, 并且后面紧跟着合法的opcode
+async fn probe(data: &[u8], eof: bool) -> Probe {
+    if data[0] == rdbFlagEOF {
+        // EOF后面只有8字节的校验和
+        return match (data.len(), eof) {
+            (9, true) => Probe::Match,
+            (n, false) if n <= 9 => Probe::NeedMore,
+            _ => Probe::Mismatch,
+        };
+    }
+    if !is_resume_opcode(data[0]) {
+        return Probe::Mismatch;
+    }
+    let mut loader = Loader::new(Rc::new(RefCell::new(Cursor::new(data))));
+    let mut e = BinEntry::default();
+    match loader.nextBinEntry(&mut e).await {
+        Ok(()) => match data.get(loader.rdbReader.nread as usize) {
+            Some(b) if is_opcode(*b) => Probe::Match,
+            Some(_) => Probe::Mismatch,
+            None if eof => Probe::Match,
+            None => Probe::NeedMore,
+        },
+        Err(err) if is_eof(err.as_ref()) => Probe::NeedMore,
+        Err(_) => Probe::Mismatch,
+    }
+}
+
+impl<R: AsyncRead + Unpin> Loader<R> {
+    // 从出错的entry开始位置的下一个字节往后查找, 找到后把多读的数据退回给rdbReader
+    // 一直到数据末尾都没有找到时返回RDB END
+    pub(crate) async fn salvage(&mut self, start: i64, journal: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        let r = &mut self.rdbReader;
+        r.remainMember = 0;
+        r.is_cache_buf = false;
+        r.buf.clear();
+        r.fieldExpires.clear();
+        // window[0]在数据中的offset
+        let mut offset = start;
+        let mut window = vec![];
+        if !journal.is_empty() {
+            offset = start + 1;
+            window.extend_from_slice(&journal[1..]);
+        }
+        window.extend_from_slice(&r.replay[r.replayPos..]);
+        r.replay.clear();
+        r.replayPos = 0;
+        let mut eof = false;
+        let mut i = 0;
+        loop {
+            if i >= SALVAGE_WINDOW {
+                window.drain(..i);
+                offset = offset + i as i64;
+                i = 0;
+            }
+            let result = if i < window.len() {
+                probe(&window[i..], eof).await
+            } else {
+                Probe::NeedMore
+            };
+            match result {
+                Probe::Match => break,
+                Probe::NeedMore if !eof && window.len() - i < SALVAGE_WINDOW => {
+                    let mut p = vec![0; 64 * 1024];
+                    let n = r.readRaw(&mut p).await.unwrap_or(0);
+                    if n == 0 {
+                        eof = true;
+                    }
+                    window.extend_from_slice(&p[..n]);
+                }
+                _ if i >= window.len() => {
+                    let skipped = offset + i as i64 - start;
+                    self.skippedBytes = self.skippedBytes + skipped as u64;
+                    self.skipCount = self.skipCount + 1;
+                    self.truncated = true;
+                    r.nread = offset + i as i64;
+                    println!("[RDB] salvage 到数据末尾也没有找到可以继续的位置, 跳过 {} 字节", skipped);
+                    return Err(Box::from("RDB END"));
+                }
+                _ => i = i + 1,
+            }
+        }
+        let skipped = offset + i as i64 - start;
+        self.skippedBytes = self.skippedBytes + skipped as u64;
+        self.skipCount = self.skipCount + 1;
+        r.nread = offset + i as i64;
+        r.replay = window.split_off(i);
+        println!("[RDB] salvage 跳过 {} 字节, 从offset {} 继续", skipped, r.nread);
+        Ok(())
+    }
+}
@@ -11,18 +11,14 @@ impl sliceBuffer {
     pub fn new(s: Vec<u8>) -> Self {
         sliceBuffer { s, i: 0 }
     }
+    // n来自数据本身, 负数或者超出范围都是坏数据
     pub fn Slice(&mut self, n: i32) -> Result<Vec<u8>, Box<dyn Error>> {
-        if (self.i + n) > self.s.len() as i32 {
+        if n < 0 || self.i as i64 + n as i64 > self.s.len() as i64 {
             return Err(Box::from("io error"));
         };
-        let mut index = self.i as usize;
-        let mut rsl = vec![];
-        while index < (self.i + n) as usize {
-            rsl.push(*self.s.get(index).unwrap());
-            index = index + 1;
-        }
+        let start = self.i as usize;
         self.i = self.i + n;
-        Ok(rsl)
+        Ok(self.s[start..self.i as usize].to_vec())
     }
     pub fn ReadByte(&mut self) -> Result<u8, Box<dyn Error>> {
         if self.i >= self.s.len() as i32 {
@@ -32,35 +28,32 @@ impl sliceBuffer {
         self.i = self.i + 1;
         Ok(rsl)
     }
-    pub fn Read(&mut self, p: &mut Vec<u8>) -> Result<usize, Box<dyn Error>> {
-        if p.len() == 0 {
+    pub fn Read(&mut self, p: &mut [u8]) -> Result<usize, Box<dyn Error>> {
+        if p.is_empty() {
             return Err(Box::from("nil read"));
         }
         if self.i >= self.s.len() as i32 {
             return Err(Box::from("io error"));
         };
-        let mut index = 0usize;
-        while index < p.len() {
-            let p = p.get_mut(index).unwrap();
-            *p = *self.s.get(index + self.i as usize).unwrap();
-            index = index + 1;
-        }
-        Ok(index)
+        let start = self.i as usize;
+        let n = std::cmp::min(p.len(), self.s.len() - start);
+        p[..n].copy_from_slice(&self.s[start..start + n]);
+        self.i = self.i + n as i32;
+        Ok(n)
     }
     pub fn Seek(&mut self, offset: i64, whence: i32) -> Result<i64, Box<dyn Error>> {
-        let mut abs = 0;
-        match whence {
-            0 => {
-                abs = offset;
-            }
-            1 => {
-                abs = (self.i + offset as i32) as i64;
-            }
-            2 => abs = (self.s.len() + offset as usize) as i64,
+        let abs = match whence {
+            0 => Some(offset),
+            1 => (self.i as i64).checked_add(offset),
+            2 => (self.s.len() as i64).checked_add(offset),
             _ => {
                 return Err(Box::from("invalid whence"));
             }
         };
+        let abs = match abs {
+            Some(d) => d,
+            None => return Err(Box::from("position out of range")),
+        };
         if abs < 0 {
             return Err(Box::from("negative position"));
         }
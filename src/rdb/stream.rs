@@ -0,0 +1,107 @@
+use crate::rdb::loader::BinEntry;
+use crate::rdb::value::{Item, StreamID, ValueIter};
+use crate::utils::version::TargetVersion;
+use crate::utils::memory::CmdSender;
+
+use std::collections::HashMap;
+use std::error::Error;
+
+// 把stream拆成命令重建: XADD每个entry, XSETID, XGROUP CREATE, 然后用XCLAIM恢复PEL
+// 用于大key以及目的端不能RESTORE的情况
+// ENTRIESADDED, MAXDELETEDID和ENTRIESREAD需要目的端为7.0+(rdb version 10)
+// PEL里已经被删除的entry XCLAIM会忽略, 这部分无法恢复
+// 目的端低于6.2时没有XGROUP CREATECONSUMER, 只能靠XCLAIM创建consumer,
+// 没有pending entry的consumer无法恢复
+// 拆分成多段时第一段删除旧的值, 元数据在最后一段
+pub async fn over_restore_stream_entry(
+    e: &BinEntry,
+    full_cmd_sender: &mut CmdSender,
+    target: TargetVersion,
+) -> Result<(), Box<dyn Error>> {
+    let key = e.Key.clone();
+    let mut it = ValueIter::new(e).await?;
+    if e.IsFirstChunk() {
+        full_cmd_sender.send(redis::cmd("DEL").arg(key.clone()).to_owned()).await?;
+    }
+    let mut count = 0u64;
+    while let Some(item) = it.next_item().await? {
+        match item {
+            Item::StreamEntry(entry) => {
+                let mut cmd = redis::cmd("XADD");
+                cmd.arg(key.clone()).arg(entry.id.to_string());
+                for (field, value) in entry.fields {
+                    cmd.arg(field).arg(value);
+                }
+                full_cmd_sender.send(cmd).await?;
+                count = count + 1;
+            }
+            Item::StreamMeta(meta) => {
+                if count == 0 && e.IsFirstChunk() {
+                    // XSETID需要stream存在, 空的stream先添加再裁剪掉
+                    full_cmd_sender.send(
+                        redis::cmd("XADD").arg(key.clone()).arg("MAXLEN").arg(0).arg("0-1").arg("x").arg("x").to_owned()
+                    ).await?;
+                }
+                // 从来没有添加过entry的stream, last_id是0-0, 不能XSETID到比0-1小的id
+                let set_id = count != 0 || meta.last_id != StreamID::default();
+                let mut xsetid = redis::cmd("XSETID");
+                xsetid.arg(key.clone()).arg(meta.last_id.to_string());
+                if let (Some(entries_added), Some(max_deleted_id)) = (meta.entries_added, meta.max_deleted_id) {
+                    if target.rdb >= 10 {
+                        xsetid.arg("ENTRIESADDED").arg(entries_added).arg("MAXDELETEDID").arg(max_deleted_id.to_string());
+                    }
+                }
+                if set_id {
+                    full_cmd_sender.send(xsetid).await?;
+                }
+                for group in meta.groups.iter() {
+                    let mut create = redis::cmd("XGROUP");
+                    create.arg("CREATE").arg(key.clone()).arg(group.name.clone()).arg(group.last_id.to_string());
+                    if let Some(entries_read) = group.entries_read {
+                        if target.rdb >= 10 {
+                            create.arg("ENTRIESREAD").arg(entries_read);
+                        }
+                    }
+                    full_cmd_sender.send(create).await?;
+                    let pel: HashMap<_, _> = group.pending.iter().map(|p| (p.id, (p.delivery_time, p.delivery_count))).collect();
+                    for consumer in group.consumers.iter() {
+                        // 有pending entry的consumer由XCLAIM创建
+                        if consumer.pending.is_empty() && target.stream_create_consumer {
+                            full_cmd_sender.send(
+                                redis::cmd("XGROUP").arg("CREATECONSUMER").arg(key.clone()).arg(group.name.clone()).arg(consumer.name.clone()).to_owned()
+                            ).await?;
+                        } else if consumer.pending.is_empty() {
+                            println!(
+                                "stream key {} group {} consumer {} 没有pending entry, 目的端不支持XGROUP CREATECONSUMER, 无法恢复",
+                                String::from_utf8_lossy(&key),
+                                String::from_utf8_lossy(&group.name),
+                                String::from_utf8_lossy(&consumer.name)
+                            );
+                        }
+                        for id in consumer.pending.iter() {
+                            let (delivery_time, delivery_count) = pel.get(id).cloned().unwrap_or((0, 1));
+                            full_cmd_sender.send(
+                                redis::cmd("XCLAIM").arg(key.clone()).arg(group.name.clone()).arg(consumer.name.clone()).arg(0).arg(id.to_string())
+                                    .arg("TIME").arg(delivery_time)
+                                    .arg("RETRYCOUNT").arg(delivery_count)
+                                    .arg("FORCE").arg("JUSTID")
+                                    .to_owned()
+                            ).await?;
+                        }
+                    }
+                }
+                println!(
+                    "restore stream key {} entries {} groups {}",
+                    String::from_utf8_lossy(&key),
+                    count,
+                    meta.groups.len()
+                );
+            }
+            _ => return Err(Box::from("stream中出现了非stream的元素")),
+        }
+    }
+    if e.ExpireAt != 0 {
+        full_cmd_sender.send(redis::cmd("PEXPIREAT").arg(key.clone()).arg(e.ExpireAt).to_owned()).await?;
+    }
+    Ok(())
+}
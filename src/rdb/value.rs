@@ -0,0 +1,568 @@
+use crate::rdb::loader;
+use crate::rdb::loader::{rdbModuleName, rdbReader, rdbTypeName, BinEntry, Loader};
+use crate::rdb::slice_buffer::sliceBuffer;
+use crate::rdb::writer::dump_payload;
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io::Cursor;
+use tokio::io::AsyncRead;
+
+// 一个key解码后的值
+#[derive(Clone, Debug, PartialEq)]
+pub enum RedisValue {
+    String(Vec<u8>),
+    List(Vec<Vec<u8>>),
+    Set(Vec<Vec<u8>>),
+    // member, score
+    ZSet(Vec<(Vec<u8>, f64)>),
+    // field, value, 过期时间(毫秒时间戳, 0表示没有)
+    Hash(Vec<(Vec<u8>, Vec<u8>, u64)>),
+    Stream(StreamValue),
+    Module(ModuleValue),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StreamID {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamID {
+    // rdb中PEL的id是16字节大端
+    pub fn from_raw(raw: &[u8]) -> StreamID {
+        let mut ms = [0u8; 8];
+        let mut seq = [0u8; 8];
+        ms.copy_from_slice(&raw[..8]);
+        seq.copy_from_slice(&raw[8..16]);
+        StreamID {
+            ms: u64::from_be_bytes(ms),
+            seq: u64::from_be_bytes(seq),
+        }
+    }
+}
+
+impl fmt::Display for StreamID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamValue {
+    pub entries: Vec<StreamEntry>,
+    pub meta: StreamMeta,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamEntry {
+    pub id: StreamID,
+    pub fields: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+// entries之后的信息, first_id, max_deleted_id, entries_added 7.0(RDBTypeStreamListPacks2)开始才有
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamMeta {
+    pub length: u64,
+    pub last_id: StreamID,
+    pub first_id: Option<StreamID>,
+    pub max_deleted_id: Option<StreamID>,
+    pub entries_added: Option<u64>,
+    pub groups: Vec<StreamGroup>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamGroup {
+    pub name: Vec<u8>,
+    pub last_id: StreamID,
+    // -1 表示无效
+    pub entries_read: Option<i64>,
+    pub pending: Vec<StreamPending>,
+    pub consumers: Vec<StreamConsumer>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamPending {
+    pub id: StreamID,
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamConsumer {
+    pub name: Vec<u8>,
+    pub seen_time: u64,
+    pub active_time: Option<u64>,
+    pub pending: Vec<StreamID>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleValue {
+    // module类型名, 例如 ReJSON-RL
+    pub name: String,
+    pub version: u64,
+    // 类型之后的原始数据, 包括module id
+    pub raw: Vec<u8>,
+}
+
+// 集合类型的一个元素, 大key按元素逐个读取
+#[derive(Clone, Debug, PartialEq)]
+pub enum Item {
+    String(Vec<u8>),
+    // list或者set的元素
+    Member(Vec<u8>),
+    // zset的 member, score
+    Scored(Vec<u8>, f64),
+    // hash的 field, value, 过期时间
+    Field(Vec<u8>, Vec<u8>, u64),
+    StreamEntry(StreamEntry),
+    // stream的最后一个元素
+    StreamMeta(StreamMeta),
+    Module(ModuleValue),
+}
+
+// 按元素读取BinEntry的value dump
+// 每次解码一个单元(一个元素, 或者一个ziplist/listpack/quicklist节点), 不会一次展开整个value
+pub struct ValueIter {
+    r: rdbReader<Cursor<Vec<u8>>>,
+    t: u8,
+    // 剩余的单元数
+    remain: u64,
+    pending: VecDeque<Item>,
+    // hash metadata的过期时间基准
+    min_expire: u64,
+    stream_done: bool,
+}
+
+impl ValueIter {
+    pub async fn new(e: &BinEntry) -> Result<ValueIter, Box<dyn Error>> {
+        let mut r = rdbReader::fromBytes(e.Value.clone());
+        let t = r.ReadByte().await?;
+        let mut it = ValueIter {
+            r,
+            t,
+            remain: 1,
+            pending: VecDeque::new(),
+            min_expire: 0,
+            stream_done: false,
+        };
+        match t {
+            loader::RdbTypeString
+            | loader::RdbTypeHashZipmap
+            | loader::RdbTypeListZiplist
+            | loader::RdbTypeSetIntset
+            | loader::RdbTypeZSetZiplist
+            | loader::RdbTypeHashZiplist
+            | loader::RdbTypeHashListpack
+            | loader::RdbTypeZSetListpack
+            | loader::RdbTypeSetListpack
+            | loader::RdbTypeHashListpackExPreGa => {}
+            loader::RdbTypeHashMetadataPreGa => {
+                it.remain = it.r.ReadLength64().await?;
+            }
+            loader::RdbTypeList
+            | loader::RdbTypeSet
+            | loader::RdbTypeZSet
+            | loader::RdbTypeZSet2
+            | loader::RdbTypeQuicklist
+            | loader::RdbTypeQuicklist2
+            | loader::RdbTypeHash
+            | loader::RDBTypeStreamListPacks
+            | loader::RDBTypeStreamListPacks2
+            | loader::RDBTypeStreamListPacks3 => {
+                // 拆分的大key只有第一段有长度, stream的元数据只在最后一段
+                let mut n = e.RealMemberCount as u64;
+                if e.IsFirstChunk() {
+                    let rlen = it.r.ReadLength64().await?;
+                    if n == 0 {
+                        n = rlen;
+                    }
+                }
+                it.remain = n;
+            }
+            loader::RdbTypeHashMetadata => {
+                it.min_expire = it.r.readUint64().await?;
+                it.remain = it.r.ReadLength64().await?;
+            }
+            loader::RdbTypeHashListpackEx => {
+                it.min_expire = it.r.readUint64().await?;
+            }
+            loader::RdbTypeModule2 => {
+                let module_id = it.r.ReadLength64().await?;
+                let (name, version) = rdbModuleName(module_id);
+                it.pending.push_back(Item::Module(ModuleValue {
+                    name,
+                    version,
+                    raw: dump_payload(&e.Value).to_vec(),
+                }));
+                it.remain = 0;
+            }
+            _ => return Err(Box::from(format!("unknown object-type {}", t))),
+        }
+        Ok(it)
+    }
+    pub fn rdb_type(&self) -> u8 {
+        self.t
+    }
+    pub async fn next_item(&mut self) -> Result<Option<Item>, Box<dyn Error>> {
+        while self.pending.is_empty() {
+            if self.remain == 0 {
+                if loader::rdbIsStream(self.t) && !self.stream_done && self.has_meta() {
+                    self.stream_done = true;
+                    let meta = self.read_stream_meta().await?;
+                    return Ok(Some(Item::StreamMeta(meta)));
+                }
+                return Ok(None);
+            }
+            self.remain = self.remain - 1;
+            self.fill().await?;
+        }
+        Ok(self.pending.pop_front())
+    }
+    // 除了DUMP结尾的版本号和crc还有数据, 说明是stream的最后一段(或者没有拆分)
+    fn has_meta(&self) -> bool {
+        let raw = self.r.raw.borrow();
+        raw.position() as usize + 10 < raw.get_ref().len()
+    }
+    // 解码一个单元放到pending
+    async fn fill(&mut self) -> Result<(), Box<dyn Error>> {
+        let r = &mut self.r;
+        match self.t {
+            loader::RdbTypeString => {
+                self.pending.push_back(Item::String(r.ReadString().await?));
+            }
+            loader::RdbTypeList | loader::RdbTypeSet => {
+                self.pending.push_back(Item::Member(r.ReadString().await?));
+            }
+            loader::RdbTypeZSet | loader::RdbTypeZSet2 => {
+                let member = r.ReadString().await?;
+                let score = if self.t == loader::RdbTypeZSet2 {
+                    r.ReadDouble().await?
+                } else {
+                    r.ReadFloat().await?
+                };
+                self.pending.push_back(Item::Scored(member, score));
+            }
+            loader::RdbTypeHash => {
+                let field = r.ReadString().await?;
+                let value = r.ReadString().await?;
+                self.pending.push_back(Item::Field(field, value, 0));
+            }
+            loader::RdbTypeHashMetadataPreGa => {
+                // 和GA一样ttl在field前面, 保存的是绝对时间
+                let ttl = r.ReadLength64().await?;
+                let field = r.ReadString().await?;
+                let value = r.ReadString().await?;
+                self.pending.push_back(Item::Field(field, value, ttl));
+            }
+            loader::RdbTypeHashMetadata => {
+                // 保存的是相对min_expire的值, 0表示没有过期时间
+                let mut ttl = r.ReadLength64().await?;
+                if ttl != 0 {
+                    ttl = ttl + self.min_expire - 1;
+                }
+                let field = r.ReadString().await?;
+                let value = r.ReadString().await?;
+                self.pending.push_back(Item::Field(field, value, ttl));
+            }
+            loader::RdbTypeHashZipmap => {
+                let mut buf = sliceBuffer::new(r.ReadString().await?);
+                // zmlen
+                buf.ReadByte()?;
+                loop {
+                    let (length, _) = r.readZipmapItemLength(&mut buf, false).await?;
+                    if length == -1 {
+                        break;
+                    }
+                    let field = buf.Slice(length)?;
+                    let value = r.ReadZipmapItem(&mut buf, true).await?;
+                    self.pending.push_back(Item::Field(field, value, 0));
+                }
+            }
+            loader::RdbTypeListZiplist | loader::RdbTypeQuicklist => {
+                let mut buf = sliceBuffer::new(r.ReadString().await?);
+                let n = r.ReadZiplistLength(&mut buf).await?;
+                for _ in 0..n {
+                    self.pending.push_back(Item::Member(r.ReadZiplistEntry(&mut buf).await?));
+                }
+            }
+            loader::RdbTypeZSetZiplist => {
+                let mut buf = sliceBuffer::new(r.ReadString().await?);
+                let n = r.ReadZiplistLength(&mut buf).await? / 2;
+                for _ in 0..n {
+                    let member = r.ReadZiplistEntry(&mut buf).await?;
+                    let score = String::from_utf8_lossy(&r.ReadZiplistEntry(&mut buf).await?).parse::<f64>()?;
+                    self.pending.push_back(Item::Scored(member, score));
+                }
+            }
+            loader::RdbTypeHashZiplist => {
+                let mut buf = sliceBuffer::new(r.ReadString().await?);
+                let n = r.ReadZiplistLength(&mut buf).await? / 2;
+                for _ in 0..n {
+                    let field = r.ReadZiplistEntry(&mut buf).await?;
+                    let value = r.ReadZiplistEntry(&mut buf).await?;
+                    self.pending.push_back(Item::Field(field, value, 0));
+                }
+            }
+            loader::RdbTypeSetIntset => {
+                let mut buf = sliceBuffer::new(r.ReadString().await?);
+                for v in r.ReadIntset(&mut buf).await? {
+                    self.pending.push_back(Item::Member(format!("{}", v).into_bytes()));
+                }
+            }
+            loader::RdbTypeHashListpack => {
+                let mut buf = sliceBuffer::new(r.ReadString().await?);
+                let n = r.ReadListpackLength(&mut buf).await? / 2;
+                for _ in 0..n {
+                    let field = r.ReadListpackEntry(&mut buf).await?;
+                    let value = r.ReadListpackEntry(&mut buf).await?;
+                    self.pending.push_back(Item::Field(field, value, 0));
+                }
+            }
+            loader::RdbTypeZSetListpack => {
+                let mut buf = sliceBuffer::new(r.ReadString().await?);
+                let n = r.ReadListpackLength(&mut buf).await? / 2;
+                for _ in 0..n {
+                    let member = r.ReadListpackEntry(&mut buf).await?;
+                    let score = String::from_utf8_lossy(&r.ReadListpackEntry(&mut buf).await?).parse::<f64>()?;
+                    self.pending.push_back(Item::Scored(member, score));
+                }
+            }
+            loader::RdbTypeSetListpack => {
+                let mut buf = sliceBuffer::new(r.ReadString().await?);
+                let n = r.ReadListpackLength(&mut buf).await?;
+                for _ in 0..n {
+                    self.pending.push_back(Item::Member(r.ReadListpackEntry(&mut buf).await?));
+                }
+            }
+            loader::RdbTypeHashListpackExPreGa | loader::RdbTypeHashListpackEx => {
+                // field value ttl 三个一组, ttl为0表示没有过期时间
+                let mut buf = sliceBuffer::new(r.ReadString().await?);
+                let n = r.ReadListpackLength(&mut buf).await? / 3;
+                for _ in 0..n {
+                    let field = r.ReadListpackEntry(&mut buf).await?;
+                    let value = r.ReadListpackEntry(&mut buf).await?;
+                    let ttl = String::from_utf8_lossy(&r.ReadListpackEntry(&mut buf).await?).parse::<u64>()?;
+                    self.pending.push_back(Item::Field(field, value, ttl));
+                }
+            }
+            loader::RdbTypeQuicklist2 => {
+                let container = r.ReadLength64().await?;
+                let data = r.ReadString().await?;
+                if container == loader::quicklistNodeContainerPlain as u64 {
+                    self.pending.push_back(Item::Member(data));
+                } else {
+                    let mut buf = sliceBuffer::new(data);
+                    let n = r.ReadListpackLength(&mut buf).await?;
+                    for _ in 0..n {
+                        self.pending.push_back(Item::Member(r.ReadListpackEntry(&mut buf).await?));
+                    }
+                }
+            }
+            loader::RDBTypeStreamListPacks | loader::RDBTypeStreamListPacks2 | loader::RDBTypeStreamListPacks3 => {
+                self.fill_stream_node().await?;
+            }
+            _ => return Err(Box::from(format!("unknown object-type {}", self.t))),
+        }
+        Ok(())
+    }
+    // 一个listpack节点: master entry(count, deleted, 字段数, 字段, 0), 然后是每个entry
+    // entry: flags, ms-diff, seq-diff, [字段数], 字段和值, lp-count
+    async fn fill_stream_node(&mut self) -> Result<(), Box<dyn Error>> {
+        let r = &mut self.r;
+        let master = r.ReadString().await?;
+        if master.len() != 16 {
+            return Err(Box::from(format!("stream node key length {} != 16", master.len())));
+        }
+        let master_id = StreamID::from_raw(&master);
+        let mut buf = sliceBuffer::new(r.ReadString().await?);
+        // 跳过listpack的总长度和元素个数
+        buf.Seek(6, 0)?;
+        read_listpack_int(r, &mut buf).await?;
+        read_listpack_int(r, &mut buf).await?;
+        let n_master_fields = read_listpack_int(r, &mut buf).await?;
+        let mut master_fields = vec![];
+        for _ in 0..n_master_fields {
+            master_fields.push(r.ReadListpackEntry(&mut buf).await?);
+        }
+        r.ReadListpackEntry(&mut buf).await?;
+        while buf.s.get(buf.i as usize).is_some_and(|b| *b != loader::lpEOF) {
+            let flags = read_listpack_int(r, &mut buf).await?;
+            let id = StreamID {
+                ms: master_id.ms.wrapping_add(read_listpack_int(r, &mut buf).await? as u64),
+                seq: master_id.seq.wrapping_add(read_listpack_int(r, &mut buf).await? as u64),
+            };
+            let mut fields = vec![];
+            if flags & STREAM_ITEM_FLAG_SAME_FIELDS != 0 {
+                for field in master_fields.iter() {
+                    fields.push((field.clone(), r.ReadListpackEntry(&mut buf).await?));
+                }
+            } else {
+                let n_fields = read_listpack_int(r, &mut buf).await?;
+                for _ in 0..n_fields {
+                    let field = r.ReadListpackEntry(&mut buf).await?;
+                    let value = r.ReadListpackEntry(&mut buf).await?;
+                    fields.push((field, value));
+                }
+            }
+            // lp-count
+            r.ReadListpackEntry(&mut buf).await?;
+            if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+                self.pending.push_back(Item::StreamEntry(StreamEntry { id, fields }));
+            }
+        }
+        Ok(())
+    }
+    async fn read_stream_meta(&mut self) -> Result<StreamMeta, Box<dyn Error>> {
+        let t = self.t;
+        let r = &mut self.r;
+        let mut meta = StreamMeta {
+            length: r.ReadLength64().await?,
+            last_id: read_stream_id(r).await?,
+            ..Default::default()
+        };
+        if t != loader::RDBTypeStreamListPacks {
+            meta.first_id = Some(read_stream_id(r).await?);
+            meta.max_deleted_id = Some(read_stream_id(r).await?);
+            meta.entries_added = Some(r.ReadLength64().await?);
+        }
+        let n_cgroups = r.ReadLength64().await?;
+        for _ in 0..n_cgroups {
+            let mut group = StreamGroup {
+                name: r.ReadString().await?,
+                last_id: read_stream_id(r).await?,
+                ..Default::default()
+            };
+            if t != loader::RDBTypeStreamListPacks {
+                group.entries_read = Some(r.ReadLength64().await? as i64);
+            }
+            let n_pending = r.ReadLength64().await?;
+            for _ in 0..n_pending {
+                let id = StreamID::from_raw(&r.ReadBytes(16).await?);
+                let delivery_time = r.readUint64().await?;
+                let delivery_count = r.ReadLength64().await?;
+                group.pending.push(StreamPending {
+                    id,
+                    delivery_time,
+                    delivery_count,
+                });
+            }
+            let n_consumers = r.ReadLength64().await?;
+            for _ in 0..n_consumers {
+                let mut consumer = StreamConsumer {
+                    name: r.ReadString().await?,
+                    seen_time: r.readUint64().await?,
+                    ..Default::default()
+                };
+                if t == loader::RDBTypeStreamListPacks3 {
+                    consumer.active_time = Some(r.readUint64().await?);
+                }
+                let n_pending = r.ReadLength64().await?;
+                for _ in 0..n_pending {
+                    consumer.pending.push(StreamID::from_raw(&r.ReadBytes(16).await?));
+                }
+                group.consumers.push(consumer);
+            }
+            meta.groups.push(group);
+        }
+        Ok(meta)
+    }
+}
+
+// listpack中每个stream entry的flags
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAME_FIELDS: i64 = 2;
+
+async fn read_stream_id<R: AsyncRead + Unpin>(r: &mut rdbReader<R>) -> Result<StreamID, Box<dyn Error>> {
+    let ms = r.ReadLength64().await?;
+    let seq = r.ReadLength64().await?;
+    Ok(StreamID { ms, seq })
+}
+
+async fn read_listpack_int<R: AsyncRead + Unpin>(r: &mut rdbReader<R>, buf: &mut sliceBuffer) -> Result<i64, Box<dyn Error>> {
+    let entry = r.ReadListpackEntry(buf).await?;
+    Ok(String::from_utf8_lossy(&entry).parse::<i64>()?)
+}
+
+// 一次解码整个value, 大key用ValueIter
+pub async fn decode_value(e: &BinEntry) -> Result<RedisValue, Box<dyn Error>> {
+    let mut it = ValueIter::new(e).await?;
+    let mut value = match rdbTypeName(it.rdb_type()) {
+        "string" => RedisValue::String(vec![]),
+        "list" => RedisValue::List(vec![]),
+        "set" => RedisValue::Set(vec![]),
+        "zset" => RedisValue::ZSet(vec![]),
+        "hash" => RedisValue::Hash(vec![]),
+        "stream" => RedisValue::Stream(StreamValue::default()),
+        _ => match it.next_item().await? {
+            Some(Item::Module(d)) => return Ok(RedisValue::Module(d)),
+            _ => return Err(Box::from(format!("unknown object-type {}", it.rdb_type()))),
+        },
+    };
+    while let Some(item) = it.next_item().await? {
+        match (&mut value, item) {
+            (RedisValue::String(v), Item::String(d)) => *v = d,
+            (RedisValue::List(v), Item::Member(d)) | (RedisValue::Set(v), Item::Member(d)) => v.push(d),
+            (RedisValue::ZSet(v), Item::Scored(m, s)) => v.push((m, s)),
+            (RedisValue::Hash(v), Item::Field(f, d, ttl)) => v.push((f, d, ttl)),
+            (RedisValue::Stream(v), Item::StreamEntry(d)) => v.entries.push(d),
+            (RedisValue::Stream(v), Item::StreamMeta(d)) => v.meta = d,
+            (_, item) => return Err(Box::from(format!("unexpected item {:?}", item))),
+        }
+    }
+    Ok(value)
+}
+
+// 遍历rdb的回调, 默认什么都不做
+// key返回false表示不需要解码这个key的value
+pub trait Visitor {
+    fn aux(&mut self, _key: &[u8], _value: &[u8]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    fn function(&mut self, _code: &[u8]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    fn key(&mut self, _e: &BinEntry) -> Result<bool, Box<dyn Error>> {
+        Ok(true)
+    }
+    fn item(&mut self, _e: &BinEntry, _item: Item) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    fn end(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+// 从Header之后开始遍历整个rdb, 每个key的元素逐个交给visitor, 最后校验crc
+pub async fn visit<R: AsyncRead + Unpin>(loader: &mut Loader<R>, visitor: &mut dyn Visitor) -> Result<(), Box<dyn Error>> {
+    loop {
+        let mut e = BinEntry::default();
+        match loader.NextBinEntry(&mut e).await {
+            Ok(()) => {
+                if e.Type == loader::RdbFlagAUX {
+                    visitor.aux(&e.Key, &e.Value)?;
+                    continue;
+                }
+                if e.Type == loader::rdbFlagFunction2 {
+                    visitor.function(&e.Value)?;
+                    continue;
+                }
+                if !visitor.key(&e)? {
+                    continue;
+                }
+                let mut it = ValueIter::new(&e).await?;
+                while let Some(item) = it.next_item().await? {
+                    visitor.item(&e, item)?;
+                }
+            }
+            Err(err) => {
+                if err.to_string().eq("RDB END") {
+                    loader.Footer().await?;
+                    return visitor.end();
+                }
+                return Err(err);
+            }
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::rdb::loader::{
+    BinEntry, rdbEncInt16, rdbEncInt32, rdbEncInt8, rdbEncLZF, rdbFlagEOF, rdbFlagExpiryMS, rdbFlagFreq,
+    rdbFlagFunction2, rdbFlagIdle, rdbFlagResizeDB, rdbFlagSelectDB, RdbFlagAUX, RdbTypeString,
+};
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use crate::rdb::crc64::Crc64;
+use crate::rdb::compress::{CompressWriter, Compression};
+
+use std::error::Error;
+use std::io::Write;
+
+// rdb文件的编码器,和Loader是对应的
+pub struct Writer<W: Write> {
+    raw: W,
+    crc64: Crc64,
+    // 字符串超过20字节时尝试lzf压缩,和redis的rdbcompression一致
+    compress: bool,
+    // 当前写入的db,用于write_entry判断是否需要SELECTDB
+    db: Option<u32>,
+    pub nwrite: u64,
+}
+
+impl Writer<CompressWriter> {
+    // 创建rdb文件并写入头部, compression是整个文件的压缩格式, compress是字符串的lzf压缩
+    pub fn create(path: &str, version: i32, compress: bool, compression: Compression) -> Result<Self, Box<dyn Error>> {
+        let mut writer = Writer::new(CompressWriter::create(path, compression)?, compress);
+        writer.header(version)?;
+        writer.write_aux(b"redis-bits", b"64")?;
+        writer.write_aux(b"ctime", format!("{}", time::OffsetDateTime::now_utc().timestamp()).as_bytes())?;
+        Ok(writer)
+    }
+    // 写入结尾后调用, 写完压缩格式的结尾
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        self.raw.finish()
+    }
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(raw: W, compress: bool) -> Writer<W> {
+        Writer {
+            raw,
+            crc64: Crc64::new(),
+            compress,
+            db: None,
+            nwrite: 0,
+        }
+    }
+    fn write(&mut self, p: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.raw.write_all(p)?;
+        self.crc64.write_all(p)?;
+        self.nwrite = self.nwrite + p.len() as u64;
+        Ok(())
+    }
+    pub fn header(&mut self, version: i32) -> Result<(), Box<dyn Error>> {
+        self.write(format!("REDIS{:04}", version).as_bytes())
+    }
+    pub fn write_aux(&mut self, key: &[u8], value: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.write(&[RdbFlagAUX])?;
+        self.write_string(key)?;
+        self.write_string(value)
+    }
+    pub fn write_function(&mut self, code: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.write(&[rdbFlagFunction2])?;
+        self.write_string(code)
+    }
+    pub fn select_db(&mut self, db: u32) -> Result<(), Box<dyn Error>> {
+        self.write(&[rdbFlagSelectDB])?;
+        self.write_length(db as u64)
+    }
+    pub fn resize_db(&mut self, db_size: u64, expire_size: u64) -> Result<(), Box<dyn Error>> {
+        self.write(&[rdbFlagResizeDB])?;
+        self.write_length(db_size)?;
+        self.write_length(expire_size)
+    }
+    pub fn write_expiry_ms(&mut self, ms: u64) -> Result<(), Box<dyn Error>> {
+        let mut p = vec![rdbFlagExpiryMS];
+        p.write_u64::<LittleEndian>(ms)?;
+        self.write(&p)
+    }
+    pub fn write_idle(&mut self, idle: u32) -> Result<(), Box<dyn Error>> {
+        self.write(&[rdbFlagIdle])?;
+        self.write_length(idle as u64)
+    }
+    pub fn write_freq(&mut self, freq: u8) -> Result<(), Box<dyn Error>> {
+        self.write(&[rdbFlagFreq, freq])
+    }
+    // 写入一个完整的key,raw是readObjectValue读到的原始value(不带类型)
+    pub fn write_object(&mut self, t: u8, key: &[u8], raw: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.write(&[t])?;
+        self.write_string(key)?;
+        self.write(raw)
+    }
+    // 被拆分的大key,后续的成员直接追加在后面
+    pub fn write_raw(&mut self, raw: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.write(raw)
+    }
+    // 写入NextBinEntry读到的一个entry,包括被拆分的大key的后续部分
+    pub fn write_entry(&mut self, e: &BinEntry) -> Result<(), Box<dyn Error>> {
+        if e.NeedReadLen != 1 {
+            return self.write_raw(dump_payload(&e.Value));
+        }
+        if self.db != Some(e.DB) {
+            self.db = Some(e.DB);
+            self.select_db(e.DB)?;
+        }
+        if e.ExpireAt != 0 {
+            self.write_expiry_ms(e.ExpireAt)?;
+        }
+        if e.IdleTime != 0 {
+            self.write_idle(e.IdleTime)?;
+        }
+        if e.Freq != 0 {
+            self.write_freq(e.Freq)?;
+        }
+        self.write_object(e.Type, &e.Key, dump_payload(&e.Value))
+    }
+    pub fn write_string_object(&mut self, key: &[u8], value: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.write(&[RdbTypeString])?;
+        self.write_string(key)?;
+        self.write_string(value)
+    }
+    pub fn write_length(&mut self, length: u64) -> Result<(), Box<dyn Error>> {
+        let mut p = vec![];
+        if length < 1 << 6 {
+            p.push(length as u8);
+        } else if length < 1 << 14 {
+            p.push((length >> 8) as u8 | 0x40);
+            p.push(length as u8);
+        } else if length <= u32::MAX as u64 {
+            p.push(0x80);
+            p.write_u32::<BigEndian>(length as u32)?;
+        } else {
+            p.push(0x81);
+            p.write_u64::<BigEndian>(length)?;
+        }
+        self.write(&p)
+    }
+    pub fn write_string(&mut self, s: &[u8]) -> Result<(), Box<dyn Error>> {
+        // 和redis一样,能用整数编码的优先用整数编码
+        if s.len() <= 11 {
+            if let Some(p) = encode_integer(s) {
+                return self.write(&p);
+            }
+        }
+        if self.compress && s.len() > 20 {
+            let compressed = lzf_compress(s);
+            // 压缩后没有变小就按原样保存
+            if compressed.len() + 4 < s.len() {
+                self.write(&[0xc0 | rdbEncLZF])?;
+                self.write_length(compressed.len() as u64)?;
+                self.write_length(s.len() as u64)?;
+                return self.write(&compressed);
+            }
+        }
+        self.write_length(s.len() as u64)?;
+        self.write(s)
+    }
+    pub fn footer(&mut self) -> Result<(), Box<dyn Error>> {
+        self.write(&[rdbFlagEOF])?;
+        let mut p = vec![];
+        p.write_u64::<LittleEndian>(self.crc64.get())?;
+        self.raw.write_all(&p)?;
+        self.nwrite = self.nwrite + p.len() as u64;
+        self.raw.flush()?;
+        Ok(())
+    }
+}
+
+// 只有转换回来完全一致的整数才能编码,例如"007"不行
+fn encode_integer(s: &[u8]) -> Option<Vec<u8>> {
+    let v = std::str::from_utf8(s).ok()?.parse::<i64>().ok()?;
+    if format!("{}", v).as_bytes() != s {
+        return None;
+    }
+    let mut p = vec![];
+    if v >= i8::MIN as i64 && v <= i8::MAX as i64 {
+        p.push(0xc0 | rdbEncInt8);
+        p.push(v as i8 as u8);
+    } else if v >= i16::MIN as i64 && v <= i16::MAX as i64 {
+        p.push(0xc0 | rdbEncInt16);
+        p.write_i16::<LittleEndian>(v as i16).ok()?;
+    } else if v >= i32::MIN as i64 && v <= i32::MAX as i64 {
+        p.push(0xc0 | rdbEncInt32);
+        p.write_i32::<LittleEndian>(v as i32).ok()?;
+    } else {
+        return None;
+    }
+    Some(p)
+}
+
+// 从createValueDump生成的dump中取出原始的value
+pub fn dump_payload(dump: &[u8]) -> &[u8] {
+    if dump.len() < 11 {
+        return &[];
+    }
+    &dump[1..dump.len() - 10]
+}
+
+const LZF_HASH_LOG: usize = 14;
+const LZF_MAX_LIT: usize = 32;
+const LZF_MAX_OFF: usize = 1 << 13;
+const LZF_MAX_REF: usize = (1 << 8) + (1 << 3);
+
+// lzfDecompress的逆过程,输出可以被redis的lzf_decompress解开
+pub fn lzf_compress(in_data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(in_data.len());
+    let mut table = vec![usize::MAX; 1 << LZF_HASH_LOG];
+    let mut lit = 0;
+    let mut i = 0;
+    while i + 2 < in_data.len() {
+        let h = ((in_data[i] as usize) << 16 | (in_data[i + 1] as usize) << 8 | in_data[i + 2] as usize)
+            .wrapping_mul(2654435761)
+            >> 8
+            & ((1 << LZF_HASH_LOG) - 1);
+        let r = table[h];
+        table[h] = i;
+        if r != usize::MAX && i - r <= LZF_MAX_OFF && in_data[r..r + 3] == in_data[i..i + 3] {
+            let max_len = std::cmp::min(LZF_MAX_REF, in_data.len() - i);
+            let mut len = 3;
+            while len < max_len && in_data[r + len] == in_data[i + len] {
+                len = len + 1;
+            }
+            lzf_flush_literal(&mut out, &in_data[lit..i]);
+            let off = i - r - 1;
+            let l = len - 2;
+            if l < 7 {
+                out.push(((l << 5) | (off >> 8)) as u8);
+            } else {
+                out.push(((7 << 5) | (off >> 8)) as u8);
+                out.push((l - 7) as u8);
+            }
+            out.push(off as u8);
+            i = i + len;
+            lit = i;
+        } else {
+            i = i + 1;
+        }
+    }
+    lzf_flush_literal(&mut out, &in_data[lit..]);
+    out
+}
+
+fn lzf_flush_literal(out: &mut Vec<u8>, lit: &[u8]) {
+    for chunk in lit.chunks(LZF_MAX_LIT) {
+        out.push((chunk.len() - 1) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
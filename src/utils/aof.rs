@@ -0,0 +1,87 @@
+use crate::rdb::compress::open_file;
+
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+// 返回需要按顺序回放的aof文件
+// 支持单个appendonly.aof, redis 7的appendonlydir目录或者其中的manifest文件
+pub fn aof_files(path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let p = Path::new(path);
+    let manifest = if p.is_dir() {
+        let mut manifest = None;
+        for entry in fs::read_dir(p)? {
+            let entry = entry?.path();
+            if entry.to_string_lossy().ends_with(".manifest") {
+                manifest = Some(entry);
+                break;
+            }
+        }
+        match manifest {
+            Some(d) => d,
+            None => return Err(Box::from(format!("{} 中没有找到manifest文件", path))),
+        }
+    } else if path.ends_with(".manifest") {
+        p.to_path_buf()
+    } else {
+        return Ok(vec![String::from(path)]);
+    };
+    let dir = manifest.parent().unwrap_or_else(|| Path::new("."));
+    let mut base = vec![];
+    let mut incrs = vec![];
+    for line in fs::read_to_string(&manifest)?.lines() {
+        let (name, seq, file_type) = parse_manifest_line(line)?;
+        let file = dir.join(name).to_string_lossy().to_string();
+        match file_type.as_str() {
+            "b" => base.push(file),
+            "i" => incrs.push((seq, file)),
+            // history文件已经被合并到新的base里了
+            "h" => {}
+            _ => return Err(Box::from(format!("未知的aof文件类型: {}", line))),
+        }
+    }
+    if base.len() > 1 {
+        return Err(Box::from("manifest中有多个base文件"));
+    }
+    incrs.sort_by_key(|(seq, _)| *seq);
+    base.extend(incrs.into_iter().map(|(_, file)| file));
+    println!("aof files {:?}", base);
+    Ok(base)
+}
+
+// 格式: file appendonly.aof.1.incr.aof seq 1 type i
+fn parse_manifest_line(line: &str) -> Result<(String, u64, String), Box<dyn Error>> {
+    let fields: Vec<String> = line.split_whitespace().map(|s| s.trim_matches('"').to_string()).collect();
+    let (mut name, mut seq, mut file_type) = (None, None, None);
+    for kv in fields.chunks(2) {
+        if kv.len() != 2 {
+            continue;
+        }
+        match kv[0].as_str() {
+            "file" => name = Some(kv[1].clone()),
+            "seq" => seq = Some(kv[1].parse::<u64>()?),
+            "type" => file_type = Some(kv[1].clone()),
+            _ => {}
+        }
+    }
+    match (name, seq, file_type) {
+        (Some(name), Some(seq), Some(file_type)) => Ok((name, seq, file_type)),
+        _ => Err(Box::from(format!("错误的manifest行: {}", line))),
+    }
+}
+
+// aof-use-rdb-preamble 或者base是rdb文件时,开头是rdb的header
+pub fn has_rdb_preamble(path: &str) -> Result<bool, Box<dyn Error>> {
+    let mut head = [0u8; 5];
+    let mut file = open_file(path)?;
+    let mut n = 0;
+    while n < head.len() {
+        let r_len = file.read(&mut head[n..])?;
+        if r_len == 0 {
+            return Ok(false);
+        }
+        n = n + r_len;
+    }
+    Ok(&head == b"REDIS")
+}
@@ -0,0 +1,832 @@
+use crate::utils::conn::open_tcp_conn;
+use crate::utils::deadletter::DeadLetter;
+use crate::utils::memory::{cmd_size, InflightLimit};
+use crate::utils::resp::{Resp, RespConn};
+use crate::utils::slot::key_hash_slot;
+use redis::{Arg, Cmd};
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_std::net::TcpStream;
+use async_std::task::{sleep, spawn, JoinHandle};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+
+// 并行写目的端: 按key的slot把命令分到多个连接, 同一个key的命令总是在同一个连接上按顺序执行
+// 涉及多个连接的命令(多key, 事务, FLUSHDB等)作为屏障: 等之前的命令都执行完, 在第一个连接上单独执行
+// SELECT按顺序发给所有连接, 每个连接都在正确的db上
+// 每个回复都会检查, 出错的命令按ReplyPolicy处理, 错误按类别计数, 最终没有执行成功的命令写到死信文件
+
+// 一次pipeline最多的命令数
+const BATCH_SIZE: usize = 10000;
+
+#[derive(Debug, PartialEq)]
+pub enum Route {
+    // 只在这个连接上执行
+    One(usize),
+    // 按顺序发给所有连接
+    All,
+    // 等所有连接执行完之前的命令再执行
+    Barrier,
+}
+
+pub(crate) fn args(cmd: &Cmd) -> Vec<&[u8]> {
+    cmd.args_iter()
+        .map(|arg| match arg {
+            Arg::Simple(d) => d,
+            Arg::Cursor => &b""[..],
+        })
+        .collect()
+}
+
+// 命令里key的位置, 不认识的命令返回None
+fn keys<'a>(name: &str, args: &[&'a [u8]]) -> Option<Vec<&'a [u8]>> {
+    let rest = args.get(1..).unwrap_or(&[]);
+    let keys = match name {
+        "set" | "setnx" | "setex" | "psetex" | "getset" | "getdel" | "getex" | "append" | "incr" | "decr"
+        | "incrby" | "decrby" | "incrbyfloat" | "setrange" | "setbit" | "bitfield" | "expire" | "pexpire"
+        | "expireat" | "pexpireat" | "persist" | "hset" | "hsetnx" | "hmset" | "hdel" | "hincrby"
+        | "hincrbyfloat" | "hexpire" | "hpexpire" | "hexpireat" | "hpexpireat" | "hpersist" | "hgetdel"
+        | "hgetex" | "hsetex" | "lpush" | "rpush" | "lpushx" | "rpushx" | "lpop" | "rpop" | "lset" | "lrem"
+        | "ltrim" | "linsert" | "sadd" | "srem" | "spop" | "zadd" | "zincrby" | "zrem" | "zremrangebyscore"
+        | "zremrangebyrank" | "zremrangebylex" | "zpopmin" | "zpopmax" | "pfadd" | "xadd" | "xdel" | "xtrim"
+        | "xack" | "xclaim" | "xautoclaim" | "xsetid" | "geoadd" | "restore" | "restore-asking" => {
+            rest.get(..1)?.to_vec()
+        }
+        // 所有参数都是key
+        "del" | "unlink" | "touch" | "exists" | "rename" | "renamenx" | "rpoplpush" | "sinterstore"
+        | "sunionstore" | "sdiffstore" | "pfmerge" | "zrangestore" => rest.to_vec(),
+        "smove" | "lmove" => rest.get(..2)?.to_vec(),
+        "mset" | "msetnx" => rest.iter().step_by(2).cloned().collect(),
+        "bitop" => rest.get(1..)?.to_vec(),
+        // XGROUP CREATE key group ...
+        "xgroup" => rest.get(1..2)?.to_vec(),
+        // COPY带DB参数时跨db, 当作屏障
+        "copy" if rest.len() == 2 || (rest.len() == 3 && rest[2].eq_ignore_ascii_case(b"replace")) => {
+            rest[..2].to_vec()
+        }
+        // ZUNIONSTORE dest numkeys key [key ...] ...
+        "zunionstore" | "zinterstore" | "zdiffstore" => {
+            let n = std::str::from_utf8(rest.get(1)?).ok()?.parse::<usize>().ok()?;
+            let mut keys = vec![*rest.first()?];
+            keys.extend_from_slice(rest.get(2..2 + n)?);
+            keys
+        }
+        _ => return None,
+    };
+    Some(keys)
+}
+
+// 命令里的key, 死信文件用来找出之后被覆盖的死信
+pub(crate) fn command_keys<'a>(args: &[&'a [u8]]) -> Option<Vec<&'a [u8]>> {
+    let name = String::from_utf8_lossy(args.first()?).to_ascii_lowercase();
+    keys(&name, args)
+}
+
+// 执行后整个key的值和之前无关的命令覆盖的key, 之前失败的死信不需要再重放
+// 只修改一部分的命令(HSET, RPUSH等)不算, 之前失败的命令仍然要重放
+pub(crate) fn overwritten_keys<'a>(args: &[&'a [u8]]) -> Vec<&'a [u8]> {
+    let name = match args.first() {
+        Some(d) => String::from_utf8_lossy(d).to_ascii_lowercase(),
+        None => return vec![],
+    };
+    let rest = &args[1..];
+    let has = |flag: &[u8]| rest.iter().skip(1).any(|a| a.eq_ignore_ascii_case(flag));
+    let keys = match name.as_str() {
+        // SET带NX/XX时不一定写入
+        "set" if has(b"nx") || has(b"xx") => return vec![],
+        "set" | "setex" | "psetex" | "getset" => rest.get(..1),
+        "mset" => return rest.iter().step_by(2).cloned().collect(),
+        "del" | "unlink" => Some(rest),
+        "restore" | "restore-asking" if has(b"replace") => rest.get(..1),
+        // 目标key整个被结果替换
+        "sunionstore" | "sinterstore" | "sdiffstore" | "zunionstore" | "zinterstore" | "zdiffstore"
+        | "zrangestore" => rest.get(..1),
+        _ => None,
+    };
+    keys.map(|d| d.to_vec()).unwrap_or_default()
+}
+
+// 重复执行结果不变的命令, 连接断开没有收到回复时可以重新发送
+fn idempotent(name: &str, args: &[&[u8]]) -> bool {
+    let has = |flag: &[u8]| args.iter().skip(1).any(|a| a.eq_ignore_ascii_case(flag));
+    match name {
+        "select" | "ping" | "set" | "setnx" | "setex" | "psetex" | "mset" | "setrange" | "setbit" | "del"
+        | "unlink" | "expire" | "pexpire" | "expireat" | "pexpireat" | "persist" | "hset" | "hsetnx" | "hmset"
+        | "hdel" | "sadd" | "srem" | "zrem" | "lset" | "pfadd" | "geoadd" | "script" | "flushdb"
+        | "flushall" => true,
+        "zadd" => !has(b"incr"),
+        // 没有REPLACE时第二次执行会返回BUSYKEY
+        "restore" => has(b"replace"),
+        _ => false,
+    }
+}
+
+fn name(cmd: &Cmd) -> String {
+    match cmd.args_iter().next() {
+        Some(Arg::Simple(d)) => String::from_utf8_lossy(d).to_ascii_lowercase(),
+        _ => String::new(),
+    }
+}
+
+// 连接断开时前read个命令收到了回复, 返回重连后要重新发送的命令和没有收到回复, 结果未知的命令
+// 重新发送的命令带着它在cmds中的位置, 补上的SELECT没有位置
+// 没有收到回复的命令不知道是否已经执行, 不在事务中的幂等命令重新发送
+// 源端已经执行了事务中的SELECT, EXEC结果未知时单独发送, 后面的命令才在正确的db上
+pub fn resend_after_lost(cmds: &[Cmd], read: usize) -> (Vec<(Option<usize>, Cmd)>, Vec<usize>) {
+    let mut resend = vec![];
+    let mut unknown = vec![];
+    let mut in_tx = false;
+    let mut tx_select: Option<Cmd> = None;
+    // 收到回复的命令中最后的SELECT, 重连后先恢复
+    let mut select: Option<Cmd> = None;
+    for (i, cmd) in cmds.iter().enumerate() {
+        let name = name(cmd);
+        if i == read {
+            resend.extend(select.take().map(|d| (None, d)));
+        }
+        if name == "multi" {
+            in_tx = true;
+        }
+        if !in_tx {
+            if i < read {
+                if name == "select" {
+                    select = Some(cmd.clone());
+                }
+            } else if idempotent(&name, &args(cmd)) {
+                resend.push((Some(i), cmd.clone()));
+            } else {
+                unknown.push(i);
+            }
+            continue;
+        }
+        match name.as_str() {
+            "select" => tx_select = Some(cmd.clone()),
+            "exec" if i < read => {
+                if let Some(d) = tx_select.take() {
+                    select = Some(d);
+                }
+            }
+            "exec" => resend.extend(tx_select.take().map(|d| (None, d))),
+            _ => {}
+        }
+        if i >= read && name != "select" {
+            unknown.push(i);
+        }
+        if name == "exec" || name == "discard" {
+            in_tx = false;
+            tx_select = None;
+        }
+    }
+    (resend, unknown)
+}
+
+// 命令应该发到哪个连接, connections为连接数
+pub fn route(cmd: &Cmd, connections: usize) -> Route {
+    let args = args(cmd);
+    let name = match args.first() {
+        Some(d) => String::from_utf8_lossy(d).to_ascii_lowercase(),
+        None => return Route::Barrier,
+    };
+    match name.as_str() {
+        "select" => return Route::All,
+        // 复制流里定时的PING, 不影响数据
+        "ping" => return Route::One(0),
+        _ => {}
+    }
+    let keys = match keys(&name, &args) {
+        Some(d) if !d.is_empty() => d,
+        _ => return Route::Barrier,
+    };
+    // 所有key都在同一个连接上时不需要屏障
+    let first = key_hash_slot(keys[0]) as usize % connections;
+    if keys.iter().all(|k| key_hash_slot(k) as usize % connections == first) {
+        Route::One(first)
+    } else {
+        Route::Barrier
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplyPolicy {
+    // 出错的命令打印出来, 停止同步
+    Fail,
+    // 每隔1秒重试, 最多重试N次, 仍然失败的跳过并记录
+    Retry(u32),
+    // 跳过并记录
+    Skip,
+}
+
+impl ReplyPolicy {
+    pub fn parse(s: &str) -> Result<ReplyPolicy, Box<dyn Error>> {
+        match s {
+            "fail" => Ok(ReplyPolicy::Fail),
+            "retry" => Ok(ReplyPolicy::Retry(3)),
+            "skip" => Ok(ReplyPolicy::Skip),
+            _ => Err(Box::from(format!("未知的目的端错误策略 {}", s))),
+        }
+    }
+}
+
+// 错误的类别, 即错误信息的第一个单词, 例如 OOM, BUSYKEY, WRONGTYPE, ERR
+pub fn error_class(err: &[u8]) -> String {
+    let word = err.split(|b| *b == b' ').next().unwrap_or(b"");
+    if !word.is_empty() && word.iter().all(|b| b.is_ascii_uppercase() || *b == b'_' || *b == b'-') {
+        String::from_utf8_lossy(word).to_string()
+    } else {
+        String::from("ERR")
+    }
+}
+
+// 连接断开时没有收到回复, 也不能重新发送的命令, 不确定是否已经执行, 不会重试
+const UNKNOWN_CLASS: &str = "UNKNOWN";
+
+// 每类错误只打印前面这么多条, 后面的只计数
+const MAX_PRINT_PER_CLASS: u64 = 100;
+
+// 目的端返回的错误统计, 所有连接共用
+#[derive(Default)]
+pub struct ReplyStats {
+    // 每类错误的命令数, 重试时再出错不重复计算
+    classes: Mutex<BTreeMap<String, u64>>,
+    pub errors: AtomicU64,
+    pub retried: AtomicU64,
+    // 最终没有执行成功, 被跳过的命令数
+    pub skipped: AtomicU64,
+    // fail策略下第一个出错的命令, 之后所有连接都停止执行
+    fatal: Mutex<Option<String>>,
+}
+
+impl ReplyStats {
+    // 返回这一类错误已经出现的次数
+    fn add(&self, class: &str) -> u64 {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        let mut classes = self.classes.lock().unwrap();
+        let count = classes.entry(class.to_string()).or_insert(0);
+        *count = *count + 1;
+        *count
+    }
+    fn count(&self, class: &str) -> u64 {
+        *self.classes.lock().unwrap().get(class).unwrap_or(&0)
+    }
+    pub fn classes(&self) -> BTreeMap<String, u64> {
+        self.classes.lock().unwrap().clone()
+    }
+    pub fn fatal(&self) -> Option<String> {
+        self.fatal.lock().unwrap().clone()
+    }
+    fn set_fatal(&self, error: String) {
+        let mut fatal = self.fatal.lock().unwrap();
+        if fatal.is_none() {
+            *fatal = Some(error);
+        }
+    }
+    pub fn print(&self, tag: &str) {
+        println!(
+            "[{}] 目的端返回错误:{} 重试:{} 跳过:{}",
+            tag,
+            self.errors.load(Ordering::Relaxed),
+            self.retried.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed)
+        );
+        for (class, count) in self.classes().iter() {
+            println!("[{}]   {}: {}", tag, class, count);
+        }
+    }
+}
+
+enum Job {
+    // 命令, 它占用的内存和源端偏移量, 广播出去的副本不占用内存
+    Cmd(Cmd, u64, u64),
+    // 之前的命令都执行完之后通知
+    Sync(oneshot::Sender<()>),
+}
+
+pub struct Applier {
+    workers: Vec<Sender<Job>>,
+    handles: Vec<JoinHandle<()>>,
+    // MULTI到EXEC之间的命令都发到第一个连接
+    in_multi: bool,
+    // 事务中的SELECT, EXEC之后其它连接也要切换到这个db
+    multi_select: Option<Cmd>,
+    // 已经发送到目的端的命令数
+    pub sent: Arc<AtomicU64>,
+    pub barriers: u64,
+    pub stats: Arc<ReplyStats>,
+}
+
+impl Applier {
+    pub fn new(
+        target_url: &'static str,
+        target_pass: &'static str,
+        connections: usize,
+        inflight: Arc<InflightLimit>,
+        policy: ReplyPolicy,
+        deadletter: Option<Arc<DeadLetter>>,
+    ) -> Applier {
+        let sent = Arc::new(AtomicU64::new(0));
+        let stats = Arc::new(ReplyStats::default());
+        let mut workers = vec![];
+        let mut handles = vec![];
+        for id in 0..std::cmp::max(connections, 1) {
+            let (sender, receiver) = channel::<Job>(BATCH_SIZE);
+            workers.push(sender);
+            let w = Worker {
+                id,
+                target_url,
+                target_pass,
+                conn: None,
+                db: 0,
+                tx: None,
+                policy,
+                stats: stats.clone(),
+                deadletter: deadletter.clone(),
+            };
+            handles.push(spawn(worker(w, receiver, inflight.clone(), sent.clone())));
+        }
+        Applier {
+            workers,
+            handles,
+            in_multi: false,
+            multi_select: None,
+            sent,
+            barriers: 0,
+            stats,
+        }
+    }
+    // offset为命令在源端的偏移量, 只用来记录死信
+    // fail策略下目的端返回错误后返回Err, 调用方停止发送并close
+    pub async fn send(&mut self, cmd: Cmd, offset: u64) -> Result<(), String> {
+        if let Some(e) = self.stats.fatal() {
+            return Err(e);
+        }
+        let bytes = cmd_size(&cmd);
+        if self.workers.len() == 1 {
+            return self.send_to(0, cmd, bytes, offset).await;
+        }
+        let name = name(&cmd);
+        if self.in_multi {
+            if name == "select" {
+                self.multi_select = Some(cmd.clone());
+            }
+            self.send_to(0, cmd, bytes, offset).await?;
+            if name == "exec" || name == "discard" {
+                self.in_multi = false;
+                self.sync_one(0).await;
+                // 第一个连接在事务中已经切换了db
+                if let Some(select) = self.multi_select.take() {
+                    if name == "exec" {
+                        for i in 1..self.workers.len() {
+                            self.send_to(i, select.clone(), 0, offset).await?;
+                        }
+                    }
+                }
+            }
+            return Ok(());
+        }
+        if name == "multi" {
+            self.sync().await;
+            self.in_multi = true;
+            return self.send_to(0, cmd, bytes, offset).await;
+        }
+        match route(&cmd, self.workers.len()) {
+            Route::One(i) => self.send_to(i, cmd, bytes, offset).await,
+            Route::All => {
+                for i in 1..self.workers.len() {
+                    self.send_to(i, cmd.clone(), 0, offset).await?;
+                }
+                self.send_to(0, cmd, bytes, offset).await
+            }
+            Route::Barrier => {
+                self.barriers = self.barriers + 1;
+                self.sync().await;
+                self.send_to(0, cmd, bytes, offset).await?;
+                self.sync_one(0).await;
+                Ok(())
+            }
+        }
+    }
+    async fn send_to(&mut self, i: usize, cmd: Cmd, bytes: u64, offset: u64) -> Result<(), String> {
+        if self.workers[i].send(Job::Cmd(cmd, bytes, offset)).await.is_err() {
+            return Err(format!("目的端连接{}已经退出", i));
+        }
+        Ok(())
+    }
+    async fn sync_one(&mut self, i: usize) {
+        let (done, wait) = oneshot::channel();
+        if self.workers[i].send(Job::Sync(done)).await.is_ok() {
+            let _ = wait.await;
+        }
+    }
+    // 等所有连接上已经发出的命令都执行完
+    pub async fn sync(&mut self) {
+        let mut waits = vec![];
+        for worker in self.workers.iter_mut() {
+            let (done, wait) = oneshot::channel();
+            if worker.send(Job::Sync(done)).await.is_ok() {
+                waits.push(wait);
+            }
+        }
+        for wait in waits {
+            let _ = wait.await;
+        }
+    }
+    // 发送剩下的命令后关闭所有连接, fail策略下出错时返回第一个错误
+    pub async fn close(self) -> Result<(), String> {
+        drop(self.workers);
+        for handle in self.handles {
+            handle.await;
+        }
+        match self.stats.fatal() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+// SELECT命令切换到的db
+fn select_db(cmd: &Cmd) -> Option<i64> {
+    let args = args(cmd);
+    if args.len() != 2 || !args[0].eq_ignore_ascii_case(b"select") {
+        return None;
+    }
+    std::str::from_utf8(args[1]).ok()?.parse::<i64>().ok()
+}
+
+// 连接目的端直到成功, 重连时先恢复之前选择的db
+async fn connect(id: usize, target_url: &str, target_pass: &str, db: i64) -> RespConn<TcpStream> {
+    loop {
+        let conn = open_tcp_conn(target_url, target_pass).await.map_err(|e| e.to_string());
+        let err = match conn {
+            Ok(mut conn) => {
+                if db == 0 {
+                    return conn;
+                }
+                let reply = conn.request(&["select".to_string(), db.to_string()]).await.map_err(|e| e.to_string());
+                match reply {
+                    Ok(Resp::Error(e)) => String::from_utf8_lossy(&e).to_string(),
+                    Ok(_) => return conn,
+                    Err(e) => e,
+                }
+            }
+            Err(e) => e,
+        };
+        println!("连接{}: 连接目的端redis失败: {}", id, err);
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+// 发送的命令, 发送时所在的db和源端偏移量
+#[derive(Clone)]
+struct Pending {
+    cmd: Cmd,
+    db: i64,
+    offset: u64,
+}
+
+// 目的端返回错误的命令, 事务中的命令没有index, 不能单独重试
+struct Failure {
+    cmd: Cmd,
+    db: i64,
+    offset: u64,
+    error: Vec<u8>,
+    index: Option<usize>,
+}
+
+// 一批命令中每个命令的结果, 按执行的顺序, 事务中的命令在EXEC的位置
+enum Outcome {
+    Done(usize),
+    DoneTx(Pending),
+    Failed(Failure),
+}
+
+// 出错的命令之后, 同一个key上还有执行成功的命令时, 重试会打乱顺序
+// 从后往前找出可以安全重试的命令
+fn retry_safe(batch: &[Pending], failures: &[&Failure]) -> HashSet<usize> {
+    let failed: HashSet<usize> = failures.iter().filter_map(|f| f.index).collect();
+    let mut safe = HashSet::new();
+    let mut touched: HashSet<(i64, &[u8])> = HashSet::new();
+    // 之后有执行成功但是不知道key的命令
+    let mut unknown = false;
+    for i in (0..batch.len()).rev() {
+        let p = &batch[i];
+        let name = name(&p.cmd);
+        let args = args(&p.cmd);
+        let keys = keys(&name, &args);
+        if failed.contains(&i) {
+            let ok = match &keys {
+                Some(keys) => !unknown && keys.iter().all(|k| !touched.contains(&(p.db, *k))),
+                None => !unknown && touched.is_empty(),
+            };
+            if ok {
+                safe.insert(i);
+            }
+            continue;
+        }
+        if name == "select" || name == "ping" {
+            continue;
+        }
+        match keys {
+            Some(keys) => {
+                for k in keys {
+                    touched.insert((p.db, k));
+                }
+            }
+            None => unknown = true,
+        }
+    }
+    safe
+}
+
+struct Worker {
+    id: usize,
+    target_url: &'static str,
+    target_pass: &'static str,
+    conn: Option<RespConn<TcpStream>>,
+    // 已经发送的命令最后所在的db
+    db: i64,
+    // MULTI之后排队的命令, EXEC的回复按顺序对应它们
+    tx: Option<Vec<Pending>>,
+    policy: ReplyPolicy,
+    stats: Arc<ReplyStats>,
+    deadletter: Option<Arc<DeadLetter>>,
+}
+
+impl Worker {
+    // 发送一批命令并读取全部回复
+    // 连接断开时已经收到回复的命令不再发送, 没有收到回复的命令按resend_after_lost重新发送,
+    // 其它的命令返回UNKNOWN错误, 按失败处理
+    async fn exec(&mut self, batch: &[Pending]) -> Vec<Resp> {
+        let mut replies: Vec<Option<Resp>> = vec![None; batch.len()];
+        // 这一轮发送的命令和它在batch中的位置, 补上的SELECT没有位置
+        let mut todo: Vec<(Option<usize>, Cmd)> = batch.iter().enumerate().map(|(i, p)| (Some(i), p.cmd.clone())).collect();
+        while !todo.is_empty() {
+            if self.conn.is_none() {
+                self.conn = Some(connect(self.id, self.target_url, self.target_pass, batch[0].db).await);
+            }
+            let mut buf = vec![];
+            for (_, cmd) in todo.iter() {
+                buf.extend_from_slice(&cmd.get_packed_command());
+            }
+            let mut got = Vec::with_capacity(todo.len());
+            let result = request(self.conn.as_mut().unwrap(), &buf, todo.len(), &mut got).await.map_err(|e| e.to_string());
+            let read = got.len();
+            for ((i, _), reply) in todo.iter().zip(got) {
+                if let Some(i) = i {
+                    replies[*i] = Some(reply);
+                }
+            }
+            let e = match result {
+                Ok(()) => break,
+                Err(e) => e,
+            };
+            self.conn = None;
+            let cmds: Vec<Cmd> = todo.iter().map(|(_, cmd)| cmd.clone()).collect();
+            let (resend, unknown) = resend_after_lost(&cmds, read);
+            for j in unknown.iter() {
+                if let Some(i) = todo[*j].0 {
+                    let error = format!("{} 连接断开, 没有收到回复, 不确定是否已经执行: {}", UNKNOWN_CLASS, e);
+                    replies[i] = Some(Resp::Error(error.into_bytes()));
+                }
+            }
+            println!(
+                "连接{}: 目的端连接断开, 重连后重新发送{}个命令, {}个命令结果未知: {}",
+                self.id,
+                resend.len(),
+                unknown.len(),
+                e
+            );
+            todo = resend.into_iter().map(|(j, cmd)| (j.and_then(|j| todo[j].0), cmd)).collect();
+        }
+        replies.into_iter().map(|r| r.unwrap_or(Resp::Null)).collect()
+    }
+    // 检查每个回复, 事务中的命令按EXEC的回复检查
+    fn check(&mut self, batch: &[Pending], replies: Vec<Resp>) -> Vec<Outcome> {
+        let mut outcomes = Vec::with_capacity(batch.len());
+        for (i, (p, reply)) in batch.iter().zip(replies).enumerate() {
+            let name = name(&p.cmd);
+            let mut tx = match self.tx.take() {
+                Some(d) => d,
+                None => {
+                    match reply {
+                        Resp::Error(error) => outcomes.push(Outcome::Failed(Failure { cmd: p.cmd.clone(), db: p.db, offset: p.offset, error, index: Some(i) })),
+                        _ if name == "multi" => self.tx = Some(vec![]),
+                        _ => outcomes.push(Outcome::Done(i)),
+                    }
+                    continue;
+                }
+            };
+            match (name.as_str(), reply) {
+                ("exec", Resp::Array(items)) => {
+                    for (q, item) in tx.into_iter().zip(items) {
+                        match item {
+                            Resp::Error(error) => outcomes.push(Outcome::Failed(Failure { cmd: q.cmd, db: q.db, offset: q.offset, error, index: None })),
+                            _ => outcomes.push(Outcome::DoneTx(q)),
+                        }
+                    }
+                }
+                // EXECABORT或者结果未知, 排队的命令都没有执行成功
+                ("exec", Resp::Error(error)) => {
+                    for q in tx {
+                        outcomes.push(Outcome::Failed(Failure { cmd: q.cmd, db: q.db, offset: q.offset, error: error.clone(), index: None }));
+                    }
+                }
+                ("exec", _) | ("discard", _) => {}
+                // 排队时就出错, 例如参数个数不对
+                (_, Resp::Error(error)) => {
+                    outcomes.push(Outcome::Failed(Failure { cmd: p.cmd.clone(), db: p.db, offset: p.offset, error, index: None }));
+                    self.tx = Some(tx);
+                }
+                (_, _) => {
+                    tx.push(p.clone());
+                    self.tx = Some(tx);
+                }
+            }
+        }
+        outcomes
+    }
+    // 执行成功的命令覆盖了之前写到死信文件的key时, 在死信文件中记录, 重放时跳过旧的命令
+    fn applied(&self, p: &Pending) {
+        if let Some(deadletter) = &self.deadletter {
+            if let Err(e) = deadletter.applied(p.db, p.offset, &p.cmd) {
+                println!("连接{}: 写入死信文件失败: {}", self.id, e);
+            }
+        }
+    }
+    // 记录不再重试的命令并写到死信文件, fail策略下通知所有连接停止
+    fn record(&self, f: &Failure) {
+        let class = error_class(&f.error);
+        let args = args(&f.cmd);
+        let name = name(&f.cmd);
+        let key = match keys(&name, &args) {
+            Some(keys) if !keys.is_empty() => String::from_utf8_lossy(keys[0]).to_string(),
+            _ => String::from("-"),
+        };
+        let line = format!(
+            "连接{}: db:{} key:{} cmd:{} err:{}",
+            self.id,
+            f.db,
+            key,
+            name,
+            String::from_utf8_lossy(&f.error)
+        );
+        if let Some(deadletter) = &self.deadletter {
+            if let Err(e) = deadletter.write(f.db, f.offset, &f.error, &f.cmd) {
+                println!("连接{}: 写入死信文件失败: {}", self.id, e);
+            }
+        }
+        if self.policy == ReplyPolicy::Fail {
+            println!("目的端返回错误, 停止同步: {}", line);
+            self.stats.set_fatal(format!("目的端返回错误: {}", line));
+            return;
+        }
+        self.stats.skipped.fetch_add(1, Ordering::Relaxed);
+        let count = self.stats.count(&class);
+        if count <= MAX_PRINT_PER_CLASS {
+            println!("[SKIP] {}", line);
+            if count == MAX_PRINT_PER_CLASS {
+                println!("[SKIP] {}错误太多, 之后只计数", class);
+            }
+        }
+    }
+    // 按顺序处理一轮的结果, 返回需要重试的命令, 按原来的db插入SELECT
+    fn settle(&mut self, batch: &[Pending], outcomes: Vec<Outcome>, attempt: u32) -> Vec<Pending> {
+        let failures: Vec<&Failure> = outcomes
+            .iter()
+            .filter_map(|o| match o {
+                Outcome::Failed(f) => Some(f),
+                _ => None,
+            })
+            .collect();
+        if attempt == 0 {
+            for f in failures.iter() {
+                self.stats.add(&error_class(&f.error));
+            }
+        }
+        let safe = match self.policy {
+            ReplyPolicy::Retry(n) if attempt < n && !failures.is_empty() => retry_safe(batch, &failures),
+            _ => HashSet::new(),
+        };
+        let mut retry = vec![];
+        let mut db = self.db;
+        for outcome in outcomes {
+            let f = match outcome {
+                Outcome::Done(i) => {
+                    self.applied(&batch[i]);
+                    continue;
+                }
+                Outcome::DoneTx(p) => {
+                    self.applied(&p);
+                    continue;
+                }
+                Outcome::Failed(f) => f,
+            };
+            if !f.index.is_some_and(|i| safe.contains(&i)) || error_class(&f.error) == UNKNOWN_CLASS {
+                self.record(&f);
+                continue;
+            }
+            if f.db != db {
+                retry.push(Pending { cmd: redis::cmd("SELECT").arg(f.db).to_owned(), db, offset: f.offset });
+                db = f.db;
+            }
+            retry.push(Pending { cmd: f.cmd, db: f.db, offset: f.offset });
+        }
+        if !retry.is_empty() && db != self.db {
+            retry.push(Pending { cmd: redis::cmd("SELECT").arg(self.db).to_owned(), db, offset: 0 });
+        }
+        retry
+    }
+    async fn flush(&mut self, batch: &[Pending]) {
+        let replies = self.exec(batch).await;
+        let outcomes = self.check(batch, replies);
+        let mut retry = self.settle(batch, outcomes, 0);
+        let mut attempt = 0;
+        while !retry.is_empty() && self.stats.fatal().is_none() {
+            attempt = attempt + 1;
+            sleep(Duration::from_secs(1)).await;
+            self.stats.retried.fetch_add(retry.len() as u64, Ordering::Relaxed);
+            let replies = self.exec(&retry).await;
+            let outcomes = self.check(&retry, replies);
+            retry = self.settle(&retry, outcomes, attempt);
+        }
+    }
+}
+
+// 写出一批命令后逐个读取回复, 出错时replies中是已经读到的回复
+async fn request(conn: &mut RespConn<TcpStream>, buf: &[u8], count: usize, replies: &mut Vec<Resp>) -> Result<(), Box<dyn Error>> {
+    conn.write_packed(buf).await?;
+    for _ in 0..count {
+        replies.push(conn.read_value().await?);
+    }
+    Ok(())
+}
+
+async fn worker(mut w: Worker, mut receiver: Receiver<Job>, inflight: Arc<InflightLimit>, sent: Arc<AtomicU64>) {
+    w.conn = Some(connect(w.id, w.target_url, w.target_pass, 0).await);
+    let mut batch = vec![];
+    let mut bytes = 0;
+    // MULTI之前所在的db, DISCARD之后恢复
+    let mut multi_db: Option<i64> = None;
+    let mut closed = false;
+    while !closed {
+        // 没有更多命令, 攒够一批或者遇到Sync时发送, 事务不会拆到两批里
+        let job = if batch.is_empty() || multi_db.is_some() {
+            match receiver.recv().await {
+                Some(d) => Some(d),
+                None if batch.is_empty() => break,
+                None => {
+                    closed = true;
+                    None
+                }
+            }
+        } else {
+            match receiver.try_recv() {
+                Ok(d) => Some(d),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Closed) => {
+                    closed = true;
+                    None
+                }
+            }
+        };
+        let mut done = None;
+        match job {
+            Some(Job::Cmd(cmd, size, offset)) => {
+                let db = w.db;
+                match name(&cmd).as_str() {
+                    "multi" => multi_db = Some(w.db),
+                    "exec" => multi_db = None,
+                    "discard" => w.db = multi_db.take().unwrap_or(w.db),
+                    _ => {}
+                }
+                if let Some(d) = select_db(&cmd) {
+                    w.db = d;
+                }
+                batch.push(Pending { cmd, db, offset });
+                bytes = bytes + size;
+                if batch.len() < BATCH_SIZE || multi_db.is_some() {
+                    continue;
+                }
+            }
+            Some(Job::Sync(d)) => done = Some(d),
+            None => {}
+        }
+        if !batch.is_empty() {
+            // 已经出错停止时不再执行, 只归还额度, 直到发送方关闭
+            if w.stats.fatal().is_none() {
+                w.flush(&batch).await;
+                sent.fetch_add(batch.len() as u64, Ordering::Relaxed);
+            }
+            inflight.release(bytes);
+            batch.clear();
+            bytes = 0;
+        }
+        if let Some(done) = done {
+            let _ = done.send(());
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::utils::resp::encode_command;
+
+use std::error::Error;
+
+// 增量阶段(复制流/aof)的命令解析
+// 读到的数据追加到BytesMut里, 每解析出一个完整的命令就把这段内存切下来, 读取参数时直接从里面取, 不再复制
+
+// 和redis的限制一致: 单个参数proto-max-bulk-len, 参数个数1024*1024, inline命令一行64KB
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+const MAX_MULTI_BULK_LEN: usize = 1024 * 1024;
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub struct Command {
+    // 命令的multi bulk数据, inline命令也转换成multi bulk
+    data: Bytes,
+    // 第一个参数在data中的位置
+    body: usize,
+    argc: usize,
+    // 命令在流里占的字节数, 用来计算复制偏移量
+    pub len: usize,
+}
+
+// 按$N\r\n依次取出参数, 数据在解析时已经检查过
+pub struct Args<'a> {
+    data: &'a [u8],
+    pos: usize,
+    left: usize,
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.left == 0 {
+            return None;
+        }
+        let mut i = self.pos + 1;
+        let mut n = 0;
+        while let Some(b) = self.data.get(i).filter(|b| b.is_ascii_digit()) {
+            n = n * 10 + (b - b'0') as usize;
+            i = i + 1;
+        }
+        let arg = self.data.get(i + 2..i + 2 + n)?;
+        self.pos = i + 2 + n + 2;
+        self.left = self.left - 1;
+        Some(arg)
+    }
+}
+
+impl Command {
+    pub fn arg_count(&self) -> usize {
+        self.argc
+    }
+    pub fn args(&self) -> Args<'_> {
+        Args {
+            data: &self.data,
+            pos: self.body,
+            left: self.argc,
+        }
+    }
+    pub fn arg(&self, i: usize) -> Option<&[u8]> {
+        self.args().nth(i)
+    }
+    // 命令名, 解析出来的命令至少有一个参数
+    pub fn name(&self) -> &[u8] {
+        self.arg(0).unwrap_or(b"")
+    }
+    // 需要改写参数时转换成Bytes, 仍然共享同一块内存
+    pub fn to_args(&self) -> Vec<Bytes> {
+        self.args().map(|a| self.data.slice_ref(a)).collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum Frame {
+    Command(Command),
+    // aof中的注释, 不带#和换行, 例如 TS:1628217470
+    Comment(Bytes, usize),
+    // 空行或者*0这样的空命令, 只需要计算偏移量
+    Empty(usize),
+}
+
+impl Frame {
+    pub fn size(&self) -> usize {
+        match self {
+            Frame::Command(c) => c.len,
+            Frame::Comment(_, n) => *n,
+            Frame::Empty(n) => *n,
+        }
+    }
+}
+
+pub struct CommandParser {
+    buf: BytesMut,
+    // 没有解析完的multi bulk命令扫描到的位置
+    scan: ScanState,
+}
+
+// 找\n的位置, 返回行尾(去掉\r)和下一行的开始
+fn find_line(buf: &[u8], from: usize) -> Option<(usize, usize)> {
+    let i = buf[from..].iter().position(|b| *b == b'\n')? + from;
+    if i > from && buf[i - 1] == b'\r' {
+        Some((i - 1, i + 1))
+    } else {
+        Some((i, i + 1))
+    }
+}
+
+// 解析*N\r\n或$N\r\n, 返回N和下一行的开始, 数字和换行一次扫描完
+// 只接受十进制数字, 溢出和超过max都是错误
+#[inline]
+fn read_len(buf: &[u8], pos: usize, max: usize, what: &str) -> Result<Option<(usize, usize)>, Box<dyn Error>> {
+    let mut n: u64 = 0;
+    let mut i = pos + 1;
+    while i < buf.len() {
+        let b = buf[i];
+        if b.is_ascii_digit() {
+            n = n * 10 + (b - b'0') as u64;
+            if n > max as u64 {
+                return Err(Box::from(format!("{}超过上限 {}", what, max)));
+            }
+            i = i + 1;
+            continue;
+        }
+        if b != b'\r' || i == pos + 1 {
+            return Err(Box::from(format!("错误的{} {:?}", what, String::from_utf8_lossy(&buf[pos..=i]))));
+        }
+        return match buf.get(i + 1) {
+            None => Ok(None),
+            Some(b'\n') => Ok(Some((n as usize, i + 2))),
+            Some(_) => Err(Box::from(format!("{}后面不是\\r\\n", what))),
+        };
+    }
+    Ok(None)
+}
+
+// 大命令分多次到达时, 下次从上次检查完的参数继续扫描, 不用每次从头再扫一遍
+#[derive(Default)]
+struct ScanState {
+    // 参数个数和第一个参数的位置, 还没有读到*N\r\n时为None
+    header: Option<(usize, usize)>,
+    // 已经检查完的参数个数, 下一个参数的位置
+    done: usize,
+    pos: usize,
+}
+
+enum Scan {
+    // 参数个数, 第一个参数的位置, 命令结束的位置
+    Complete(usize, usize, usize),
+    // 至少还需要多少字节, 不知道时为0
+    Incomplete(usize),
+}
+
+// 检查buf开头是不是一个完整的multi bulk命令, 从state记录的位置继续
+fn scan_multi_bulk(buf: &[u8], state: &mut ScanState) -> Result<Scan, Box<dyn Error>> {
+    let (count, body) = match state.header {
+        Some(d) => d,
+        None => match read_len(buf, 0, MAX_MULTI_BULK_LEN, "参数个数")? {
+            Some(d) => {
+                state.header = Some(d);
+                state.pos = d.1;
+                d
+            }
+            None => return Ok(Scan::Incomplete(0)),
+        },
+    };
+    while state.done < count {
+        let pos = state.pos;
+        match buf.get(pos) {
+            None => return Ok(Scan::Incomplete(0)),
+            Some(b'$') => {}
+            Some(b) => {
+                return Err(Box::from(format!("增量命令的参数应该以$开头, 实际是{:?}", *b as char)));
+            }
+        }
+        let (n, next) = match read_len(buf, pos, MAX_BULK_LEN, "参数长度")? {
+            Some(d) => d,
+            None => return Ok(Scan::Incomplete(0)),
+        };
+        let end = next + n + 2;
+        if buf.len() < end {
+            return Ok(Scan::Incomplete(end));
+        }
+        if buf[end - 2] != b'\r' || buf[end - 1] != b'\n' {
+            return Err(Box::from("增量命令的参数后面不是\\r\\n"));
+        }
+        state.done = state.done + 1;
+        state.pos = end;
+    }
+    let end = state.pos;
+    *state = ScanState::default();
+    Ok(Scan::Complete(count, body, end))
+}
+
+fn hex(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+// 和redis的sdssplitargs一样切分inline命令, 支持"..."(带转义)和'...'
+fn split_inline(s: &[u8]) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let mut args = vec![];
+    let mut i = 0;
+    loop {
+        while i < s.len() && s[i].is_ascii_whitespace() {
+            i = i + 1;
+        }
+        if i == s.len() {
+            return Ok(args);
+        }
+        if s[i] != b'"' && s[i] != b'\'' {
+            let start = i;
+            while i < s.len() && !s[i].is_ascii_whitespace() {
+                i = i + 1;
+            }
+            args.push(s[start..i].to_vec());
+            continue;
+        }
+        let mut arg = vec![];
+        let quote = s[i];
+        i = i + 1;
+        loop {
+            if i == s.len() {
+                return Err(Box::from("inline命令的引号不匹配"));
+            }
+            let b = s[i];
+            if b == quote {
+                i = i + 1;
+                break;
+            }
+            if quote == b'"' && b == b'\\' && i + 3 < s.len() && s[i + 1] == b'x' {
+                if let (Some(h), Some(l)) = (hex(s[i + 2]), hex(s[i + 3])) {
+                    arg.push(h * 16 + l);
+                    i = i + 4;
+                    continue;
+                }
+            }
+            if b == b'\\' && i + 1 < s.len() && (quote == b'"' || s[i + 1] == b'\'') {
+                arg.push(match (quote, s[i + 1]) {
+                    (b'"', b'n') => b'\n',
+                    (b'"', b'r') => b'\r',
+                    (b'"', b't') => b'\t',
+                    (b'"', b'b') => 8,
+                    (b'"', b'a') => 7,
+                    (_, c) => c,
+                });
+                i = i + 2;
+                continue;
+            }
+            arg.push(b);
+            i = i + 1;
+        }
+        // 引号后面必须是空白或者行尾
+        if i < s.len() && !s[i].is_ascii_whitespace() {
+            return Err(Box::from("inline命令的引号后面不是空白"));
+        }
+        args.push(arg);
+    }
+}
+
+impl Default for CommandParser {
+    fn default() -> Self {
+        CommandParser::new()
+    }
+}
+
+impl CommandParser {
+    pub fn new() -> CommandParser {
+        CommandParser {
+            buf: BytesMut::with_capacity(64 * 1024),
+            scan: ScanState::default(),
+        }
+    }
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+    // 留出至少64KB的空闲空间, 调用方直接读到里面, 省掉一次复制
+    pub fn buffer_mut(&mut self) -> &mut BytesMut {
+        self.buf.reserve(64 * 1024);
+        &mut self.buf
+    }
+    // 还没有解析的字节数
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+    // 解析一个完整的命令, 数据不够时返回None, 已经缓冲的数据保持不变
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, Box<dyn Error>> {
+        match self.buf.first() {
+            None => Ok(None),
+            Some(b'*') => self.multi_bulk(),
+            Some(b'#') => {
+                let (end, next) = match self.line(0)? {
+                    Some(d) => d,
+                    None => return Ok(None),
+                };
+                let frame = self.buf.split_to(next).freeze();
+                Ok(Some(Frame::Comment(frame.slice(1..end), next)))
+            }
+            Some(_) => {
+                let (end, next) = match self.line(0)? {
+                    Some(d) => d,
+                    None => return Ok(None),
+                };
+                // 复制流和aof里都是multi bulk, inline命令很少见, 直接转换成multi bulk
+                let args = split_inline(&self.buf[..end])?;
+                self.buf.advance(next);
+                if args.is_empty() {
+                    return Ok(Some(Frame::Empty(next)));
+                }
+                let data = Bytes::from(encode_command(&args));
+                let body = format!("*{}\r\n", args.len()).len();
+                Ok(Some(Frame::Command(Command { data, body, argc: args.len(), len: next })))
+            }
+        }
+    }
+    fn line(&self, from: usize) -> Result<Option<(usize, usize)>, Box<dyn Error>> {
+        match find_line(&self.buf, from) {
+            Some(d) => Ok(Some(d)),
+            None if self.buf.len() - from > MAX_INLINE_LEN => Err(Box::from("增量命令的一行太长")),
+            None => Ok(None),
+        }
+    }
+    fn multi_bulk(&mut self) -> Result<Option<Frame>, Box<dyn Error>> {
+        // 和redis一样, *后面是负数时当作空命令
+        if self.buf.get(1) == Some(&b'-') {
+            let (end, next) = match self.line(0)? {
+                Some(d) => d,
+                None => return Ok(None),
+            };
+            if end <= 2 || !self.buf[2..end].iter().all(|b| b.is_ascii_digit()) {
+                return Err(Box::from(format!("错误的参数个数 {:?}", String::from_utf8_lossy(&self.buf[..end]))));
+            }
+            self.buf.advance(next);
+            return Ok(Some(Frame::Empty(next)));
+        }
+        let (count, body, pos) = match scan_multi_bulk(&self.buf, &mut self.scan)? {
+            Scan::Complete(count, body, end) => (count, body, end),
+            Scan::Incomplete(need) => {
+                // 大参数一次申请好空间
+                if need > self.buf.len() {
+                    self.buf.reserve(need - self.buf.len());
+                }
+                return Ok(None);
+            }
+        };
+        if count == 0 {
+            self.buf.advance(pos);
+            return Ok(Some(Frame::Empty(pos)));
+        }
+        let data = self.buf.split_to(pos).freeze();
+        Ok(Some(Frame::Command(Command { data, body, argc: count, len: pos })))
+    }
+}
@@ -0,0 +1,182 @@
+use crate::rdb::compress::Compression;
+use crate::rdb::diff::DiffOptions;
+use crate::rdb::function::FunctionPolicy;
+use crate::rdb::module::ModulePolicy;
+use crate::rdb::salvage::{CrcPolicy, ErrorPolicy};
+use crate::utils::apply::ReplyPolicy;
+use crate::utils::deadletter::DeadLetterOptions;
+use crate::utils::filter::Filter;
+
+use std::error::Error;
+
+// 启动参数: redis-shake-rs [mode] --key=value ...
+// 列表类型的值用';'分隔,例如 --filter.db.whitelist=0;1
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub mode: String,
+    pub source_url: String,
+    pub source_pass: String,
+    pub target_url: String,
+    pub target_pass: String,
+    // 写目的端的连接数, 命令按key分到各个连接上并行发送
+    pub target_connections: usize,
+    // 目的端返回错误时的处理: fail, retry, skip; 设置target.retries时为重试这么多次
+    pub reply_policy: ReplyPolicy,
+    // 目的端拒绝的命令写到死信文件, 按大小轮转
+    pub deadletter: DeadLetterOptions,
+    // redrive模式只重放这些类别的错误(OOM, BUSYKEY...), 为空时全部重放
+    pub redrive_errors: Vec<String>,
+    // 离线模式的输入输出文件
+    pub input: String,
+    pub output: String,
+    pub rdb_compression: bool,
+    // 输出文件整体的压缩格式, None表示按输出文件的扩展名(.gz, .zst, .lz4)
+    pub output_compression: Option<Compression>,
+    pub filter: Filter,
+    // rdb-diff模式和input比较的另一个文件
+    pub diff_input: String,
+    pub diff: DiffOptions,
+    // reshard模式的slot分布,为空时从reshard_cluster的CLUSTER SHARDS获取
+    pub reshard_slots: String,
+    pub reshard_cluster: String,
+    pub reshard_cluster_pass: String,
+    // aof回放到这个时间(秒)为止,0表示全部回放
+    pub aof_stop_at: u64,
+    // rump模式每次SCAN的数量
+    pub rump_batch: usize,
+    // keyspace模式有变化的key队列的上限,以及同一个key的去抖时间
+    pub keyspace_queue_size: usize,
+    pub keyspace_debounce_ms: u64,
+    // 目的端已有同名function library时的处理: replace, skip, fail
+    pub function_policy: FunctionPolicy,
+    // 目的端没有加载key对应的module(或者是不认识的module类型)时的处理: skip, fail
+    pub module_policy: ModulePolicy,
+    // 在途命令的上限(字节): 解析出来还没有发送到目的端的命令, 0表示不限制
+    // 不包括读取缓冲区和正在拆分的一段大key, 见utils::memory
+    pub inflight_limit: u64,
+    // rdb校验和不一致时的处理: fail, warn
+    pub crc_policy: CrcPolicy,
+    // rdb解析出错时的处理: abort, salvage(跳过坏数据继续)
+    pub error_policy: ErrorPolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mode: String::from("full"),
+            source_url: String::from("127.0.0.1:6379"),
+            source_pass: String::new(),
+            target_url: String::from("127.0.0.1:6400"),
+            target_pass: String::new(),
+            target_connections: 4,
+            reply_policy: ReplyPolicy::Skip,
+            deadletter: DeadLetterOptions::default(),
+            redrive_errors: vec![],
+            input: String::new(),
+            output: String::new(),
+            rdb_compression: true,
+            output_compression: None,
+            filter: Filter::default(),
+            diff_input: String::new(),
+            diff: DiffOptions::default(),
+            reshard_slots: String::new(),
+            reshard_cluster: String::new(),
+            reshard_cluster_pass: String::new(),
+            aof_stop_at: 0,
+            rump_batch: 100,
+            keyspace_queue_size: 1000000,
+            keyspace_debounce_ms: 100,
+            function_policy: FunctionPolicy::Replace,
+            module_policy: ModulePolicy::Fail,
+            inflight_limit: 1024 * 1024 * 1024,
+            crc_policy: CrcPolicy::Fail,
+            error_policy: ErrorPolicy::Abort,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Result<Config, Box<dyn Error>> {
+        Config::parse(std::env::args().skip(1).collect())
+    }
+    pub fn parse(args: Vec<String>) -> Result<Config, Box<dyn Error>> {
+        let mut conf = Config::default();
+        for arg in args {
+            if !arg.starts_with("--") {
+                conf.mode = arg;
+                continue;
+            }
+            let mut kv = arg[2..].splitn(2, '=');
+            let key = kv.next().unwrap_or("");
+            let value = kv.next().unwrap_or("");
+            conf.set(key, value)?;
+        }
+        Ok(conf)
+    }
+    fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        match key {
+            "source.address" => self.source_url = String::from(value),
+            "source.password" => self.source_pass = String::from(value),
+            "target.address" => self.target_url = String::from(value),
+            "target.password" => self.target_pass = String::from(value),
+            "target.connections" => self.target_connections = value.parse::<usize>()?,
+            "target.on_error" => self.reply_policy = ReplyPolicy::parse(value)?,
+            "target.retries" => self.reply_policy = ReplyPolicy::Retry(value.parse::<u32>()?),
+            "deadletter.path" => self.deadletter.path = String::from(value),
+            "deadletter.max_mb" => self.deadletter.max_bytes = value.parse::<u64>()? * 1024 * 1024,
+            "deadletter.max_files" => self.deadletter.max_files = value.parse::<usize>()?,
+            "redrive.errors" => self.redrive_errors = split_list(value).map(String::from).collect(),
+            "input" => self.input = String::from(value),
+            "output" => self.output = String::from(value),
+            "rdb.compression" => self.rdb_compression = value.parse::<bool>()?,
+            "output.compression" => {
+                self.output_compression = match value {
+                    "auto" => None,
+                    _ => Some(Compression::parse(value)?),
+                }
+            }
+            "diff.input" => self.diff_input = String::from(value),
+            "diff.partitions" => self.diff.partitions = value.parse::<usize>()?,
+            "diff.temp_dir" => self.diff.temp_dir = String::from(value),
+            "diff.ttl_tolerance_ms" => self.diff.ttl_tolerance_ms = value.parse::<u64>()?,
+            "diff.max_detail" => self.diff.max_detail = value.parse::<usize>()?,
+            "reshard.slots" => self.reshard_slots = String::from(value),
+            "reshard.cluster.address" => self.reshard_cluster = String::from(value),
+            "reshard.cluster.password" => self.reshard_cluster_pass = String::from(value),
+            "aof.stop_at" => self.aof_stop_at = value.parse::<u64>()?,
+            "rump.batch" => self.rump_batch = value.parse::<usize>()?,
+            "keyspace.queue_size" => self.keyspace_queue_size = value.parse::<usize>()?,
+            "keyspace.debounce_ms" => self.keyspace_debounce_ms = value.parse::<u64>()?,
+            "function.on_conflict" => self.function_policy = FunctionPolicy::parse(value)?,
+            "module.on_missing" => self.module_policy = ModulePolicy::parse(value)?,
+            "inflight.limit_mb" => self.inflight_limit = value.parse::<u64>()? * 1024 * 1024,
+            "rdb.on_crc_mismatch" => self.crc_policy = CrcPolicy::parse(value)?,
+            "rdb.on_parse_error" => self.error_policy = ErrorPolicy::parse(value)?,
+            "filter.db.whitelist" => self.filter.db_whitelist = parse_list(value)?,
+            "filter.db.blacklist" => self.filter.db_blacklist = parse_list(value)?,
+            "filter.key.whitelist" => {
+                self.filter.key_whitelist = split_list(value).map(|s| s.as_bytes().to_vec()).collect()
+            }
+            "filter.key.blacklist" => {
+                self.filter.key_blacklist = split_list(value).map(|s| s.as_bytes().to_vec()).collect()
+            }
+            "filter.type.whitelist" => {
+                self.filter.type_whitelist = split_list(value).map(String::from).collect()
+            }
+            _ => return Err(Box::from(format!("未知的参数 {}", key))),
+        };
+        Ok(())
+    }
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(';').filter(|s| !s.is_empty())
+}
+
+fn parse_list(value: &str) -> Result<Vec<u32>, Box<dyn Error>> {
+    let mut rsl = vec![];
+    for s in split_list(value) {
+        rsl.push(s.parse::<u32>()?);
+    }
+    Ok(rsl)
+}
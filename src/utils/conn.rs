@@ -1,4 +1,4 @@
-use crate::utils::cmd::cmd_to_resp_first_line;
+use crate::utils::resp::RespConn;
 use redis::{Client, aio::Connection};
 
 use std::error::Error;
@@ -7,12 +7,12 @@ use std::ops::Add;
 use async_std::net::TcpStream;
 
 
-pub async fn open_tcp_conn(url: &str, pass: &str) ->  Result<TcpStream, Box<dyn Error>>{
-    let mut source = TcpStream::connect(url).await?;
+pub async fn open_tcp_conn(url: &str, pass: &str) ->  Result<RespConn<TcpStream>, Box<dyn Error>>{
+    let mut source = RespConn::new(TcpStream::connect(url).await?);
     if !pass.is_empty() {
-        let auth_resp = cmd_to_resp_first_line(&mut source, vec!["auth", pass]).await?;
-        if auth_resp.contains("ERR") {
-            return Err(Box::from(auth_resp));
+        let auth_resp = source.request(&["auth", pass]).await?;
+        if auth_resp.is_error() {
+            return Err(Box::from(auth_resp.to_string()));
         } else {
             println!("auth success")
         }
@@ -25,11 +25,11 @@ pub async fn open_redis_sync_conn(
     pass: &str,
     mut index: &str,
 ) -> Result<Connection, Box<dyn Error>> {
-    if index == "" {
+    if index.is_empty() {
         index = "0"
     }
     let mut path = format!("redis://{}/{}", url, index);
-    if pass != "" {
+    if !pass.is_empty() {
         path = path.add(":");
         path = path.add(pass);
     }
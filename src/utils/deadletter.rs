@@ -0,0 +1,261 @@
+use crate::utils::apply::{args, command_keys, error_class, overwritten_keys};
+use crate::utils::clock::now_ms;
+use crate::utils::command::{CommandParser, Frame};
+use redis::Cmd;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// db和key
+pub type DbKey = (i64, Vec<u8>);
+
+// 死信文件: 目的端拒绝的命令
+// 格式和aof一样, 每个命令前面一行注释记录它的db, 源端偏移量, 时间(毫秒)和错误信息, 例如
+// #DL db=0 offset=1024 ts=1628217470000 err=OOM command not allowed when used memory > 'maxmemory'.
+// *3\r\n$3\r\nset\r\n...
+// 之后同一个key被整个覆盖(SET, DEL, RESTORE REPLACE等)时, 记录一行标记, 重放时跳过这个key更早的死信, 不会覆盖新的数据
+// HSET, RPUSH这类只修改一部分的命令不记录标记, 更早的死信仍然要重放
+// #DLSEEN db=0 offset=2048 key=6b6579
+// 文件按大小轮转, 依次为 path.1, path.2 ..., 只保留最新的max_files个
+#[derive(Clone, Debug)]
+pub struct DeadLetterOptions {
+    // 默认为空, 不写死信文件, 需要用--deadletter.path指定
+    pub path: String,
+    pub max_bytes: u64,
+    pub max_files: usize,
+}
+
+impl Default for DeadLetterOptions {
+    fn default() -> Self {
+        DeadLetterOptions {
+            path: String::new(),
+            max_bytes: 64 * 1024 * 1024,
+            max_files: 10,
+        }
+    }
+}
+
+// 一条死信的注释部分
+#[derive(Clone, Debug, PartialEq)]
+pub struct Letter {
+    pub db: i64,
+    // 全量阶段是rdb中的字节位置, 增量阶段是复制偏移量(aof为文件中的位置)
+    pub offset: u64,
+    pub ts: u64,
+    pub error: String,
+}
+
+impl Letter {
+    pub fn class(&self) -> String {
+        error_class(self.error.as_bytes())
+    }
+    pub fn encode(&self, cmd: &Cmd) -> Vec<u8> {
+        // 错误信息中不能有换行
+        let error: String = self.error.chars().map(|c| if c == '\r' || c == '\n' { ' ' } else { c }).collect();
+        let mut out = format!("#DL db={} offset={} ts={} err={}\r\n", self.db, self.offset, self.ts, error).into_bytes();
+        out.extend_from_slice(&cmd.get_packed_command());
+        out
+    }
+    // 解析注释, 不带开头的#
+    pub fn parse(line: &[u8]) -> Option<Letter> {
+        let line = std::str::from_utf8(line).ok()?;
+        let mut rest = line.strip_prefix("DL ")?;
+        let mut letter = Letter { db: 0, offset: 0, ts: 0, error: String::new() };
+        for field in &["db=", "offset=", "ts="] {
+            let value = rest.strip_prefix(field)?;
+            let end = value.find(' ')?;
+            let n = value[..end].parse::<u64>().ok()?;
+            match *field {
+                "db=" => letter.db = n as i64,
+                "offset=" => letter.offset = n,
+                _ => letter.ts = n,
+            }
+            rest = &value[end + 1..];
+        }
+        letter.error = rest.strip_prefix("err=")?.to_string();
+        Some(letter)
+    }
+}
+
+// 写过死信的key又被整个覆盖的标记, key按十六进制编码
+#[derive(Clone, Debug, PartialEq)]
+pub struct Seen {
+    pub db: i64,
+    pub offset: u64,
+    pub key: Vec<u8>,
+}
+
+impl Seen {
+    pub fn encode(&self) -> Vec<u8> {
+        let key: String = self.key.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("#DLSEEN db={} offset={} key={}\r\n", self.db, self.offset, key).into_bytes()
+    }
+    // 解析注释, 不带开头的#
+    pub fn parse(line: &[u8]) -> Option<Seen> {
+        let line = std::str::from_utf8(line).ok()?;
+        let mut fields = line.strip_prefix("DLSEEN ")?.split(' ');
+        let db = fields.next()?.strip_prefix("db=")?.parse::<i64>().ok()?;
+        let offset = fields.next()?.strip_prefix("offset=")?.parse::<u64>().ok()?;
+        let hex = fields.next()?.strip_prefix("key=")?;
+        if hex.len() % 2 != 0 {
+            return None;
+        }
+        let mut key = Vec::with_capacity(hex.len() / 2);
+        for i in (0..hex.len()).step_by(2) {
+            key.push(u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()?);
+        }
+        Some(Seen { db, offset, key })
+    }
+}
+
+// 已经存在的死信文件, 按编号排序
+pub fn segments(path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let p = Path::new(path);
+    let dir = match p.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let name = match p.file_name() {
+        Some(d) => format!("{}.", d.to_string_lossy()),
+        None => return Err(Box::from(format!("错误的死信文件路径 {}", path))),
+    };
+    let mut files = vec![];
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    for entry in fs::read_dir(dir)? {
+        let file = entry?.file_name().to_string_lossy().to_string();
+        if let Some(n) = file.strip_prefix(&name).and_then(|n| n.parse::<u64>().ok()) {
+            files.push((n, dir.join(&file).to_string_lossy().to_string()));
+        }
+    }
+    files.sort();
+    Ok(files.into_iter().map(|(_, file)| file).collect())
+}
+
+struct Segment {
+    file: Option<File>,
+    // 当前文件的编号和大小
+    index: u64,
+    size: u64,
+}
+
+pub struct DeadLetter {
+    opts: DeadLetterOptions,
+    segment: Mutex<Segment>,
+    // 写过死信, 之后还没有被成功写入的key
+    pending: Mutex<HashSet<DbKey>>,
+}
+
+impl DeadLetter {
+    // 第一次写入时才创建文件, 编号接着已有的文件
+    pub fn open(opts: &DeadLetterOptions) -> Result<Option<DeadLetter>, Box<dyn Error>> {
+        if opts.path.is_empty() {
+            return Ok(None);
+        }
+        let last = segments(&opts.path)?
+            .last()
+            .and_then(|f| f.rsplit('.').next().and_then(|n| n.parse::<u64>().ok()))
+            .unwrap_or(0);
+        Ok(Some(DeadLetter {
+            opts: opts.clone(),
+            segment: Mutex::new(Segment { file: None, index: last, size: 0 }),
+            pending: Mutex::new(HashSet::new()),
+        }))
+    }
+    pub fn path(&self) -> &str {
+        &self.opts.path
+    }
+    // 写入后立即落盘, 出错退出时不会丢失
+    pub fn write(&self, db: i64, offset: u64, error: &[u8], cmd: &Cmd) -> io::Result<()> {
+        let letter = Letter {
+            db,
+            offset,
+            ts: now_ms(),
+            error: String::from_utf8_lossy(error).to_string(),
+        };
+        self.append(&letter.encode(cmd))?;
+        if let Some(keys) = command_keys(&args(cmd)) {
+            let mut pending = self.pending.lock().unwrap();
+            for key in keys {
+                pending.insert((db, key.to_vec()));
+            }
+        }
+        Ok(())
+    }
+    // 目的端成功执行了一个命令, 写过死信的key第一次被整个覆盖时记录标记
+    pub fn applied(&self, db: i64, offset: u64, cmd: &Cmd) -> io::Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let args = args(cmd);
+        for key in overwritten_keys(&args) {
+            if pending.remove(&(db, key.to_vec())) {
+                self.append(&Seen { db, offset, key: key.to_vec() }.encode())?;
+            }
+        }
+        Ok(())
+    }
+    fn append(&self, data: &[u8]) -> io::Result<()> {
+        let mut segment = self.segment.lock().unwrap();
+        if segment.file.is_none() || (segment.size > 0 && segment.size + data.len() as u64 > self.opts.max_bytes) {
+            self.rotate(&mut segment)?;
+        }
+        if let Some(file) = segment.file.as_mut() {
+            file.write_all(data)?;
+            file.flush()?;
+        }
+        segment.size = segment.size + data.len() as u64;
+        Ok(())
+    }
+    fn rotate(&self, segment: &mut Segment) -> io::Result<()> {
+        segment.index = segment.index + 1;
+        let path = format!("{}.{}", self.opts.path, segment.index);
+        segment.file = Some(OpenOptions::new().create(true).append(true).open(&path)?);
+        segment.size = 0;
+        println!("写入死信文件 {}", path);
+        // 删除最旧的文件
+        let files = segments(&self.opts.path).map_err(|e| io::Error::other(e.to_string()))?;
+        if self.opts.max_files > 0 && files.len() > self.opts.max_files {
+            for old in &files[..files.len() - self.opts.max_files] {
+                fs::remove_file(old)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// 读取全部死信文件中的标记, 返回每个key最后一个标记的序号
+// 死信和标记按在文件中的顺序编号, 从1开始, 序号比标记小的死信已经被覆盖
+pub fn superseded(files: &[String]) -> Result<HashMap<DbKey, u64>, Box<dyn Error>> {
+    let mut last = HashMap::new();
+    let mut seq = 0u64;
+    for file in files {
+        let mut reader = File::open(file)?;
+        let mut parser = CommandParser::new();
+        let mut p = vec![0; 64 * 1024];
+        loop {
+            while let Some(frame) = parser.next_frame()? {
+                if let Frame::Comment(line, _) = frame {
+                    if Letter::parse(&line).is_some() {
+                        seq = seq + 1;
+                    } else if let Some(seen) = Seen::parse(&line) {
+                        seq = seq + 1;
+                        last.insert((seen.db, seen.key), seq);
+                    }
+                }
+            }
+            let n = reader.read(&mut p)?;
+            if n == 0 {
+                break;
+            }
+            parser.feed(&p[..n]);
+        }
+    }
+    Ok(last)
+}
@@ -0,0 +1,34 @@
+use crate::rdb::loader::{rdbTypeName, BinEntry};
+
+// 按db,key前缀,类型过滤,白名单为空表示不限制
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    pub db_whitelist: Vec<u32>,
+    pub db_blacklist: Vec<u32>,
+    pub key_whitelist: Vec<Vec<u8>>,
+    pub key_blacklist: Vec<Vec<u8>>,
+    pub type_whitelist: Vec<String>,
+}
+
+impl Filter {
+    // 返回true表示这个db需要被过滤掉
+    pub fn filter_db(&self, db: u32) -> bool {
+        if self.db_blacklist.contains(&db) {
+            return true;
+        }
+        !self.db_whitelist.is_empty() && !self.db_whitelist.contains(&db)
+    }
+    pub fn filter_key(&self, key: &[u8]) -> bool {
+        if self.key_blacklist.iter().any(|p| key.starts_with(p)) {
+            return true;
+        }
+        !self.key_whitelist.is_empty() && !self.key_whitelist.iter().any(|p| key.starts_with(p))
+    }
+    pub fn filter_type(&self, t: u8) -> bool {
+        !self.type_whitelist.is_empty()
+            && !self.type_whitelist.iter().any(|name| name == rdbTypeName(t))
+    }
+    pub fn filter_entry(&self, e: &BinEntry) -> bool {
+        self.filter_db(e.DB) || self.filter_key(&e.Key) || self.filter_type(e.Type)
+    }
+}
@@ -0,0 +1,95 @@
+use crate::rdb::loader::defaultMaxChunk;
+use redis::{Arg, Cmd};
+
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Notify;
+
+// 在途命令的上限: 已经解析出来, 还没有发送到目的端的命令按参数字节数计数
+// 超过上限时解析方等待发送方释放
+// 只统计命令本身, 不包括读取rdb的缓冲区(10MB)和正在拆分的一段大key(chunk_size, 解析时有几份拷贝),
+// 进程实际占用的内存大约是 上限 + 几倍chunk_size + 固定的缓冲区
+pub struct InflightLimit {
+    // 0表示不限制
+    limit: u64,
+    used: AtomicU64,
+    released: Notify,
+}
+
+impl InflightLimit {
+    pub fn new(limit: u64) -> Arc<InflightLimit> {
+        Arc::new(InflightLimit {
+            limit,
+            used: AtomicU64::new(0),
+            released: Notify::new(),
+        })
+    }
+    // 大key拆分时每段的大小. 一段数据在解析时会有几份拷贝, 留出余量
+    pub fn chunk_size(&self) -> usize {
+        if self.limit == 0 {
+            return defaultMaxChunk;
+        }
+        std::cmp::min(defaultMaxChunk, (self.limit / 8) as usize)
+    }
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+    // 单个命令比上限还大时, 等在途的命令都发送完再放行, 否则会一直等下去
+    pub async fn acquire(&self, n: u64) {
+        if self.limit != 0 {
+            loop {
+                let used = self.used();
+                if used == 0 || used + n <= self.limit {
+                    break;
+                }
+                // release先于这里的等待时, Notify会保留这次通知, 不会丢
+                self.released.notified().await;
+            }
+        }
+        self.used.fetch_add(n, Ordering::SeqCst);
+    }
+    pub fn release(&self, n: u64) {
+        if n == 0 {
+            return;
+        }
+        self.used.fetch_sub(n, Ordering::SeqCst);
+        self.released.notify();
+    }
+}
+
+// 命令参数的总字节数
+pub fn cmd_size(cmd: &Cmd) -> u64 {
+    cmd.args_iter()
+        .map(|arg| match arg {
+            Arg::Simple(d) => d.len() as u64,
+            Arg::Cursor => 0,
+        })
+        .sum()
+}
+
+// 受在途上限约束的命令发送端, 接收方把命令发送到目的端之后release
+// 命令带上offset一起发送, 即当前entry在rdb中的位置, 写死信文件时使用
+#[derive(Clone)]
+pub struct CmdSender {
+    sender: Sender<(Cmd, u64)>,
+    pub inflight: Arc<InflightLimit>,
+    pub offset: u64,
+}
+
+impl CmdSender {
+    pub fn new(sender: Sender<(Cmd, u64)>, inflight: Arc<InflightLimit>) -> CmdSender {
+        CmdSender { sender, inflight, offset: 0 }
+    }
+    // 接收方已经退出时(目的端出错)归还额度并返回错误, 解析方停止
+    pub async fn send(&mut self, cmd: Cmd) -> Result<(), Box<dyn Error>> {
+        let size = cmd_size(&cmd);
+        self.inflight.acquire(size).await;
+        if self.sender.send((cmd, self.offset)).await.is_err() {
+            self.inflight.release(size);
+            return Err(Box::from("命令发送已经停止"));
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,14 @@
 pub mod conn;
-pub mod cmd;
+pub mod resp;
 pub mod source;
-pub mod run;
\ No newline at end of file
+pub mod run;
+pub mod clock;
+pub mod filter;
+pub mod config;
+pub mod slot;
+pub mod aof;
+pub mod version;
+pub mod memory;
+pub mod command;
+pub mod apply;
+pub mod deadletter;
\ No newline at end of file
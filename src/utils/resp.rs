@@ -0,0 +1,398 @@
+use futures_util::io::{AsyncRead, AsyncWrite};
+use futures_util::{AsyncReadExt, AsyncWriteExt};
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// RESP2/RESP3的值, 字符串都是二进制安全的
+#[derive(Clone, Debug, PartialEq)]
+pub enum Resp {
+    Simple(Vec<u8>),
+    // -ERR 和 RESP3的!bulk error
+    Error(Vec<u8>),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Resp>),
+    // $-1, *-1 和 RESP3的_
+    Null,
+    Boolean(bool),
+    Double(f64),
+    BigNumber(Vec<u8>),
+    // =txt:xxx, 格式和内容
+    Verbatim(Vec<u8>, Vec<u8>),
+    Map(Vec<(Resp, Resp)>),
+    Set(Vec<Resp>),
+    Push(Vec<Resp>),
+}
+
+impl Resp {
+    pub fn is_error(&self) -> bool {
+        matches!(self, Resp::Error(_))
+    }
+}
+
+impl fmt::Display for Resp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resp::Simple(s) | Resp::Error(s) | Resp::Bulk(s) | Resp::BigNumber(s) | Resp::Verbatim(_, s) => {
+                write!(f, "{}", String::from_utf8_lossy(s))
+            }
+            Resp::Integer(i) => write!(f, "{}", i),
+            Resp::Null => write!(f, "(nil)"),
+            Resp::Boolean(b) => write!(f, "{}", b),
+            Resp::Double(d) => write!(f, "{}", d),
+            Resp::Array(items) | Resp::Set(items) | Resp::Push(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Resp::Map(items) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}:{}", k, v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+// 单个bulk的上限, 和redis的proto-max-bulk-len默认值一致
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+// 数组元素个数的上限, 防止错误的长度申请过多内存
+const MAX_ARRAY_LEN: i64 = 1024 * 1024 * 1024;
+// 嵌套的层数上限, 防止恶意数据导致栈溢出
+const MAX_DEPTH: usize = 128;
+
+fn write_line(out: &mut Vec<u8>, prefix: u8, s: &[u8]) {
+    out.push(prefix);
+    out.extend_from_slice(s);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn write_blob(out: &mut Vec<u8>, prefix: u8, s: &[u8]) {
+    write_line(out, prefix, format!("{}", s.len()).as_bytes());
+    out.extend_from_slice(s);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn write_items(out: &mut Vec<u8>, prefix: u8, items: &[Resp], resp3: bool) {
+    write_line(out, prefix, format!("{}", items.len()).as_bytes());
+    for item in items {
+        encode_value(item, out, resp3);
+    }
+}
+
+// 按RESP2编码, null为$-1
+pub fn encode(value: &Resp, out: &mut Vec<u8>) {
+    encode_value(value, out, false)
+}
+
+// 协商了RESP3(HELLO 3)之后使用, null为_
+pub fn encode_resp3(value: &Resp, out: &mut Vec<u8>) {
+    encode_value(value, out, true)
+}
+
+fn encode_value(value: &Resp, out: &mut Vec<u8>, resp3: bool) {
+    match value {
+        Resp::Simple(s) => write_line(out, b'+', s),
+        Resp::Error(s) => write_line(out, b'-', s),
+        Resp::Integer(i) => write_line(out, b':', format!("{}", i).as_bytes()),
+        Resp::Bulk(s) => write_blob(out, b'$', s),
+        Resp::Array(items) => write_items(out, b'*', items, resp3),
+        Resp::Null if resp3 => out.extend_from_slice(b"_\r\n"),
+        Resp::Null => out.extend_from_slice(b"$-1\r\n"),
+        Resp::Boolean(b) => write_line(out, b'#', if *b { b"t" } else { b"f" }),
+        Resp::Double(d) => {
+            let s = if d.is_infinite() {
+                if *d > 0.0 { "inf".to_string() } else { "-inf".to_string() }
+            } else {
+                format!("{}", d)
+            };
+            write_line(out, b',', s.as_bytes())
+        }
+        Resp::BigNumber(s) => write_line(out, b'(', s),
+        Resp::Verbatim(format, s) => {
+            let mut data = format.clone();
+            data.push(b':');
+            data.extend_from_slice(s);
+            write_blob(out, b'=', &data)
+        }
+        Resp::Map(items) => {
+            write_line(out, b'%', format!("{}", items.len()).as_bytes());
+            for (k, v) in items {
+                encode_value(k, out, resp3);
+                encode_value(v, out, resp3);
+            }
+        }
+        Resp::Set(items) => write_items(out, b'~', items, resp3),
+        Resp::Push(items) => write_items(out, b'>', items, resp3),
+    }
+}
+
+// 命令编码成bulk数组, 参数可以是任意字节
+pub fn encode_command<A: AsRef<[u8]>>(args: &[A]) -> Vec<u8> {
+    let mut out = vec![];
+    write_line(&mut out, b'*', format!("{}", args.len()).as_bytes());
+    for arg in args {
+        write_blob(&mut out, b'$', arg.as_ref());
+    }
+    out
+}
+
+// 从pos开始找\r\n, 返回这一行的内容和下一行的开始
+fn line(buf: &[u8], pos: usize) -> Option<(&[u8], usize)> {
+    let mut i = pos;
+    while i + 1 < buf.len() {
+        if buf[i] == b'\r' && buf[i + 1] == b'\n' {
+            return Some((&buf[pos..i], i + 2));
+        }
+        i = i + 1;
+    }
+    None
+}
+
+pub fn parse_int(s: &[u8]) -> Result<i64, Box<dyn Error>> {
+    let v = std::str::from_utf8(s)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .ok_or_else(|| format!("resp: 错误的整数 {:?}", String::from_utf8_lossy(s)))?;
+    Ok(v)
+}
+
+// 长度为-1表示null
+fn parse_len(s: &[u8], max: i64) -> Result<Option<usize>, Box<dyn Error>> {
+    let n = parse_int(s)?;
+    if n == -1 {
+        return Ok(None);
+    }
+    if n < 0 || n > max {
+        return Err(Box::from(format!("resp: 错误的长度 {}", n)));
+    }
+    Ok(Some(n as usize))
+}
+
+// 解析出的值和下一个位置, 数据不够时为None
+type Parsed<T> = Result<Option<(T, usize)>, Box<dyn Error>>;
+
+fn blob(buf: &[u8], pos: usize, n: usize) -> Parsed<Vec<u8>> {
+    if buf.len() < pos + n + 2 {
+        return Ok(None);
+    }
+    if &buf[pos + n..pos + n + 2] != b"\r\n" {
+        return Err(Box::from("resp: bulk后面不是\\r\\n"));
+    }
+    Ok(Some((buf[pos..pos + n].to_vec(), pos + n + 2)))
+}
+
+fn items(buf: &[u8], mut pos: usize, n: usize, depth: usize) -> Parsed<Vec<Resp>> {
+    // 元素个数来自网络, 按实际解析出来的增长
+    let mut items = Vec::with_capacity(std::cmp::min(n, 1024));
+    for _ in 0..n {
+        match decode_at(buf, pos, depth)? {
+            Some((v, next)) => {
+                items.push(v);
+                pos = next;
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some((items, pos)))
+}
+
+// depth为当前的嵌套层数
+fn decode_at(buf: &[u8], pos: usize, depth: usize) -> Parsed<Resp> {
+    if pos >= buf.len() {
+        return Ok(None);
+    }
+    if depth > MAX_DEPTH {
+        return Err(Box::from(format!("resp: 嵌套超过{}层", MAX_DEPTH)));
+    }
+    let (s, next) = match line(buf, pos + 1) {
+        Some(d) => d,
+        None => return Ok(None),
+    };
+    let value = match buf[pos] {
+        b'+' => Resp::Simple(s.to_vec()),
+        b'-' => Resp::Error(s.to_vec()),
+        b':' => Resp::Integer(parse_int(s)?),
+        b'_' => Resp::Null,
+        b'#' => match s {
+            b"t" => Resp::Boolean(true),
+            b"f" => Resp::Boolean(false),
+            _ => return Err(Box::from(format!("resp: 错误的boolean {:?}", String::from_utf8_lossy(s)))),
+        },
+        b',' => {
+            let d = std::str::from_utf8(s)
+                .ok()
+                .and_then(|s| s.parse::<f64>().ok())
+                .ok_or_else(|| format!("resp: 错误的double {:?}", String::from_utf8_lossy(s)))?;
+            Resp::Double(d)
+        }
+        b'(' => Resp::BigNumber(s.to_vec()),
+        b'$' | b'!' | b'=' => {
+            let n = match parse_len(s, MAX_BULK_LEN)? {
+                Some(n) => n,
+                None => return Ok(Some((Resp::Null, next))),
+            };
+            let (data, end) = match blob(buf, next, n)? {
+                Some(d) => d,
+                None => return Ok(None),
+            };
+            let value = match buf[pos] {
+                b'$' => Resp::Bulk(data),
+                b'!' => Resp::Error(data),
+                _ => {
+                    if data.len() < 4 || data[3] != b':' {
+                        return Err(Box::from("resp: 错误的verbatim string"));
+                    }
+                    Resp::Verbatim(data[..3].to_vec(), data[4..].to_vec())
+                }
+            };
+            return Ok(Some((value, end)));
+        }
+        b'*' | b'~' | b'>' => {
+            let n = match parse_len(s, MAX_ARRAY_LEN)? {
+                Some(n) => n,
+                None => return Ok(Some((Resp::Null, next))),
+            };
+            let (items, end) = match items(buf, next, n, depth + 1)? {
+                Some(d) => d,
+                None => return Ok(None),
+            };
+            let value = match buf[pos] {
+                b'*' => Resp::Array(items),
+                b'~' => Resp::Set(items),
+                _ => Resp::Push(items),
+            };
+            return Ok(Some((value, end)));
+        }
+        b'%' | b'|' => {
+            let n = match parse_len(s, MAX_ARRAY_LEN / 2)? {
+                Some(n) => n,
+                None => return Ok(Some((Resp::Null, next))),
+            };
+            let (items, end) = match items(buf, next, n * 2, depth + 1)? {
+                Some(d) => d,
+                None => return Ok(None),
+            };
+            if buf[pos] == b'|' {
+                // attribute是附加信息, 跳过, 返回后面真正的值
+                return decode_at(buf, end, depth + 1);
+            }
+            let mut pairs = Vec::with_capacity(n);
+            let mut it = items.into_iter();
+            while let (Some(k), Some(v)) = (it.next(), it.next()) {
+                pairs.push((k, v));
+            }
+            return Ok(Some((Resp::Map(pairs), end)));
+        }
+        b => return Err(Box::from(format!("resp: 未知的类型 {:?}", b as char))),
+    };
+    Ok(Some((value, next)))
+}
+
+// 从buf开头解析一个完整的值, 返回值和用掉的字节数, 数据不完整时返回None
+pub fn decode(buf: &[u8]) -> Result<Option<(Resp, usize)>, Box<dyn Error>> {
+    decode_at(buf, 0, 0)
+}
+
+// 带缓冲的RESP连接, 缓冲中剩下的数据可以继续通过AsyncRead读取(例如PSYNC之后的rdb)
+pub struct RespConn<S> {
+    inner: S,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<S> RespConn<S> {
+    pub fn new(inner: S) -> RespConn<S> {
+        RespConn {
+            inner,
+            buf: Vec::with_capacity(16 * 1024),
+            pos: 0,
+        }
+    }
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> RespConn<S> {
+    async fn fill(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        let mut p = [0u8; 16 * 1024];
+        let n = self.inner.read(&mut p).await?;
+        if n == 0 {
+            return Err(Box::from("resp: 连接已关闭"));
+        }
+        self.buf.extend_from_slice(&p[..n]);
+        Ok(())
+    }
+    pub async fn read_value(&mut self) -> Result<Resp, Box<dyn Error>> {
+        loop {
+            if let Some((value, n)) = decode(&self.buf[self.pos..])? {
+                self.pos = self.pos + n;
+                return Ok(value);
+            }
+            self.fill().await?;
+        }
+    }
+    // 读取一行, 不包括结尾的\r\n, PSYNC之后rdb的长度不是完整的RESP值
+    pub async fn read_line(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        loop {
+            if let Some(i) = self.buf[self.pos..].iter().position(|b| *b == b'\n') {
+                let mut line = self.buf[self.pos..self.pos + i].to_vec();
+                self.pos = self.pos + i + 1;
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(line);
+            }
+            self.fill().await?;
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> RespConn<S> {
+    pub async fn send<A: AsRef<[u8]>>(&mut self, args: &[A]) -> Result<(), Box<dyn Error>> {
+        self.inner.write_all(&encode_command(args)).await?;
+        Ok(())
+    }
+    // 发送已经编码好的数据, 例如一批命令
+    pub async fn write_packed(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.inner.write_all(data).await?;
+        Ok(())
+    }
+    // 发送命令并读取一个回复
+    pub async fn request<A: AsRef<[u8]>>(&mut self, args: &[A]) -> Result<Resp, Box<dyn Error>> {
+        self.send(args).await?;
+        self.read_value().await
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for RespConn<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, p: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.pos < this.buf.len() {
+            let n = std::cmp::min(p.len(), this.buf.len() - this.pos);
+            p[..n].copy_from_slice(&this.buf[this.pos..this.pos + n]);
+            this.pos = this.pos + n;
+            return Poll::Ready(Ok(n));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, p)
+    }
+}
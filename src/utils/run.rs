@@ -1,46 +1,80 @@
+// 模块名沿用Go版
+#[allow(non_snake_case)]
 pub mod Runner {
     use crate::rdb::full::full;
     use crate::rdb::incr::incr;
+    use crate::rdb::function::{FunctionPolicy, Functions};
+    use crate::rdb::module::{ModulePolicy, Modules};
+    use crate::rdb::salvage::{CrcPolicy, ErrorPolicy};
+    use crate::rdb::rump::rump;
+    use crate::rdb::keyspace::keyspace;
     use crate::rdb::loader::Loader;
-    use crate::utils::conn::{open_tcp_conn, open_redis_sync_conn};
-    use crate::utils::source::{pre_to_inc, pre_to_rdb, report_offset};
+    use crate::utils::conn::open_tcp_conn;
+    use crate::utils::aof::{aof_files, has_rdb_preamble};
+    use crate::utils::source::{open_files, open_rdb_file, pre_to_inc, pre_to_rdb, report_offset};
+    use crate::utils::filter::Filter;
+    use crate::utils::memory::{cmd_size, CmdSender, InflightLimit};
+    use crate::utils::apply::{command_keys, Applier, ReplyPolicy};
+    use crate::utils::deadletter::{segments, superseded, DeadLetter, DeadLetterOptions, Letter, Seen};
+    use crate::utils::command::{CommandParser, Frame};
+    use crate::rdb::loader::{BinEntry, rdbFlagFunction2, RdbFlagAUX};
+    use crate::rdb::writer::Writer;
+    use crate::rdb::compress::Compression;
+    use crate::rdb::diff::{diff, DiffOptions, DiffSummary};
+    use crate::utils::slot::SlotLayout;
+    use crate::utils::version::target_version;
+    use std::error::Error;
     use crate::{atomic_u64_fetch_add, atomic_u64_load, source_report_offset};
-    use redis::{Cmd, Value, RedisResult};
+    use redis::Cmd;
     use std::cell::RefCell;
 
-    
     use std::process::exit;
     use std::rc::Rc;
     use std::sync::atomic::{AtomicU64, Ordering};
     use std::sync::Arc;
-    use async_std::task::{spawn,sleep,yield_now};
+    use async_std::task::{spawn,sleep,JoinHandle};
     use std::time::Duration;
-    
-    
-    
     use futures_util::AsyncReadExt;
-    
     use tokio::io::{AsyncWriteExt, BufReader};
     use tokio::sync::mpsc::channel;
-    use tokio::sync::mpsc::error::TryRecvError;
     use async_std::io::{BufReader as AsyncBufReader};
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn mod_full(
         source_url: &'static str,
         source_pass: &'static str,
         target_url: &'static str,
         target_pass: &'static str,
-    ) {
-        let mut source = open_tcp_conn(source_url, source_pass).await.unwrap();
+        filter: &Filter,
+        function_policy: FunctionPolicy,
+        module_policy: ModulePolicy,
+        inflight_limit: u64,
+        crc_policy: CrcPolicy,
+        error_policy: ErrorPolicy,
+        connections: usize,
+        reply_policy: ReplyPolicy,
+        deadletter: &DeadLetterOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let inflight = InflightLimit::new(inflight_limit);
+        let deadletter = DeadLetter::open(deadletter)?.map(Arc::new);
+        let functions = Functions::load_target(target_url, target_pass, function_policy).await?;
+        let mut modules = Modules::load_target(target_url, target_pass, module_policy).await?;
+        let target = target_version(target_url, target_pass).await?;
+        let mut source = open_tcp_conn(source_url, source_pass).await?;
 
-        let (offset, rdb_size, uuid) = pre_to_rdb(&mut source).await.unwrap();
+        let (offset, rdb_size, uuid) = pre_to_rdb(&mut source).await?;
 
-        let mut source_buf = AsyncBufReader::with_capacity(10*1024*1024,source.clone());
+        // 上报offset用的连接, 读取用带缓冲的RespConn, 里面可能已经有一部分rdb
+        let mut source_stream = source.get_ref().clone();
+        let mut source_buf = AsyncBufReader::with_capacity(10*1024*1024,source);
 
         // 带缓存的管道
         let (mut pipe_writer, pipe_reader) = async_pipe::pipe();
         let pipe_reader_buf = BufReader::with_capacity(10*1024*1024,pipe_reader);
         let mut loader = Loader::new(Rc::new(RefCell::new(pipe_reader_buf)));
+        loader.rdbReader.maxChunk = inflight.chunk_size();
+        loader.crcPolicy = crc_policy;
+        loader.errorPolicy = error_policy;
 
         let rdb_read_count = Arc::new(AtomicU64::new(0));
         let rdb_read_count_c = rdb_read_count.clone();
@@ -53,7 +87,7 @@ pub mod Runner {
         let offset_count_c = offset_count.clone();
         // 读取源端数据
         spawn(async move {
-            let mut source_c = source.clone();
+            let mut source_c = source_stream.clone();
             source_report_offset!(source_c, offset_count);
             let mut p = [0; 512*1024];
             // 全量的数据
@@ -103,20 +137,21 @@ pub mod Runner {
                 } else {
                     // todo
                     // 没有读取到,只有错误的时候没有读取到?
-                    let re_connect_conn = match open_tcp_conn(source_url, source_pass).await {
+                    let mut re_connect_conn = match open_tcp_conn(source_url, source_pass).await {
                         Ok(d) => d,
                         Err(_e) => {
                             continue
                         },
                     };
                     match pre_to_inc(
-                        &mut source,
+                        &mut re_connect_conn,
                         uuid.as_ref(),
                         format!("{}", offset_count_c.load(Ordering::SeqCst) + 1).as_ref(),
                     ).await {
                         Ok(()) => {
+                            source_stream = re_connect_conn.get_ref().clone();
                             let offset_count_c_1 = offset_count_c.clone();
-                            let mut source_c = source.clone();
+                            let mut source_c = source_stream.clone();
                             source_report_offset!(source_c, offset_count_c_1);
                         }
                         Err(_e) => {
@@ -143,57 +178,434 @@ pub mod Runner {
         println!("读取RDB文件头部!");
         println!("rdb头部为 {:?}", loader.Header().await);
         // 全量rdb的命令
-        let (mut full_cmd_sender, mut full_cmd_receiver) = channel::<Cmd>(20000);
-        spawn(async move {
-            let mut pipe = redis::pipe();
-            let mut full_cmd_count = 0;
-            let mut target_conn = open_redis_sync_conn(target_url, target_pass, "").await.unwrap();
+        let (mut full_cmd_sender, full_handle) = spawn_full_sender(target_url, target_pass, connections, reply_policy, deadletter.clone(), rdb_status_c, inflight.clone());
+        let result = full(&mut loader, &mut full_cmd_sender, filter, &functions, &mut modules, target).await;
+        drop(full_cmd_sender);
+        // 目的端出错时发送方先停止, 返回它的错误
+        full_handle.await?;
+        result?;
+        // 等待RDB完成命令发送
+        loop {
+            let ird = atomic_u64_load!(rdb_status_c1);
+            if ird!=2 {
+                sleep(Duration::from_millis(100)).await;
+            } else {
+                break;
+            }
+        }
+        incr(&mut loader, target_url, target_pass, connections, reply_policy, deadletter, offset as u64, 0, function_policy, inflight).await
+    }
+
+    // 回放aof文件, rdb的部分走全量, 命令的部分走增量
+    #[allow(clippy::too_many_arguments)]
+    pub async fn mod_aof(
+        input: &str,
+        target_url: &'static str,
+        target_pass: &'static str,
+        filter: &Filter,
+        stop_at_ts: u64,
+        function_policy: FunctionPolicy,
+        module_policy: ModulePolicy,
+        inflight_limit: u64,
+        crc_policy: CrcPolicy,
+        error_policy: ErrorPolicy,
+        connections: usize,
+        reply_policy: ReplyPolicy,
+        deadletter: &DeadLetterOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let inflight = InflightLimit::new(inflight_limit);
+        let deadletter = DeadLetter::open(deadletter)?.map(Arc::new);
+        let files = aof_files(input)?;
+        if files.is_empty() {
+            return Err(Box::from("没有需要回放的aof文件"));
+        }
+        let preamble = has_rdb_preamble(&files[0])?;
+        let mut loader = open_files(files).await?;
+        loader.rdbReader.maxChunk = inflight.chunk_size();
+        loader.crcPolicy = crc_policy;
+        loader.errorPolicy = error_policy;
+        if preamble {
+            println!("rdb头部为 {:?}", loader.Header().await?);
+            let functions = Functions::load_target(target_url, target_pass, function_policy).await?;
+            let mut modules = Modules::load_target(target_url, target_pass, module_policy).await?;
+            let target = target_version(target_url, target_pass).await?;
+            let rdb_status = Arc::new(AtomicU64::new(0));
+            let (mut full_cmd_sender, full_handle) = spawn_full_sender(target_url, target_pass, connections, reply_policy, deadletter.clone(), rdb_status.clone(), inflight.clone());
+            let result = full(&mut loader, &mut full_cmd_sender, filter, &functions, &mut modules, target).await;
+            drop(full_cmd_sender);
+            full_handle.await?;
+            result?;
+            atomic_u64_fetch_add!(rdb_status, 1);
+            // 等待RDB完成命令发送
+            while atomic_u64_load!(rdb_status) != 2 {
+                sleep(Duration::from_millis(100)).await;
+            }
+        }
+        // 死信中记录aof中的位置
+        let start_offset = loader.rdbReader.nread as u64;
+        incr(&mut loader, target_url, target_pass, connections, reply_policy, deadletter, start_offset, stop_at_ts, function_policy, inflight).await
+    }
+
+    // 源端禁用了psync时,用scan的方式做全量
+    pub async fn mod_rump(
+        source_url: &str,
+        source_pass: &str,
+        target_url: &str,
+        target_pass: &str,
+        filter: &Filter,
+        batch: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        rump(source_url, source_pass, target_url, target_pass, filter, batch).await
+    }
+
+    // 源端禁用了psync时,通过keyspace notification做增量
+    pub async fn mod_keyspace(
+        source_url: &'static str,
+        source_pass: &'static str,
+        target_url: &'static str,
+        target_pass: &'static str,
+        filter: &'static Filter,
+        queue_size: usize,
+        debounce_ms: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        keyspace(
+            source_url,
+            source_pass,
+            target_url,
+            target_pass,
+            filter,
+            queue_size,
+            Duration::from_millis(debounce_ms),
+        ).await
+    }
+
+    // 把死信文件中的命令重新发送到目的端, errors不为空时只重放这些类别的错误
+    // input可以是单个死信文件, 也可以是死信文件的前缀, 这时按编号读取全部文件
+    pub async fn mod_redrive(
+        input: &str,
+        target_url: &'static str,
+        target_pass: &'static str,
+        connections: usize,
+        reply_policy: ReplyPolicy,
+        deadletter: &DeadLetterOptions,
+        errors: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        let files = if std::path::Path::new(input).is_file() {
+            vec![String::from(input)]
+        } else {
+            segments(input)?
+        };
+        if files.is_empty() {
+            return Err(Box::from(format!("没有找到死信文件 {}", input)));
+        }
+        // 重放时再次失败的命令写到新的死信文件, 和输入相同时轮转可能会删掉还没有读取的文件
+        let deadletter = if deadletter.path == input {
+            println!("死信文件和输入相同, 重放失败的命令不再记录");
+            None
+        } else {
+            DeadLetter::open(deadletter)?.map(Arc::new)
+        };
+        let inflight = InflightLimit::new(0);
+        let mut applier = Applier::new(target_url, target_pass, connections, inflight.clone(), reply_policy, deadletter);
+        let stats = applier.stats.clone();
+        let last_seen = superseded(&files)?;
+        let (mut replay_count, mut filter_count, mut superseded_count) = (0u64, 0u64, 0u64);
+        // 和superseded中一样给死信和标记编号
+        let mut seq = 0u64;
+        let mut db = 0;
+        for file in files.iter() {
+            println!("重放死信文件 {}", file);
+            let mut reader = std::fs::File::open(file)?;
+            let mut parser = CommandParser::new();
+            let mut letter = None;
+            let mut p = vec![0; 64 * 1024];
             loop {
-                match full_cmd_receiver.try_recv() {
-                    Ok(cmd) => {
-                        full_cmd_count = full_cmd_count + 1;
-                        pipe.add_command(cmd);
-                        if full_cmd_count >= 10000 {
-                            let _:RedisResult<Value> = pipe.query_async(&mut target_conn).await;
-                            pipe.clear();
-                            full_cmd_count = 0;
+                while let Some(frame) = parser.next_frame()? {
+                    let command = match frame {
+                        Frame::Command(d) => d,
+                        Frame::Comment(line, _) => {
+                            letter = Letter::parse(&line);
+                            if letter.is_some() || Seen::parse(&line).is_some() {
+                                seq = seq + 1;
+                            }
+                            continue;
                         }
+                        Frame::Empty(_) => continue,
+                    };
+                    let l = match letter.take() {
+                        Some(d) => d,
+                        None => return Err(Box::from(format!("{} 中的命令前面没有#DL注释", file))),
+                    };
+                    if !errors.is_empty() && !errors.iter().any(|e| e.eq_ignore_ascii_case(&l.class())) {
+                        filter_count = filter_count + 1;
+                        continue;
                     }
-                    Err(e) => {
-                        match e {
-                            TryRecvError::Empty=>{
-                                if atomic_u64_load!(rdb_status_c)==1{
-                                    if full_cmd_count==0{
-                                        // 认为rdb完成了
-                                        atomic_u64_fetch_add!(rdb_status_c,1);
-                                        break;
-                                    }
-                                }
-                                if full_cmd_count > 0 {
-                                    let _:RedisResult<Value> = pipe.query_async(&mut target_conn).await;
-                                    pipe.clear();
-                                    full_cmd_count = 0;
-                                };
-                                yield_now().await;
-                            },
-                            TryRecvError::Closed=>{
-                                unimplemented!("RDB channel close!")
-                            }
+                    let args: Vec<&[u8]> = command.args().collect();
+                    if let Some(keys) = command_keys(&args) {
+                        if keys.iter().any(|k| last_seen.get(&(l.db, k.to_vec())).is_some_and(|s| *s > seq)) {
+                            superseded_count = superseded_count + 1;
+                            continue;
                         }
                     }
-                };
+                    let mut cmds = vec![];
+                    if l.db != db {
+                        db = l.db;
+                        cmds.push(redis::cmd("SELECT").arg(db).to_owned());
+                    }
+                    let mut cmd = Cmd::new();
+                    for arg in command.args() {
+                        cmd.arg(arg);
+                    }
+                    cmds.push(cmd);
+                    for cmd in cmds {
+                        inflight.acquire(cmd_size(&cmd)).await;
+                        if let Err(e) = applier.send(cmd, l.offset).await {
+                            // 出错的命令已经写到新的死信文件, 等其它连接停止后返回
+                            let _ = applier.close().await;
+                            stats.print("REDRIVE");
+                            return Err(Box::from(e));
+                        }
+                    }
+                    replay_count = replay_count + 1;
+                }
+                let n = std::io::Read::read(&mut reader, &mut p)?;
+                if n == 0 {
+                    if parser.buffered() > 0 {
+                        return Err(Box::from(format!("{} 结尾有{}字节不完整的命令", file, parser.buffered())));
+                    }
+                    break;
+                }
+                parser.feed(&p[..n]);
             }
+        }
+        let result = applier.close().await;
+        println!(
+            "[REDRIVE] files:{} replay:{} filtered:{} superseded:{}",
+            files.len(),
+            replay_count,
+            filter_count,
+            superseded_count
+        );
+        stats.print("REDRIVE");
+        Ok(result?)
+    }
+
+    // 全量阶段写目的端, 命令按key分到多个连接
+    // full结束后所有CmdSender都被drop, 剩下的命令发送完成后rdb_status加1
+    // 目的端出错(fail策略)时停止接收, CmdSender发送失败, 错误通过返回的JoinHandle得到
+    fn spawn_full_sender(
+        target_url: &'static str,
+        target_pass: &'static str,
+        connections: usize,
+        reply_policy: ReplyPolicy,
+        deadletter: Option<Arc<DeadLetter>>,
+        rdb_status_c: Arc<AtomicU64>,
+        inflight: Arc<InflightLimit>,
+    ) -> (CmdSender, JoinHandle<Result<(), String>>) {
+        let (full_cmd_sender, mut full_cmd_receiver) = channel::<(Cmd, u64)>(20000);
+        let mut applier = Applier::new(target_url, target_pass, connections, inflight.clone(), reply_policy, deadletter);
+        let inflight_c = inflight.clone();
+        let handle = spawn(async move {
+            while let Some((cmd, offset)) = full_cmd_receiver.recv().await {
+                if applier.send(cmd, offset).await.is_err() {
+                    full_cmd_receiver.close();
+                    while let Some((cmd, _)) = full_cmd_receiver.recv().await {
+                        inflight_c.release(cmd_size(&cmd));
+                    }
+                }
+            }
+            if applier.barriers > 0 {
+                println!("[FULL] 屏障命令:{}", applier.barriers);
+            }
+            // 关闭时等待剩下的命令发送完成, 之后的统计才是完整的
+            let stats = applier.stats.clone();
+            let result = applier.close().await;
+            stats.print("FULL");
+            atomic_u64_fetch_add!(rdb_status_c, 1);
+            result
         });
-        full(&mut loader, &mut full_cmd_sender).await.unwrap();
-        // 等待RDB完成命令发送
+        (CmdSender::new(full_cmd_sender, inflight), handle)
+    }
+
+    // 离线过滤: 读取rdb文件,按filter过滤后写出一个新的rdb文件
+    pub async fn mod_filter(
+        input: &str,
+        output: &str,
+        filter: &Filter,
+        compress: bool,
+        compression: Option<Compression>,
+        crc_policy: CrcPolicy,
+        error_policy: ErrorPolicy,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut loader = open_rdb_file(input).await?;
+        loader.crcPolicy = crc_policy;
+        loader.errorPolicy = error_policy;
+        let version = loader.Header().await?;
+        let compression = compression.unwrap_or(Compression::from_path(output));
+        let mut writer = Writer::create(output, version, compress, compression)?;
+        let (mut keep_count, mut skip_count) = (0u64, 0u64);
         loop {
-            let ird = atomic_u64_load!(rdb_status_c1);
-            if ird!=2 {
-                sleep(Duration::from_millis(100)).await;
-            } else {
-                break;
+            let mut e = BinEntry::default();
+            match loader.NextBinEntry(&mut e).await {
+                Ok(()) => {
+                    if e.Type == rdbFlagFunction2 {
+                        writer.write_function(&e.Value)?;
+                        continue;
+                    }
+                    if e.Type == RdbFlagAUX {
+                        // lua脚本原样保留
+                        writer.write_aux(&e.Key, &e.Value)?;
+                        continue;
+                    }
+                    if filter.filter_entry(&e) {
+                        if e.NeedReadLen == 1 {
+                            skip_count = skip_count + 1;
+                        }
+                        continue;
+                    }
+                    if e.NeedReadLen == 1 {
+                        keep_count = keep_count + 1;
+                    }
+                    writer.write_entry(&e)?;
+                }
+                Err(err) => {
+                    loader.PrintReport();
+                    if err.to_string().eq("RDB END") {
+                        loader.Footer().await?;
+                        break;
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        writer.footer()?;
+        writer.finish()?;
+        println!(
+            "[FILTER] keep keys:{} skip keys:{} output bytes:{}",
+            keep_count, skip_count, writer.nwrite
+        );
+        Ok(())
+    }
+
+    // 离线拆分: 按目标集群的slot分布把一个rdb拆成每个节点一个rdb文件
+    #[allow(clippy::too_many_arguments)]
+    pub async fn mod_reshard(
+        input: &str,
+        output_dir: &str,
+        layout: &SlotLayout,
+        filter: &Filter,
+        compress: bool,
+        compression: Option<Compression>,
+        crc_policy: CrcPolicy,
+        error_policy: ErrorPolicy,
+    ) -> Result<(), Box<dyn Error>> {
+        let compression = compression.unwrap_or(Compression::None);
+        let mut loader = open_rdb_file(input).await?;
+        loader.crcPolicy = crc_policy;
+        loader.errorPolicy = error_policy;
+        let version = loader.Header().await?;
+        std::fs::create_dir_all(output_dir)?;
+        let mut paths = vec![];
+        let mut writers = vec![];
+        for node in layout.nodes.iter() {
+            let path = format!(
+                "{}/{}.rdb{}",
+                output_dir.trim_end_matches('/'),
+                node.replace(':', "_"),
+                compression.extension()
+            );
+            writers.push(Writer::create(&path, version, compress, compression)?);
+            paths.push(path);
+        }
+        let mut key_counts = vec![0u64; writers.len()];
+        let mut other_db_counts = vec![0u64; writers.len()];
+        let (mut skip_count, mut no_slot_count) = (0u64, 0u64);
+        loop {
+            let mut e = BinEntry::default();
+            match loader.NextBinEntry(&mut e).await {
+                Ok(()) => {
+                    if e.Type == rdbFlagFunction2 {
+                        // function在集群的每个节点都需要
+                        for writer in writers.iter_mut() {
+                            writer.write_function(&e.Value)?;
+                        }
+                        continue;
+                    }
+                    if e.Type == RdbFlagAUX {
+                        // lua脚本每个节点都需要
+                        for writer in writers.iter_mut() {
+                            writer.write_aux(&e.Key, &e.Value)?;
+                        }
+                        continue;
+                    }
+                    if filter.filter_entry(&e) {
+                        if e.NeedReadLen == 1 {
+                            skip_count = skip_count + 1;
+                        }
+                        continue;
+                    }
+                    let index = match layout.node_of(&e.Key) {
+                        Some(d) => d,
+                        None => {
+                            if e.NeedReadLen == 1 {
+                                no_slot_count = no_slot_count + 1;
+                            }
+                            continue;
+                        }
+                    };
+                    // 集群只能加载db 0, 其它db的key不写入, 按节点计数
+                    if e.DB != 0 {
+                        if e.NeedReadLen == 1 {
+                            if other_db_counts[index] == 0 {
+                                println!("[RESHARD] key {} 在db {}, 集群只能加载db 0, 跳过", String::from_utf8_lossy(&e.Key), e.DB);
+                            }
+                            other_db_counts[index] = other_db_counts[index] + 1;
+                        }
+                        continue;
+                    }
+                    if e.NeedReadLen == 1 {
+                        key_counts[index] = key_counts[index] + 1;
+                    }
+                    writers[index].write_entry(&e)?;
+                }
+                Err(err) => {
+                    loader.PrintReport();
+                    if err.to_string().eq("RDB END") {
+                        loader.Footer().await?;
+                        break;
+                    }
+                    return Err(err);
+                }
             }
         }
-        incr(&mut loader, target_url, target_pass).await.unwrap();
+        for (index, writer) in writers.iter_mut().enumerate() {
+            writer.footer()?;
+            writer.finish()?;
+            println!(
+                "[RESHARD] node:{} file:{} keys:{} other db keys:{} bytes:{}",
+                layout.nodes[index], paths[index], key_counts[index], other_db_counts[index], writer.nwrite
+            );
+        }
+        println!("[RESHARD] skip keys:{} no slot keys:{}", skip_count, no_slot_count);
+        Ok(())
+    }
+
+    // 离线比较两个rdb文件, 不一样的地方写到output, output为空时输出到标准输出
+    pub async fn mod_diff(
+        input: &str,
+        other: &str,
+        output: &str,
+        opts: &DiffOptions,
+        crc_policy: CrcPolicy,
+        error_policy: ErrorPolicy,
+    ) -> Result<DiffSummary, Box<dyn Error>> {
+        let mut out: Box<dyn std::io::Write> = if output.is_empty() {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(std::io::BufWriter::new(std::fs::File::create(output)?))
+        };
+        let summary = diff(input, other, opts, crc_policy, error_policy, &mut out).await?;
+        summary.print();
+        Ok(summary)
     }
 }
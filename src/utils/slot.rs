@@ -0,0 +1,173 @@
+use crate::utils::conn::open_redis_sync_conn;
+use redis::Value;
+
+use std::error::Error;
+
+pub const SLOT_COUNT: usize = 16384;
+
+// 和redis cluster一样的crc16(XMODEM)
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for b in data {
+        crc ^= (*b as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+// 计算key所在的slot,支持{hash tag}
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let mut k = key;
+    if let Some(s) = key.iter().position(|b| *b == b'{') {
+        if let Some(e) = key[s + 1..].iter().position(|b| *b == b'}') {
+            if e != 0 {
+                k = &key[s + 1..s + 1 + e];
+            }
+        }
+    }
+    crc16(k) & (SLOT_COUNT as u16 - 1)
+}
+
+// slot到目标节点的映射
+pub struct SlotLayout {
+    pub nodes: Vec<String>,
+    pub slots: Vec<Option<usize>>,
+}
+
+impl SlotLayout {
+    // 格式: 127.0.0.1:7000@0-5460;127.0.0.1:7001@5461-10922,16000
+    pub fn parse(spec: &str) -> Result<SlotLayout, Box<dyn Error>> {
+        let mut layout = SlotLayout {
+            nodes: vec![],
+            slots: vec![None; SLOT_COUNT],
+        };
+        for node_spec in spec.split(';').filter(|s| !s.is_empty()) {
+            let mut parts = node_spec.rsplitn(2, '@');
+            let ranges = parts.next().unwrap_or("");
+            let node = match parts.next() {
+                Some(d) => d,
+                None => return Err(Box::from(format!("slot配置缺少节点: {}", node_spec))),
+            };
+            let mut slots = vec![];
+            for range in ranges.split(',').filter(|s| !s.is_empty()) {
+                let mut se = range.splitn(2, '-');
+                let start = se.next().unwrap_or("").parse::<usize>()?;
+                let end = match se.next() {
+                    Some(d) => d.parse::<usize>()?,
+                    None => start,
+                };
+                slots.push((start, end));
+            }
+            layout.add_node(node, &slots)?;
+        }
+        Ok(layout)
+    }
+    // 从一个在线集群的CLUSTER SHARDS获取slot分布(redis 7.0+)
+    pub async fn from_cluster_shards(url: &str, pass: &str) -> Result<SlotLayout, Box<dyn Error>> {
+        let mut conn = open_redis_sync_conn(url, pass, "").await?;
+        let shards: Value = redis::cmd("CLUSTER").arg("SHARDS").query_async(&mut conn).await?;
+        let mut layout = SlotLayout {
+            nodes: vec![],
+            slots: vec![None; SLOT_COUNT],
+        };
+        let shards = match shards {
+            Value::Bulk(d) => d,
+            _ => return Err(Box::from("CLUSTER SHARDS 响应格式错误")),
+        };
+        for shard in shards {
+            let mut slots = vec![];
+            let mut node = None;
+            for (k, v) in pairs(&shard) {
+                if k == "slots" {
+                    let ints: Vec<usize> = items(v).iter().filter_map(to_usize).collect();
+                    for se in ints.chunks(2) {
+                        if se.len() == 2 {
+                            slots.push((se[0], se[1]));
+                        }
+                    }
+                } else if k == "nodes" {
+                    for n in items(v) {
+                        let mut ip = String::new();
+                        let mut port = String::new();
+                        let mut is_master = false;
+                        for (nk, nv) in pairs(n) {
+                            match nk.as_str() {
+                                "endpoint" => ip = to_string(nv),
+                                "port" => port = to_string(nv),
+                                "role" => is_master = to_string(nv) == "master",
+                                _ => {}
+                            }
+                        }
+                        if is_master {
+                            node = Some(format!("{}:{}", ip, port));
+                        }
+                    }
+                }
+            }
+            if let Some(node) = node {
+                if !slots.is_empty() {
+                    layout.add_node(&node, &slots)?;
+                }
+            }
+        }
+        Ok(layout)
+    }
+    fn add_node(&mut self, node: &str, slots: &[(usize, usize)]) -> Result<(), Box<dyn Error>> {
+        let index = self.nodes.len();
+        self.nodes.push(String::from(node));
+        for (start, end) in slots {
+            if start > end || *end >= SLOT_COUNT {
+                return Err(Box::from(format!("错误的slot范围 {}-{}", start, end)));
+            }
+            for slot in *start..=*end {
+                if self.slots[slot].is_some() {
+                    return Err(Box::from(format!("slot {} 被重复分配", slot)));
+                }
+                self.slots[slot] = Some(index);
+            }
+        }
+        Ok(())
+    }
+    pub fn node_of(&self, key: &[u8]) -> Option<usize> {
+        self.slots[key_hash_slot(key) as usize]
+    }
+}
+
+fn items(v: &Value) -> &[Value] {
+    match v {
+        Value::Bulk(d) => d.as_slice(),
+        _ => &[],
+    }
+}
+
+// RESP2下map是按 k1 v1 k2 v2 展开的数组
+fn pairs(v: &Value) -> Vec<(String, &Value)> {
+    items(v)
+        .chunks(2)
+        .filter(|kv| kv.len() == 2)
+        .map(|kv| (to_string(&kv[0]), &kv[1]))
+        .collect()
+}
+
+fn to_string(v: &Value) -> String {
+    match v {
+        Value::Data(d) => String::from_utf8_lossy(d).to_string(),
+        Value::Status(d) => d.clone(),
+        Value::Int(d) => format!("{}", d),
+        _ => String::new(),
+    }
+}
+
+fn to_usize(v: &Value) -> Option<usize> {
+    match v {
+        Value::Int(d) => Some(*d as usize),
+        Value::Data(d) => String::from_utf8_lossy(d).parse::<usize>().ok(),
+        _ => None,
+    }
+}
@@ -4,92 +4,74 @@ use std::error;
 
 use async_std::net::TcpStream;
 
-use crate::utils::cmd::{cmd_to_resp_first_line, cmd_to_string, read_line};
+use crate::utils::resp::{encode_command, parse_int, Resp, RespConn};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use async_std::task::sleep;
 use std::time::Duration;
-use futures_util::{AsyncWriteExt, AsyncReadExt};
+use futures_util::AsyncWriteExt;
+use crate::rdb::compress::open_file;
+use crate::rdb::loader::{Loader, SyncReader};
+use std::io::Read;
 
-pub async fn pre_to_rdb(source: &mut TcpStream) -> Result<(i64, i64, String), Box<dyn error::Error>> {
-    // 设置监听端口
-    let set_port_resp = cmd_to_resp_first_line(source, vec!["replconf", "listening-port", "8083"]).await?;
-    if !set_port_resp.eq(&String::from("+OK")) {
-        return Err(Box::from("设置监听端口失败"));
+// 发送REPLCONF listening-port, 主节点返回+OK
+async fn replconf_port(source: &mut RespConn<TcpStream>) -> Result<(), Box<dyn error::Error>> {
+    let set_port_resp = source.request(&["replconf", "listening-port", "8083"]).await?;
+    if set_port_resp != Resp::Simple(b"OK".to_vec()) {
+        return Err(Box::from(format!("设置监听端口失败: {}", set_port_resp)));
     }
     println!("set listening-port is {}", set_port_resp);
+    Ok(())
+}
 
-    // psync ? -1
-    let header = cmd_to_resp_first_line(source, vec!["psync", "?", "-1"]).await?;
-    let mut resp = String::new();
-    let mut uuid = String::new();
-    let mut offset = 0;
-    let mut index = 0;
-    for s_str in header.split(" ") {
-        if index == 0 {
-            resp = String::from(s_str);
-        }
-        if index == 1 {
-            uuid = String::from(s_str);
-        }
-        if index == 2 {
-            offset = String::from(s_str).parse::<i64>().unwrap();
-        }
-        index = index + 1;
-    }
-    if index != 3 {
-        return Err(Box::from("未知的响应头!"));
+pub async fn pre_to_rdb(source: &mut RespConn<TcpStream>) -> Result<(i64, i64, String), Box<dyn error::Error>> {
+    // 设置监听端口
+    replconf_port(source).await?;
+    // psync ? -1, 返回 +FULLRESYNC <replid> <offset>
+    let header = source.request(&["psync", "?", "-1"]).await?;
+    let header = match header {
+        Resp::Simple(d) => String::from_utf8_lossy(&d).to_string(),
+        _ => return Err(Box::from(format!("未知的响应头! {}", header))),
+    };
+    let fields: Vec<&str> = header.split(' ').collect();
+    if fields.len() != 3 || !fields[0].eq_ignore_ascii_case("FULLRESYNC") {
+        return Err(Box::from(format!("未知的响应头! {}", header)));
     }
+    let uuid = String::from(fields[1]);
+    let offset = fields[2].parse::<i64>()?;
     println!("uuid   is {} \r\noffset is {}", uuid, offset);
-    // rdb size
-    let rdb_size = read_line(source).await?.replace("$", "").parse::<i64>().unwrap();
-    println!("rdb_size  {:?}", rdb_size);
-    // ignore \n
-    let mut resp_char = [0;1];
-    match source.read_exact(&mut resp_char).await{
-        Ok(())=>{
-            if resp_char[0] == '\n' as u8{
-
-            }
-        },
-        Err(_e)=>{
-
+    // rdb size, 之前主节点生成rdb的时候会发送\n保持连接
+    let line = loop {
+        let line = source.read_line().await?;
+        if !line.is_empty() {
+            break line;
         }
     };
+    if !line.starts_with(b"$") || line.starts_with(b"$EOF:") {
+        return Err(Box::from(format!("不支持的rdb长度 {}", String::from_utf8_lossy(&line))));
+    }
+    let rdb_size = parse_int(&line[1..])?;
+    println!("rdb_size  {:?}", rdb_size);
     Ok((offset, rdb_size, uuid))
 }
 
 pub async fn pre_to_inc(
-    source: &mut TcpStream,
+    source: &mut RespConn<TcpStream>,
     uuid: &str,
     offset: &str,
 ) -> Result<(), Box<dyn error::Error>> {
     // 设置监听端口
-    let set_port_resp = cmd_to_resp_first_line(source, vec!["replconf", "listening-port", "8083"]).await?;
-    if !set_port_resp.eq(&String::from("+OK")) {
-        return Err(Box::from("设置监听端口失败"));
-    }
-    println!("set listening-port is {}", set_port_resp);
-    // psync ? -1
-    let header = cmd_to_resp_first_line(source, vec!["psync", uuid, offset]).await?;
-    if header.to_uppercase() == "+CONTINUE" {
-        println!("源端重连成功!");
-    } else {
-        return Err(Box::from("重连失败!"));
-    }
-    // ignore \n
-    let mut resp_char = [0;1];
-    match source.read_exact(&mut resp_char).await{
-        Ok(())=>{
-            if resp_char[0] == '\n' as u8{
-
-            }
-        },
-        Err(_e)=>{
-
+    replconf_port(source).await?;
+    // psync <replid> <offset>
+    let header = source.request(&["psync", uuid, offset]).await?;
+    // +CONTINUE 或者 +CONTINUE <new replid>
+    match header {
+        Resp::Simple(d) if d.to_ascii_uppercase().starts_with(b"CONTINUE") => {
+            println!("源端重连成功!");
+            Ok(())
         }
-    };
-    Ok(())
+        _ => Err(Box::from(format!("重连失败! {}", header))),
+    }
 }
 
 pub async fn report_offset(
@@ -99,14 +81,11 @@ pub async fn report_offset(
     // 上报发送的offset
     loop {
         let send_offset = offset.load(Ordering::SeqCst);
-        source.write(
-            cmd_to_string(vec!["replconf", "ack", format!("{}", send_offset).as_str()]).as_bytes(),
-        ).await?;
+        source.write_all(&encode_command(&["replconf", "ack", format!("{}", send_offset).as_str()])).await?;
         sleep(Duration::from_secs(1)).await;
     }
-    Ok(())
 }
-#[macro_export(source_report_offset)]
+#[macro_export]
 macro_rules! source_report_offset {
     ($conn:ident,$offset:ident) => {
         async_std::task::spawn(async move {
@@ -120,3 +99,20 @@ macro_rules! source_report_offset {
         });
     };
 }
+
+// 离线模式读取的文件, 直接同步读取不经过管道
+pub type FileLoader = Loader<SyncReader<std::io::BufReader<Box<dyn Read>>>>;
+
+// 离线模式: 把rdb文件交给Loader解析
+pub async fn open_rdb_file(path: &str) -> Result<FileLoader, Box<dyn error::Error>> {
+    open_files(vec![String::from(path)]).await
+}
+
+// 多个文件按顺序拼接成一个流(例如aof的base和incr文件), 每个文件可以是压缩过的
+pub async fn open_files(paths: Vec<String>) -> Result<FileLoader, Box<dyn error::Error>> {
+    let mut reader: Box<dyn Read> = Box::new(std::io::empty());
+    for path in paths.iter() {
+        reader = Box::new(reader.chain(open_file(path)?));
+    }
+    Ok(Loader::fromRead(reader))
+}
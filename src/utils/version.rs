@@ -0,0 +1,74 @@
+use crate::utils::conn::open_redis_sync_conn;
+
+use std::error::Error;
+
+// 目的端的能力: 能读取的最高rdb版本, 以及命令重建时可以用的命令
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TargetVersion {
+    pub rdb: u16,
+    // XGROUP CREATECONSUMER, redis 6.2+
+    pub stream_create_consumer: bool,
+}
+
+impl TargetVersion {
+    pub fn latest() -> TargetVersion {
+        TargetVersion {
+            rdb: RDB_VERSION_LATEST,
+            stream_create_consumer: true,
+        }
+    }
+
+    pub fn from_redis_version(version: &str) -> Option<TargetVersion> {
+        let (major, minor) = parse_redis_version(version)?;
+        Some(TargetVersion {
+            rdb: redis_version_to_rdb(version)?,
+            stream_create_consumer: (major, minor) >= (6, 2),
+        })
+    }
+}
+
+// 从INFO server的redis_version推算目的端的能力
+pub async fn target_version(url: &str, pass: &str) -> Result<TargetVersion, Box<dyn Error>> {
+    let mut conn = open_redis_sync_conn(url, pass, "").await?;
+    let info: redis::RedisResult<String> = redis::cmd("INFO").arg("server").query_async(&mut conn).await;
+    let version = info.ok().and_then(|info| {
+        info.lines()
+            .find(|line| line.starts_with("redis_version:"))
+            .map(|line| line["redis_version:".len()..].trim().to_string())
+    });
+    match version.as_deref().and_then(TargetVersion::from_redis_version) {
+        Some(d) => {
+            println!("目的端 redis_version:{} rdb version:{}", version.unwrap_or_default(), d.rdb);
+            Ok(d)
+        }
+        None => {
+            // 代理之类的获取不到版本, 按最新的版本处理
+            println!("无法获取目的端的版本({:?}), 按rdb version {}处理", version, RDB_VERSION_LATEST);
+            Ok(TargetVersion::latest())
+        }
+    }
+}
+
+const RDB_VERSION_LATEST: u16 = 12;
+
+fn parse_redis_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse::<u32>().ok()?;
+    let minor = parts.next()?.parse::<u32>().ok()?;
+    Some((major, minor))
+}
+
+// rdb version 7(quicklist)从3.2开始
+pub fn redis_version_to_rdb(version: &str) -> Option<u16> {
+    let rdb = match parse_redis_version(version)? {
+        (0..=2, _) => 6,
+        (3, 0..=1) => 6,
+        (3, _) => 7,
+        (4, _) => 8,
+        (5, _) | (6, _) => 9,
+        (7, 0) => 10,
+        (7, 1..=2) => 11,
+        _ => RDB_VERSION_LATEST,
+    };
+    Some(rdb)
+}
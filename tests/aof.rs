@@ -0,0 +1,61 @@
+// redis 7的多文件aof: 按manifest找到base和incr文件并排好顺序
+mod common;
+
+use common::temp_path;
+use redis_shake_rs::utils::aof::{aof_files, has_rdb_preamble};
+
+use std::fs;
+
+fn aof_dir(name: &str, manifest: &str) -> String {
+    let dir = temp_path(name);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(format!("{}/appendonly.aof.manifest", dir), manifest).unwrap();
+    dir
+}
+
+#[test]
+fn multi_part_manifest() {
+    let dir = aof_dir(
+        "aof-multi",
+        "file appendonly.aof.2.base.rdb seq 2 type b\n\
+         file appendonly.aof.1.incr.aof seq 1 type h\n\
+         file appendonly.aof.4.incr.aof seq 4 type i\n\
+         file \"appendonly.aof.3.incr.aof\" seq 3 type i\n",
+    );
+    let expected = vec![
+        format!("{}/appendonly.aof.2.base.rdb", dir),
+        format!("{}/appendonly.aof.3.incr.aof", dir),
+        format!("{}/appendonly.aof.4.incr.aof", dir),
+    ];
+    assert_eq!(aof_files(&dir).unwrap(), expected);
+    assert_eq!(aof_files(&format!("{}/appendonly.aof.manifest", dir)).unwrap(), expected);
+}
+
+#[test]
+fn bad_manifest() {
+    let dir = aof_dir("aof-two-base", "file a.base.aof seq 1 type b\nfile b.base.aof seq 2 type b\n");
+    assert!(aof_files(&dir).is_err());
+    let dir = aof_dir("aof-no-seq", "file a.base.aof type b\n");
+    assert!(aof_files(&dir).is_err());
+    let dir = aof_dir("aof-bad-type", "file a.base.aof seq 1 type x\n");
+    assert!(aof_files(&dir).is_err());
+
+    let empty = temp_path("aof-empty-dir");
+    fs::create_dir_all(&empty).unwrap();
+    assert!(aof_files(&empty).is_err());
+}
+
+#[test]
+fn single_file_and_preamble() {
+    let path = temp_path("appendonly.aof");
+    fs::write(&path, "*1\r\n$4\r\nPING\r\n").unwrap();
+    assert_eq!(aof_files(&path).unwrap(), vec![path.clone()]);
+    assert!(!has_rdb_preamble(&path).unwrap());
+
+    let rdb = temp_path("appendonly-preamble.aof");
+    fs::write(&rdb, "REDIS0009").unwrap();
+    assert!(has_rdb_preamble(&rdb).unwrap());
+    let short = temp_path("appendonly-short.aof");
+    fs::write(&short, "RED").unwrap();
+    assert!(!has_rdb_preamble(&short).unwrap());
+}
@@ -0,0 +1,444 @@
+// 并行写目的端时命令的路由: 同一个key总在同一个连接上, 跨连接的命令作为屏障
+// 以及用一个假的目的端检查回复的错误处理和死信文件
+mod common;
+
+use common::temp_path;
+use redis::{cmd, Cmd};
+use redis_shake_rs::utils::apply::{error_class, resend_after_lost, route, Applier, ReplyPolicy, Route};
+use redis_shake_rs::utils::command::{CommandParser, Frame};
+use redis_shake_rs::utils::deadletter::{segments, DeadLetter, DeadLetterOptions, Letter, Seen};
+use redis_shake_rs::utils::memory::{cmd_size, InflightLimit};
+use redis_shake_rs::utils::resp::{encode, Resp};
+use redis_shake_rs::utils::run::Runner;
+use redis_shake_rs::utils::slot::key_hash_slot;
+
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task::{block_on, spawn};
+use futures_util::{AsyncReadExt, AsyncWriteExt};
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+fn command(args: &[&str]) -> Cmd {
+    let mut c = cmd(args[0]);
+    for arg in &args[1..] {
+        c.arg(*arg);
+    }
+    c
+}
+
+#[test]
+fn single_key_follows_slot() {
+    for key in &["a", "foo", "user:1000", "{tag}x"] {
+        let want = Route::One(key_hash_slot(key.as_bytes()) as usize % 4);
+        assert_eq!(route(&command(&["SET", key, "v"]), 4), want);
+        assert_eq!(route(&command(&["hset", key, "f", "v"]), 4), want);
+        assert_eq!(route(&command(&["RESTORE", key, "0", "payload", "REPLACE"]), 4), want);
+    }
+    assert_eq!(route(&command(&["select", "3"]), 4), Route::All);
+    assert_eq!(route(&command(&["PING"]), 4), Route::One(0));
+}
+
+#[test]
+fn multi_key_commands() {
+    // hashtag相同的key在同一个slot
+    let same = Route::One(key_hash_slot(b"tag") as usize % 4);
+    assert_eq!(route(&command(&["del", "{tag}a", "{tag}b"]), 4), same);
+    assert_eq!(route(&command(&["mset", "{tag}a", "1", "{tag}b", "2"]), 4), same);
+    assert_eq!(route(&command(&["zunionstore", "{tag}d", "2", "{tag}a", "{tag}b", "WEIGHTS", "1", "2"]), 4), same);
+    // 找两个落在不同连接上的key
+    let a = "a";
+    let b = (0..100).map(|i| format!("k{}", i)).find(|k| key_hash_slot(k.as_bytes()) % 4 != key_hash_slot(b"a") % 4).unwrap();
+    assert_eq!(route(&command(&["del", a, &b]), 4), Route::Barrier);
+    assert_eq!(route(&command(&["mset", a, "1", &b, "2"]), 4), Route::Barrier);
+    assert_eq!(route(&command(&["rename", a, &b]), 4), Route::Barrier);
+    // 只有一个连接时都在同一个连接上
+    assert_eq!(route(&command(&["del", a, &b]), 1), Route::One(0));
+}
+
+#[test]
+fn barriers() {
+    for args in &[
+        &["flushdb"][..],
+        &["FLUSHALL", "ASYNC"],
+        &["eval", "return 1", "0"],
+        &["multi"],
+        &["swapdb", "0", "1"],
+        &["copy", "a", "b", "DB", "2"],
+        &["unknowncmd", "a"],
+        &["set"],
+    ] {
+        assert_eq!(route(&command(args), 4), Route::Barrier, "{:?}", args);
+    }
+    assert_ne!(route(&command(&["copy", "{t}a", "{t}b", "replace"]), 4), Route::Barrier);
+}
+
+#[test]
+fn error_classes() {
+    assert_eq!(error_class(b"OOM command not allowed when used memory > 'maxmemory'."), "OOM");
+    assert_eq!(error_class(b"BUSYKEY Target key name already exists."), "BUSYKEY");
+    assert_eq!(error_class(b"WRONGTYPE Operation against a key holding the wrong kind of value"), "WRONGTYPE");
+    assert_eq!(error_class(b"ERR DUMP payload version or checksum are wrong"), "ERR");
+    assert_eq!(error_class(b"unknown error"), "ERR");
+    assert_eq!(ReplyPolicy::parse("retry").unwrap(), ReplyPolicy::Retry(3));
+    assert!(ReplyPolicy::parse("ignore").is_err());
+}
+
+// 命令名和它在原来一批中的位置
+fn names(resend: &[(Option<usize>, Cmd)]) -> Vec<(String, Option<usize>)> {
+    resend
+        .iter()
+        .map(|(i, c)| (String::from_utf8_lossy(&c.get_packed_command()).split("\r\n").nth(2).unwrap().to_string(), *i))
+        .collect()
+}
+
+#[test]
+fn lost_batch_resends_idempotent_only() {
+    let batch = vec![
+        command(&["set", "a", "1"]),
+        command(&["incr", "b"]),
+        command(&["select", "1"]),
+        command(&["multi"]),
+        command(&["set", "c", "1"]),
+        command(&["select", "2"]),
+        command(&["exec"]),
+        command(&["del", "d"]),
+        command(&["append", "e", "x"]),
+        command(&["restore", "f", "0", "payload"]),
+        command(&["restore", "f", "0", "payload", "REPLACE"]),
+    ];
+    let cmd = |name: &str, i: Option<usize>| (name.to_string(), i);
+    let (resend, unknown) = resend_after_lost(&batch, 0);
+    // 事务中的SELECT也要发送, 后面的DEL在db 2上
+    assert_eq!(
+        names(&resend),
+        vec![cmd("set", Some(0)), cmd("select", Some(2)), cmd("select", None), cmd("del", Some(7)), cmd("restore", Some(10))]
+    );
+    assert_eq!(unknown, vec![1, 3, 4, 6, 8, 9]);
+    // 收到回复的命令不再发送, 先恢复它们选择的db
+    let (resend, unknown) = resend_after_lost(&batch, 3);
+    assert_eq!(names(&resend), vec![cmd("select", None), cmd("select", None), cmd("del", Some(7)), cmd("restore", Some(10))]);
+    assert_eq!(unknown, vec![3, 4, 6, 8, 9]);
+    let (resend, unknown) = resend_after_lost(&batch, 7);
+    assert_eq!(names(&resend), vec![cmd("select", None), cmd("del", Some(7)), cmd("restore", Some(10))]);
+    assert_eq!(unknown, vec![8, 9]);
+}
+
+// 执行成功的写命令, 格式为 db:命令 参数...
+type Log = Arc<Mutex<Vec<String>>>;
+
+// 假的目的端: key以bad开头的命令总是返回WRONGTYPE, 以flaky开头的key第一次返回OOM
+// 以drop开头的key第一次执行之后断开连接, 这个命令和之后的命令都收不到回复
+async fn serve(mut stream: TcpStream, log: Log, seen: Arc<Mutex<HashSet<String>>>) {
+    let mut parser = CommandParser::new();
+    let mut db = 0;
+    // 事务中排队的命令, EXEC时执行
+    let mut tx: Option<Vec<Vec<String>>> = None;
+    let mut p = [0u8; 16 * 1024];
+    loop {
+        let n = match stream.read(&mut p).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        parser.feed(&p[..n]);
+        let mut out = vec![];
+        while let Some(frame) = parser.next_frame().unwrap() {
+            let args: Vec<String> = match frame {
+                Frame::Command(c) => c.args().map(|a| String::from_utf8_lossy(a).to_string()).collect(),
+                _ => continue,
+            };
+            let name = args[0].to_ascii_lowercase();
+            let queued = match (name.as_str(), tx.as_mut()) {
+                ("multi", _) => {
+                    tx = Some(vec![]);
+                    encode(&Resp::Simple(b"OK".to_vec()), &mut out);
+                    continue;
+                }
+                ("discard", _) => {
+                    tx = None;
+                    encode(&Resp::Simple(b"OK".to_vec()), &mut out);
+                    continue;
+                }
+                ("exec", _) => tx.take().unwrap(),
+                (_, Some(tx)) => {
+                    tx.push(args);
+                    encode(&Resp::Simple(b"QUEUED".to_vec()), &mut out);
+                    continue;
+                }
+                _ => vec![args],
+            };
+            let mut replies = vec![];
+            for args in queued {
+                let key = args.get(1).cloned().unwrap_or_default();
+                let reply = if args[0].eq_ignore_ascii_case("select") {
+                    db = args[1].parse::<i64>().unwrap();
+                    Resp::Simple(b"OK".to_vec())
+                } else if key.starts_with("bad") {
+                    Resp::Error(b"WRONGTYPE Operation against a key holding the wrong kind of value".to_vec())
+                } else if key.starts_with("flaky") && seen.lock().unwrap().insert(key.clone()) {
+                    Resp::Error(b"OOM command not allowed when used memory > 'maxmemory'.".to_vec())
+                } else {
+                    log.lock().unwrap().push(format!("{}:{}", db, args.join(" ")));
+                    Resp::Simple(b"OK".to_vec())
+                };
+                if key.starts_with("drop") && seen.lock().unwrap().insert(key.clone()) {
+                    let _ = stream.write_all(&out).await;
+                    return;
+                }
+                replies.push(reply);
+            }
+            match name.as_str() {
+                "exec" => encode(&Resp::Array(replies), &mut out),
+                _ => encode(&replies[0], &mut out),
+            }
+        }
+        stream.write_all(&out).await.unwrap();
+    }
+}
+
+fn fake_target() -> (&'static str, Log) {
+    let log: Log = Arc::new(Mutex::new(vec![]));
+    let seen = Arc::new(Mutex::new(HashSet::new()));
+    let listener = block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+    let addr = Box::leak(listener.local_addr().unwrap().to_string().into_boxed_str());
+    let log_c = log.clone();
+    spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            spawn(serve(stream, log_c.clone(), seen.clone()));
+        }
+    });
+    (addr, log)
+}
+
+// 两个连接写假的目的端, 返回还没有关闭的Applier, 命令的偏移量是它的序号
+fn apply(policy: ReplyPolicy, cmds: Vec<Cmd>, deadletter: Option<Arc<DeadLetter>>) -> (Applier, &'static str, Log) {
+    let (addr, log) = fake_target();
+    let inflight = InflightLimit::new(0);
+    let mut applier = Applier::new(addr, "", 2, inflight.clone(), policy, deadletter);
+    block_on(async {
+        for (i, c) in cmds.into_iter().enumerate() {
+            inflight.acquire(cmd_size(&c)).await;
+            if applier.send(c, i as u64).await.is_err() {
+                break;
+            }
+        }
+        applier.sync().await;
+    });
+    (applier, addr, log)
+}
+
+fn writes() -> Vec<Cmd> {
+    vec![
+        command(&["select", "1"]),
+        command(&["set", "a", "1"]),
+        command(&["set", "bad", "1"]),
+        command(&["set", "flaky1", "1"]),
+        command(&["set", "flaky2", "1"]),
+        command(&["set", "flaky2", "2"]),
+        command(&["multi"]),
+        command(&["set", "{t}b", "1"]),
+        command(&["set", "bad{t}", "1"]),
+        command(&["exec"]),
+    ]
+}
+
+#[test]
+fn skip_records_and_counts() {
+    let (applier, _, log) = apply(ReplyPolicy::Skip, writes(), None);
+    let stats = applier.stats.clone();
+    block_on(applier.close()).unwrap();
+    let log = log.lock().unwrap().clone();
+    assert!(log.contains(&"1:set a 1".to_string()), "{:?}", log);
+    assert!(log.contains(&"1:set {t}b 1".to_string()), "{:?}", log);
+    assert!(log.contains(&"1:set flaky2 2".to_string()), "{:?}", log);
+    assert!(!log.iter().any(|l| l.contains("flaky1")), "{:?}", log);
+    assert_eq!(stats.errors.load(Ordering::Relaxed), 4);
+    assert_eq!(stats.skipped.load(Ordering::Relaxed), 4);
+    assert_eq!(stats.retried.load(Ordering::Relaxed), 0);
+    let classes: Vec<(String, u64)> = stats.classes().into_iter().collect();
+    assert_eq!(classes, vec![("OOM".to_string(), 2), ("WRONGTYPE".to_string(), 2)]);
+}
+
+#[test]
+fn retry_keeps_key_order() {
+    let (applier, _, log) = apply(ReplyPolicy::Retry(2), writes(), None);
+    let stats = applier.stats.clone();
+    block_on(applier.close()).unwrap();
+    let log = log.lock().unwrap().clone();
+    // 重试成功, 仍然在原来的db上
+    assert!(log.contains(&"1:set flaky1 1".to_string()), "{:?}", log);
+    // 同一批里后面的命令已经改了这个key时不能重试, 否则会覆盖新的值
+    let flaky2: Vec<&String> = log.iter().filter(|l| l.contains("flaky2")).collect();
+    assert_eq!(flaky2.last().map(|s| s.as_str()), Some("1:set flaky2 2"), "{:?}", log);
+    assert_eq!(stats.errors.load(Ordering::Relaxed), 4);
+    assert!(stats.retried.load(Ordering::Relaxed) >= 3);
+    // bad一直失败, 事务里的bad{t}不单独重试
+    let skipped = stats.skipped.load(Ordering::Relaxed);
+    assert!(skipped == 2 || skipped == 3, "{}", skipped);
+}
+
+#[test]
+fn fail_stops_all_connections() {
+    let (mut applier, _, log) = apply(ReplyPolicy::Fail, writes(), None);
+    let stats = applier.stats.clone();
+    // 出错之后不再接收命令, 错误返回给调用方而不是退出进程
+    let err = block_on(applier.send(command(&["set", "after", "1"]), 10)).unwrap_err();
+    assert!(err.contains("WRONGTYPE") || err.contains("OOM"), "{}", err);
+    assert_eq!(block_on(applier.close()), Err(err));
+    assert!(!log.lock().unwrap().iter().any(|l| l.contains("after")));
+    assert_eq!(stats.skipped.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn select_in_multi_reaches_all_connections() {
+    // 两个落在不同连接上的key
+    let a = (0..100).map(|i| format!("a{}", i)).find(|k| key_hash_slot(k.as_bytes()).is_multiple_of(2)).unwrap();
+    let b = (0..100).map(|i| format!("b{}", i)).find(|k| key_hash_slot(k.as_bytes()) % 2 == 1).unwrap();
+    let (applier, _, log) = apply(
+        ReplyPolicy::Skip,
+        vec![
+            command(&["multi"]),
+            command(&["select", "2"]),
+            command(&["set", "t", "1"]),
+            command(&["exec"]),
+            command(&["set", &a, "1"]),
+            command(&["set", &b, "1"]),
+            // DISCARD的事务中的SELECT没有执行
+            command(&["multi"]),
+            command(&["select", "5"]),
+            command(&["discard"]),
+            command(&["set", &a, "2"]),
+            command(&["set", &b, "2"]),
+        ],
+        None,
+    );
+    block_on(applier.close()).unwrap();
+    let log = log.lock().unwrap().clone();
+    for want in &["2:set t 1".to_string(), format!("2:set {} 1", a), format!("2:set {} 1", b), format!("2:set {} 2", a), format!("2:set {} 2", b)] {
+        assert!(log.contains(want), "{} {:?}", want, log);
+    }
+}
+
+#[test]
+fn lost_replies_are_not_resent_blindly() {
+    let opts = DeadLetterOptions { path: temp_path("lost.aof"), max_bytes: 1024 * 1024, max_files: 10 };
+    let deadletter = DeadLetter::open(&opts).unwrap().map(Arc::new);
+    let (applier, _, log) = apply(
+        ReplyPolicy::Retry(2),
+        vec![
+            command(&["select", "1"]),
+            command(&["set", "{d}a", "1"]),
+            command(&["incr", "drop{d}"]),
+            command(&["set", "{d}b", "1"]),
+            command(&["append", "{d}c", "x"]),
+        ],
+        deadletter,
+    );
+    let stats = applier.stats.clone();
+    block_on(applier.close()).unwrap();
+    let log = log.lock().unwrap().clone();
+    let count = |line: &str| log.iter().filter(|l| l.as_str() == line).count();
+    // 已经执行但是没有收到回复的INCR不能再执行一次, 重连后仍然在db 1上
+    assert_eq!(count("1:incr drop{d}"), 1, "{:?}", log);
+    assert_eq!(count("1:set {d}a 1"), 1, "{:?}", log);
+    assert_eq!(count("1:set {d}b 1"), 1, "{:?}", log);
+    // APPEND和INCR在同一批时结果未知, 不会重试, 写到死信文件, 否则正常执行
+    let letters = letters(&opts.path);
+    assert!(letters.iter().all(|(l, _)| l.class() == "UNKNOWN" && l.db == 1), "{:?}", letters);
+    let lost: Vec<String> = letters.iter().map(|(_, args)| args.join(" ")).collect();
+    assert_eq!(lost[0], "incr drop{d}");
+    assert_eq!(count("1:append {d}c x") + lost.len(), 2, "{:?} {:?}", log, lost);
+    assert_eq!(stats.classes().get("UNKNOWN").cloned(), Some(lost.len() as u64));
+    assert_eq!(stats.retried.load(Ordering::Relaxed), 0);
+    assert!(log.iter().all(|l| l.starts_with("1:")), "{:?}", log);
+}
+
+// 读出死信文件中的注释和命令
+fn letters(path: &str) -> Vec<(Letter, Vec<String>)> {
+    let mut parser = CommandParser::new();
+    for file in segments(path).unwrap() {
+        parser.feed(&std::fs::read(file).unwrap());
+    }
+    let mut rsl = vec![];
+    let mut letter = None;
+    while let Some(frame) = parser.next_frame().unwrap() {
+        match frame {
+            Frame::Comment(line, _) => letter = Letter::parse(&line),
+            Frame::Command(c) => rsl.push((
+                letter.take().unwrap(),
+                c.args().map(|a| String::from_utf8_lossy(a).to_string()).collect(),
+            )),
+            Frame::Empty(_) => {}
+        }
+    }
+    rsl
+}
+
+#[test]
+fn deadletter_and_redrive() {
+    let opts = DeadLetterOptions { path: temp_path("deadletter.aof"), max_bytes: 1024 * 1024, max_files: 10 };
+    let deadletter = DeadLetter::open(&opts).unwrap().map(Arc::new);
+    let (applier, addr, log) = apply(ReplyPolicy::Skip, writes(), deadletter);
+    block_on(applier.close()).unwrap();
+    let mut got: Vec<(String, i64, u64, String)> = letters(&opts.path)
+        .into_iter()
+        .map(|(l, args)| (args.join(" "), l.db, l.offset, l.class()))
+        .collect();
+    got.sort();
+    assert_eq!(
+        got,
+        vec![
+            ("set bad 1".to_string(), 1, 2, "WRONGTYPE".to_string()),
+            ("set bad{t} 1".to_string(), 1, 8, "WRONGTYPE".to_string()),
+            ("set flaky1 1".to_string(), 1, 3, "OOM".to_string()),
+            ("set flaky2 1".to_string(), 1, 4, "OOM".to_string()),
+        ]
+    );
+    // 之后成功写入的flaky2有标记
+    let data = segments(&opts.path).unwrap().iter().map(|f| std::fs::read(f).unwrap()).collect::<Vec<_>>().concat();
+    let marker = Seen { db: 1, offset: 5, key: b"flaky2".to_vec() }.encode();
+    assert!(data.windows(marker.len()).any(|w| w == &marker[..]), "{}", String::from_utf8_lossy(&data));
+    // 只重放OOM, 假的目的端第二次不再返回OOM
+    let redrive = DeadLetterOptions { path: temp_path("redrive.aof"), ..opts.clone() };
+    let errors = vec!["oom".to_string()];
+    block_on(Runner::mod_redrive(&opts.path, addr, "", 2, ReplyPolicy::Skip, &redrive, &errors)).unwrap();
+    let log = log.lock().unwrap().clone();
+    assert_eq!(log.iter().filter(|l| l.as_str() == "1:set flaky1 1").count(), 1, "{:?}", log);
+    assert!(!log.iter().any(|l| l.contains("bad")), "{:?}", log);
+    // 旧的flaky2不会覆盖之后写入的新值
+    let flaky2: Vec<&String> = log.iter().filter(|l| l.contains("flaky2")).collect();
+    assert_eq!(flaky2, vec!["1:set flaky2 2"], "{:?}", log);
+    assert!(segments(&redrive.path).unwrap().is_empty());
+    // 全部重放, WRONGTYPE再次失败, 写到新的死信文件, 偏移量不变
+    block_on(Runner::mod_redrive(&opts.path, addr, "", 1, ReplyPolicy::Skip, &redrive, &[])).unwrap();
+    let again: Vec<(String, u64)> = letters(&redrive.path).into_iter().map(|(l, args)| (args.join(" "), l.offset)).collect();
+    assert_eq!(again, vec![("set bad 1".to_string(), 2), ("set bad{t} 1".to_string(), 8)]);
+}
+
+#[test]
+fn partial_writes_do_not_supersede() {
+    let opts = DeadLetterOptions { path: temp_path("partial.aof"), max_bytes: 1024 * 1024, max_files: 10 };
+    let deadletter = DeadLetter::open(&opts).unwrap().map(Arc::new);
+    // 每个key的第一个命令返回OOM, 之后的命令执行成功
+    let cmds = vec![
+        command(&["hset", "flakyh", "f1", "v"]),
+        command(&["hset", "flakyh", "f2", "v"]),
+        command(&["rpush", "flakyl", "a"]),
+        command(&["rpush", "flakyl", "b"]),
+        command(&["set", "flakys", "1"]),
+        command(&["set", "flakys", "2"]),
+    ];
+    let (applier, addr, log) = apply(ReplyPolicy::Skip, cmds, deadletter);
+    block_on(applier.close()).unwrap();
+    assert_eq!(letters(&opts.path).len(), 3);
+    let redrive = DeadLetterOptions { path: temp_path("partial-redrive.aof"), ..opts.clone() };
+    block_on(Runner::mod_redrive(&opts.path, addr, "", 2, ReplyPolicy::Skip, &redrive, &[])).unwrap();
+    let log = log.lock().unwrap().clone();
+    // HSET和RPUSH只写了一部分, 丢失的field和元素仍然要补上
+    assert!(log.contains(&"0:hset flakyh f1 v".to_string()), "{:?}", log);
+    assert!(log.contains(&"0:rpush flakyl a".to_string()), "{:?}", log);
+    // SET覆盖了整个key, 旧的值不再重放
+    assert!(!log.contains(&"0:set flakys 1".to_string()), "{:?}", log);
+    assert!(segments(&redrive.path).unwrap().is_empty());
+}
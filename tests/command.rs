@@ -0,0 +1,129 @@
+// 增量命令解析: 精确的字节数, inline命令, 数据不完整和错误的长度
+use redis_shake_rs::utils::command::{CommandParser, Frame};
+
+// 命令展开成参数列表, 注释和空命令用特殊的标记
+fn describe(frames: &[Frame]) -> Vec<(Vec<Vec<u8>>, usize)> {
+    frames
+        .iter()
+        .map(|f| match f {
+            Frame::Command(c) => (c.args().map(|a| a.to_vec()).collect(), f.size()),
+            Frame::Comment(line, n) => (vec![b"#".to_vec(), line.to_vec()], *n),
+            Frame::Empty(n) => (vec![], *n),
+        })
+        .collect()
+}
+
+fn parse_all(data: &[u8]) -> Vec<Frame> {
+    let mut parser = CommandParser::new();
+    parser.feed(data);
+    let mut frames = vec![];
+    while let Some(f) = parser.next_frame().unwrap() {
+        frames.push(f);
+    }
+    assert_eq!(parser.buffered(), 0);
+    frames
+}
+
+fn args(v: &[&[u8]]) -> Vec<Vec<u8>> {
+    v.iter().map(|a| a.to_vec()).collect()
+}
+
+#[test]
+fn multi_bulk_inline_and_comments() {
+    let data = b"*3\r\n$3\r\nSET\r\n$4\r\nk\r\n1\r\n$0\r\n\r\n#TS:1628217470\r\n\nPING\r\nset  a \"b c\\x41\\n\" 'd\\'e'\r\n*0\r\n*-1\r\n";
+    let frames = parse_all(data);
+    assert_eq!(
+        describe(&frames),
+        vec![
+            (args(&[b"SET", b"k\r\n1", b""]), 29),
+            (args(&[b"#", b"TS:1628217470"]), 16),
+            (vec![], 1),
+            (args(&[b"PING"]), 6),
+            (args(&[b"set", b"a", b"b cA\n", b"d'e"]), 27),
+            (vec![], 4),
+            (vec![], 5),
+        ]
+    );
+    assert_eq!(frames.iter().map(|f| f.size()).sum::<usize>(), data.len());
+    if let Frame::Command(c) = &frames[4] {
+        assert_eq!(c.arg_count(), 4);
+        assert_eq!(c.name(), b"set");
+        assert_eq!(c.arg(2), Some(&b"b cA\n"[..]));
+        assert_eq!(c.arg(4), None);
+        assert_eq!(c.to_args()[3], &b"d'e"[..]);
+    } else {
+        panic!("{:?}", frames[4]);
+    }
+}
+
+// 一次喂一个字节, 结果和一次喂完一样
+#[test]
+fn byte_by_byte() {
+    let data = b"*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n#TS:1\nSET x y\n*1\r\n$4\r\nPING\r\n";
+    let whole = parse_all(data);
+    let mut parser = CommandParser::new();
+    let mut frames = vec![];
+    for b in data.iter() {
+        parser.feed(&[*b]);
+        while let Some(f) = parser.next_frame().unwrap() {
+            frames.push(f);
+        }
+    }
+    assert_eq!(describe(&frames), describe(&whole));
+    assert_eq!(whole.len(), 4);
+}
+
+// 大命令分很多次到达, 每次从上次扫描到的参数继续, 中间穿插其它命令
+#[test]
+fn big_command_in_pieces() {
+    let mut data = b"*20001\r\n$5\r\nRPUSH\r\n".to_vec();
+    for i in 0..20000 {
+        let arg = format!("member-{}", i);
+        data.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+    }
+    data.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+    let mut parser = CommandParser::new();
+    let mut frames = vec![];
+    for piece in data.chunks(7) {
+        parser.feed(piece);
+        while let Some(f) = parser.next_frame().unwrap() {
+            frames.push(f);
+        }
+    }
+    assert_eq!(parser.buffered(), 0);
+    assert_eq!(describe(&frames), describe(&parse_all(&data)));
+    assert_eq!(frames.len(), 2);
+    if let Frame::Command(c) = &frames[0] {
+        assert_eq!(c.arg_count(), 20001);
+        assert_eq!(c.arg(20000), Some(&b"member-19999"[..]));
+    } else {
+        panic!("{:?}", frames[0]);
+    }
+}
+
+#[test]
+fn malformed_lengths_are_errors() {
+    for data in [
+        &b"*x\r\n"[..],
+        b"*-x\r\n",
+        b"*99999999999999999999999\r\n",
+        b"*1\r\n$-1\r\n",
+        b"*1\r\n$abc\r\n",
+        b"*1\r\n$999999999999\r\n",
+        b"*1\r\n$1\n",
+        b"*1\r\n:1\r\n",
+        b"*1\r\n$1\r\nab\r\n",
+        b"SET \"a\r\n",
+        b"SET \"a\"b\r\n",
+    ]
+    .iter()
+    {
+        let mut parser = CommandParser::new();
+        parser.feed(data);
+        assert!(parser.next_frame().is_err(), "{:?}", String::from_utf8_lossy(data));
+    }
+    // 没有换行的超长inline
+    let mut parser = CommandParser::new();
+    parser.feed(&vec![b'a'; 65 * 1024]);
+    assert!(parser.next_frame().is_err());
+}
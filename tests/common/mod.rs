@@ -0,0 +1,11 @@
+// 各个测试共用的辅助函数
+#![allow(dead_code)]
+
+use std::fs;
+
+// 每个测试进程一个临时目录
+pub fn temp_path(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("redis-shake-rs-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name).to_string_lossy().to_string()
+}
@@ -0,0 +1,101 @@
+// 压缩过的rdb文件边读边解压, 结果和没压缩的一样; Writer写出的压缩文件也能读回来
+mod common;
+
+use common::temp_path;
+use redis_shake_rs::rdb::compress::{CompressWriter, Compression};
+use redis_shake_rs::rdb::loader::{self, BinEntry, Loader};
+use redis_shake_rs::rdb::writer::Writer;
+use redis_shake_rs::utils::source::{open_rdb_file, FileLoader};
+
+use async_std::task::block_on;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const COMPRESSIONS: [Compression; 4] = [Compression::None, Compression::Gzip, Compression::Zstd, Compression::Lz4];
+
+fn corpus_file() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/redis-7.4.rdb")
+}
+
+async fn read_all<R: tokio::io::AsyncRead + Unpin>(
+    loader: &mut Loader<R>,
+) -> Result<(i32, Vec<BinEntry>), Box<dyn Error>> {
+    let version = loader.Header().await?;
+    let mut entries = vec![];
+    loop {
+        let mut e = BinEntry::default();
+        match loader.NextBinEntry(&mut e).await {
+            Ok(()) => entries.push(e),
+            Err(err) if err.to_string() == "RDB END" => break,
+            Err(err) => return Err(err),
+        }
+    }
+    loader.Footer().await?;
+    Ok((version, entries))
+}
+
+// db, key, 类型, 值, 过期时间
+type Key = (u32, Vec<u8>, u8, Vec<u8>, u64);
+
+fn keys(entries: &[BinEntry]) -> Vec<Key> {
+    entries.iter().map(|e| (e.DB, e.Key.clone(), e.Type, e.Value.clone(), e.ExpireAt)).collect()
+}
+
+#[test]
+fn read_compressed_rdb() {
+    let data = fs::read(corpus_file()).unwrap();
+    let (_, want) = block_on(read_all(&mut Loader::fromBytes(data.clone()))).unwrap();
+    for compression in COMPRESSIONS.iter() {
+        let path = temp_path(&format!("dump.rdb{}", compression.extension()));
+        let mut w = CompressWriter::create(&path, *compression).unwrap();
+        w.write_all(&data).unwrap();
+        w.finish().unwrap();
+        let mut loader: FileLoader = block_on(open_rdb_file(&path)).unwrap();
+        let (_, got) = block_on(read_all(&mut loader)).unwrap_or_else(|e| panic!("{:?}: {}", compression, e));
+        assert_eq!(keys(&got), keys(&want), "{:?}", compression);
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+// 解压后的数据坏了, 校验和按解压后的数据计算, 能发现
+#[test]
+fn crc_checked_after_decompress() {
+    let mut data = fs::read(corpus_file()).unwrap();
+    let n = data.len();
+    data[n - 1] ^= 0xff;
+    let path = temp_path("bad.rdb.zst");
+    let mut w = CompressWriter::create(&path, Compression::Zstd).unwrap();
+    w.write_all(&data).unwrap();
+    w.finish().unwrap();
+    let mut loader = block_on(open_rdb_file(&path)).unwrap();
+    let err = block_on(read_all(&mut loader)).expect_err("校验和应该不一致");
+    assert!(err.to_string().contains("校验和"), "{}", err);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn write_compressed_dump() {
+    let data = fs::read(corpus_file()).unwrap();
+    let (version, want) = block_on(read_all(&mut Loader::fromBytes(data))).unwrap();
+    for compression in COMPRESSIONS.iter() {
+        let path = temp_path(&format!("out.rdb{}", compression.extension()));
+        let mut writer = Writer::create(&path, version, true, *compression).unwrap();
+        for e in want.iter() {
+            match e.Type {
+                loader::rdbFlagFunction2 => writer.write_function(&e.Value).unwrap(),
+                loader::RdbFlagAUX => writer.write_aux(&e.Key, &e.Value).unwrap(),
+                _ => writer.write_entry(e).unwrap(),
+            }
+        }
+        writer.footer().unwrap();
+        writer.finish().unwrap();
+        let head = fs::read(&path).unwrap();
+        assert_eq!(Compression::detect(&head), *compression);
+        let mut loader = block_on(open_rdb_file(&path)).unwrap();
+        let (_, got) = block_on(read_all(&mut loader)).unwrap_or_else(|e| panic!("{:?}: {}", compression, e));
+        assert_eq!(keys(&got), keys(&want), "{:?}", compression);
+        fs::remove_file(&path).unwrap();
+    }
+}
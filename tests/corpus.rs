@@ -0,0 +1,476 @@
+// tests/corpus下各个redis版本的rdb, 解码结果和.txt逐行比较
+// 这些rdb是gen.py合成的, 不是redis-server导出的; redis-server导出的rdb在tests/corpus/real下, 只和.keys比较
+// 再把每个key用OverRestoreBigRdbEntry拆成命令(按很小的段拆分), 执行到内存中的模型上, 结果要和解码一致
+use redis_shake_rs::rdb::crc64::Crc64;
+use redis_shake_rs::rdb::full::OverRestoreBigRdbEntry;
+use redis_shake_rs::rdb::loader::{self, rdbTypeName, BinEntry, Loader};
+use redis_shake_rs::rdb::value::{
+    decode_value, RedisValue, StreamConsumer, StreamEntry, StreamGroup, StreamID, StreamPending, StreamValue,
+};
+use redis_shake_rs::utils::memory::{CmdSender, InflightLimit};
+use redis_shake_rs::utils::version::TargetVersion;
+
+use async_std::task::block_on;
+use redis::{Arg, Cmd};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+fn corpus() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|f| f.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "rdb"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "{:?}中没有rdb文件", dir);
+    files
+}
+
+async fn load(data: Vec<u8>, max_chunk: usize) -> Result<Vec<BinEntry>, Box<dyn Error>> {
+    let mut loader = Loader::fromBytes(data);
+    loader.rdbReader.maxChunk = max_chunk;
+    loader.Header().await?;
+    let mut entries = vec![];
+    loop {
+        let mut e = BinEntry::default();
+        match loader.NextBinEntry(&mut e).await {
+            Ok(()) => entries.push(e),
+            Err(err) if err.to_string() == "RDB END" => break,
+            Err(err) => return Err(err),
+        }
+    }
+    loader.Footer().await?;
+    Ok(entries)
+}
+
+fn is_key(e: &BinEntry) -> bool {
+    e.Type != loader::RdbFlagAUX && e.Type != loader::rdbFlagFunction2
+}
+
+// 和gen.py的q一致
+fn q(b: &[u8]) -> String {
+    if b.len() > 128 {
+        let mut crc = Crc64::new();
+        crc.update(b);
+        return format!("<{} bytes crc64 {:016x}>", b.len(), crc.get());
+    }
+    let mut out = String::from("\"");
+    for c in b {
+        if *c == b'"' || *c == b'\\' || *c < 0x20 || *c > 0x7e {
+            out.push_str(&format!("\\x{:02x}", c));
+        } else {
+            out.push(*c as char);
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn score(s: f64) -> String {
+    if s.is_infinite() {
+        return if s > 0.0 { "inf".to_string() } else { "-inf".to_string() };
+    }
+    format!("{}", s)
+}
+
+fn render_value(value: &RedisValue, lines: &mut Vec<String>) {
+    match value {
+        RedisValue::String(v) => lines.push(format!("  value {}", q(v))),
+        RedisValue::List(v) => lines.extend(v.iter().map(|m| format!("  item {}", q(m)))),
+        RedisValue::Set(v) => {
+            let mut v = v.clone();
+            v.sort();
+            lines.extend(v.iter().map(|m| format!("  member {}", q(m))));
+        }
+        RedisValue::ZSet(v) => {
+            let mut v = v.clone();
+            v.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)));
+            lines.extend(v.iter().map(|(m, s)| format!("  score {} {}", score(*s), q(m))));
+        }
+        RedisValue::Hash(v) => {
+            let mut v = v.clone();
+            v.sort();
+            for (f, d, ttl) in v.iter() {
+                let mut line = format!("  field {} {}", q(f), q(d));
+                if *ttl != 0 {
+                    line.push_str(&format!(" ttl {}", ttl));
+                }
+                lines.push(line);
+            }
+        }
+        RedisValue::Stream(s) => {
+            for entry in s.entries.iter() {
+                let fields: Vec<String> = entry.fields.iter().map(|(f, v)| format!("{} {}", q(f), q(v))).collect();
+                lines.push(format!("  entry {} {}", entry.id, fields.join(" ")));
+            }
+            lines.push(format!("  length {}", s.meta.length));
+            lines.push(format!("  last_id {}", s.meta.last_id));
+            if let Some(id) = s.meta.first_id {
+                lines.push(format!("  first_id {}", id));
+            }
+            if let Some(id) = s.meta.max_deleted_id {
+                lines.push(format!("  max_deleted_id {}", id));
+            }
+            if let Some(n) = s.meta.entries_added {
+                lines.push(format!("  entries_added {}", n));
+            }
+            for g in s.meta.groups.iter() {
+                let mut line = format!("  group {} last_id {}", q(&g.name), g.last_id);
+                if let Some(n) = g.entries_read {
+                    line.push_str(&format!(" entries_read {}", n));
+                }
+                lines.push(line);
+                for p in g.pending.iter() {
+                    lines.push(format!("    pending {} time {} count {}", p.id, p.delivery_time, p.delivery_count));
+                }
+                for c in g.consumers.iter() {
+                    let mut line = format!("    consumer {} seen {}", q(&c.name), c.seen_time);
+                    if let Some(t) = c.active_time {
+                        line.push_str(&format!(" active {}", t));
+                    }
+                    lines.push(line);
+                    lines.extend(c.pending.iter().map(|id| format!("      pending {}", id)));
+                }
+            }
+        }
+        RedisValue::Module(m) => {
+            let raw: Vec<String> = m.raw.iter().map(|b| format!("{:02x}", b)).collect();
+            lines.push(format!("  module {} {} {}", m.name, m.version, raw.concat()));
+        }
+    }
+}
+
+async fn render(e: &BinEntry) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut lines = vec![];
+    if e.Type == loader::RdbFlagAUX {
+        lines.push(format!("aux {}", q(&e.Key)));
+        lines.push(format!("  value {}", q(&e.Value)));
+        return Ok(lines);
+    }
+    if e.Type == loader::rdbFlagFunction2 {
+        lines.push(format!("function {}", q(&e.Key)));
+        lines.push(format!("  code {}", q(&e.Value)));
+        return Ok(lines);
+    }
+    lines.push(format!("key {} {} {}({})", e.DB, q(&e.Key), rdbTypeName(e.Type), e.Type));
+    if e.ExpireAt != 0 {
+        lines.push(format!("  expire {}", e.ExpireAt));
+    }
+    if e.IdleTime != 0 {
+        lines.push(format!("  idle {}", e.IdleTime));
+    }
+    if e.Freq != 0 {
+        lines.push(format!("  freq {}", e.Freq));
+    }
+    render_value(&decode_value(e).await?, &mut lines);
+    Ok(lines)
+}
+
+#[test]
+fn decode_matches_golden() {
+    for path in corpus() {
+        let golden = fs::read_to_string(path.with_extension("txt")).unwrap();
+        let want: Vec<&str> = golden.lines().filter(|l| !l.starts_with('#')).collect();
+        let got = block_on(async {
+            let mut lines = vec![];
+            for e in load(fs::read(&path)?, loader::defaultMaxChunk).await? {
+                lines.extend(render(&e).await?);
+            }
+            Ok::<_, Box<dyn Error>>(lines)
+        })
+        .unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+        for (i, (g, w)) in got.iter().zip(want.iter()).enumerate() {
+            assert_eq!(g, w, "{:?} 第{}行", path, i + 1);
+        }
+        assert_eq!(got.len(), want.len(), "{:?} 行数不一致", path);
+    }
+}
+
+// AUX字段和RESIZEDB收集到loader.metadata, 版本和golden第一行一致
+#[test]
+fn metadata_matches_golden() {
+    for path in corpus() {
+        let golden = fs::read_to_string(path.with_extension("txt")).unwrap();
+        let header: Vec<&str> = golden.lines().next().unwrap().split(' ').collect();
+        let (ver, version) = (header[2], header[5].trim_end_matches(",").parse::<i32>().unwrap());
+        let mut loader = Loader::fromBytes(fs::read(&path).unwrap());
+        block_on(async {
+            loader.Header().await?;
+            loop {
+                match loader.NextBinEntry(&mut BinEntry::default()).await {
+                    Ok(()) => {}
+                    Err(err) if err.to_string() == "RDB END" => break,
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok::<_, Box<dyn Error>>(())
+        })
+        .unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+        let m = &loader.metadata;
+        assert_eq!(m.Version, version, "{:?}", path);
+        // redis 2.8的rdb没有AUX和RESIZEDB
+        if version < 7 {
+            assert!(m.Aux.is_empty() && m.DbSizes.is_empty(), "{:?}", path);
+            continue;
+        }
+        assert_eq!(m.RedisVer, ver, "{:?}", path);
+        assert_eq!((m.RedisBits, m.Ctime, m.UsedMem), (64, 1700000000, 1048576), "{:?}", path);
+        assert_eq!(m.DbSizes.get(&0), Some(&(40, 2)), "{:?}", path);
+        if version >= 8 {
+            assert_eq!((m.ReplId.as_str(), m.ReplOffset), ("0".repeat(40).as_str(), 0), "{:?}", path);
+            assert!(m.Aux.iter().any(|(k, _)| k == "lua"), "{:?}", path);
+        }
+    }
+}
+
+// 执行OverRestoreBigRdbEntry发出的命令
+#[derive(Default)]
+struct Model {
+    values: BTreeMap<Vec<u8>, RedisValue>,
+    expires: BTreeMap<Vec<u8>, u64>,
+}
+
+fn num<T: std::str::FromStr>(b: &[u8]) -> T
+where
+    T::Err: std::fmt::Debug,
+{
+    String::from_utf8_lossy(b).parse::<T>().unwrap()
+}
+
+fn stream_id(b: &[u8]) -> StreamID {
+    let s = String::from_utf8_lossy(b);
+    let mut it = s.splitn(2, '-');
+    StreamID {
+        ms: it.next().unwrap().parse().unwrap(),
+        seq: it.next().unwrap().parse().unwrap(),
+    }
+}
+
+impl Model {
+    fn stream(&mut self, key: &[u8]) -> &mut StreamValue {
+        match self.values.entry(key.to_vec()).or_insert_with(|| RedisValue::Stream(StreamValue::default())) {
+            RedisValue::Stream(s) => s,
+            v => panic!("{} 不是stream {:?}", String::from_utf8_lossy(key), v),
+        }
+    }
+    fn group(&mut self, key: &[u8], name: &[u8]) -> &mut StreamGroup {
+        self.stream(key).meta.groups.iter_mut().find(|g| g.name == name).unwrap()
+    }
+    fn apply(&mut self, cmd: &Cmd) {
+        let args: Vec<Vec<u8>> = cmd
+            .args_iter()
+            .map(|a| match a {
+                Arg::Simple(d) => d.to_vec(),
+                Arg::Cursor => vec![],
+            })
+            .collect();
+        let key = args[1].clone();
+        match String::from_utf8_lossy(&args[0]).to_uppercase().as_str() {
+            "DEL" => {
+                self.values.remove(&key);
+                self.expires.remove(&key);
+            }
+            "PEXPIREAT" => {
+                self.expires.insert(key, num(&args[2]));
+            }
+            "SET" => {
+                self.values.insert(key, RedisValue::String(args[2].clone()));
+            }
+            "RPUSH" | "SADD" | "ZADD" | "HSET" => {
+                let value = self.values.entry(key).or_insert_with(|| match args[0].as_slice() {
+                    b"RPUSH" => RedisValue::List(vec![]),
+                    b"SADD" => RedisValue::Set(vec![]),
+                    b"ZADD" => RedisValue::ZSet(vec![]),
+                    _ => RedisValue::Hash(vec![]),
+                });
+                match value {
+                    RedisValue::List(v) | RedisValue::Set(v) => v.push(args[2].clone()),
+                    RedisValue::ZSet(v) => {
+                        // 和redis的strtod一样, 超出范围的数不是inf
+                        let score: f64 = num(&args[2]);
+                        assert!(score.is_finite() || args[2].ends_with(b"inf"), "{:?}", args);
+                        v.push((args[3].clone(), score))
+                    }
+                    RedisValue::Hash(v) => v.push((args[2].clone(), args[3].clone(), 0)),
+                    _ => panic!("{:?}", args),
+                }
+            }
+            "HPEXPIREAT" => {
+                if let Some(RedisValue::Hash(v)) = self.values.get_mut(&key) {
+                    let field = v.iter_mut().find(|f| f.0 == args[5]).unwrap();
+                    field.2 = num(&args[2]);
+                }
+            }
+            "XADD" => {
+                let (maxlen, i) = if args[2] == b"MAXLEN" { (Some(num::<usize>(&args[3])), 4) } else { (None, 2) };
+                let fields = args[i + 1..].chunks(2).map(|kv| (kv[0].clone(), kv[1].clone())).collect();
+                let s = self.stream(&key);
+                s.entries.push(StreamEntry {
+                    id: stream_id(&args[i]),
+                    fields,
+                });
+                if maxlen == Some(0) {
+                    s.entries.clear();
+                }
+            }
+            "XSETID" => {
+                let s = self.stream(&key);
+                s.meta.last_id = stream_id(&args[2]);
+                for kv in args[3..].chunks(2) {
+                    match kv[0].as_slice() {
+                        b"ENTRIESADDED" => s.meta.entries_added = Some(num(&kv[1])),
+                        b"MAXDELETEDID" => s.meta.max_deleted_id = Some(stream_id(&kv[1])),
+                        _ => panic!("{:?}", args),
+                    }
+                }
+            }
+            "XGROUP" => {
+                let key = args[2].clone();
+                match args[1].as_slice() {
+                    b"CREATE" => {
+                        let mut group = StreamGroup {
+                            name: args[3].clone(),
+                            last_id: stream_id(&args[4]),
+                            ..Default::default()
+                        };
+                        if args.len() > 6 && args[5] == b"ENTRIESREAD" {
+                            group.entries_read = Some(num(&args[6]));
+                        }
+                        self.stream(&key).meta.groups.push(group);
+                    }
+                    b"CREATECONSUMER" => {
+                        let consumer = StreamConsumer {
+                            name: args[4].clone(),
+                            ..Default::default()
+                        };
+                        self.group(&key, &args[3]).consumers.push(consumer);
+                    }
+                    _ => panic!("{:?}", args),
+                }
+            }
+            "XCLAIM" => {
+                // XCLAIM key group consumer 0 id TIME t RETRYCOUNT n FORCE JUSTID
+                let id = stream_id(&args[5]);
+                let group = self.group(&key, &args[2]);
+                group.pending.push(StreamPending {
+                    id,
+                    delivery_time: num(&args[7]),
+                    delivery_count: num(&args[9]),
+                });
+                // 和redis一样, XCLAIM时消费者不存在就创建
+                if !group.consumers.iter().any(|c| c.name == args[3]) {
+                    group.consumers.push(StreamConsumer {
+                        name: args[3].clone(),
+                        ..Default::default()
+                    });
+                }
+                group.consumers.iter_mut().find(|c| c.name == args[3]).unwrap().pending.push(id);
+            }
+            _ => panic!("不支持的命令 {:?}", args),
+        }
+    }
+}
+
+// 命令重建不了的信息: 消费者的seen/active time(XCLAIM时会更新), first_id和length由entries决定
+fn restore_view(value: &RedisValue) -> RedisValue {
+    let mut value = value.clone();
+    if let RedisValue::Stream(s) = &mut value {
+        s.meta.length = s.entries.len() as u64;
+        s.meta.first_id = None;
+        for g in s.meta.groups.iter_mut() {
+            g.pending.sort_by_key(|p| p.id);
+            g.consumers.sort_by(|a, b| a.name.cmp(&b.name));
+            for c in g.consumers.iter_mut() {
+                c.seen_time = 0;
+                c.active_time = None;
+                c.pending.sort();
+            }
+        }
+    }
+    value
+}
+
+#[test]
+fn restore_matches_decode() {
+    for path in corpus() {
+        block_on(async {
+            let data = fs::read(&path)?;
+            // 按64字节拆分, 集合类型的key都会拆成多段
+            let chunks = load(data.clone(), 64).await?;
+            let whole = load(data, loader::defaultMaxChunk).await?;
+            assert!(chunks.len() > whole.len(), "{:?} 没有拆分", path);
+            let (sender, mut receiver) = mpsc::channel(100000);
+            let mut sender = CmdSender::new(sender, InflightLimit::new(0));
+            for e in chunks.iter().filter(|e| is_key(e) && e.Type != loader::RdbTypeModule2) {
+                OverRestoreBigRdbEntry(e, &mut sender, TargetVersion::latest()).await?;
+            }
+            drop(sender);
+            let mut model = Model::default();
+            while let Some((cmd, _)) = receiver.recv().await {
+                model.apply(&cmd);
+            }
+            for e in whole.iter().filter(|e| is_key(e) && e.Type != loader::RdbTypeModule2) {
+                let key = String::from_utf8_lossy(&e.Key);
+                let mut want = vec![];
+                render_value(&restore_view(&decode_value(e).await?), &mut want);
+                let mut got = vec![];
+                let value = model.values.get(&e.Key).unwrap_or_else(|| panic!("{:?} 没有恢复 {}", path, key));
+                render_value(&restore_view(value), &mut got);
+                assert_eq!(got, want, "{:?} {}", path, key);
+                assert_eq!(model.expires.get(&e.Key).cloned().unwrap_or(0), e.ExpireAt, "{:?} {}", path, key);
+            }
+            Ok::<_, Box<dyn Error>>(())
+        })
+        .unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+    }
+}
+
+// redis-server导出的rdb, 每个key的类型和元素个数和从redis-server读出的一致
+#[test]
+fn real_dumps_match_server() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/real");
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|f| f.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "rdb"))
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        println!("{:?}中还没有redis-server导出的rdb, 用dump.sh生成", dir);
+        return;
+    }
+    for path in files {
+        let keys = fs::read_to_string(path.with_extension("keys")).unwrap();
+        let want: Vec<&str> = keys.lines().collect();
+        let got = block_on(async {
+            // 拆分成多段的key按key累加
+            let mut sizes: BTreeMap<(u32, Vec<u8>), (&str, usize)> = BTreeMap::new();
+            for e in load(fs::read(&path)?, loader::defaultMaxChunk).await? {
+                if !is_key(&e) {
+                    continue;
+                }
+                let n = match decode_value(&e).await? {
+                    RedisValue::String(v) => v.len(),
+                    RedisValue::List(v) | RedisValue::Set(v) => v.len(),
+                    RedisValue::ZSet(v) => v.len(),
+                    RedisValue::Hash(v) => v.len(),
+                    RedisValue::Stream(v) => v.entries.len(),
+                    RedisValue::Module(_) => 0,
+                };
+                sizes.entry((e.DB, e.Key.clone())).or_insert((rdbTypeName(e.Type), 0)).1 += n;
+            }
+            Ok::<_, Box<dyn Error>>(sizes)
+        })
+        .unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+        let mut got: Vec<String> = got
+            .iter()
+            .map(|((db, key), (t, n))| format!("{} {} {} {}", db, t, String::from_utf8_lossy(key), n))
+            .collect();
+        got.sort();
+        assert_eq!(got, want, "{:?}", path);
+    }
+}
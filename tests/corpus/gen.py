@@ -0,0 +1,718 @@
+#!/usr/bin/env python3
+# 生成tests/corpus下的rdb文件和期望的解码结果(.txt), 修改后重新运行: python3 tests/corpus/gen.py
+#
+# 这些文件不是从redis-server导出的, 而是按照各个版本rdb.c, ziplist.c, listpack.c, t_stream.c
+# 的格式独立编码的. 每个版本只使用这个版本默认配置下会写出的编码, 另外2.8包含它仍然能加载的
+# zipmap和秒级过期时间, 7.0包含plain节点的quicklist(正常只有超过1GB的元素才会出现).
+# 期望结果直接由下面的数据生成, 不经过编码, 所以和loader是互相独立的两份实现.
+
+import os
+import random
+import re
+import struct
+
+here = os.path.dirname(os.path.abspath(__file__))
+
+# ---------------------------------------------------------------- crc64 (jones, reflected)
+
+crcTable = []
+for i in range(256):
+    crc = i
+    for _ in range(8):
+        crc = (crc >> 1) ^ 0x95AC9329AC4BC9B5 if crc & 1 else crc >> 1
+    crcTable.append(crc)
+
+
+def crc64(data):
+    crc = 0
+    for b in data:
+        crc = crcTable[(crc ^ b) & 0xFF] ^ (crc >> 8)
+    return crc
+
+
+assert crc64(b"123456789") == 0xE9C6D914C4B8D9CA
+
+# ---------------------------------------------------------------- 基本编码
+
+intRe = re.compile(rb"^-?(0|[1-9][0-9]*)$")
+
+
+def toInt(b, maxLen):
+    # 和string2ll一样只接受规范的十进制
+    if len(b) == 0 or len(b) > maxLen or not intRe.match(b) or b == b"-0":
+        return None
+    v = int(b)
+    if v < -(1 << 63) or v >= 1 << 63:
+        return None
+    return v
+
+
+def encLen(n):
+    if n < 1 << 6:
+        return bytes([n])
+    if n < 1 << 14:
+        return bytes([0x40 | (n >> 8), n & 0xFF])
+    if n <= 0xFFFFFFFF:
+        return b"\x80" + struct.pack(">I", n)
+    return b"\x81" + struct.pack(">Q", n)
+
+
+def lzfCompress(data):
+    out = bytearray()
+    lit = bytearray()
+    table = {}
+
+    def flush():
+        while lit:
+            chunk = lit[:32]
+            out.append(len(chunk) - 1)
+            out.extend(chunk)
+            del lit[:32]
+
+    i = 0
+    while i < len(data):
+        if i + 2 < len(data):
+            key = bytes(data[i:i + 3])
+            ref = table.get(key)
+            table[key] = i
+            if ref is not None and i - ref - 1 < 8192:
+                length = 3
+                maxLen = min(264, len(data) - i)
+                while length < maxLen and data[ref + length] == data[i + length]:
+                    length += 1
+                flush()
+                off = i - ref - 1
+                n = length - 2
+                if n < 7:
+                    out.append((n << 5) | (off >> 8))
+                else:
+                    out.append((7 << 5) | (off >> 8))
+                    out.append(n - 7)
+                out.append(off & 0xFF)
+                i += length
+                continue
+        lit.append(data[i])
+        i += 1
+    flush()
+    return bytes(out)
+
+
+def encStr(b, compress=True):
+    v = toInt(b, 11)
+    if v is not None:
+        if -(1 << 7) <= v < 1 << 7:
+            return b"\xc0" + struct.pack("<b", v)
+        if -(1 << 15) <= v < 1 << 15:
+            return b"\xc1" + struct.pack("<h", v)
+        if -(1 << 31) <= v < 1 << 31:
+            return b"\xc2" + struct.pack("<i", v)
+    if compress and len(b) > 20:
+        c = lzfCompress(b)
+        if len(c) < len(b) - 4:
+            return b"\xc3" + encLen(len(c)) + encLen(len(b)) + c
+    return encLen(len(b)) + b
+
+
+def encDouble(d):
+    # rdbSaveDoubleValue, zset的老格式
+    if d != d:
+        return b"\xfd"
+    if d == float("inf"):
+        return b"\xfe"
+    if d == float("-inf"):
+        return b"\xff"
+    s = ("%.17g" % d).encode()
+    return bytes([len(s)]) + s
+
+
+def d2string(d):
+    # ziplist/listpack中的score
+    if d == float("inf"):
+        return b"inf"
+    if d == float("-inf"):
+        return b"-inf"
+    if d == int(d) and abs(d) < 1 << 53:
+        return str(int(d)).encode()
+    return ("%.17g" % d).encode()
+
+
+def ms(t):
+    return struct.pack("<Q", t)
+
+
+# ---------------------------------------------------------------- ziplist, listpack, intset, zipmap
+
+def ziplistEntry(prevlen, v):
+    pl = bytes([prevlen]) if prevlen < 254 else b"\xfe" + struct.pack("<I", prevlen)
+    i = toInt(v, 32)
+    if i is not None:
+        if 0 <= i <= 12:
+            enc = bytes([0xF1 + i])
+        elif -(1 << 7) <= i < 1 << 7:
+            enc = b"\xfe" + struct.pack("<b", i)
+        elif -(1 << 15) <= i < 1 << 15:
+            enc = b"\xc0" + struct.pack("<h", i)
+        elif -(1 << 23) <= i < 1 << 23:
+            enc = b"\xf0" + (i & 0xFFFFFF).to_bytes(3, "little")
+        elif -(1 << 31) <= i < 1 << 31:
+            enc = b"\xd0" + struct.pack("<i", i)
+        else:
+            enc = b"\xe0" + struct.pack("<q", i)
+    else:
+        n = len(v)
+        if n < 1 << 6:
+            enc = bytes([n]) + v
+        elif n < 1 << 14:
+            enc = bytes([0x40 | (n >> 8), n & 0xFF]) + v
+        else:
+            enc = b"\x80" + struct.pack(">I", n) + v
+    return pl + enc
+
+
+def ziplist(items):
+    body = b""
+    prev = 0
+    tail = 10
+    for v in items:
+        e = ziplistEntry(prev, v)
+        tail = 10 + len(body)
+        body += e
+        prev = len(e)
+    return struct.pack("<IIH", 10 + len(body) + 1, tail, min(len(items), 65535)) + body + b"\xff"
+
+
+def backlen(n):
+    if n <= 127:
+        return bytes([n])
+    if n < 16383:
+        return bytes([n >> 7, (n & 127) | 128])
+    if n < 2097151:
+        return bytes([n >> 14, ((n >> 7) & 127) | 128, (n & 127) | 128])
+    raise ValueError(n)
+
+
+def listpackEntry(v):
+    if isinstance(v, int):
+        v = str(v).encode()
+    i = toInt(v, 20)
+    if i is not None:
+        if 0 <= i <= 127:
+            enc = bytes([i])
+        elif -(1 << 12) <= i < 1 << 12:
+            u = i & 0x1FFF
+            enc = bytes([0xC0 | (u >> 8), u & 0xFF])
+        elif -(1 << 15) <= i < 1 << 15:
+            enc = b"\xf1" + struct.pack("<h", i)
+        elif -(1 << 23) <= i < 1 << 23:
+            enc = b"\xf2" + (i & 0xFFFFFF).to_bytes(3, "little")
+        elif -(1 << 31) <= i < 1 << 31:
+            enc = b"\xf3" + struct.pack("<i", i)
+        else:
+            enc = b"\xf4" + struct.pack("<q", i)
+    else:
+        n = len(v)
+        if n < 1 << 6:
+            enc = bytes([0x80 | n]) + v
+        elif n < 1 << 12:
+            enc = bytes([0xE0 | (n >> 8), n & 0xFF]) + v
+        else:
+            enc = b"\xf0" + struct.pack("<I", n) + v
+    return enc + backlen(len(enc))
+
+
+def listpack(items):
+    body = b"".join(listpackEntry(v) for v in items)
+    return struct.pack("<IH", 6 + len(body) + 1, min(len(items), 65535)) + body + b"\xff"
+
+
+def intset(values):
+    values = sorted(values)
+    if all(-(1 << 15) <= v < 1 << 15 for v in values):
+        width, fmt = 2, "<h"
+    elif all(-(1 << 31) <= v < 1 << 31 for v in values):
+        width, fmt = 4, "<i"
+    else:
+        width, fmt = 8, "<q"
+    return struct.pack("<II", width, len(values)) + b"".join(struct.pack(fmt, v) for v in values)
+
+
+def zipmapLen(n):
+    return bytes([n]) if n < 254 else b"\xfe" + struct.pack("<I", n)
+
+
+def zipmap(pairs, free):
+    out = bytes([len(pairs) if len(pairs) < 254 else 254])
+    for f, v in pairs:
+        n = free.get(f, 0)
+        out += zipmapLen(len(f)) + f + zipmapLen(len(v)) + bytes([n]) + v + b"\x00" * n
+    return out + b"\xff"
+
+
+# ---------------------------------------------------------------- 类型
+
+typeNames = {
+    0: "string", 1: "list", 2: "set", 3: "zset", 4: "hash", 5: "zset", 7: "module",
+    9: "hash", 10: "list", 11: "set", 12: "zset", 13: "hash", 14: "list", 15: "stream",
+    16: "hash", 17: "zset", 18: "list", 19: "stream", 20: "set", 21: "stream",
+    24: "hash", 25: "hash",
+}
+
+moduleCharSet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_"
+
+
+def moduleId(name, version):
+    mid = 0
+    for c in name.encode():
+        mid = (mid << 6) | moduleCharSet.index(c)
+    return (mid << 10) | version
+
+
+def streamNode(entries):
+    # entries: [(ms, seq, fields, deleted)], master entry的字段取第一个entry
+    master = entries[0]
+    masterFields = [f for f, _ in master[2]]
+    items = [sum(1 for e in entries if not e[3]), sum(1 for e in entries if e[3]), len(masterFields)]
+    items += masterFields + [0]
+    for eMs, eSeq, fields, deleted in entries:
+        same = [f for f, _ in fields] == masterFields
+        flags = (1 if deleted else 0) | (2 if same else 0)
+        items += [flags, eMs - master[0], eSeq - master[1]]
+        if same:
+            items += [v for _, v in fields]
+            items.append(len(fields) + 3)
+        else:
+            items.append(len(fields))
+            for f, v in fields:
+                items += [f, v]
+            items.append(len(fields) * 2 + 4)
+    return encStr(struct.pack(">QQ", master[0], master[1])) + encStr(listpack(items))
+
+
+def encStream(t, s):
+    out = encLen(len(s["nodes"]))
+    for node in s["nodes"]:
+        out += streamNode(node)
+    out += encLen(s["length"]) + encLen(s["last_id"][0]) + encLen(s["last_id"][1])
+    if t != 15:
+        out += encLen(s["first_id"][0]) + encLen(s["first_id"][1])
+        out += encLen(s["max_deleted_id"][0]) + encLen(s["max_deleted_id"][1])
+        out += encLen(s["entries_added"])
+    out += encLen(len(s["groups"]))
+    for g in s["groups"]:
+        out += encStr(g["name"]) + encLen(g["last_id"][0]) + encLen(g["last_id"][1])
+        if t != 15:
+            out += encLen(g["entries_read"] & 0xFFFFFFFFFFFFFFFF)
+        out += encLen(len(g["pending"]))
+        for (pMs, pSeq), time, count in g["pending"]:
+            out += struct.pack(">QQ", pMs, pSeq) + ms(time) + encLen(count)
+        out += encLen(len(g["consumers"]))
+        for c in g["consumers"]:
+            out += encStr(c["name"]) + ms(c["seen"])
+            if t == 21:
+                out += ms(c["active"])
+            out += encLen(len(c["pending"]))
+            for pMs, pSeq in c["pending"]:
+                out += struct.pack(">QQ", pMs, pSeq)
+    return out
+
+
+def encModuleValue(ops):
+    out = b""
+    for op, v in ops:
+        if op == "uint":
+            out += encLen(2) + encLen(v)
+        elif op == "sint":
+            out += encLen(1) + encLen(v & 0xFFFFFFFFFFFFFFFF)
+        elif op == "string":
+            out += encLen(5) + encStr(v)
+        elif op == "float":
+            out += encLen(3) + struct.pack("<f", v)
+        elif op == "double":
+            out += encLen(4) + struct.pack("<d", v)
+    return out + encLen(0)
+
+
+def encValue(t, v):
+    if t == 0:
+        return encStr(v)
+    if t in (1, 2):
+        return encLen(len(v)) + b"".join(encStr(m) for m in v)
+    if t in (3, 5):
+        out = encLen(len(v))
+        # skiplist从尾部开始保存
+        for m, s in reversed(v):
+            out += encStr(m) + (struct.pack("<d", s) if t == 5 else encDouble(s))
+        return out
+    if t == 4:
+        return encLen(len(v)) + b"".join(encStr(f) + encStr(x) for f, x, _ in v)
+    if t == 7:
+        mid = moduleId(v["name"], v["version"])
+        return encLen(mid) + encModuleValue(v["ops"])
+    if t == 9:
+        return encStr(zipmap([(f, x) for f, x, _ in v["pairs"]], v["free"]))
+    if t == 10:
+        return encStr(ziplist(v))
+    if t == 11:
+        return encStr(intset([int(m) for m in v]))
+    if t == 12:
+        return encStr(ziplist([x for m, s in v for x in (m, d2string(s))]))
+    if t == 13:
+        return encStr(ziplist([x for f, y, _ in v for x in (f, y)]))
+    if t == 14:
+        return encLen(len(v)) + b"".join(encStr(ziplist(node)) for node in v)
+    if t in (15, 19, 21):
+        return encStream(t, v)
+    if t == 16:
+        return encStr(listpack([x for f, y, _ in v for x in (f, y)]))
+    if t == 17:
+        return encStr(listpack([x for m, s in v for x in (m, d2string(s))]))
+    if t == 18:
+        out = encLen(len(v))
+        for container, node in v:
+            out += encLen(container) + encStr(node[0] if container == 1 else listpack(node))
+        return out
+    if t == 20:
+        return encStr(listpack(v))
+    if t == 24:
+        minExpire = min(ttl for _, _, ttl in v if ttl)
+        out = ms(minExpire) + encLen(len(v))
+        for f, x, ttl in v:
+            out += encLen(ttl - minExpire + 1 if ttl else 0) + encStr(f) + encStr(x)
+        return out
+    if t == 25:
+        minExpire = min(ttl for _, _, ttl in v if ttl)
+        return ms(minExpire) + encStr(listpack([x for f, y, ttl in v for x in (f, y, ttl)]))
+    raise ValueError(t)
+
+
+# ---------------------------------------------------------------- 期望结果
+
+def q(b):
+    # 长的值只比较长度和crc64
+    if len(b) > 128:
+        return "<%d bytes crc64 %016x>" % (len(b), crc64(b))
+    out = '"'
+    for c in b:
+        if c in (0x22, 0x5C) or c < 0x20 or c > 0x7E:
+            out += "\\x%02x" % c
+        else:
+            out += chr(c)
+    return out + '"'
+
+
+def fmtScore(d):
+    if d == float("inf"):
+        return "inf"
+    if d == float("-inf"):
+        return "-inf"
+    if d == int(d) and abs(d) < 1e16:
+        return str(int(d))
+    r = repr(d)
+    assert "e" not in r, r
+    return r
+
+
+def sid(i):
+    return "%d-%d" % i
+
+
+def decoded(t, v):
+    # 解码后的值, 和value.rs的RedisValue对应
+    name = typeNames[t]
+    if t == 9:
+        return name, v["pairs"]
+    if t == 11:
+        return name, v
+    if t == 14:
+        return name, [m for node in v for m in node]
+    if t == 18:
+        return name, [m for _, node in v for m in node]
+    return name, v
+
+
+def render(k):
+    t = k["type"]
+    name, v = decoded(t, k["value"])
+    lines = ["key %d %s %s(%d)" % (k["db"], q(k["key"]), name, t)]
+    if k.get("expire"):
+        lines.append("  expire %d" % k["expire"])
+    if k.get("idle"):
+        lines.append("  idle %d" % k["idle"])
+    if k.get("freq"):
+        lines.append("  freq %d" % k["freq"])
+    if name == "string":
+        lines.append("  value " + q(v))
+    elif name == "list":
+        lines += ["  item " + q(m) for m in v]
+    elif name == "set":
+        lines += ["  member " + q(m) for m in sorted(v)]
+    elif name == "zset":
+        lines += ["  score %s %s" % (fmtScore(s), q(m)) for m, s in sorted(v, key=lambda x: (x[1], x[0]))]
+    elif name == "hash":
+        for f, x, ttl in sorted(v):
+            lines.append("  field %s %s" % (q(f), q(x)) + (" ttl %d" % ttl if ttl else ""))
+    elif name == "stream":
+        for node in v["nodes"]:
+            for eMs, eSeq, fields, deleted in node:
+                if not deleted:
+                    lines.append("  entry %d-%d " % (eMs, eSeq) + " ".join(q(f) + " " + q(x) for f, x in fields))
+        lines.append("  length %d" % v["length"])
+        lines.append("  last_id " + sid(v["last_id"]))
+        if t != 15:
+            lines.append("  first_id " + sid(v["first_id"]))
+            lines.append("  max_deleted_id " + sid(v["max_deleted_id"]))
+            lines.append("  entries_added %d" % v["entries_added"])
+        for g in v["groups"]:
+            line = "  group %s last_id %s" % (q(g["name"]), sid(g["last_id"]))
+            if t != 15:
+                line += " entries_read %d" % g["entries_read"]
+            lines.append(line)
+            for p, time, count in g["pending"]:
+                lines.append("    pending %s time %d count %d" % (sid(p), time, count))
+            for c in g["consumers"]:
+                line = "    consumer %s seen %d" % (q(c["name"]), c["seen"])
+                if t == 21:
+                    line += " active %d" % c["active"]
+                lines.append(line)
+                lines += ["      pending " + sid(p) for p in c["pending"]]
+    elif name == "module":
+        raw = encValue(t, v)
+        lines.append("  module %s %d %s" % (v["name"], v["version"], raw.hex()))
+    return lines
+
+
+# ---------------------------------------------------------------- 数据
+
+rnd = random.Random(20200601)
+
+
+def noise(n):
+    return bytes(rnd.randrange(256) for _ in range(n))
+
+
+noise300 = noise(300)
+bigElement = (b"0123456789abcdef" * 1100)[:17000]
+expireAt = 4102444800000
+
+strings = [
+    (b"str:raw", b"hello world"),
+    (b"str:int8", b"-100"),
+    (b"str:int16", b"30000"),
+    (b"str:int32", b"-2000000000"),
+    (b"str:int64", b"9223372036854775807"),
+    (b"str:leading-zero", b"007"),
+    (b"str:lzf", b"abcdefgh" * 40 + b"tail"),
+    (b"str:14bit", noise300),
+    (b"str:binary", b"\x00\x01\xff\"\\ \r\n"),
+    (b"str:empty", b""),
+]
+
+listInts = [b"0", b"12", b"13", b"-1", b"127", b"-128", b"128", b"-32768", b"32767", b"8388607",
+            b"-8388608", b"8388608", b"2147483647", b"-2147483648", b"2147483648",
+            b"9223372036854775807", b"-9223372036854775808"]
+listSmall = [b"a", b"hello"] + listInts + [b"x" * 63, b"-0", b"+1", b"1.5"]
+listBig = [b"first", b"y" * 100, b"12345", b"-7", b"last"]
+
+set16 = [b"-5", b"1", b"300", b"-32768", b"32767"]
+set32 = [b"-2147483648", b"70000", b"5"]
+set64 = [b"-9223372036854775808", b"9223372036854775807", b"0", b"-1"]
+setStrings = [b"apple", b"banana", b"123", b"-7", b"\xe4\xb8\xad\xe6\x96\x87"]
+setBig = setStrings + [b"z" * 100]
+
+zsetSmall = [(b"c", -3.25), (b"a", 1.0), (b"b", 2.5), (b"d", 100000.0), (b"e", 0.1)]
+zsetBig = [(b"ninf", float("-inf")), (b"neg", -2.5), (b"zero", 0.0), (b"tenth", 0.1),
+           (b"pi", 3.14159), (b"big", 1234.5678), (b"m" * 100, 5.0), (b"pinf", float("inf"))]
+
+hashSmall = [(b"name", b"redis", 0), (b"ver", b"7", 0), (b"neg", b"-42", 0), (b"big", b"123456789012", 0)]
+hashBig = hashSmall + [(b"long", b"v" * 100, 0)]
+hashZipmap = {"pairs": [(b"f1", b"v1", 0), (b"f2", noise300, 0), (b"n", b"-42", 0)], "free": {b"f1": 2}}
+
+moduleValue = {"name": "ReJSON-RL", "version": 3,
+               "ops": [("uint", 7), ("string", b'{"a":1}'), ("double", 1.5), ("float", 0.25), ("sint", -3)]}
+
+luaScript = b"return redis.call('get', KEYS[1])"
+functionCode = b"#!lua name=mylib\nredis.register_function('f1', function() return 1 end)"
+
+
+def stream(t):
+    nodes = [
+        [(1700000000000, 0, [(b"name", b"a"), (b"n", b"1")], False),
+         (1700000000000, 1, [(b"name", b"b"), (b"n", b"2")], False),
+         (1700000000001, 0, [(b"other", b"x")], False)],
+        [(1700000000002, 0, [(b"name", b"c"), (b"n", b"3")], True),
+         (1700000000005, 0, [(b"name", b"d"), (b"n", b"-5000")], False)],
+    ]
+    groups = [
+        {"name": b"g1", "last_id": (1700000000001, 0), "entries_read": 3,
+         "pending": [((1700000000000, 0), 1700000001000, 2), ((1700000000001, 0), 1700000002000, 1)],
+         "consumers": [
+             {"name": b"alice", "seen": 1700000003000, "active": 1700000002500, "pending": [(1700000000000, 0)]},
+             {"name": b"bob", "seen": 1700000004000, "active": 1700000002000, "pending": [(1700000000001, 0)]},
+             {"name": b"idle", "seen": 1700000000500, "active": 0, "pending": []},
+         ]},
+        {"name": b"g2", "last_id": (0, 0), "entries_read": -1, "pending": [], "consumers": []},
+    ]
+    return {"nodes": nodes, "length": 4, "last_id": (1700000000005, 0), "first_id": (1700000000000, 0),
+            "max_deleted_id": (1700000000002, 0), "entries_added": 5, "groups": groups}
+
+
+def emptyStream(t):
+    return {"nodes": [], "length": 0, "last_id": (1700000000009, 3), "first_id": (0, 0),
+            "max_deleted_id": (1700000000009, 3), "entries_added": 2, "groups": []}
+
+
+def key(db, k, t, v, **kw):
+    d = {"db": db, "key": k, "type": t, "value": v}
+    d.update(kw)
+    return ("key", d)
+
+
+def aux(v):
+    out = [("aux", b"redis-ver", v["ver"].encode()), ("aux", b"redis-bits", b"64"),
+           ("aux", b"ctime", b"1700000000"), ("aux", b"used-mem", b"1048576")]
+    if v["rdb"] >= 8:
+        out += [("aux", b"repl-stream-db", b"0"), ("aux", b"repl-id", b"0" * 40),
+                ("aux", b"repl-offset", b"0"), ("aux", b"aof-preamble", b"0")]
+    if v["rdb"] >= 10:
+        out.append(("aux", b"aof-base", b"0"))
+    return out
+
+
+def build(v):
+    rdb = v["rdb"]
+    ops = []
+    if rdb >= 7:
+        ops += aux(v)
+    if rdb >= 9:
+        ops.append(("moduleaux", "TSDB-TYPE", 1, [("uint", 2), ("string", b"tsdb-aux")]))
+    if rdb >= 10:
+        ops.append(("function", functionCode))
+    if rdb >= 8:
+        ops.append(("aux", b"lua", luaScript))
+    ops.append(("select", 0))
+    if rdb >= 7:
+        ops.append(("resizedb", 40, 2))
+    if rdb >= 12:
+        ops.append(("slotinfo", 0, 40, 2))
+    for k, s in strings:
+        ops.append(key(0, k, 0, s))
+    ops.append(key(0, b"str:expire", 0, b"bye", expire=expireAt))
+    if rdb == 6:
+        # 2.6之前的秒级过期时间
+        ops.append(key(0, b"str:expire-sec", 0, b"old", expireSec=expireAt // 1000))
+    if rdb == 8:
+        ops.append(key(0, b"str:lru", 0, b"idle", idle=3600))
+    if rdb >= 10:
+        ops.append(key(0, b"str:lfu", 0, b"hot", freq=200))
+
+    # list
+    if rdb == 6:
+        ops.append(key(0, b"list:small", 10, listSmall))
+        ops.append(key(0, b"list:big", 1, listBig))
+    elif rdb < 10:
+        ops.append(key(0, b"list:small", 14, [listSmall]))
+        ops.append(key(0, b"list:big", 14, [listBig + [noise300, b"after"], [bigElement], [b"tail"]]))
+    else:
+        ops.append(key(0, b"list:small", 18, [(2, listSmall)]))
+        ops.append(key(0, b"list:big", 18, [(2, listBig + [noise300, b"after"]), (1, [bigElement]), (2, [b"tail"])]))
+
+    # set
+    ops.append(key(0, b"set:int16", 11, set16))
+    ops.append(key(0, b"set:int32", 11, set32))
+    ops.append(key(0, b"set:int64", 11, set64))
+    if rdb >= 11:
+        ops.append(key(0, b"set:small", 20, setStrings))
+    ops.append(key(0, b"set:big", 2, setBig))
+
+    # zset
+    ops.append(key(0, b"zset:small", 17 if rdb >= 10 else 12, zsetSmall))
+    ops.append(key(0, b"zset:big", 5 if rdb >= 8 else 3, zsetBig))
+
+    # hash
+    ops.append(key(0, b"hash:small", 16 if rdb >= 10 else 13, hashSmall))
+    ops.append(key(0, b"hash:big", 4, hashBig))
+    if rdb == 6:
+        ops.append(key(0, b"hash:zipmap", 9, hashZipmap))
+    if rdb >= 12:
+        ops.append(key(0, b"hash:ttl-small", 25, [(b"a", b"1", expireAt + 5000), (b"b", b"2", 0),
+                                                   (b"c", b"3", expireAt)]))
+        ops.append(key(0, b"hash:ttl-big", 24, [(b"a", b"1", 0), (b"b", b"v" * 100, expireAt + 1),
+                                                 (b"c", b"-3", expireAt)], expire=expireAt + 10000))
+
+    # stream
+    if rdb >= 9:
+        st = 21 if rdb >= 11 else 19 if rdb >= 10 else 15
+        ops.append(key(0, b"stream", st, stream(st)))
+        ops.append(key(0, b"stream:empty", st, emptyStream(st)))
+
+    # module
+    if rdb >= 8:
+        ops.append(key(0, b"module:json", 7, moduleValue))
+
+    ops.append(("select", 3))
+    if rdb >= 7:
+        ops.append(("resizedb", 1, 1))
+    ops.append(key(3, b"db3:key", 0, b"in db 3", expire=expireAt))
+    return ops
+
+
+def write(v):
+    ops = build(v)
+    out = b"REDIS%04d" % v["rdb"]
+    golden = ["# redis %s rdb version %d, 由gen.py生成" % (v["ver"], v["rdb"])]
+    for op in ops:
+        kind = op[0]
+        if kind == "aux":
+            out += b"\xfa" + encStr(op[1]) + encStr(op[2])
+            if op[1] == b"lua":
+                golden.append("aux %s" % q(op[1]))
+                golden.append("  value " + q(op[2]))
+        elif kind == "moduleaux":
+            out += b"\xf7" + encLen(moduleId(op[1], op[2])) + encModuleValue(op[3])
+        elif kind == "function":
+            name = op[1].split(b"\n")[0].split(b"name=")[1]
+            out += b"\xf5" + encStr(op[1])
+            golden.append("function %s" % q(name))
+            golden.append("  code " + q(op[1]))
+        elif kind == "select":
+            out += b"\xfe" + encLen(op[1])
+        elif kind == "resizedb":
+            out += b"\xfb" + encLen(op[1]) + encLen(op[2])
+        elif kind == "slotinfo":
+            out += b"\xf4" + encLen(op[1]) + encLen(op[2]) + encLen(op[3])
+        else:
+            k = op[1]
+            if k.get("expire"):
+                out += b"\xfc" + ms(k["expire"])
+            if k.get("expireSec"):
+                out += b"\xfd" + struct.pack("<I", k["expireSec"])
+                k["expire"] = k["expireSec"] * 1000
+            if k.get("idle"):
+                out += b"\xf8" + encLen(k["idle"])
+            if k.get("freq"):
+                out += b"\xf9" + bytes([k["freq"]])
+            out += bytes([k["type"]]) + encStr(k["key"]) + encValue(k["type"], k["value"])
+            golden += render(k)
+    out += b"\xff"
+    out += struct.pack("<Q", crc64(out))
+    base = os.path.join(here, "redis-%s" % v["ver"])
+    with open(base + ".rdb", "wb") as f:
+        f.write(out)
+    with open(base + ".txt", "w") as f:
+        f.write("\n".join(golden) + "\n")
+
+
+versions = [
+    {"ver": "2.8", "rdb": 6},
+    {"ver": "3.2", "rdb": 7},
+    {"ver": "4.0", "rdb": 8},
+    {"ver": "5.0", "rdb": 9},
+    {"ver": "6.2", "rdb": 9},
+    {"ver": "7.0", "rdb": 10},
+    {"ver": "7.2", "rdb": 11},
+    {"ver": "7.4", "rdb": 12},
+]
+
+if __name__ == "__main__":
+    for v in versions:
+        write(v)
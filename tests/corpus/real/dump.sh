@@ -0,0 +1,80 @@
+#!/bin/sh
+# 用各个版本的redis-server生成真实的rdb, 以及从redis-server读出的key列表(.keys)
+# 需要docker, 运行: sh tests/corpus/real/dump.sh [版本 ...], 默认生成全部版本
+# .keys每行为 db 类型 key 元素个数(字符串为长度), 按字节排序, 和loader互相独立
+set -e
+export LC_ALL=C
+here=$(cd "$(dirname "$0")" && pwd)
+versions=${*:-"2.8 3.2 4.0 5.0 6.2 7.0 7.2 7.4.0-rc1 7.4"}
+name=redis-shake-rs-corpus-$$
+
+cli() {
+    docker exec -i $name redis-cli "$@"
+}
+
+# 每个版本写入相同的数据, 小key用紧凑编码, big开头的key超过默认的阈值
+data() {
+    echo "set str hello"
+    echo "set int 12345"
+    echo "set bigstr $(head -c 20000 /dev/zero | tr '\0' x)"
+    echo "set ttl v"
+    echo "pexpireat ttl 4102444800000"
+    echo "rpush list a b c 1 2 3"
+    echo "sadd set a b c"
+    echo "sadd intset 1 2 3 100000"
+    echo "zadd zset 1 a 2.5 b -3 c"
+    echo "hset hash f1 v1"
+    echo "hset hash f2 v2"
+    i=0
+    while [ $i -lt 600 ]; do
+        echo "rpush biglist item$i"
+        echo "sadd bigset member$i"
+        echo "zadd bigzset $i m$i"
+        echo "hset bighash field$i value$i"
+        i=$((i + 1))
+    done
+    case $1 in
+    2.8 | 3.2 | 4.0) ;;
+    *)
+        echo "xadd stream 1-1 f v"
+        echo "xadd stream 2-1 f v2"
+        echo "xgroup create stream g 0"
+        ;;
+    esac
+    # 7.4.0-rc1的hash字段过期用的是GA之前的格式(RDB_TYPE_HASH_METADATA_PRE_GA)
+    case $1 in
+    7.4*)
+        echo "hset ttlhash f1 v1 f2 v2"
+        echo "hpexpireat ttlhash 4102444800000 fields 1 f1"
+        ;;
+    esac
+    echo "select 1"
+    echo "set db1 v"
+}
+
+for ver in $versions; do
+    docker run -d --rm --name $name redis:$ver redis-server --save "" --appendonly no >/dev/null
+    until cli ping >/dev/null 2>&1; do
+        sleep 0.2
+    done
+    data $ver | cli >/dev/null
+    cli save >/dev/null
+    docker cp $name:/data/dump.rdb "$here/redis-$ver.rdb"
+    for db in 0 1; do
+        cli -n $db --scan | while read -r key; do
+            type=$(cli -n $db type "$key")
+            case $type in
+            string) n=$(cli -n $db strlen "$key") ;;
+            list) n=$(cli -n $db llen "$key") ;;
+            set) n=$(cli -n $db scard "$key") ;;
+            zset) n=$(cli -n $db zcard "$key") ;;
+            hash) n=$(cli -n $db hlen "$key") ;;
+            stream) n=$(cli -n $db xlen "$key") ;;
+            *) n=0 ;;
+            esac
+            echo "$db $type $key $n"
+        done
+    done | sort >"$here/redis-$ver.keys"
+    docker rm -f $name >/dev/null
+    echo "redis-$ver.rdb"
+done
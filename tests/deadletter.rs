@@ -0,0 +1,92 @@
+// 死信文件的格式和轮转
+mod common;
+
+use common::temp_path;
+use redis::cmd;
+use redis_shake_rs::utils::deadletter::{segments, superseded, DeadLetter, DeadLetterOptions, Letter, Seen};
+
+#[test]
+fn letter_round_trip() {
+    let letter = Letter {
+        db: 3,
+        offset: 123456,
+        ts: 1628217470000,
+        error: String::from("BUSYKEY Target key name already exists."),
+    };
+    let data = letter.encode(cmd("RESTORE").arg("k\r\n").arg(0).arg(&b"\x00\xff"[..]));
+    let end = data.iter().position(|b| *b == b'\n').unwrap();
+    assert_eq!(&data[..end - 1], &b"#DL db=3 offset=123456 ts=1628217470000 err=BUSYKEY Target key name already exists."[..]);
+    assert_eq!(Letter::parse(&data[1..end - 1]), Some(letter.clone()));
+    assert_eq!(&data[end + 1..], &b"*4\r\n$7\r\nRESTORE\r\n$3\r\nk\r\n\r\n$1\r\n0\r\n$2\r\n\x00\xff\r\n"[..]);
+    assert_eq!(letter.class(), "BUSYKEY");
+    // 错误信息中的换行被替换掉
+    let multi_line = Letter { error: String::from("ERR a\r\nb"), ..letter };
+    let data = multi_line.encode(&cmd("PING"));
+    assert!(data.starts_with(b"#DL db=3 offset=123456 ts=1628217470000 err=ERR a  b\r\n*1\r\n"));
+    assert_eq!(Letter::parse(b"TS:1628217470"), None);
+    assert_eq!(Letter::parse(b"DL db=x offset=1 ts=1 err=ERR"), None);
+}
+
+#[test]
+fn seen_marks_overwritten_keys() {
+    let seen = Seen { db: 2, offset: 99, key: b"k \r\n\xff".to_vec() };
+    let data = seen.encode();
+    assert_eq!(&data[..], &b"#DLSEEN db=2 offset=99 key=6b200d0aff\r\n"[..]);
+    assert_eq!(Seen::parse(&data[1..data.len() - 2]), Some(seen));
+    assert_eq!(Letter::parse(&data[1..data.len() - 2]), None);
+
+    let path = temp_path("seen.aof");
+    let opts = DeadLetterOptions { path: path.clone(), max_bytes: 1024 * 1024, max_files: 10 };
+    let deadletter = DeadLetter::open(&opts).unwrap().unwrap();
+    // 没有死信的key不记录
+    deadletter.applied(0, 1, cmd("SET").arg("a").arg("1")).unwrap();
+    assert!(segments(&path).unwrap().is_empty());
+    deadletter.write(0, 2, b"OOM", cmd("SET").arg("a").arg("2")).unwrap();
+    deadletter.write(0, 3, b"OOM", cmd("SET").arg("b").arg("1")).unwrap();
+    deadletter.write(0, 4, b"OOM", cmd("HSET").arg("h").arg("f1").arg("v")).unwrap();
+    // 其它db的同名key不算
+    deadletter.applied(1, 5, cmd("SET").arg("a").arg("3")).unwrap();
+    deadletter.applied(0, 6, cmd("DEL").arg("a").arg("c")).unwrap();
+    // 每个key只记录第一次
+    deadletter.applied(0, 7, cmd("SET").arg("a").arg("4")).unwrap();
+    // 只修改一部分或者不一定写入的命令不算覆盖
+    deadletter.applied(0, 8, cmd("HSET").arg("h").arg("f2").arg("v")).unwrap();
+    deadletter.applied(0, 9, cmd("SET").arg("b").arg("2").arg("NX")).unwrap();
+    deadletter.applied(0, 10, cmd("APPEND").arg("b").arg("2")).unwrap();
+    let last = superseded(&segments(&path).unwrap()).unwrap();
+    // a的死信序号为1, 标记为4; b和h的死信没有被覆盖
+    assert_eq!(last.len(), 1);
+    assert_eq!(last.get(&(0, b"a".to_vec())), Some(&4));
+    // RESTORE REPLACE和*STORE的目标key整个被替换
+    deadletter.applied(0, 11, cmd("RESTORE").arg("h").arg(0).arg("x").arg("REPLACE")).unwrap();
+    deadletter.applied(0, 12, cmd("SUNIONSTORE").arg("b").arg("s1").arg("s2")).unwrap();
+    let last = superseded(&segments(&path).unwrap()).unwrap();
+    assert_eq!(last.get(&(0, b"h".to_vec())), Some(&5));
+    assert_eq!(last.get(&(0, b"b".to_vec())), Some(&6));
+}
+
+#[test]
+fn rotate_keeps_newest_files() {
+    let path = temp_path("rotate.aof");
+    let opts = DeadLetterOptions { path: path.clone(), max_bytes: 100, max_files: 2 };
+    let deadletter = DeadLetter::open(&opts).unwrap().unwrap();
+    // 还没有写入时不创建文件
+    assert!(segments(&path).unwrap().is_empty());
+    for i in 0..5 {
+        deadletter.write(0, i, b"OOM command not allowed", cmd("SET").arg(format!("key{}", i)).arg("v")).unwrap();
+    }
+    let files = segments(&path).unwrap();
+    assert_eq!(files, vec![format!("{}.4", path), format!("{}.5", path)]);
+    // 重新打开时编号接着已有的文件
+    let deadletter = DeadLetter::open(&opts).unwrap().unwrap();
+    deadletter.write(0, 5, b"ERR", &cmd("PING")).unwrap();
+    assert_eq!(segments(&path).unwrap().last(), Some(&format!("{}.6", path)));
+    assert!(DeadLetter::open(&DeadLetterOptions { path: String::new(), ..opts }).unwrap().is_none());
+}
+
+#[test]
+fn disabled_by_default() {
+    let opts = DeadLetterOptions::default();
+    assert!(opts.path.is_empty());
+    assert!(DeadLetter::open(&opts).unwrap().is_none());
+}
@@ -0,0 +1,273 @@
+// ziplist, zipmap, intset, lzf和长度编码的round trip, 以及任意输入不会panic
+// 任意输入的部分和fuzz/fuzz_targets下的target是一样的
+use redis_shake_rs::rdb::loader::{lzfDecompress, rdbReader};
+use redis_shake_rs::rdb::slice_buffer::sliceBuffer;
+use redis_shake_rs::rdb::writer::{lzf_compress, Writer};
+
+use async_std::task::block_on;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+fn reader() -> rdbReader<std::io::Cursor<Vec<u8>>> {
+    rdbReader::fromBytes(vec![])
+}
+
+// 和redis的string2ll一样, 只有规范的十进制才按整数编码
+fn parse_int(s: &[u8]) -> Option<i64> {
+    let v = std::str::from_utf8(s).ok()?.parse::<i64>().ok()?;
+    if format!("{}", v).as_bytes() != s {
+        return None;
+    }
+    Some(v)
+}
+
+fn ziplist_entry(prevlen: usize, v: &[u8]) -> Vec<u8> {
+    let mut p = vec![];
+    if prevlen < 254 {
+        p.push(prevlen as u8);
+    } else {
+        p.push(254);
+        p.extend_from_slice(&(prevlen as u32).to_le_bytes());
+    }
+    match parse_int(v) {
+        Some(i) if (0..=12).contains(&i) => p.push(0xf1 + i as u8),
+        Some(i) if i >= i8::MIN as i64 && i <= i8::MAX as i64 => {
+            p.push(0xfe);
+            p.push(i as i8 as u8);
+        }
+        Some(i) if i >= i16::MIN as i64 && i <= i16::MAX as i64 => {
+            p.push(0xc0);
+            p.extend_from_slice(&(i as i16).to_le_bytes());
+        }
+        Some(i) if (-(1 << 23)..1 << 23).contains(&i) => {
+            p.push(0xf0);
+            p.extend_from_slice(&(i as i32).to_le_bytes()[..3]);
+        }
+        Some(i) if i >= i32::MIN as i64 && i <= i32::MAX as i64 => {
+            p.push(0xd0);
+            p.extend_from_slice(&(i as i32).to_le_bytes());
+        }
+        Some(i) => {
+            p.push(0xe0);
+            p.extend_from_slice(&i.to_le_bytes());
+        }
+        None if v.len() < 1 << 6 => {
+            p.push(v.len() as u8);
+            p.extend_from_slice(v);
+        }
+        None if v.len() < 1 << 14 => {
+            p.push(0x40 | (v.len() >> 8) as u8);
+            p.push(v.len() as u8);
+            p.extend_from_slice(v);
+        }
+        None => {
+            p.push(0x80);
+            p.extend_from_slice(&(v.len() as u32).to_be_bytes());
+            p.extend_from_slice(v);
+        }
+    }
+    p
+}
+
+fn ziplist(items: &[Vec<u8>]) -> Vec<u8> {
+    let mut body = vec![];
+    let mut prev = 0;
+    let mut tail = 10;
+    for v in items {
+        let e = ziplist_entry(prev, v);
+        tail = 10 + body.len();
+        prev = e.len();
+        body.extend(e);
+    }
+    let mut p = vec![];
+    p.extend_from_slice(&(10 + body.len() as u32 + 1).to_le_bytes());
+    p.extend_from_slice(&(tail as u32).to_le_bytes());
+    p.extend_from_slice(&(std::cmp::min(items.len(), 65535) as u16).to_le_bytes());
+    p.extend(body);
+    p.push(0xff);
+    p
+}
+
+fn zipmap_len(p: &mut Vec<u8>, n: usize) {
+    if n < 254 {
+        p.push(n as u8);
+    } else {
+        p.push(254);
+        p.extend_from_slice(&(n as u32).to_le_bytes());
+    }
+}
+
+fn zipmap(pairs: &[(Vec<u8>, Vec<u8>, u8)]) -> Vec<u8> {
+    let mut p = vec![std::cmp::min(pairs.len(), 254) as u8];
+    for (f, v, free) in pairs {
+        zipmap_len(&mut p, f.len());
+        p.extend_from_slice(f);
+        zipmap_len(&mut p, v.len());
+        p.push(*free);
+        p.extend_from_slice(v);
+        p.resize(p.len() + *free as usize, 0);
+    }
+    p.push(255);
+    p
+}
+
+fn intset(values: &[i64]) -> Vec<u8> {
+    let width = if values.iter().all(|v| *v >= i16::MIN as i64 && *v <= i16::MAX as i64) {
+        2
+    } else if values.iter().all(|v| *v >= i32::MIN as i64 && *v <= i32::MAX as i64) {
+        4
+    } else {
+        8
+    };
+    let mut p = vec![];
+    p.extend_from_slice(&(width as u32).to_le_bytes());
+    p.extend_from_slice(&(values.len() as u32).to_le_bytes());
+    for v in values {
+        p.extend_from_slice(&v.to_le_bytes()[..width]);
+    }
+    p
+}
+
+fn element() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        any::<i64>().prop_map(|i| format!("{}", i).into_bytes()),
+        (-20i64..20).prop_map(|i| format!("{}", i).into_bytes()),
+        vec(any::<u8>(), 0..300),
+        (any::<u8>(), 16384..17000usize).prop_map(|(b, n)| vec![b; n]),
+    ]
+}
+
+// 长度253和254在zipmap里的编码不同, 多生成一些边界附近的长度
+fn zipmap_bytes() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![vec(any::<u8>(), 0..300), vec(any::<u8>(), 250..260)]
+}
+
+proptest! {
+    #[test]
+    fn lzf_round_trip(data in prop_oneof![
+        vec(any::<u8>(), 0..4096),
+        vec(0u8..4, 0..20000),
+    ]) {
+        let compressed = lzf_compress(&data);
+        prop_assert_eq!(lzfDecompress(&compressed, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn lzf_arbitrary_input(data in vec(any::<u8>(), 0..512), outlen in 0usize..4096) {
+        let _ = lzfDecompress(&data, outlen);
+    }
+
+    #[test]
+    fn ziplist_round_trip(items in vec(element(), 0..40)) {
+        let mut r = reader();
+        let mut buf = sliceBuffer::new(ziplist(&items));
+        let got = block_on(async {
+            let n = r.ReadZiplistLength(&mut buf).await?;
+            let mut got = vec![];
+            for _ in 0..n {
+                got.push(r.ReadZiplistEntry(&mut buf).await?);
+            }
+            Ok::<_, Box<dyn std::error::Error>>(got)
+        }).unwrap();
+        prop_assert_eq!(got, items);
+    }
+
+    #[test]
+    fn ziplist_arbitrary_input(data in vec(any::<u8>(), 0..512)) {
+        let mut r = reader();
+        let mut buf = sliceBuffer::new(data);
+        let _ = block_on(async {
+            let n = r.ReadZiplistLength(&mut buf).await?;
+            for _ in 0..n {
+                r.ReadZiplistEntry(&mut buf).await?;
+            }
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+    }
+
+    #[test]
+    fn zipmap_round_trip(pairs in vec((zipmap_bytes(), zipmap_bytes(), 0u8..4), 0..20)) {
+        let mut r = reader();
+        let mut buf = sliceBuffer::new(zipmap(&pairs));
+        let got = block_on(async {
+            buf.ReadByte()?;
+            let n = r.CountZipmapItems(&mut buf).await?;
+            buf.ReadByte()?;
+            let mut got = vec![];
+            loop {
+                let (length, _) = r.readZipmapItemLength(&mut buf, false).await?;
+                if length == -1 {
+                    break;
+                }
+                let field = buf.Slice(length)?;
+                let value = r.ReadZipmapItem(&mut buf, true).await?;
+                got.push((field, value));
+            }
+            Ok::<_, Box<dyn std::error::Error>>((n, got))
+        }).unwrap();
+        prop_assert_eq!(got.0 as usize, pairs.len() * 2);
+        let want: Vec<_> = pairs.into_iter().map(|(f, v, _)| (f, v)).collect();
+        prop_assert_eq!(got.1, want);
+    }
+
+    #[test]
+    fn zipmap_arbitrary_input(data in vec(any::<u8>(), 0..512)) {
+        let mut r = reader();
+        let mut buf = sliceBuffer::new(data);
+        let _ = block_on(async {
+            r.CountZipmapItems(&mut buf).await?;
+            loop {
+                let (length, _) = r.readZipmapItemLength(&mut buf, false).await?;
+                if length == -1 {
+                    break;
+                }
+                buf.Slice(length)?;
+                r.ReadZipmapItem(&mut buf, true).await?;
+            }
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+    }
+
+    #[test]
+    fn intset_round_trip(mut values in prop_oneof![
+        vec(any::<i16>().prop_map(|v| v as i64), 0..50),
+        vec(any::<i32>().prop_map(|v| v as i64), 0..50),
+        vec(any::<i64>(), 0..50),
+    ]) {
+        values.sort();
+        values.dedup();
+        let mut r = reader();
+        let mut buf = sliceBuffer::new(intset(&values));
+        prop_assert_eq!(block_on(r.ReadIntset(&mut buf)).unwrap(), values);
+    }
+
+    #[test]
+    fn intset_arbitrary_input(data in vec(any::<u8>(), 0..512)) {
+        let mut r = reader();
+        let _ = block_on(r.ReadIntset(&mut sliceBuffer::new(data)));
+    }
+
+    #[test]
+    fn length_round_trip(length in prop_oneof![
+        0u64..64,
+        64u64..16384,
+        16384u64..=u32::MAX as u64,
+        any::<u64>(),
+    ]) {
+        let mut data = vec![];
+        Writer::new(&mut data, false).write_length(length).unwrap();
+        prop_assert_eq!(block_on(rdbReader::fromBytes(data.clone()).ReadLength64()).unwrap(), length);
+        let encoded = block_on(rdbReader::fromBytes(data).readEncodedLength());
+        if length <= u32::MAX as u64 {
+            prop_assert_eq!(encoded.unwrap(), (length as u32, false));
+        } else {
+            prop_assert!(encoded.is_err());
+        }
+    }
+
+    #[test]
+    fn length_arbitrary_input(data in vec(any::<u8>(), 0..16)) {
+        let _ = block_on(rdbReader::fromBytes(data.clone()).readEncodedLength());
+        let _ = block_on(rdbReader::fromBytes(data).ReadLength64());
+    }
+}
@@ -0,0 +1,147 @@
+// rdb-diff: 两个rdb按key比较, 报告只在一边的key, 类型, 元素和过期时间的差异
+mod common;
+
+use common::temp_path;
+use redis_shake_rs::rdb::compress::Compression;
+use redis_shake_rs::rdb::diff::{diff, DiffOptions, DiffSummary};
+use redis_shake_rs::rdb::loader;
+use redis_shake_rs::rdb::salvage::{CrcPolicy, ErrorPolicy};
+use redis_shake_rs::rdb::writer::Writer;
+
+use async_std::task::block_on;
+use std::fs;
+use std::path::Path;
+
+// 长度加上每个字符串, list/set/hash的value都是这种格式
+fn payload(items: &[&[u8]], pairs: bool) -> Vec<u8> {
+    let mut raw = vec![];
+    let mut w = Writer::new(&mut raw, false);
+    let n = if pairs { items.len() / 2 } else { items.len() };
+    w.write_length(n as u64).unwrap();
+    for item in items {
+        w.write_string(item).unwrap();
+    }
+    raw
+}
+
+enum Key<'a> {
+    String(&'a [u8]),
+    List(Vec<&'a [u8]>),
+    Set(Vec<&'a [u8]>),
+    Hash(Vec<&'a [u8]>),
+}
+
+fn write(path: &str, keys: &[(&[u8], u64, Key)]) {
+    let mut w = Writer::create(path, 9, false, Compression::from_path(path)).unwrap();
+    w.select_db(0).unwrap();
+    for (key, expire_at, value) in keys {
+        if *expire_at != 0 {
+            w.write_expiry_ms(*expire_at).unwrap();
+        }
+        match value {
+            Key::String(v) => w.write_string_object(key, v).unwrap(),
+            Key::List(v) => w.write_object(loader::RdbTypeList, key, &payload(v, false)).unwrap(),
+            Key::Set(v) => w.write_object(loader::RdbTypeSet, key, &payload(v, false)).unwrap(),
+            Key::Hash(v) => w.write_object(loader::RdbTypeHash, key, &payload(v, true)).unwrap(),
+        }
+    }
+    w.footer().unwrap();
+    w.finish().unwrap();
+}
+
+fn run(a: &str, b: &str, opts: &DiffOptions) -> (DiffSummary, String) {
+    let mut out = vec![];
+    let summary = block_on(diff(a, b, opts, CrcPolicy::Fail, ErrorPolicy::Abort, &mut out)).unwrap();
+    (summary, String::from_utf8(out).unwrap())
+}
+
+fn snapshots(name: &str) -> (String, String) {
+    let a = temp_path(&format!("{}-a.rdb", name));
+    let b = temp_path(&format!("{}-b.rdb.gz", name));
+    write(
+        &a,
+        &[
+            (b"same", 0, Key::String(b"v")),
+            (b"str", 0, Key::String(b"1")),
+            (b"only_a", 0, Key::String(b"x")),
+            (b"type", 0, Key::String(b"x")),
+            (b"ttl", 1700000000000, Key::String(b"x")),
+            (b"list", 0, Key::List(vec![b"a", b"b", b"c"])),
+            (b"hash", 0, Key::Hash(vec![b"f1", b"v1", b"f2", b"v2"])),
+            (b"set", 0, Key::Set(vec![b"m1", b"m2"])),
+        ],
+    );
+    write(
+        &b,
+        &[
+            (b"set", 0, Key::Set(vec![b"m2", b"m1"])),
+            (b"hash", 0, Key::Hash(vec![b"f1", b"v1", b"f2", b"changed", b"f3", b"v3"])),
+            (b"list", 0, Key::List(vec![b"a", b"b"])),
+            (b"ttl", 1700000000500, Key::String(b"x")),
+            (b"type", 0, Key::Set(vec![b"x"])),
+            (b"only_b", 0, Key::String(b"y")),
+            (b"str", 0, Key::String(b"2")),
+            (b"same", 0, Key::String(b"v")),
+        ],
+    );
+    (a, b)
+}
+
+#[test]
+fn report_differences() {
+    let (a, b) = snapshots("report");
+    for partitions in [1, 3, 16].iter() {
+        let opts = DiffOptions {
+            partitions: *partitions,
+            temp_dir: temp_path("report-tmp"),
+            ..DiffOptions::default()
+        };
+        fs::create_dir_all(&opts.temp_dir).unwrap();
+        let (summary, report) = run(&a, &b, &opts);
+        // 临时文件都删掉了
+        assert_eq!(fs::read_dir(&opts.temp_dir).unwrap().count(), 0);
+        assert_eq!((summary.keys_a, summary.keys_b), (8, 8), "{}", report);
+        assert_eq!((summary.only_a, summary.only_b), (1, 1), "{}", report);
+        assert_eq!(summary.type_diff, 1, "{}", report);
+        // str, list, hash
+        assert_eq!(summary.value_diff, 3, "{}", report);
+        assert_eq!(summary.ttl_diff, 1, "{}", report);
+        // same, set
+        assert_eq!(summary.same, 2, "{}", report);
+        assert!(!summary.is_same());
+        for line in [
+            "only-in-a db0 \"only_a\" type:string",
+            "only-in-b db0 \"only_b\" type:string",
+            "type db0 \"type\" a:string b:set",
+            "ttl db0 \"ttl\" a:1700000000000 b:1700000000500",
+            "    index \"2\": only in a \"c\"",
+            "    field \"f2\": a:\"v2\" b:\"changed\"",
+            "    field \"f3\": only in b \"v3\"",
+            "    a:\"1\" b:\"2\"",
+        ]
+        .iter()
+        {
+            assert!(report.lines().any(|l| l == *line), "没有 {:?}\n{}", line, report);
+        }
+    }
+}
+
+#[test]
+fn ttl_tolerance() {
+    let (a, b) = snapshots("ttl");
+    let opts = DiffOptions {
+        ttl_tolerance_ms: 1000,
+        ..DiffOptions::default()
+    };
+    assert_eq!(run(&a, &b, &opts).0.ttl_diff, 0);
+}
+
+#[test]
+fn same_snapshot() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/redis-7.4.rdb");
+    let path = corpus.to_string_lossy();
+    let (summary, report) = run(&path, &path, &DiffOptions::default());
+    assert!(summary.is_same(), "{}", report);
+    assert!(report.is_empty(), "{}", report);
+    assert_eq!(summary.same, summary.keys_a);
+}
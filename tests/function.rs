@@ -0,0 +1,78 @@
+// Redis Functions: library名字的解析和按冲突策略生成/改写命令
+use redis_shake_rs::rdb::function::{library_name, rewrite_incr_args, FunctionPolicy, Functions};
+
+use bytes::Bytes;
+use std::collections::HashSet;
+
+const CODE: &[u8] = b"#!lua name=mylib\nredis.register_function('f', function() return 1 end)";
+
+fn args(cmd: &redis::Cmd) -> Vec<Vec<u8>> {
+    cmd.args_iter()
+        .map(|a| match a {
+            redis::Arg::Simple(d) => d.to_vec(),
+            redis::Arg::Cursor => b"0".to_vec(),
+        })
+        .collect()
+}
+
+fn to_args(p: &[&str]) -> Vec<Bytes> {
+    p.iter().map(|s| Bytes::copy_from_slice(s.as_bytes())).collect()
+}
+
+#[test]
+fn parse_library_name() {
+    assert_eq!(library_name(CODE), Some(b"mylib".to_vec()));
+    assert_eq!(library_name(b"#!lua engine=x name=other\nreturn"), Some(b"other".to_vec()));
+    assert_eq!(library_name(b"return 1"), None);
+    assert_eq!(library_name(b"#!lua\nreturn 1"), None);
+}
+
+#[test]
+fn full_cmd_policy() {
+    let functions = |policy| Functions {
+        policy,
+        existing: vec![b"mylib".to_vec()].into_iter().collect::<HashSet<_>>(),
+    };
+    let cmd = functions(FunctionPolicy::Replace).full_cmd(CODE).unwrap().unwrap();
+    assert_eq!(args(&cmd), vec![b"FUNCTION".to_vec(), b"LOAD".to_vec(), b"REPLACE".to_vec(), CODE.to_vec()]);
+    assert!(functions(FunctionPolicy::Skip).full_cmd(CODE).unwrap().is_none());
+    assert!(functions(FunctionPolicy::Fail).full_cmd(CODE).is_err());
+
+    // 目的端没有这个library时都是直接LOAD
+    let empty = Functions {
+        policy: FunctionPolicy::Fail,
+        existing: HashSet::new(),
+    };
+    let cmd = empty.full_cmd(CODE).unwrap().unwrap();
+    assert_eq!(args(&cmd), vec![b"FUNCTION".to_vec(), b"LOAD".to_vec(), CODE.to_vec()]);
+}
+
+#[test]
+fn rewrite_incr() {
+    let mut a = to_args(&["FUNCTION", "LOAD", "code"]);
+    rewrite_incr_args(&mut a, FunctionPolicy::Replace);
+    assert_eq!(a, to_args(&["FUNCTION", "LOAD", "REPLACE", "code"]));
+
+    let mut a = to_args(&["function", "load", "replace", "code"]);
+    rewrite_incr_args(&mut a, FunctionPolicy::Skip);
+    assert_eq!(a, to_args(&["function", "load", "code"]));
+
+    let mut a = to_args(&["FUNCTION", "RESTORE", "payload", "FLUSH"]);
+    rewrite_incr_args(&mut a, FunctionPolicy::Skip);
+    assert_eq!(a, to_args(&["FUNCTION", "RESTORE", "payload", "APPEND"]));
+
+    let mut a = to_args(&["FUNCTION", "RESTORE", "payload"]);
+    rewrite_incr_args(&mut a, FunctionPolicy::Replace);
+    assert_eq!(a, to_args(&["FUNCTION", "RESTORE", "payload", "REPLACE"]));
+
+    // fail策略和其它命令原样发送
+    let mut a = to_args(&["FUNCTION", "LOAD", "code"]);
+    rewrite_incr_args(&mut a, FunctionPolicy::Fail);
+    assert_eq!(a, to_args(&["FUNCTION", "LOAD", "code"]));
+    let mut a = to_args(&["FUNCTION", "DELETE", "mylib"]);
+    rewrite_incr_args(&mut a, FunctionPolicy::Replace);
+    assert_eq!(a, to_args(&["FUNCTION", "DELETE", "mylib"]));
+
+    assert!(FunctionPolicy::parse("replace").is_ok());
+    assert!(FunctionPolicy::parse("other").is_err());
+}
@@ -0,0 +1,101 @@
+// redis 7.4 hash field过期时间的几种编码
+// 下面的rdb是按7.4.0-rc1和7.4.0的rdb.c手工构造的, 不是真实的rc服务器生成的dump
+mod common;
+
+use common::temp_path;
+use redis_shake_rs::rdb::compress::Compression;
+use redis_shake_rs::rdb::loader::{
+    BinEntry, RdbTypeHashListpackExPreGa, RdbTypeHashMetadata, RdbTypeHashMetadataPreGa,
+};
+use redis_shake_rs::rdb::writer::Writer;
+use redis_shake_rs::utils::source::open_rdb_file;
+
+use async_std::task::block_on;
+
+const T1: u64 = 4102444800000;
+const T2: u64 = 4102444805000;
+
+fn read_all(path: &str) -> Vec<BinEntry> {
+    block_on(async {
+        let mut loader = open_rdb_file(path).await.unwrap();
+        loader.Header().await.unwrap();
+        let mut entries = vec![];
+        loop {
+            let mut e = BinEntry::default();
+            match loader.NextBinEntry(&mut e).await {
+                Ok(()) => entries.push(e),
+                Err(err) if err.to_string() == "RDB END" => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        loader.Footer().await.unwrap();
+        entries
+    })
+}
+
+// 每个field: ttl(长度编码), field, value
+fn metadata(min_expire: Option<u64>, fields: &[(&[u8], &[u8], u64)]) -> Vec<u8> {
+    let mut raw = vec![];
+    if let Some(min) = min_expire {
+        raw.extend_from_slice(&min.to_le_bytes());
+    }
+    let mut w = Writer::new(&mut raw, false);
+    w.write_length(fields.len() as u64).unwrap();
+    for (field, value, ttl) in fields {
+        w.write_length(*ttl).unwrap();
+        w.write_string(field).unwrap();
+        w.write_string(value).unwrap();
+    }
+    raw
+}
+
+// listpack中field value ttl三个一组
+fn listpack_ex(fields: &[(&[u8], &[u8], u64)]) -> Vec<u8> {
+    let mut body = vec![];
+    for (field, value, ttl) in fields {
+        for s in [*field, *value] {
+            body.push(0x80 | s.len() as u8);
+            body.extend_from_slice(s);
+            body.push(1 + s.len() as u8);
+        }
+        body.push(0xf4);
+        body.extend_from_slice(&ttl.to_le_bytes());
+        body.push(9);
+    }
+    let mut lp = ((body.len() + 7) as u32).to_le_bytes().to_vec();
+    lp.extend_from_slice(&((fields.len() * 3) as u16).to_le_bytes());
+    lp.extend(body);
+    lp.push(0xff);
+    let mut raw = vec![];
+    Writer::new(&mut raw, false).write_string(&lp).unwrap();
+    raw
+}
+
+#[test]
+fn field_expires() {
+    let path = temp_path("hash-ttl.rdb");
+    let mut w = Writer::create(&path, 12, false, Compression::None).unwrap();
+    w.select_db(0).unwrap();
+    // 7.4.0-rc1: ttl是绝对时间
+    let pre_ga = metadata(None, &[(b"f1", b"v1", T1), (b"f2", b"v2", 0), (b"f3", b"v3", T2)]);
+    w.write_object(RdbTypeHashMetadataPreGa, b"pre-ga", &pre_ga).unwrap();
+    // 7.4.0: ttl是相对minExpire的值加1
+    let ga = metadata(Some(T1), &[(b"f1", b"v1", 1), (b"f2", b"v2", 0), (b"f3", b"v3", T2 - T1 + 1)]);
+    w.write_object(RdbTypeHashMetadata, b"ga", &ga).unwrap();
+    let lp = listpack_ex(&[(b"f1", b"v1", T1), (b"f2", b"v2", 0)]);
+    w.write_object(RdbTypeHashListpackExPreGa, b"lp-pre-ga", &lp).unwrap();
+    w.footer().unwrap();
+    drop(w);
+
+    let entries = read_all(&path);
+    let expires: Vec<_> = entries.iter().map(|e| (e.Key.clone(), e.FieldExpires.clone())).collect();
+    let both = vec![(b"f1".to_vec(), T1), (b"f3".to_vec(), T2)];
+    assert_eq!(
+        expires,
+        vec![
+            (b"pre-ga".to_vec(), both.clone()),
+            (b"ga".to_vec(), both),
+            (b"lp-pre-ga".to_vec(), vec![(b"f1".to_vec(), T1)]),
+        ]
+    );
+}
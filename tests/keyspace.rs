@@ -0,0 +1,38 @@
+// keyspace模式中有变化的key的队列: 容量, 丢弃计数和去抖
+use redis_shake_rs::rdb::keyspace::ChangedKeys;
+
+use std::thread::sleep;
+use std::time::Duration;
+
+#[test]
+fn capacity_and_drops() {
+    let mut c = ChangedKeys::new(2);
+    assert!(c.push(0, b"a".to_vec()));
+    assert!(c.push(0, b"b".to_vec()));
+    // 已经在队列里的key不占新的位置
+    assert!(c.push(0, b"a".to_vec()));
+    assert!(!c.push(0, b"c".to_vec()));
+    assert!(!c.push(1, b"a".to_vec()));
+    assert_eq!(c.len(), 2);
+    assert_eq!(c.dropped, 2);
+
+    assert_eq!(c.pop_ready(Duration::from_secs(0), 1), vec![(0, b"a".to_vec())]);
+    assert!(c.push(0, b"c".to_vec()));
+    assert_eq!(c.dropped, 2);
+}
+
+#[test]
+fn debounce() {
+    let mut c = ChangedKeys::new(100);
+    c.push(0, b"a".to_vec());
+    assert!(c.pop_ready(Duration::from_millis(50), 10).is_empty());
+    sleep(Duration::from_millis(60));
+    c.push(0, b"b".to_vec());
+    // 去抖时间内重复的通知合并成一次
+    c.push(0, b"a".to_vec());
+    assert_eq!(c.pop_ready(Duration::from_millis(50), 10), vec![(0, b"a".to_vec())]);
+    assert_eq!(c.len(), 1);
+    sleep(Duration::from_millis(60));
+    assert_eq!(c.pop_ready(Duration::from_millis(50), 10), vec![(0, b"b".to_vec())]);
+    assert!(c.is_empty());
+}
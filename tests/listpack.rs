@@ -0,0 +1,88 @@
+// redis 7的listpack编码: 各种整数和字符串的entry
+use redis_shake_rs::rdb::loader::Loader;
+use redis_shake_rs::rdb::slice_buffer::sliceBuffer;
+
+use async_std::task::block_on;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// lpEncodeBacklen的字节数, 内容不影响解析
+fn backlen(entry_len: usize) -> Vec<u8> {
+    let n = match entry_len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        _ => 3,
+    };
+    vec![0; n]
+}
+
+fn listpack(entries: &[Vec<u8>]) -> Vec<u8> {
+    let mut body = vec![];
+    for e in entries {
+        body.extend_from_slice(e);
+        body.extend(backlen(e.len()));
+    }
+    let mut p = ((body.len() + 7) as u32).to_le_bytes().to_vec();
+    p.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    p.extend(body);
+    p.push(0xff);
+    p
+}
+
+fn decode(lp: Vec<u8>) -> Vec<String> {
+    let (_w, r) = async_pipe::pipe();
+    let mut loader = Loader::new(Rc::new(RefCell::new(tokio::io::BufReader::new(r))));
+    let mut buf = sliceBuffer::new(lp);
+    block_on(async {
+        let n = loader.rdbReader.ReadListpackLength(&mut buf).await.unwrap();
+        let mut items = vec![];
+        for _ in 0..n {
+            let item = loader.rdbReader.ReadListpackEntry(&mut buf).await.unwrap();
+            items.push(String::from_utf8(item).unwrap());
+        }
+        items
+    })
+}
+
+#[test]
+fn integer_encodings() {
+    let mut int64 = vec![0xf4];
+    int64.extend_from_slice(&(-(1i64 << 40)).to_le_bytes());
+    let lp = listpack(&[
+        vec![0x05],
+        // 13位整数 -100
+        vec![0xc0 | 0x1f, 0x9c],
+        vec![0xf1, 0x18, 0xfc],
+        vec![0xf2, 0x40, 0x42, 0x0f],
+        // 24位负数
+        vec![0xf2, 0xff, 0xff, 0xff],
+        vec![0xf3, 0, 0, 0, 0x80],
+        int64,
+    ]);
+    assert_eq!(
+        decode(lp),
+        vec!["5", "-100", "-1000", "1000000", "-1", "-2147483648", "-1099511627776"]
+    );
+}
+
+#[test]
+fn string_encodings() {
+    let mut s12 = vec![0xe0, 200];
+    s12.extend(vec![b'b'; 200]);
+    let mut s32 = vec![0xf0];
+    s32.extend_from_slice(&5000u32.to_le_bytes());
+    s32.extend(vec![b'c'; 5000]);
+    let lp = listpack(&[b"\x83abc".to_vec(), s12, s32, vec![0x80]]);
+    assert_eq!(
+        decode(lp),
+        vec!["abc".to_string(), "b".repeat(200), "c".repeat(5000), String::new()]
+    );
+}
+
+#[test]
+fn unknown_header() {
+    let (_w, r) = async_pipe::pipe();
+    let mut loader = Loader::new(Rc::new(RefCell::new(tokio::io::BufReader::new(r))));
+    let mut buf = sliceBuffer::new(vec![0xf5, 0x00]);
+    assert!(block_on(loader.rdbReader.ReadListpackEntry(&mut buf)).is_err());
+}
@@ -0,0 +1,84 @@
+// Loader的几种数据来源: 内存, 同步Read, 按顺序拼接的多个文件
+mod common;
+
+use common::temp_path;
+use redis_shake_rs::rdb::loader::{BinEntry, Loader};
+use redis_shake_rs::rdb::writer::Writer;
+use redis_shake_rs::utils::source::open_files;
+
+use async_std::task::block_on;
+use std::fs;
+use tokio::io::AsyncRead;
+
+fn rdb() -> Vec<u8> {
+    let mut raw = vec![];
+    let mut w = Writer::new(&mut raw, false);
+    w.header(9).unwrap();
+    w.select_db(0).unwrap();
+    w.write_string_object(b"k1", b"v1").unwrap();
+    w.select_db(3).unwrap();
+    w.write_expiry_ms(4102444800000).unwrap();
+    w.write_string_object(b"k2", &vec![b'x'; 1000]).unwrap();
+    w.footer().unwrap();
+    raw
+}
+
+fn keys<R: AsyncRead + Unpin>(mut loader: Loader<R>) -> Vec<(u32, Vec<u8>, u64)> {
+    block_on(async {
+        loader.Header().await.unwrap();
+        let mut keys = vec![];
+        loop {
+            let mut e = BinEntry::default();
+            match loader.NextBinEntry(&mut e).await {
+                Ok(()) => keys.push((e.DB, e.Key, e.ExpireAt)),
+                Err(err) if err.to_string() == "RDB END" => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        loader.Footer().await.unwrap();
+        keys
+    })
+}
+
+fn expected() -> Vec<(u32, Vec<u8>, u64)> {
+    vec![(0, b"k1".to_vec(), 0), (3, b"k2".to_vec(), 4102444800000)]
+}
+
+#[test]
+fn from_bytes() {
+    assert_eq!(keys(Loader::fromBytes(rdb())), expected());
+}
+
+#[test]
+fn from_read() {
+    let data = rdb();
+    assert_eq!(keys(Loader::fromRead(std::io::Cursor::new(data))), expected());
+}
+
+#[test]
+fn concatenated_files() {
+    let data = rdb();
+    let (a, b) = (temp_path("loader-part-a"), temp_path("loader-part-b"));
+    fs::write(&a, &data[..data.len() / 2]).unwrap();
+    fs::write(&b, &data[data.len() / 2..]).unwrap();
+    let loader = block_on(open_files(vec![a, b])).unwrap();
+    assert_eq!(keys(loader), expected());
+}
+
+#[test]
+fn bad_checksum() {
+    let mut data = rdb();
+    let n = data.len();
+    data[n - 1] = data[n - 1] ^ 0xff;
+    let mut loader = Loader::fromBytes(data);
+    block_on(async {
+        loader.Header().await.unwrap();
+        loop {
+            let mut e = BinEntry::default();
+            if loader.NextBinEntry(&mut e).await.is_err() {
+                break;
+            }
+        }
+        assert!(loader.Footer().await.is_err());
+    });
+}
@@ -0,0 +1,107 @@
+// 在途命令的上限, 以及大key按成员拆成多段
+use redis_shake_rs::rdb::full::OverRestoreBigRdbEntry;
+use redis_shake_rs::rdb::loader::{BinEntry, Loader, RdbTypeList};
+use redis_shake_rs::rdb::writer::Writer;
+use redis_shake_rs::utils::memory::{CmdSender, InflightLimit};
+use redis_shake_rs::utils::version::TargetVersion;
+
+use async_std::future::timeout;
+use async_std::task::{block_on, sleep, spawn};
+use std::time::Duration;
+
+#[test]
+fn acquire_waits_for_release() {
+    let limit = InflightLimit::new(100);
+    block_on(limit.acquire(80));
+    let l = limit.clone();
+    let waiter = spawn(async move {
+        l.acquire(50).await;
+    });
+    block_on(async {
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(limit.used(), 80);
+        limit.release(80);
+        timeout(Duration::from_secs(5), waiter).await.unwrap();
+    });
+    assert_eq!(limit.used(), 50);
+}
+
+#[test]
+fn oversized_and_unlimited() {
+    // 比上限还大的命令在没有在途命令时放行
+    let limit = InflightLimit::new(10);
+    block_on(limit.acquire(100));
+    assert_eq!(limit.used(), 100);
+    limit.release(100);
+
+    let unlimited = InflightLimit::new(0);
+    block_on(unlimited.acquire(1 << 40));
+    assert_eq!(unlimited.chunk_size(), 16 * 1024 * 1024);
+    assert_eq!(InflightLimit::new(8 * 1024).chunk_size(), 1024);
+}
+
+fn big_list(n: usize) -> Vec<u8> {
+    let mut raw = vec![];
+    let mut w = Writer::new(&mut raw, false);
+    w.header(9).unwrap();
+    w.select_db(0).unwrap();
+    let mut value = vec![];
+    {
+        let mut vw = Writer::new(&mut value, false);
+        vw.write_length(n as u64).unwrap();
+        for i in 0..n {
+            vw.write_string(format!("member-{:04}", i).as_bytes()).unwrap();
+        }
+    }
+    w.write_object(RdbTypeList, b"l", &value).unwrap();
+    w.footer().unwrap();
+    raw
+}
+
+#[test]
+fn big_list_in_chunks() {
+    let mut loader = Loader::fromBytes(big_list(100));
+    loader.rdbReader.maxChunk = 200;
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1000);
+    let mut sender = CmdSender::new(tx, InflightLimit::new(0));
+    let chunks = block_on(async {
+        loader.Header().await.unwrap();
+        let mut chunks = 0;
+        loop {
+            let mut e = BinEntry::default();
+            match loader.NextBinEntry(&mut e).await {
+                Ok(()) => {
+                    assert_eq!(e.IsFirstChunk(), chunks == 0);
+                    OverRestoreBigRdbEntry(&e, &mut sender, TargetVersion::latest()).await.unwrap();
+                    chunks = chunks + 1;
+                }
+                Err(err) if err.to_string() == "RDB END" => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        loader.Footer().await.unwrap();
+        chunks
+    });
+    assert!(chunks > 1, "chunks {}", chunks);
+    drop(sender);
+    let mut names = vec![];
+    let mut members = vec![];
+    while let Some((cmd, _)) = block_on(rx.recv()) {
+        let args: Vec<Vec<u8>> = cmd
+            .args_iter()
+            .map(|a| match a {
+                redis::Arg::Simple(d) => d.to_vec(),
+                redis::Arg::Cursor => vec![],
+            })
+            .collect();
+        names.push(args[0].clone());
+        if args[0] == b"RPUSH" {
+            members.push(String::from_utf8(args[2].clone()).unwrap());
+        }
+    }
+    // 只有第一段删除旧的值
+    assert_eq!(names.iter().filter(|n| n.as_slice() == b"DEL").count(), 1);
+    assert_eq!(names[0], b"DEL".to_vec());
+    let expected: Vec<String> = (0..100).map(|i| format!("member-{:04}", i)).collect();
+    assert_eq!(members, expected);
+}
@@ -0,0 +1,74 @@
+// module类型的key: 按目的端的MODULE LIST和策略决定是否同步
+use redis_shake_rs::rdb::loader::{rdbModuleName, RdbTypeModule2};
+use redis_shake_rs::rdb::module::{parse_module_list, ModulePolicy, Modules};
+
+use redis::Value;
+use std::collections::{BTreeMap, HashSet};
+
+const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// 9个字符的类型名和版本号编码成module id, 放在64位长度里
+fn module_dump(type_name: &str, encver: u64) -> Vec<u8> {
+    let mut id = 0u64;
+    for c in type_name.bytes() {
+        id = id << 6 | CHARSET.iter().position(|b| *b == c).unwrap() as u64;
+    }
+    id = id << 10 | encver;
+    let mut dump = vec![RdbTypeModule2, 0x81];
+    dump.extend_from_slice(&id.to_be_bytes());
+    dump
+}
+
+fn modules(policy: ModulePolicy, loaded: &[&str]) -> Modules {
+    Modules {
+        policy,
+        loaded: loaded.iter().map(|s| s.to_string()).collect::<HashSet<_>>(),
+        report: BTreeMap::new(),
+    }
+}
+
+#[test]
+fn module_name() {
+    let dump = module_dump("ReJSON-RL", 3);
+    let id = u64::from_be_bytes([dump[2], dump[3], dump[4], dump[5], dump[6], dump[7], dump[8], dump[9]]);
+    assert_eq!(rdbModuleName(id), ("ReJSON-RL".to_string(), 3));
+}
+
+#[test]
+fn known_module_policy() {
+    let json = module_dump("ReJSON-RL", 3);
+    let mut m = modules(ModulePolicy::Skip, &["rejson"]);
+    assert!(m.check(b"k1", &json).unwrap());
+    // 不是module类型的key不检查
+    assert!(m.check(b"s", &[0, 1, b'a']).unwrap());
+
+    let mut m = modules(ModulePolicy::Skip, &["search"]);
+    assert!(!m.check(b"k1", &json).unwrap());
+    assert_eq!(m.report["ReJSON-RL"], (0, vec![b"k1".to_vec()]));
+
+    let mut m = modules(ModulePolicy::Fail, &[]);
+    assert!(m.check(b"k1", &json).is_err());
+}
+
+#[test]
+fn unknown_module_policy() {
+    let custom = module_dump("mytype-AB", 1);
+    let mut m = modules(ModulePolicy::Skip, &["rejson", "mymodule"]);
+    assert!(!m.check(b"c1", &custom).unwrap());
+    assert_eq!(m.report["mytype-AB"], (0, vec![b"c1".to_vec()]));
+
+    let mut m = modules(ModulePolicy::Fail, &["mymodule"]);
+    assert!(m.check(b"c1", &custom).is_err());
+}
+
+#[test]
+fn module_list() {
+    let data = |s: &str| Value::Data(s.as_bytes().to_vec());
+    let list = Value::Bulk(vec![
+        Value::Bulk(vec![data("name"), data("ReJSON"), data("ver"), Value::Int(20609)]),
+        Value::Bulk(vec![data("name"), data("bf"), data("ver"), Value::Int(20612)]),
+    ]);
+    let loaded = parse_module_list(&list);
+    assert_eq!(loaded, ["rejson", "bf"].iter().map(|s| s.to_string()).collect::<HashSet<_>>());
+    assert!(parse_module_list(&Value::Bulk(vec![])).is_empty());
+}
@@ -0,0 +1,73 @@
+// 按集群的slot分布拆分rdb, 集群只能加载db 0, 其它db的key不写入
+mod common;
+
+use common::temp_path;
+use redis_shake_rs::rdb::compress::Compression;
+use redis_shake_rs::rdb::loader::BinEntry;
+use redis_shake_rs::rdb::salvage::{CrcPolicy, ErrorPolicy};
+use redis_shake_rs::rdb::writer::Writer;
+use redis_shake_rs::utils::filter::Filter;
+use redis_shake_rs::utils::run::Runner;
+use redis_shake_rs::utils::slot::{key_hash_slot, SlotLayout};
+use redis_shake_rs::utils::source::open_rdb_file;
+
+use async_std::task::block_on;
+
+fn read_keys(path: &str) -> Vec<(u32, Vec<u8>)> {
+    block_on(async {
+        let mut loader = open_rdb_file(path).await.unwrap();
+        loader.Header().await.unwrap();
+        let mut keys = vec![];
+        loop {
+            let mut e = BinEntry::default();
+            match loader.NextBinEntry(&mut e).await {
+                Ok(()) => keys.push((e.DB, e.Key)),
+                Err(err) if err.to_string() == "RDB END" => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        loader.Footer().await.unwrap();
+        keys
+    })
+}
+
+#[test]
+fn hash_slot() {
+    assert_eq!(key_hash_slot(b"123456789"), 0x31c3);
+    assert_eq!(key_hash_slot(b"foo"), 12182);
+    assert_eq!(key_hash_slot(b"{user1000}.following"), key_hash_slot(b"user1000"));
+    assert_eq!(key_hash_slot(b"foo{bar}{zap}"), key_hash_slot(b"bar"));
+}
+
+#[test]
+fn parse_layout() {
+    let layout = SlotLayout::parse("127.0.0.1:7000@0-5460;127.0.0.1:7001@5461-16383").unwrap();
+    assert_eq!(layout.nodes, vec!["127.0.0.1:7000", "127.0.0.1:7001"]);
+    assert_eq!(layout.node_of(b"123456789"), Some(1));
+    assert_eq!(layout.node_of(b"{a}"), Some(1));
+    assert!(SlotLayout::parse("127.0.0.1:7000").is_err());
+
+    let layout = SlotLayout::parse("127.0.0.1:7000@0-100").unwrap();
+    assert_eq!(layout.node_of(b"foo"), None);
+}
+
+#[test]
+fn skip_other_db_keys() {
+    let input = temp_path("reshard-input.rdb");
+    let mut w = Writer::create(&input, 9, false, Compression::None).unwrap();
+    w.select_db(0).unwrap();
+    w.write_string_object(b"a", b"1").unwrap();
+    w.select_db(1).unwrap();
+    w.write_string_object(b"b", b"2").unwrap();
+    w.select_db(0).unwrap();
+    w.write_string_object(b"c", b"3").unwrap();
+    w.footer().unwrap();
+    drop(w);
+
+    let output = temp_path("reshard-output");
+    let layout = SlotLayout::parse("127.0.0.1:7000@0-16383").unwrap();
+    block_on(Runner::mod_reshard(&input, &output, &layout, &Filter::default(), false, None, CrcPolicy::Fail, ErrorPolicy::Abort)).unwrap();
+
+    let keys = read_keys(&format!("{}/127.0.0.1_7000.rdb", output));
+    assert_eq!(keys, vec![(0, b"a".to_vec()), (0, b"c".to_vec())]);
+}
@@ -0,0 +1,130 @@
+// RESP2/RESP3编解码, 以及RespConn的缓冲读取
+use redis_shake_rs::utils::resp::{decode, encode, encode_command, encode_resp3, Resp, RespConn};
+
+use async_std::task::block_on;
+use futures_util::io::Cursor;
+use futures_util::AsyncReadExt;
+
+fn values() -> Vec<Resp> {
+    vec![
+        Resp::Simple(b"OK".to_vec()),
+        Resp::Error(b"ERR unknown command".to_vec()),
+        Resp::Integer(-42),
+        Resp::Bulk(b"a\r\nb\x00\xff".to_vec()),
+        Resp::Bulk(vec![]),
+        Resp::Null,
+        Resp::Boolean(true),
+        Resp::Double(1.5),
+        Resp::Double(f64::INFINITY),
+        Resp::BigNumber(b"3492890328409238509324850943850943825024385".to_vec()),
+        Resp::Verbatim(b"txt".to_vec(), b"Some string".to_vec()),
+        Resp::Array(vec![Resp::Bulk(b"set".to_vec()), Resp::Integer(1), Resp::Array(vec![])]),
+        Resp::Map(vec![(Resp::Simple(b"first".to_vec()), Resp::Integer(1)), (Resp::Bulk(b"second".to_vec()), Resp::Null)]),
+        Resp::Set(vec![Resp::Integer(1), Resp::Integer(2)]),
+        Resp::Push(vec![Resp::Bulk(b"message".to_vec()), Resp::Bulk(b"ch".to_vec())]),
+    ]
+}
+
+#[test]
+fn round_trip() {
+    for v in values() {
+        let mut out = vec![];
+        encode(&v, &mut out);
+        assert_eq!(decode(&out).unwrap(), Some((v.clone(), out.len())), "{:?}", v);
+        // 不完整的数据返回None, 不会出错
+        for i in 0..out.len() {
+            assert_eq!(decode(&out[..i]).unwrap(), None, "{:?} {}", v, i);
+        }
+    }
+}
+
+#[test]
+fn null_encoding() {
+    // 没有协商RESP3时null按RESP2编码
+    let mut out = vec![];
+    encode(&Resp::Array(vec![Resp::Null]), &mut out);
+    assert_eq!(out, b"*1\r\n$-1\r\n");
+    let mut out = vec![];
+    encode_resp3(&Resp::Array(vec![Resp::Null]), &mut out);
+    assert_eq!(out, b"*1\r\n_\r\n");
+    for v in values() {
+        let mut out = vec![];
+        encode_resp3(&v, &mut out);
+        assert_eq!(decode(&out).unwrap(), Some((v.clone(), out.len())), "{:?}", v);
+    }
+}
+
+#[test]
+fn nesting_limit() {
+    let nested = |depth: usize| {
+        let mut data = b"*1\r\n".repeat(depth);
+        data.extend_from_slice(b":1\r\n");
+        data
+    };
+    assert!(decode(&nested(100)).unwrap().is_some());
+    let err = decode(&nested(100000)).unwrap_err();
+    assert!(err.to_string().contains("嵌套"), "{}", err);
+    // attribute也算一层
+    assert!(decode(&b"|0\r\n".repeat(100000)).is_err());
+}
+
+#[test]
+fn resp2_nulls_and_attributes() {
+    assert_eq!(decode(b"$-1\r\n").unwrap(), Some((Resp::Null, 5)));
+    assert_eq!(decode(b"*-1\r\n").unwrap(), Some((Resp::Null, 5)));
+    assert_eq!(decode(b"!5\r\nERR x\r\n").unwrap(), Some((Resp::Error(b"ERR x".to_vec()), 11)));
+    // attribute跳过, 返回后面的值
+    let data = b"|1\r\n+ttl\r\n:3\r\n:7\r\n";
+    assert_eq!(decode(data).unwrap(), Some((Resp::Integer(7), data.len())));
+}
+
+#[test]
+fn malformed() {
+    for data in [
+        &b"$-2\r\n"[..],
+        b"$abc\r\n",
+        b"$3\r\nabcde\r\n",
+        b":1x\r\n",
+        b"#x\r\n",
+        b"*99999999999\r\n",
+        b"=2\r\nab\r\n",
+        b"?\r\n",
+    ]
+    .iter()
+    {
+        assert!(decode(data).is_err(), "{:?}", String::from_utf8_lossy(data));
+    }
+}
+
+#[test]
+fn command_is_binary_safe() {
+    let cmd = encode_command(&[&b"SET"[..], b"k\r\n", b"\x00\xff"]);
+    assert_eq!(cmd, b"*3\r\n$3\r\nSET\r\n$3\r\nk\r\n\r\n$2\r\n\x00\xff\r\n".to_vec());
+}
+
+// PSYNC的回复, 之后是\n保活, rdb长度和rdb数据, 读完头部后剩下的数据通过AsyncRead读取
+#[test]
+fn conn_reads_handshake_then_payload() {
+    let data = b"+FULLRESYNC 8de1787ba490483314a4d30f1c628bc5025eb761 2443808505\r\n\n\n$5\r\nREDIS*1\r\n$4\r\nPING\r\n".to_vec();
+    let mut conn = RespConn::new(Cursor::new(data));
+    block_on(async {
+        let header = conn.read_value().await.unwrap();
+        assert_eq!(header.to_string(), "FULLRESYNC 8de1787ba490483314a4d30f1c628bc5025eb761 2443808505");
+        assert_eq!(conn.read_line().await.unwrap(), b"");
+        assert_eq!(conn.read_line().await.unwrap(), b"");
+        assert_eq!(conn.read_line().await.unwrap(), b"$5");
+        let mut rest = vec![];
+        conn.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"REDIS*1\r\n$4\r\nPING\r\n".to_vec());
+    });
+}
+
+#[test]
+fn conn_request() {
+    let mut conn = RespConn::new(Cursor::new(vec![]));
+    block_on(async {
+        conn.send(&["auth", "pass"]).await.unwrap();
+        assert!(conn.read_value().await.is_err());
+    });
+    assert_eq!(conn.get_ref().get_ref(), &encode_command(&["auth", "pass"]));
+}
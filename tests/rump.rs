@@ -0,0 +1,37 @@
+// rump模式的keyspace解析和按类型重建的命令
+use redis_shake_rs::rdb::rump::{parse_keyspace, rebuild_cmd};
+
+fn args(cmd: &redis::Cmd) -> Vec<Vec<u8>> {
+    cmd.args_iter()
+        .map(|a| match a {
+            redis::Arg::Simple(d) => d.to_vec(),
+            redis::Arg::Cursor => b"0".to_vec(),
+        })
+        .collect()
+}
+
+#[test]
+fn keyspace() {
+    let info = "# Keyspace\r\ndb0:keys=1,expires=0,avg_ttl=0\r\ndb12:keys=5,expires=1,avg_ttl=100\r\n";
+    assert_eq!(parse_keyspace(info).unwrap(), vec![0, 12]);
+    assert_eq!(parse_keyspace("# Keyspace\r\n").unwrap(), Vec::<u32>::new());
+    assert!(parse_keyspace("dbx:keys=1").is_err());
+}
+
+#[test]
+fn hash_uses_hmset() {
+    let cmd = rebuild_cmd("HMSET", b"h", vec![b"f1".to_vec(), b"v1".to_vec(), b"f2".to_vec(), b"v2".to_vec()]);
+    assert_eq!(
+        args(&cmd),
+        vec![b"HMSET".to_vec(), b"h".to_vec(), b"f1".to_vec(), b"v1".to_vec(), b"f2".to_vec(), b"v2".to_vec()]
+    );
+}
+
+#[test]
+fn zset_swaps_member_and_score() {
+    let cmd = rebuild_cmd("ZADD", b"z", vec![b"m1".to_vec(), b"1.5".to_vec(), b"m2".to_vec(), b"2".to_vec()]);
+    assert_eq!(
+        args(&cmd),
+        vec![b"ZADD".to_vec(), b"z".to_vec(), b"1.5".to_vec(), b"m1".to_vec(), b"2".to_vec(), b"m2".to_vec()]
+    );
+}
@@ -0,0 +1,92 @@
+// 校验和不一致和解析出错时的处理策略
+use redis_shake_rs::rdb::loader::{BinEntry, Loader};
+use redis_shake_rs::rdb::salvage::{CrcPolicy, ErrorPolicy};
+use redis_shake_rs::rdb::writer::Writer;
+
+use async_std::task::block_on;
+use std::io::Cursor;
+
+// k1和k3之间夹着一段坏数据
+fn broken_rdb() -> Vec<u8> {
+    let mut raw = vec![];
+    let mut w = Writer::new(&mut raw, false);
+    w.header(9).unwrap();
+    w.select_db(0).unwrap();
+    w.write_string_object(b"k1", b"v1").unwrap();
+    w.write_raw(&[0x42, 0x42, 0x42]).unwrap();
+    w.write_string_object(b"k3", b"v3").unwrap();
+    w.footer().unwrap();
+    raw
+}
+
+fn good_rdb() -> Vec<u8> {
+    let mut raw = vec![];
+    let mut w = Writer::new(&mut raw, false);
+    w.header(9).unwrap();
+    w.select_db(0).unwrap();
+    w.write_string_object(b"k1", b"v1").unwrap();
+    w.footer().unwrap();
+    raw
+}
+
+// 返回读到的key和Footer的结果
+fn load(loader: &mut Loader<Cursor<Vec<u8>>>) -> Result<(Vec<Vec<u8>>, bool), String> {
+    block_on(async {
+        loader.Header().await.unwrap();
+        let mut keys = vec![];
+        loop {
+            let mut e = BinEntry::default();
+            match loader.NextBinEntry(&mut e).await {
+                Ok(()) => keys.push(e.Key),
+                Err(err) if err.to_string() == "RDB END" => break,
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+        Ok((keys, loader.Footer().await.is_ok()))
+    })
+}
+
+#[test]
+fn crc_policy() {
+    let mut data = good_rdb();
+    let n = data.len();
+    data[n - 1] = data[n - 1] ^ 0xff;
+
+    let mut loader = Loader::fromBytes(data.clone());
+    assert_eq!(load(&mut loader), Ok((vec![b"k1".to_vec()], false)));
+
+    let mut loader = Loader::fromBytes(data);
+    loader.crcPolicy = CrcPolicy::Warn;
+    assert_eq!(load(&mut loader), Ok((vec![b"k1".to_vec()], true)));
+
+    assert_eq!(CrcPolicy::parse("warn").unwrap(), CrcPolicy::Warn);
+    assert!(CrcPolicy::parse("ignore").is_err());
+}
+
+#[test]
+fn abort_reports_last_good_key() {
+    let mut loader = Loader::fromBytes(broken_rdb());
+    let err = load(&mut loader).unwrap_err();
+    assert!(err.contains("最后一个完整的key:k1"), "{}", err);
+}
+
+#[test]
+fn salvage_skips_bad_bytes() {
+    let mut loader = Loader::fromBytes(broken_rdb());
+    loader.errorPolicy = ErrorPolicy::Salvage;
+    // 跳过了数据, 校验和不一致也只是警告
+    assert_eq!(load(&mut loader), Ok((vec![b"k1".to_vec(), b"k3".to_vec()], true)));
+    assert_eq!(loader.skipCount, 1);
+    assert_eq!(loader.skippedBytes, 3);
+}
+
+#[test]
+fn salvage_truncated() {
+    let data = broken_rdb();
+    // 截断在k3中间, 后面没有可以继续的位置
+    let cut = data.len() - 12;
+    let mut loader = Loader::fromBytes(data[..cut].to_vec());
+    loader.errorPolicy = ErrorPolicy::Salvage;
+    assert_eq!(load(&mut loader), Ok((vec![b"k1".to_vec()], true)));
+    assert!(loader.truncated);
+}
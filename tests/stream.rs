@@ -0,0 +1,124 @@
+// 把stream拆成XADD/XSETID/XGROUP/XCLAIM命令重建
+use redis_shake_rs::rdb::loader::{BinEntry, RDBTypeStreamListPacks};
+use redis_shake_rs::rdb::stream::over_restore_stream_entry;
+use redis_shake_rs::rdb::writer::Writer;
+use redis_shake_rs::utils::memory::{CmdSender, InflightLimit};
+use redis_shake_rs::utils::version::TargetVersion;
+
+use async_std::task::block_on;
+
+fn args(cmd: &redis::Cmd) -> Vec<String> {
+    cmd.args_iter()
+        .map(|a| match a {
+            redis::Arg::Simple(d) => String::from_utf8_lossy(d).to_string(),
+            redis::Arg::Cursor => "0".to_string(),
+        })
+        .collect()
+}
+
+// 没有entry的stream, 一个group, 一个consumer, consumer有n_pending个pending entry
+fn empty_stream(last_id: (u64, u64), n_pending: u64) -> Vec<u8> {
+    let mut raw = vec![RDBTypeStreamListPacks];
+    {
+        let mut w = Writer::new(&mut raw, false);
+        // listpacks, length, last_id
+        w.write_length(0).unwrap();
+        w.write_length(0).unwrap();
+        w.write_length(last_id.0).unwrap();
+        w.write_length(last_id.1).unwrap();
+        // group
+        w.write_length(1).unwrap();
+        w.write_string(b"g").unwrap();
+        w.write_length(last_id.0).unwrap();
+        w.write_length(last_id.1).unwrap();
+        // group的PEL
+        w.write_length(n_pending).unwrap();
+        for i in 0..n_pending {
+            let mut id = 5u64.to_be_bytes().to_vec();
+            id.extend_from_slice(&i.to_be_bytes());
+            w.write_raw(&id).unwrap();
+            w.write_raw(&1000u64.to_le_bytes()).unwrap();
+            w.write_length(2).unwrap();
+        }
+        // consumer
+        w.write_length(1).unwrap();
+        w.write_string(b"c").unwrap();
+        w.write_raw(&0u64.to_le_bytes()).unwrap();
+        w.write_length(n_pending).unwrap();
+        for i in 0..n_pending {
+            let mut id = 5u64.to_be_bytes().to_vec();
+            id.extend_from_slice(&i.to_be_bytes());
+            w.write_raw(&id).unwrap();
+        }
+    }
+    raw
+}
+
+fn rebuild(value: Vec<u8>, create_consumer: bool) -> Vec<Vec<String>> {
+    let e = BinEntry {
+        Key: b"s".to_vec(),
+        Value: value,
+        ..BinEntry::default()
+    };
+    let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+    let mut tx = CmdSender::new(tx, InflightLimit::new(0));
+    block_on(async {
+        let target = TargetVersion {
+            rdb: 9,
+            stream_create_consumer: create_consumer,
+        };
+        over_restore_stream_entry(&e, &mut tx, target).await.unwrap();
+        drop(tx);
+        let mut cmds = vec![];
+        while let Some((cmd, _)) = rx.recv().await {
+            cmds.push(args(&cmd));
+        }
+        cmds
+    })
+}
+
+fn to_args(p: &[&str]) -> Vec<String> {
+    p.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn empty_stream_zero_id() {
+    // last_id是0-0时不能XSETID
+    let cmds = rebuild(empty_stream((0, 0), 0), true);
+    assert_eq!(
+        cmds,
+        vec![
+            to_args(&["DEL", "s"]),
+            to_args(&["XADD", "s", "MAXLEN", "0", "0-1", "x", "x"]),
+            to_args(&["XGROUP", "CREATE", "s", "g", "0-0"]),
+            to_args(&["XGROUP", "CREATECONSUMER", "s", "g", "c"]),
+        ]
+    );
+}
+
+#[test]
+fn empty_stream_set_id() {
+    let cmds = rebuild(empty_stream((7, 3), 0), true);
+    assert_eq!(cmds[1], to_args(&["XADD", "s", "MAXLEN", "0", "0-1", "x", "x"]));
+    assert_eq!(cmds[2], to_args(&["XSETID", "s", "7-3"]));
+}
+
+#[test]
+fn consumer_without_createconsumer() {
+    // 目的端低于6.2: 没有pending entry的consumer无法创建
+    let cmds = rebuild(empty_stream((7, 3), 0), false);
+    assert_eq!(cmds.last().unwrap(), &to_args(&["XGROUP", "CREATE", "s", "g", "7-3"]));
+
+    // 有pending entry的consumer都由XCLAIM创建
+    for create_consumer in [true, false] {
+        let cmds = rebuild(empty_stream((7, 3), 2), create_consumer);
+        assert!(cmds.iter().all(|c| c[1] != "CREATECONSUMER"));
+        assert_eq!(
+            cmds[cmds.len() - 2..].to_vec(),
+            vec![
+                to_args(&["XCLAIM", "s", "g", "c", "0", "5-0", "TIME", "1000", "RETRYCOUNT", "2", "FORCE", "JUSTID"]),
+                to_args(&["XCLAIM", "s", "g", "c", "0", "5-1", "TIME", "1000", "RETRYCOUNT", "2", "FORCE", "JUSTID"]),
+            ]
+        );
+    }
+}
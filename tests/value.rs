@@ -0,0 +1,146 @@
+// RedisValue解码和Visitor遍历
+mod common;
+
+use common::temp_path;
+use redis_shake_rs::rdb::compress::Compression;
+use redis_shake_rs::rdb::loader::{
+    BinEntry, RdbTypeHashMetadataPreGa, RdbTypeList, RdbTypeSetIntset, RdbTypeZSet2,
+};
+use redis_shake_rs::rdb::value::{decode_value, visit, Item, RedisValue, Visitor};
+use redis_shake_rs::rdb::writer::Writer;
+use redis_shake_rs::utils::source::open_rdb_file;
+
+use async_std::task::block_on;
+use std::error::Error;
+
+fn strings(items: &[&[u8]]) -> Vec<u8> {
+    let mut raw = vec![];
+    let mut w = Writer::new(&mut raw, false);
+    w.write_length(items.len() as u64).unwrap();
+    for s in items {
+        w.write_string(s).unwrap();
+    }
+    raw
+}
+
+fn write_rdb(path: &str) {
+    let mut w = Writer::create(path, 12, false, Compression::None).unwrap();
+    // 只有lua脚本的aux会作为entry返回
+    w.write_aux(b"lua", b"return 1").unwrap();
+    w.select_db(0).unwrap();
+    w.write_string_object(b"s", b"v").unwrap();
+    w.write_object(RdbTypeList, b"l", &strings(&[b"a", b"b"])).unwrap();
+
+    let mut intset = vec![];
+    intset.extend_from_slice(&2u32.to_le_bytes());
+    intset.extend_from_slice(&2u32.to_le_bytes());
+    intset.extend_from_slice(&(-3i16).to_le_bytes());
+    intset.extend_from_slice(&7i16.to_le_bytes());
+    let mut raw = vec![];
+    Writer::new(&mut raw, false).write_string(&intset).unwrap();
+    w.write_object(RdbTypeSetIntset, b"i", &raw).unwrap();
+
+    let mut raw = vec![];
+    {
+        let mut zw = Writer::new(&mut raw, false);
+        zw.write_length(1).unwrap();
+        zw.write_string(b"m").unwrap();
+    }
+    raw.extend_from_slice(&1.5f64.to_le_bytes());
+    w.write_object(RdbTypeZSet2, b"z", &raw).unwrap();
+
+    // 7.4.0-rc1的hash: ttl, field, value
+    let mut raw = vec![];
+    {
+        let mut hw = Writer::new(&mut raw, false);
+        hw.write_length(1).unwrap();
+        hw.write_length(4102444800000).unwrap();
+        hw.write_string(b"f").unwrap();
+        hw.write_string(b"v").unwrap();
+    }
+    w.write_object(RdbTypeHashMetadataPreGa, b"h", &raw).unwrap();
+    w.footer().unwrap();
+}
+
+fn entries(path: &str) -> Vec<BinEntry> {
+    block_on(async {
+        let mut loader = open_rdb_file(path).await.unwrap();
+        loader.Header().await.unwrap();
+        let mut entries = vec![];
+        loop {
+            let mut e = BinEntry::default();
+            match loader.NextBinEntry(&mut e).await {
+                Ok(()) => entries.push(e),
+                Err(err) if err.to_string() == "RDB END" => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        entries
+    })
+}
+
+#[test]
+fn decode() {
+    let path = temp_path("value-decode.rdb");
+    write_rdb(&path);
+    let values: Vec<_> = entries(&path)
+        .iter()
+        .skip(1)
+        .map(|e| block_on(decode_value(e)).unwrap())
+        .collect();
+    assert_eq!(
+        values,
+        vec![
+            RedisValue::String(b"v".to_vec()),
+            RedisValue::List(vec![b"a".to_vec(), b"b".to_vec()]),
+            RedisValue::Set(vec![b"-3".to_vec(), b"7".to_vec()]),
+            RedisValue::ZSet(vec![(b"m".to_vec(), 1.5)]),
+            RedisValue::Hash(vec![(b"f".to_vec(), b"v".to_vec(), 4102444800000)]),
+        ]
+    );
+}
+
+#[derive(Default)]
+struct Counter {
+    aux: Vec<Vec<u8>>,
+    keys: Vec<Vec<u8>>,
+    items: usize,
+    ended: bool,
+}
+
+impl Visitor for Counter {
+    fn aux(&mut self, key: &[u8], _value: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.aux.push(key.to_vec());
+        Ok(())
+    }
+    fn key(&mut self, e: &BinEntry) -> Result<bool, Box<dyn Error>> {
+        self.keys.push(e.Key.clone());
+        // list不解码
+        Ok(e.Key != b"l")
+    }
+    fn item(&mut self, _e: &BinEntry, _item: Item) -> Result<(), Box<dyn Error>> {
+        self.items = self.items + 1;
+        Ok(())
+    }
+    fn end(&mut self) -> Result<(), Box<dyn Error>> {
+        self.ended = true;
+        Ok(())
+    }
+}
+
+#[test]
+fn visitor() {
+    let path = temp_path("value-visit.rdb");
+    write_rdb(&path);
+    let mut counter = Counter::default();
+    block_on(async {
+        let mut loader = open_rdb_file(&path).await.unwrap();
+        loader.Header().await.unwrap();
+        visit(&mut loader, &mut counter).await.unwrap();
+    });
+    assert_eq!(counter.aux, vec![b"lua".to_vec()]);
+    assert_eq!(counter.keys, vec![b"s".to_vec(), b"l".to_vec(), b"i".to_vec(), b"z".to_vec(), b"h".to_vec()]);
+    // string 1, intset 2, zset 1, hash 1
+    assert_eq!(counter.items, 5);
+    assert!(counter.ended);
+}
@@ -0,0 +1,33 @@
+// 目的端redis版本到rdb版本和命令能力的映射
+use redis_shake_rs::utils::version::{redis_version_to_rdb, TargetVersion};
+
+#[test]
+fn rdb_version_boundaries() {
+    let cases = [
+        ("2.8.24", 6),
+        ("3.0.7", 6),
+        ("3.1.0", 6),
+        ("3.2.0", 7),
+        ("4.0.14", 8),
+        ("5.0.0", 9),
+        ("6.2.14", 9),
+        ("7.0.0", 10),
+        ("7.2.4", 11),
+        ("7.4.0", 12),
+        ("8.0.0", 12),
+    ];
+    for (version, rdb) in cases {
+        assert_eq!(redis_version_to_rdb(version), Some(rdb), "{}", version);
+    }
+    assert_eq!(redis_version_to_rdb("unknown"), None);
+    assert_eq!(redis_version_to_rdb("7"), None);
+}
+
+#[test]
+fn stream_create_consumer() {
+    let create_consumer = |v| TargetVersion::from_redis_version(v).unwrap().stream_create_consumer;
+    assert!(!create_consumer("6.0.16"));
+    assert!(create_consumer("6.2.0"));
+    assert!(create_consumer("7.0.0"));
+    assert!(TargetVersion::latest().stream_create_consumer);
+}
@@ -0,0 +1,145 @@
+// Writer写出的rdb要能被Loader原样读回, lzf压缩要能被lzfDecompress解开
+mod common;
+
+use common::temp_path;
+use redis_shake_rs::rdb::crc64::Crc64;
+use redis_shake_rs::rdb::loader::{lzfDecompress, BinEntry, RdbTypeHash, RdbTypeString};
+use redis_shake_rs::rdb::salvage::{CrcPolicy, ErrorPolicy};
+use redis_shake_rs::rdb::writer::{dump_payload, lzf_compress, Writer};
+use redis_shake_rs::utils::filter::Filter;
+use redis_shake_rs::utils::run::Runner;
+use redis_shake_rs::utils::source::open_rdb_file;
+
+use async_std::task::block_on;
+use std::fs::File;
+use std::io::BufWriter;
+
+// 固定种子的伪随机数据, 基本不可压缩
+fn noise(n: usize) -> Vec<u8> {
+    let mut x: u32 = 0x9e3779b9;
+    let mut p = Vec::with_capacity(n);
+    for _ in 0..n {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        p.push(x as u8);
+    }
+    p
+}
+
+#[test]
+fn crc64_check_value() {
+    let mut crc = Crc64::new();
+    crc.update(b"123456789");
+    assert_eq!(crc.get(), 0xe9c6d914c4b8d9ca);
+}
+
+#[test]
+fn lzf_round_trip() {
+    let mut inputs = vec![
+        b"".to_vec(),
+        b"ab".to_vec(),
+        b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec(),
+        b"hello world hello world hello world hello world".to_vec(),
+        vec![b'x'; 10000],
+        noise(5000),
+    ];
+    // 超过LZF_MAX_OFF的回溯距离不能引用
+    let mut far = noise(9000);
+    far.extend_from_within(0..100);
+    inputs.push(far);
+    for input in inputs {
+        let compressed = lzf_compress(&input);
+        let out = lzfDecompress(&compressed, input.len()).unwrap();
+        assert_eq!(out, input);
+    }
+}
+
+#[test]
+fn write_string_encoding() {
+    let mut p = vec![];
+    {
+        let mut w = Writer::new(&mut p, true);
+        w.write_string(b"12").unwrap();
+        w.write_string(b"-200").unwrap();
+        w.write_string(b"007").unwrap();
+    }
+    assert_eq!(p, vec![0xc0, 12, 0xc1, 0x38, 0xff, 3, b'0', b'0', b'7']);
+
+    let value = vec![b'v'; 100];
+    let mut p = vec![];
+    Writer::new(&mut p, true).write_string(&value).unwrap();
+    assert_eq!(p[0], 0xc3);
+}
+
+#[test]
+fn filter_entry() {
+    let filter = Filter {
+        db_blacklist: vec![1],
+        key_blacklist: vec![b"drop:".to_vec()],
+        type_whitelist: vec!["string".to_string()],
+        ..Default::default()
+    };
+    let entry = |db: u32, key: &[u8], t: u8| BinEntry {
+        DB: db,
+        Key: key.to_vec(),
+        Type: t,
+        ..Default::default()
+    };
+    assert!(!filter.filter_entry(&entry(0, b"keep:1", RdbTypeString)));
+    assert!(filter.filter_entry(&entry(0, b"drop:1", RdbTypeString)));
+    assert!(filter.filter_entry(&entry(1, b"keep:1", RdbTypeString)));
+    assert!(filter.filter_entry(&entry(0, b"keep:1", RdbTypeHash)));
+}
+
+fn read_all(path: &str) -> Vec<BinEntry> {
+    block_on(async {
+        let mut loader = open_rdb_file(path).await.unwrap();
+        loader.Header().await.unwrap();
+        let mut entries = vec![];
+        loop {
+            let mut e = BinEntry::default();
+            match loader.NextBinEntry(&mut e).await {
+                Ok(()) => entries.push(e),
+                Err(err) if err.to_string() == "RDB END" => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        loader.Footer().await.unwrap();
+        entries
+    })
+}
+
+#[test]
+fn filter_round_trip() {
+    let input = temp_path("writer-input.rdb");
+    let mut w = Writer::new(BufWriter::new(File::create(&input).unwrap()), true);
+    w.header(9).unwrap();
+    w.select_db(0).unwrap();
+    w.write_string_object(b"keep:1", &[b'a'; 64]).unwrap();
+    w.write_string_object(b"drop:1", b"1").unwrap();
+    w.select_db(1).unwrap();
+    w.write_expiry_ms(4102444800000).unwrap();
+    w.write_string_object(b"keep:2", b"12345").unwrap();
+    w.footer().unwrap();
+    drop(w);
+
+    let output = temp_path("writer-output.rdb");
+    let filter = Filter {
+        key_blacklist: vec![b"drop:".to_vec()],
+        ..Default::default()
+    };
+    block_on(Runner::mod_filter(&input, &output, &filter, true, None, CrcPolicy::Fail, ErrorPolicy::Abort)).unwrap();
+
+    let render = |entries: Vec<BinEntry>| -> Vec<_> {
+        entries
+            .into_iter()
+            .filter(|e| e.Type == RdbTypeString && !e.Key.starts_with(b"drop:"))
+            .map(|e| (e.DB, e.Key.clone(), e.ExpireAt, dump_payload(&e.Value).to_vec()))
+            .collect()
+    };
+    let keys = render(read_all(&output));
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys, render(read_all(&input)));
+    assert_eq!((keys[1].0, keys[1].2), (1, 4102444800000));
+}